@@ -1,8 +1,23 @@
 use clap::{App, Arg};
 
+use rustual_boy_core::interconnect::BusErrorPolicy;
+
 pub struct CommandLineConfig {
     pub rom_path: String,
     pub sram_path: String,
+    pub rewind_memory_budget: usize,
+    pub rewind_interval: u32,
+    pub bus_error_policy: BusErrorPolicy,
+    pub link_listen_addr: Option<String>,
+    pub link_connect_addr: Option<String>,
+    pub link_sync_period: u64,
+    pub gdb_port: Option<u16>,
+    pub record_path: Option<String>,
+    pub play_path: Option<String>,
+    pub key_bindings_path: Option<String>,
+    pub golden_path: Option<String>,
+    pub golden_frames: Option<usize>,
+    pub bless: bool,
 }
 
 pub fn parse_args() -> CommandLineConfig {
@@ -18,6 +33,68 @@ pub fn parse_args() -> CommandLineConfig {
               .help("Path to an SRAM")
               .short("s")
               .long("sram")
+        ).arg(Arg::with_name("REWIND_BUDGET")
+              .help("Maximum amount of memory used for rewind snapshots, in megabytes")
+              .long("rewind-budget")
+              .takes_value(true)
+              .default_value("64")
+        ).arg(Arg::with_name("REWIND_INTERVAL")
+              .help("Number of frames between rewind snapshots")
+              .long("rewind-interval")
+              .takes_value(true)
+              .default_value("1")
+        ).arg(Arg::with_name("BUS_ERROR_POLICY")
+              .help("What to do when the CPU accesses unmapped memory: stop, log or open-bus \
+                     (the last two take the hex value unmapped reads return, eg. open-bus=ffff)")
+              .long("bus-error-policy")
+              .takes_value(true)
+              .validator(|value| parse_bus_error_policy(&value).map(|_| ()))
+              .default_value("stop")
+        ).arg(Arg::with_name("LINK_LISTEN")
+              .help("Wait for another emulator to connect its link cable to this address (eg. 127.0.0.1:7900)")
+              .long("link-listen")
+              .takes_value(true)
+              .conflicts_with("LINK_CONNECT")
+        ).arg(Arg::with_name("LINK_CONNECT")
+              .help("Connect the link cable to another emulator listening on this address")
+              .long("link-connect")
+              .takes_value(true)
+        ).arg(Arg::with_name("LINK_SYNC_PERIOD")
+              .help("Number of CPU cycles between link cable syncs (must match on both ends). Each sync is a \
+                     network round trip; lower values make transfers faster but need a faster connection")
+              .long("link-sync-period")
+              .takes_value(true)
+              .default_value("20000")
+        ).arg(Arg::with_name("GDB")
+              .help("Wait for GDB to connect on this local TCP port before starting")
+              .long("gdb")
+              .takes_value(true)
+        ).arg(Arg::with_name("RECORD")
+              .help("Record game pad input to a movie file")
+              .long("record")
+              .takes_value(true)
+              .conflicts_with("PLAY")
+        ).arg(Arg::with_name("PLAY")
+              .help("Play back game pad input from a movie file")
+              .long("play")
+              .takes_value(true)
+        ).arg(Arg::with_name("KEYS")
+              .help("Path to a key bindings file")
+              .long("keys")
+              .takes_value(true)
+        ).arg(Arg::with_name("GOLDEN")
+              .help("Run headlessly and compare video/audio output against a golden record file")
+              .long("golden")
+              .takes_value(true)
+        ).arg(Arg::with_name("FRAMES")
+              .help("Number of frames to run headlessly (defaults to the length of the golden record)")
+              .long("frames")
+              .takes_value(true)
+              .requires("GOLDEN")
+        ).arg(Arg::with_name("BLESS")
+              .help("Write the golden record from this run instead of comparing against it")
+              .long("bless")
+              .requires("GOLDEN")
         );
 
     let matches = app.get_matches();
@@ -25,11 +102,57 @@ pub fn parse_args() -> CommandLineConfig {
     // unwrap is safe here becuase clap guarantees that required arguments are never None
     let rom_path = matches.value_of("ROM").unwrap();
 
+    let rewind_budget_mb = value_t!(matches, "REWIND_BUDGET", usize).unwrap_or_else(|e| e.exit());
+    let rewind_interval = value_t!(matches, "REWIND_INTERVAL", u32).unwrap_or_else(|e| e.exit());
+    let link_sync_period = value_t!(matches, "LINK_SYNC_PERIOD", u64).unwrap_or_else(|e| e.exit());
+    let gdb_port = if matches.is_present("GDB") {
+        Some(value_t!(matches, "GDB", u16).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    let golden_frames = if matches.is_present("FRAMES") {
+        Some(value_t!(matches, "FRAMES", usize).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+
     CommandLineConfig {
         rom_path: rom_path.into(),
         sram_path: match matches.value_of("SRAM") {
             Some(v) => v.into(),
             None => rom_path.replace(".vb", ".srm")
         },
+        rewind_memory_budget: rewind_budget_mb * 1024 * 1024,
+        rewind_interval: rewind_interval,
+        // unwrap is safe here because the value has already been validated
+        bus_error_policy: parse_bus_error_policy(matches.value_of("BUS_ERROR_POLICY").unwrap()).unwrap(),
+        link_listen_addr: matches.value_of("LINK_LISTEN").map(|addr| addr.into()),
+        link_connect_addr: matches.value_of("LINK_CONNECT").map(|addr| addr.into()),
+        link_sync_period: link_sync_period,
+        gdb_port: gdb_port,
+        record_path: matches.value_of("RECORD").map(|path| path.into()),
+        play_path: matches.value_of("PLAY").map(|path| path.into()),
+        key_bindings_path: matches.value_of("KEYS").map(|path| path.into()),
+        golden_path: matches.value_of("GOLDEN").map(|path| path.into()),
+        golden_frames: golden_frames,
+        bless: matches.is_present("BLESS"),
+    }
+}
+
+fn parse_bus_error_policy(value: &str) -> Result<BusErrorPolicy, String> {
+    let (name, open_bus_value) = match value.find('=') {
+        Some(index) => {
+            let open_bus_value = u16::from_str_radix(&value[index + 1..], 16)
+                .map_err(|_| format!("Invalid open bus value: {}", &value[index + 1..]))?;
+            (&value[..index], Some(open_bus_value))
+        }
+        _ => (value, None),
+    };
+
+    match (name, open_bus_value) {
+        ("stop", None) => Ok(BusErrorPolicy::Stop),
+        ("log", _) => Ok(BusErrorPolicy::Log(open_bus_value.unwrap_or(0))),
+        ("open-bus", _) => Ok(BusErrorPolicy::OpenBus(open_bus_value.unwrap_or(0))),
+        _ => Err(format!("Invalid bus error policy: {} (expected stop, log[=<hex>] or open-bus[=<hex>])", value)),
     }
 }
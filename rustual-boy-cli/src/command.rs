@@ -1,28 +1,42 @@
-use combine::{choice, eof, many1, optional, Parser, parser, try, value};
+use combine::{any, choice, eof, many1, optional, Parser, parser, try, value};
 use combine::char::{alpha_num, digit, hex_digit, space, spaces, string};
 use combine::primitives::{ParseResult, Stream};
 
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_core::watchpoint::WatchpointKind;
+
 use std::str::{self, FromStr};
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum Command {
     ShowCpuCache,
     ShowRegs,
     Step(u32),
+    Next,
+    Finish,
+    Backtrace,
     Continue,
-    Goto(u32),
-    ShowMem(Option<u32>),
+    Goto(Expression),
+    ShowMem(Option<Expression>),
     Disassemble(u32),
+    Assemble(u32, String),
     Label,
     AddLabel(String, u32),
     RemoveLabel(String),
     Breakpoint,
-    AddBreakpoint(u32),
-    RemoveBreakpoint(u32),
+    AddBreakpoint(BreakpointArgs),
+    RemoveBreakpoint(Expression),
     Watchpoint,
-    AddWatchpoint(u32),
-    RemoveWatchpoint(u32),
+    AddWatchpoint(WatchpointArgs),
+    RemoveWatchpoint(Expression),
+    Trace(Option<TraceArgs>),
+    TraceOff,
+    LowBattery(Option<bool>),
+    PadConnected(Option<bool>),
     Exit,
     Repeat,
 }
@@ -55,19 +69,34 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
         .map(|(_, count)| Command::Step(count.unwrap_or(1)))
         .boxed();
 
+    let next =
+        choice([try(string("next")), try(string("n"))])
+        .map(|_| Command::Next)
+        .boxed();
+
+    let finish =
+        choice([try(string("finish")), try(string("fin"))])
+        .map(|_| Command::Finish)
+        .boxed();
+
+    let backtrace =
+        choice([try(string("backtrace")), try(string("bt"))])
+        .map(|_| Command::Backtrace)
+        .boxed();
+
     let continue_ =
         choice([try(string("continue")), try(string("c"))])
         .map(|_| Command::Continue)
         .boxed();
 
     let goto =
-        (choice([try(string("goto")), try(string("g"))]), spaces(), u32_hex())
+        (choice([try(string("goto")), try(string("g"))]), spaces(), expression())
         .map(|(_, _, addr)| Command::Goto(addr))
         .boxed();
 
     let show_mem =
         (choice([try(string("showmem")), try(string("m"))]),
-            optional((spaces(), u32_hex()).map(|x| x.1)))
+            optional((spaces(), expression()).map(|x| x.1)))
         .map(|(_, addr)| Command::ShowMem(addr))
         .boxed();
 
@@ -77,6 +106,15 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
         .map(|(_, count)| Command::Disassemble(count.unwrap_or(4)))
         .boxed();
 
+    let assemble =
+        (choice([try(string("assemble")), try(string("asm"))]),
+            space(),
+            u32_hex(),
+            space(),
+            many1::<String, _>(any()))
+        .map(|(_, _, addr, _, source)| Command::Assemble(addr, source))
+        .boxed();
+
     let label =
         choice([try(string("label")), try(string("l"))])
         .map(|_| Command::Label)
@@ -103,17 +141,18 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
         .map(|_| Command::Breakpoint)
         .boxed();
 
+    // `breakpoint`/`b` lists breakpoints without arguments, and adds one with them
     let add_breakpoint =
-        (choice([try(string("addbreakpoint")), try(string("ab"))]),
+        (choice([try(string("addbreakpoint")), try(string("ab")), try(string("breakpoint")), try(string("b"))]),
             space(),
-            u32_hex())
-        .map(|(_, _, addr)| Command::AddBreakpoint(addr))
+            breakpoint_args())
+        .map(|(_, _, args)| Command::AddBreakpoint(args))
         .boxed();
 
     let remove_breakpoint =
         (choice([try(string("removebreakpoint")), try(string("rb"))]),
             space(),
-            u32_hex())
+            expression())
         .map(|(_, _, addr)| Command::RemoveBreakpoint(addr))
         .boxed();
 
@@ -125,17 +164,41 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
     let add_watchpoint =
         (choice([try(string("addwatchpoint")), try(string("aw"))]),
             space(),
-            u32_hex())
-        .map(|(_, _, addr)| Command::AddWatchpoint(addr))
+            watchpoint_args())
+        .map(|(_, _, args)| Command::AddWatchpoint(args))
         .boxed();
 
     let remove_watchpoint =
         (choice([try(string("removewatchpoint")), try(string("rw"))]),
             space(),
-            u32_hex())
+            expression())
         .map(|(_, _, addr)| Command::RemoveWatchpoint(addr))
         .boxed();
 
+    // `trace` alone shows whether a trace is running
+    let trace =
+        (string("trace"),
+            optional((space(), trace_args()).map(|x| x.1)))
+        .map(|(_, args)| Command::Trace(args))
+        .boxed();
+
+    let trace_off =
+        (string("trace"), spaces(), string("off"))
+        .map(|_| Command::TraceOff)
+        .boxed();
+
+    let low_battery =
+        (choice([try(string("lowbattery")), try(string("lb"))]),
+            optional((spaces(), on_off()).map(|x| x.1)))
+        .map(|(_, value)| Command::LowBattery(value))
+        .boxed();
+
+    let pad_connected =
+        (choice([try(string("padconnected")), try(string("pad"))]),
+            optional((spaces(), on_off()).map(|x| x.1)))
+        .map(|(_, value)| Command::PadConnected(value))
+        .boxed();
+
     let exit =
         choice([try(string("exit")), try(string("quit")), try(string("e")), try(string("x")), try(string("q"))])
         .map(|_| Command::Exit)
@@ -148,10 +211,14 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
             show_cpu_cache,
             show_regs,
             step,
+            next,
+            finish,
+            backtrace,
             continue_,
             goto,
             show_mem,
             disassemble,
+            assemble,
             label,
             add_label,
             remove_label,
@@ -161,6 +228,10 @@ fn command<I: Stream<Item=char>>(input: I) -> ParseResult<Command, I> {
             watchpoint,
             add_watchpoint,
             remove_watchpoint,
+            trace_off,
+            trace,
+            low_battery,
+            pad_connected,
             exit,
             repeat,
         ]
@@ -185,6 +256,801 @@ fn u32_hex<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=u32> +
         .boxed()
 }
 
+fn expression<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=Expression> + 'a> {
+    many1(any())
+        .and_then(|s: String| s.parse::<Expression>())
+        .boxed()
+}
+
+fn breakpoint_args<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=BreakpointArgs> + 'a> {
+    many1(any())
+        .and_then(|s: String| s.parse::<BreakpointArgs>())
+        .boxed()
+}
+
+fn watchpoint_args<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=WatchpointArgs> + 'a> {
+    many1(any())
+        .and_then(|s: String| s.parse::<WatchpointArgs>())
+        .boxed()
+}
+
+fn trace_args<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=TraceArgs> + 'a> {
+    many1(any())
+        .and_then(|s: String| s.parse::<TraceArgs>())
+        .boxed()
+}
+
+fn on_off<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=bool> + 'a> {
+    choice([try(string("on")), try(string("off"))])
+        .map(|s| s == "on")
+        .boxed()
+}
+
 fn label_name<'a, I: Stream<Item=char> + 'a>() -> Box<Parser<Input=I, Output=String> + 'a> {
     many1::<String, _>(alpha_num()).boxed()
 }
+
+/// A debugger expression, eg. `r10 == 5 && [.counter].b > 3`.
+///
+/// Numbers are hex (with an optional `0x` or `$` prefix), like everywhere else in the
+/// debugger. Registers are `r0`-`r31` (or `sp`, `gp`, `tp` and `lp`), `pc` and the
+/// system registers by name, labels are written `.name`, and `[addr]` reads a word from
+/// memory (`[addr].h` and `[addr].b` read a halfword or byte; unmapped memory reads as
+/// zero, or the open bus value if the bus error policy has one). Operators are the same as in C, with the same
+/// precedence (so `r6 & 1 == 0` is `r6 & (1 == 0)`), and comparisons are unsigned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    Number(u32),
+    Register(Register),
+    Label(String),
+    Memory(Box<Expression>, MemoryWidth),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Gpr(usize),
+    Pc,
+    /// A system register by its `ldsr`/`stsr` id
+    System(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryWidth {
+    Byte,
+    Halfword,
+    Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Eq,
+    NotEq,
+    And,
+    Xor,
+    Or,
+    LogicalAnd,
+    LogicalOr,
+}
+
+const SYSTEM_REGISTERS: [(&'static str, u32); 7] = [
+    ("eipc", 0),
+    ("eipsw", 1),
+    ("fepc", 2),
+    ("fepsw", 3),
+    ("ecr", 4),
+    ("psw", 5),
+    ("chcw", 24),
+];
+
+// Precedences are C's, from `||` (loosest) to `*` (tightest)
+const BINARY_OPS: [(&'static str, BinaryOp, u32); 18] = [
+    ("||", BinaryOp::LogicalOr, 1),
+    ("&&", BinaryOp::LogicalAnd, 2),
+    ("|", BinaryOp::Or, 3),
+    ("^", BinaryOp::Xor, 4),
+    ("&", BinaryOp::And, 5),
+    ("==", BinaryOp::Eq, 6),
+    ("!=", BinaryOp::NotEq, 6),
+    ("<", BinaryOp::Lt, 7),
+    ("<=", BinaryOp::LtEq, 7),
+    (">", BinaryOp::Gt, 7),
+    (">=", BinaryOp::GtEq, 7),
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Rem, 10),
+];
+
+impl Expression {
+    pub fn evaluate(&self, virtual_boy: &mut VirtualBoy, labels: &HashMap<String, u32>) -> Result<u32, Cow<'static, str>> {
+        Ok(match *self {
+            Expression::Number(value) => value,
+            Expression::Register(Register::Gpr(index)) => virtual_boy.cpu.reg_gpr(index),
+            Expression::Register(Register::Pc) => virtual_boy.cpu.reg_pc(),
+            Expression::Register(Register::System(id)) => virtual_boy.cpu.reg_system(id),
+            Expression::Label(ref name) => match labels.get(name) {
+                Some(&addr) => addr,
+                None => return Err(format!("Label .{} does not exist", name).into()),
+            },
+            Expression::Memory(ref addr, width) => {
+                let addr = addr.evaluate(virtual_boy, labels)?;
+                // Peek so evaluating a condition doesn't disturb the emulated program's timing or bus errors
+                let interconnect = &mut virtual_boy.interconnect;
+                match width {
+                    MemoryWidth::Byte => interconnect.peek_byte(addr) as u32,
+                    MemoryWidth::Halfword => interconnect.peek_halfword(addr & !0x01) as u32,
+                    MemoryWidth::Word => {
+                        let addr = addr & !0x03;
+                        (interconnect.peek_halfword(addr) as u32) | ((interconnect.peek_halfword(addr + 2) as u32) << 16)
+                    }
+                }
+            }
+            Expression::Unary(op, ref operand) => {
+                let value = operand.evaluate(virtual_boy, labels)?;
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::LogicalNot => (value == 0) as u32,
+                }
+            }
+            Expression::Binary(op, ref lhs, ref rhs) => {
+                let lhs = lhs.evaluate(virtual_boy, labels)?;
+                // Short circuit so conditions like `r6 != 0 && [r6] == 1` don't touch memory needlessly
+                match op {
+                    BinaryOp::LogicalAnd if lhs == 0 => return Ok(0),
+                    BinaryOp::LogicalOr if lhs != 0 => return Ok(1),
+                    _ => {}
+                }
+                let rhs = rhs.evaluate(virtual_boy, labels)?;
+                match op {
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 => return Err("Division by zero".into()),
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Rem => lhs % rhs,
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Shl => lhs.checked_shl(rhs).unwrap_or(0),
+                    BinaryOp::Shr => lhs.checked_shr(rhs).unwrap_or(0),
+                    BinaryOp::Lt => (lhs < rhs) as u32,
+                    BinaryOp::LtEq => (lhs <= rhs) as u32,
+                    BinaryOp::Gt => (lhs > rhs) as u32,
+                    BinaryOp::GtEq => (lhs >= rhs) as u32,
+                    BinaryOp::Eq => (lhs == rhs) as u32,
+                    BinaryOp::NotEq => (lhs != rhs) as u32,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::LogicalAnd | BinaryOp::LogicalOr => (rhs != 0) as u32,
+                }
+            }
+        })
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ExpressionParser::new(s)?;
+        let expression = parser.expression(1)?;
+        parser.end()?;
+        Ok(expression)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Number(value) => write!(f, "0x{:x}", value),
+            Expression::Register(Register::Gpr(index)) => write!(f, "r{}", index),
+            Expression::Register(Register::Pc) => write!(f, "pc"),
+            Expression::Register(Register::System(id)) => {
+                match SYSTEM_REGISTERS.iter().find(|x| x.1 == id) {
+                    Some(&(name, _)) => write!(f, "{}", name),
+                    None => write!(f, "sr{}", id),
+                }
+            }
+            Expression::Label(ref name) => write!(f, ".{}", name),
+            Expression::Memory(ref addr, width) => {
+                let suffix = match width {
+                    MemoryWidth::Byte => ".b",
+                    MemoryWidth::Halfword => ".h",
+                    MemoryWidth::Word => "",
+                };
+                write!(f, "[{}]{}", addr, suffix)
+            }
+            Expression::Unary(op, ref operand) => {
+                let symbol = match op {
+                    UnaryOp::Negate => "-",
+                    UnaryOp::Not => "~",
+                    UnaryOp::LogicalNot => "!",
+                };
+                write!(f, "{}", symbol)?;
+                write_operand(f, operand)
+            }
+            Expression::Binary(op, ref lhs, ref rhs) => {
+                let symbol = BINARY_OPS.iter().find(|x| x.1 == op).unwrap().0;
+                write_operand(f, lhs)?;
+                write!(f, " {} ", symbol)?;
+                write_operand(f, rhs)
+            }
+        }
+    }
+}
+
+// Parenthesizes compound operands so the printed expression parses back the same way
+fn write_operand(f: &mut fmt::Formatter, operand: &Expression) -> fmt::Result {
+    match *operand {
+        Expression::Unary(..) | Expression::Binary(..) => write!(f, "({})", operand),
+        _ => write!(f, "{}", operand),
+    }
+}
+
+/// Arguments to the breakpoint commands: an address, optionally followed by `if <condition>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointArgs {
+    pub addr: Expression,
+    pub condition: Option<Expression>,
+}
+
+impl FromStr for BreakpointArgs {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ExpressionParser::new(s)?;
+        let addr = parser.expression(1)?;
+        let condition = if parser.peek() == Some(&Token::Word("if".into())) {
+            parser.next();
+            Some(parser.expression(1)?)
+        } else {
+            None
+        };
+        parser.end()?;
+
+        Ok(BreakpointArgs {
+            addr: addr,
+            condition: condition,
+        })
+    }
+}
+
+/// Arguments to `addwatchpoint`: a start address, optionally `to <end>` to watch a range,
+/// and optionally `read`, `write` or `access` (the default)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchpointArgs {
+    pub start: Expression,
+    pub end: Option<Expression>,
+    pub kind: WatchpointKind,
+}
+
+impl FromStr for WatchpointArgs {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ExpressionParser::new(s)?;
+        let start = parser.expression(1)?;
+        let end = if parser.peek() == Some(&Token::Word("to".into())) {
+            parser.next();
+            Some(parser.expression(1)?)
+        } else {
+            None
+        };
+        let kind = match parser.peek() {
+            Some(&Token::Word(ref word)) if word == "read" => Some(WatchpointKind::Read),
+            Some(&Token::Word(ref word)) if word == "write" => Some(WatchpointKind::Write),
+            Some(&Token::Word(ref word)) if word == "access" => Some(WatchpointKind::Access),
+            _ => None,
+        };
+        if kind.is_some() {
+            parser.next();
+        }
+        parser.end()?;
+
+        Ok(WatchpointArgs {
+            start: start,
+            end: end,
+            kind: kind.unwrap_or(WatchpointKind::Access),
+        })
+    }
+}
+
+/// Arguments to `trace`: the file to write to, optionally `<start> to <end>` to only
+/// trace instructions in that address range, and optionally `frames <first> to <last>`
+/// (decimal, counted from when the trace starts) to only trace those frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceArgs {
+    pub path: String,
+    pub range: Option<(Expression, Expression)>,
+    pub frames: Option<(u64, u64)>,
+}
+
+impl FromStr for TraceArgs {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let path_len = s.find(char::is_whitespace).unwrap_or(s.len());
+        let (path, rest) = s.split_at(path_len);
+        if path.is_empty() {
+            return expression_error("Expected a file to trace to");
+        }
+
+        let frames_keyword = Token::Word("frames".into());
+        let to_keyword = Token::Word("to".into());
+
+        let mut parser = ExpressionParser::new(rest)?;
+        let range = match parser.peek() {
+            None => None,
+            Some(token) if *token == frames_keyword => None,
+            _ => {
+                let start = parser.expression(1)?;
+                if parser.next() != Some(to_keyword.clone()) {
+                    return expression_error("Expected `to` after the start of the address range");
+                }
+                Some((start, parser.expression(1)?))
+            }
+        };
+        let frames = if parser.peek() == Some(&frames_keyword) {
+            parser.next();
+            let first = parser.frame_number()?;
+            if parser.next() != Some(to_keyword) {
+                return expression_error("Expected `to` after the first frame");
+            }
+            Some((first, parser.frame_number()?))
+        } else {
+            None
+        };
+        parser.end()?;
+
+        Ok(TraceArgs {
+            path: path.into(),
+            range: range,
+            frames: frames,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    message: String,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ExpressionError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+fn expression_error<T, S: Into<String>>(message: S) -> Result<T, ExpressionError> {
+    Err(ExpressionError {
+        message: message.into(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u32),
+    Word(String),
+    Label(String),
+    Symbol(&'static str),
+}
+
+struct ExpressionParser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl ExpressionParser {
+    fn new(s: &str) -> Result<ExpressionParser, ExpressionError> {
+        Ok(ExpressionParser {
+            tokens: tokenize(s)?,
+            index: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn end(&self) -> Result<(), ExpressionError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => expression_error(format!("Unexpected {}", describe_token(token))),
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ExpressionError> {
+        match self.next() {
+            Some(Token::Symbol(s)) if s == symbol => Ok(()),
+            Some(token) => expression_error(format!("Expected `{}`, found {}", symbol, describe_token(&token))),
+            None => expression_error(format!("Expected `{}`", symbol)),
+        }
+    }
+
+    fn frame_number(&mut self) -> Result<u64, ExpressionError> {
+        match self.next() {
+            Some(Token::Word(word)) => match word.parse::<u64>() {
+                Ok(frame) => Ok(frame),
+                Err(_) => expression_error(format!("Invalid frame number `{}`", word)),
+            },
+            Some(token) => expression_error(format!("Expected a frame number, found {}", describe_token(&token))),
+            None => expression_error("Expected a frame number"),
+        }
+    }
+
+    // Precedence climbing; only operators that bind at least as tightly as `min_precedence` are consumed
+    fn expression(&mut self, min_precedence: u32) -> Result<Expression, ExpressionError> {
+        let mut lhs = self.unary()?;
+
+        loop {
+            let (op, precedence) = match self.peek() {
+                Some(&Token::Symbol(symbol)) => match BINARY_OPS.iter().find(|x| x.0 == symbol) {
+                    Some(&(_, op, precedence)) if precedence >= min_precedence => (op, precedence),
+                    _ => break,
+                },
+                _ => break,
+            };
+            self.next();
+
+            let rhs = self.expression(precedence + 1)?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ExpressionError> {
+        let op = match self.peek() {
+            Some(&Token::Symbol("-")) => Some(UnaryOp::Negate),
+            Some(&Token::Symbol("~")) => Some(UnaryOp::Not),
+            Some(&Token::Symbol("!")) => Some(UnaryOp::LogicalNot),
+            _ => None,
+        };
+
+        match op {
+            Some(op) => {
+                self.next();
+                Ok(Expression::Unary(op, Box::new(self.unary()?)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, ExpressionError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::Label(name)) => Ok(Expression::Label(name)),
+            Some(Token::Word(word)) => word_expression(&word),
+            Some(Token::Symbol("(")) => {
+                let expression = self.expression(1)?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            Some(Token::Symbol("[")) => {
+                let addr = self.expression(1)?;
+                self.expect("]")?;
+
+                let width = match self.peek() {
+                    Some(&Token::Label(ref suffix)) if suffix == "b" => Some(MemoryWidth::Byte),
+                    Some(&Token::Label(ref suffix)) if suffix == "h" => Some(MemoryWidth::Halfword),
+                    Some(&Token::Label(ref suffix)) if suffix == "w" => Some(MemoryWidth::Word),
+                    _ => None,
+                };
+                if width.is_some() {
+                    self.next();
+                }
+
+                Ok(Expression::Memory(Box::new(addr), width.unwrap_or(MemoryWidth::Word)))
+            }
+            Some(token) => expression_error(format!("Unexpected {}", describe_token(&token))),
+            None => expression_error("Unexpected end of expression"),
+        }
+    }
+}
+
+// A bare word is a register name if it is one, otherwise a hex number
+fn word_expression(word: &str) -> Result<Expression, ExpressionError> {
+    let register = match word {
+        "pc" => Some(Register::Pc),
+        "sp" => Some(Register::Gpr(3)),
+        "gp" => Some(Register::Gpr(4)),
+        "tp" => Some(Register::Gpr(5)),
+        "lp" => Some(Register::Gpr(31)),
+        _ => {
+            if let Some(&(_, id)) = SYSTEM_REGISTERS.iter().find(|x| x.0 == word) {
+                Some(Register::System(id))
+            } else if word.starts_with('r') {
+                match word[1..].parse::<usize>() {
+                    Ok(index) if index < 32 => Some(Register::Gpr(index)),
+                    _ => None,
+                }
+            } else {
+                None
+            }
+        }
+    };
+
+    if let Some(register) = register {
+        return Ok(Expression::Register(register));
+    }
+
+    let digits = if word.starts_with("0x") { &word[2..] } else { word };
+    match u32::from_str_radix(digits, 16) {
+        Ok(value) => Ok(Expression::Number(value)),
+        Err(_) => expression_error(format!("Unknown register or invalid number `{}`", word)),
+    }
+}
+
+fn describe_token(token: &Token) -> String {
+    match *token {
+        Token::Number(value) => format!("number 0x{:x}", value),
+        Token::Word(ref word) => format!("`{}`", word),
+        Token::Label(ref name) => format!("`.{}`", name),
+        Token::Symbol(symbol) => format!("`{}`", symbol),
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, ExpressionError> {
+    const SYMBOLS: [&'static str; 24] = [
+        "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+        "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~", "(", ")", "[", "]",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = s;
+
+    loop {
+        rest = rest.trim_left();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        if c.is_alphanumeric() || c == '$' || c == '.' {
+            let (prefix, word_start) = if c == '$' || c == '.' { (Some(c), 1) } else { (None, 0) };
+            let word_len = rest[word_start..].find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len() - word_start);
+            let word = &rest[word_start..word_start + word_len];
+            rest = &rest[word_start + word_len..];
+
+            if word.is_empty() {
+                return expression_error(format!("Expected a name after `{}`", c));
+            }
+
+            tokens.push(match prefix {
+                Some('$') => match u32::from_str_radix(word, 16) {
+                    Ok(value) => Token::Number(value),
+                    Err(_) => return expression_error(format!("Invalid number `${}`", word)),
+                },
+                Some(_) => Token::Label(word.into()),
+                None => Token::Word(word.into()),
+            });
+            continue;
+        }
+
+        match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            Some(symbol) => {
+                tokens.push(Token::Symbol(symbol));
+                rest = &rest[symbol.len()..];
+            }
+            None => return expression_error(format!("Unexpected character `{}`", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustual_boy_core::rom::Rom;
+    use rustual_boy_core::sram::Sram;
+
+    fn parse(s: &str) -> Expression {
+        s.parse::<Expression>().unwrap()
+    }
+
+    fn num(value: u32) -> Box<Expression> {
+        Box::new(Expression::Number(value))
+    }
+
+    fn binary(op: BinaryOp, lhs: Box<Expression>, rhs: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::Binary(op, lhs, rhs))
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("r10==$ff&&.loop <=0x10").unwrap(), vec![
+            Token::Word("r10".into()),
+            Token::Symbol("=="),
+            Token::Number(0xff),
+            Token::Symbol("&&"),
+            Token::Label("loop".into()),
+            Token::Symbol("<="),
+            Token::Word("0x10".into()),
+        ]);
+        assert_eq!(tokenize("[r6].b << ~1").unwrap(), vec![
+            Token::Symbol("["),
+            Token::Word("r6".into()),
+            Token::Symbol("]"),
+            Token::Label("b".into()),
+            Token::Symbol("<<"),
+            Token::Symbol("~"),
+            Token::Word("1".into()),
+        ]);
+        assert!(tokenize("r1 @ 2").is_err());
+        assert!(tokenize("$zz").is_err());
+        assert!(tokenize(". 1").is_err());
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(parse("10"), Expression::Number(0x10));
+        assert_eq!(parse("0xfe00"), Expression::Number(0xfe00));
+        assert_eq!(parse("$fe00"), Expression::Number(0xfe00));
+        assert_eq!(parse("r31"), Expression::Register(Register::Gpr(31)));
+        assert_eq!(parse("sp"), Expression::Register(Register::Gpr(3)));
+        assert_eq!(parse("pc"), Expression::Register(Register::Pc));
+        assert_eq!(parse("psw"), Expression::Register(Register::System(5)));
+        assert_eq!(parse(".main"), Expression::Label("main".into()));
+        assert_eq!(parse("[r6]"), Expression::Memory(Box::new(Expression::Register(Register::Gpr(6))), MemoryWidth::Word));
+        assert_eq!(parse("[8].h"), Expression::Memory(num(8), MemoryWidth::Halfword));
+        assert_eq!(parse("-~!1"), Expression::Unary(UnaryOp::Negate, Box::new(
+            Expression::Unary(UnaryOp::Not, Box::new(
+                Expression::Unary(UnaryOp::LogicalNot, num(1)))))));
+
+        // r32 isn't a register, and isn't hex either
+        assert!("r32".parse::<Expression>().is_err());
+        assert!("(1".parse::<Expression>().is_err());
+        assert!("[1".parse::<Expression>().is_err());
+        assert!("1 +".parse::<Expression>().is_err());
+        assert!("1 2".parse::<Expression>().is_err());
+        assert!("".parse::<Expression>().is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parse("1 + 2 * 3"), *binary(BinaryOp::Add, num(1), binary(BinaryOp::Mul, num(2), num(3))));
+        assert_eq!(parse("(1 + 2) * 3"), *binary(BinaryOp::Mul, binary(BinaryOp::Add, num(1), num(2)), num(3)));
+        assert_eq!(parse("1 << 2 + 3"), *binary(BinaryOp::Shl, num(1), binary(BinaryOp::Add, num(2), num(3))));
+        assert_eq!(parse("1 < 2 == 3 > 4"),
+            *binary(BinaryOp::Eq, binary(BinaryOp::Lt, num(1), num(2)), binary(BinaryOp::Gt, num(3), num(4))));
+        assert_eq!(parse("1 & 2 == 3"), *binary(BinaryOp::And, num(1), binary(BinaryOp::Eq, num(2), num(3))));
+        assert_eq!(parse("1 | 2 ^ 3 & 4"),
+            *binary(BinaryOp::Or, num(1), binary(BinaryOp::Xor, num(2), binary(BinaryOp::And, num(3), num(4)))));
+        assert_eq!(parse("1 || 2 && 3 | 4"),
+            *binary(BinaryOp::LogicalOr, num(1), binary(BinaryOp::LogicalAnd, num(2), binary(BinaryOp::Or, num(3), num(4)))));
+
+        // Operators of the same precedence are left associative
+        assert_eq!(parse("8 - 4 - 2"), *binary(BinaryOp::Sub, binary(BinaryOp::Sub, num(8), num(4)), num(2)));
+        assert_eq!(parse("8 / 4 % 3"), *binary(BinaryOp::Rem, binary(BinaryOp::Div, num(8), num(4)), num(3)));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in &[
+            "r10 == 5 && [.counter].b > 3",
+            "-(1 + 2) * ~[sp + 8].h",
+            "(1 || 2) && !(pc >= $fe00)",
+            "8 - (4 - 2) << 1",
+            "eipc | fepc ^ chcw & lp",
+        ] {
+            let expression = parse(s);
+            let printed = expression.to_string();
+            assert_eq!(parse(&printed), expression, "{} printed as {}", s, printed);
+        }
+
+        assert_eq!(parse("1+2*3").to_string(), "0x1 + (0x2 * 0x3)");
+        assert_eq!(parse("[r6].b").to_string(), "[r6].b");
+    }
+
+    #[test]
+    fn breakpoint_args() {
+        assert_eq!("fffff000".parse::<BreakpointArgs>().unwrap(), BreakpointArgs {
+            addr: Expression::Number(0xfffff000),
+            condition: None,
+        });
+        assert_eq!(".loop if r10 == 3".parse::<BreakpointArgs>().unwrap(), BreakpointArgs {
+            addr: Expression::Label("loop".into()),
+            condition: Some(*binary(BinaryOp::Eq, Box::new(Expression::Register(Register::Gpr(10))), num(3))),
+        });
+        assert!(".loop if".parse::<BreakpointArgs>().is_err());
+        assert!(".loop when r10".parse::<BreakpointArgs>().is_err());
+    }
+
+    #[test]
+    fn watchpoint_args() {
+        assert_eq!("5000000".parse::<WatchpointArgs>().unwrap(), WatchpointArgs {
+            start: Expression::Number(0x05000000),
+            end: None,
+            kind: WatchpointKind::Access,
+        });
+        assert_eq!("5000000 to 5000010 write".parse::<WatchpointArgs>().unwrap(), WatchpointArgs {
+            start: Expression::Number(0x05000000),
+            end: Some(Expression::Number(0x05000010)),
+            kind: WatchpointKind::Write,
+        });
+        assert_eq!(".buf read".parse::<WatchpointArgs>().unwrap().kind, WatchpointKind::Read);
+        assert!("5000000 to".parse::<WatchpointArgs>().is_err());
+        assert!("5000000 execute".parse::<WatchpointArgs>().is_err());
+    }
+
+    #[test]
+    fn trace_args() {
+        assert_eq!("trace.log".parse::<TraceArgs>().unwrap(), TraceArgs {
+            path: "trace.log".into(),
+            range: None,
+            frames: None,
+        });
+        assert_eq!("out/trace.log .start to .start + 100 frames 10 to 20".parse::<TraceArgs>().unwrap(), TraceArgs {
+            path: "out/trace.log".into(),
+            range: Some((
+                Expression::Label("start".into()),
+                *binary(BinaryOp::Add, Box::new(Expression::Label("start".into())), num(0x100)))),
+            frames: Some((10, 20)),
+        });
+        assert_eq!("trace.log frames 0 to 5".parse::<TraceArgs>().unwrap().frames, Some((0, 5)));
+        assert!("".parse::<TraceArgs>().is_err());
+        assert!("trace.log 1000".parse::<TraceArgs>().is_err());
+        assert!("trace.log frames a to 5".parse::<TraceArgs>().is_err());
+        assert!("trace.log frames 0 5".parse::<TraceArgs>().is_err());
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut virtual_boy = VirtualBoy::new(Rom::from_bytes(&[0; 1024]).unwrap(), Sram::new());
+        virtual_boy.cpu.set_reg_gpr(6, 0x05000000);
+        virtual_boy.interconnect.write_halfword(0x05000000, 0x1234);
+        virtual_boy.interconnect.write_halfword(0x05000002, 0x5678);
+        let mut labels = HashMap::new();
+        labels.insert("buf".to_string(), 0x05000000);
+
+        let mut evaluate = |s: &str| parse(s).evaluate(&mut virtual_boy, &labels);
+        assert_eq!(evaluate("[r6]"), Ok(0x56781234));
+        assert_eq!(evaluate("[.buf + 1].b == 12"), Ok(1));
+        assert_eq!(evaluate("[.buf + 3].h"), Ok(0x5678));
+        assert_eq!(evaluate("-1 > 0"), Ok(1));
+        assert_eq!(evaluate("0 && [.missing]"), Ok(0));
+        assert!(evaluate("[.missing]").is_err());
+        assert!(evaluate("1 / 0").is_err());
+
+        // Reading unmapped memory doesn't leave a bus error for the emulated program
+        assert_eq!(evaluate("[3000000]"), Ok(0));
+        assert!(!virtual_boy.interconnect.has_bus_error());
+    }
+}
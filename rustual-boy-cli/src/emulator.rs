@@ -1,21 +1,27 @@
 use minifb::{WindowOptions, Window, Key, KeyRepeat, Scale};
 
 use command::*;
+use key_bindings::KeyBindings;
 
-use rustual_boy_core::sinks::{AudioFrame, Sink, SinkRef, VideoFrame};
+use rustual_boy_core::assembler::assemble;
+use rustual_boy_core::call_stack::{Frame, FrameKind};
+use rustual_boy_core::disassembler::disassemble;
+use rustual_boy_core::emulation_error::EmulationError;
+use rustual_boy_core::sinks::{AudioFrame, FrameDetectingSink, Sink, SinkRef, VideoFrame};
 use rustual_boy_core::time_source::TimeSource;
 use rustual_boy_core::rom::Rom;
 use rustual_boy_core::sram::Sram;
-use rustual_boy_core::instruction::*;
-use rustual_boy_core::game_pad::Button;
-use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_core::virtual_boy::{StopReason, VirtualBoy};
+use rustual_boy_core::watchpoint::Watchpoint;
 
-use rustual_boy_middleware::{Anaglyphizer, GammaAdjustSink, MostRecentSink};
+use rustual_boy_middleware::{Anaglyphizer, GammaAdjustSink, GdbControl, GdbStub, MostRecentSink, MoviePlayer, MovieRecorder, NetworkLink, RewindBuffer, TraceFilter, TraceLogger};
 
 use std::time;
 use std::thread::{self, JoinHandle};
-use std::io::{stdin, stdout, Write};
-use std::collections::{HashSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, stdin, stdout, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc::{channel, Receiver};
 
 const CPU_CYCLE_TIME_NS: u64 = 50;
@@ -30,6 +36,26 @@ impl Sink<AudioFrame> for SimpleAudioFrameSink {
     }
 }
 
+/// An input movie being recorded or played back. Either way, input only changes
+/// when a frame is emitted, so a recording plays back exactly the same.
+pub enum Movie {
+    /// `input` holds the live button state (laid out like `GamePad::buttons`) until it's
+    /// applied (and recorded) on the next frame
+    Recording { recorder: MovieRecorder<BufWriter<File>>, input: u16 },
+    Playing(MoviePlayer<BufReader<File>>),
+}
+
+/// An instruction trace started with the `trace` command
+struct Trace {
+    path: String,
+    logger: TraceLogger<BufWriter<File>>,
+}
+
+struct Breakpoint {
+    condition: Option<Expression>,
+    hit_count: u64,
+}
+
 #[derive(PartialEq, Eq)]
 enum Mode {
     Running,
@@ -42,7 +68,9 @@ pub struct Emulator {
     pub virtual_boy: VirtualBoy,
     mode: Mode,
 
-    breakpoints: HashSet<u32>,
+    breakpoints: HashMap<u32, Breakpoint>,
+    // While running for `next` or `finish`, the call stack depth to stop at
+    finish_depth: Option<usize>,
 
     labels: HashMap<String, u32>,
     cursor: u32,
@@ -57,10 +85,19 @@ pub struct Emulator {
     time_source_start_time_ns: u64,
 
     emulated_cycles: u64,
+
+    rewind_buffer: RewindBuffer,
+
+    link: Option<NetworkLink<TcpStream>>,
+    gdb: Option<GdbStub>,
+    movie: Option<Movie>,
+    trace: Option<Trace>,
+
+    key_bindings: KeyBindings,
 }
 
 impl Emulator {
-    pub fn new(rom: Rom, sram: Sram, audio_buffer_sink: Box<SinkRef<[AudioFrame]>>, time_source: Box<TimeSource>) -> Emulator {
+    pub fn new(rom: Rom, sram: Sram, audio_buffer_sink: Box<SinkRef<[AudioFrame]>>, time_source: Box<TimeSource>, rewind_buffer: RewindBuffer, link: Option<NetworkLink<TcpStream>>, gdb: Option<GdbStub>, movie: Option<Movie>, key_bindings: KeyBindings) -> Emulator {
         let (stdin_sender, stdin_receiver) = channel();
         let stdin_thread = thread::spawn(move || {
             loop {
@@ -79,7 +116,8 @@ impl Emulator {
             virtual_boy: VirtualBoy::new(rom, sram),
             mode: Mode::Running,
 
-            breakpoints: HashSet::new(),
+            breakpoints: HashMap::new(),
+            finish_depth: None,
 
             labels: HashMap::new(),
             cursor: 0,
@@ -94,6 +132,15 @@ impl Emulator {
             time_source_start_time_ns: 0,
 
             emulated_cycles: 0,
+
+            rewind_buffer: rewind_buffer,
+
+            link: link,
+            gdb: gdb,
+            movie: movie,
+            trace: None,
+
+            key_bindings: key_bindings,
         }
     }
 
@@ -116,17 +163,75 @@ impl Emulator {
             let target_emulated_time_ns = self.time_source.time_ns() - self.time_source_start_time_ns;
             let target_emulated_cycles = target_emulated_time_ns / CPU_CYCLE_TIME_NS;
 
+            // Rewinding would desync us from the other end of the link cable or from the movie's input,
+            // and pull the rug out from under GDB
+            let is_rewinding = self.mode == Mode::Running && self.link.is_none() && self.gdb.is_none() && self.movie.is_none() && self.window.is_key_down(Key::Backspace);
+
             match self.mode {
+                Mode::Running if is_rewinding => {
+                    if self.emulated_cycles < target_emulated_cycles {
+                        if self.rewind_buffer.rewind(&mut self.virtual_boy) {
+                            // Emulate a single frame from the restored snapshot so there's something to display
+                            match self.virtual_boy.run_frame(&mut video_frame_sink, &mut audio_frame_sink) {
+                                Ok(frame_result) => self.emulated_cycles += frame_result.cycles,
+                                Err(e) => {
+                                    println!("{}", e);
+                                    self.start_debugger();
+                                }
+                            }
+                            audio_frame_sink.inner.clear();
+                        } else {
+                            // Nothing left to rewind to; hold the current state until the key is released
+                            self.emulated_cycles = target_emulated_cycles;
+                        }
+                    }
+                }
                 Mode::Running => {
                     let mut start_debugger = false;
+                    let mut is_gdb_stopped = self.poll_gdb() == GdbControl::Stopped;
+
+                    if !is_gdb_stopped && !self.needs_stepping() && self.emulated_cycles < target_emulated_cycles {
+                        let num_cycles = target_emulated_cycles - self.emulated_cycles;
+                        match self.virtual_boy.run_cycles(num_cycles, &mut video_frame_sink, &mut audio_frame_sink) {
+                            Ok(result) => {
+                                self.emulated_cycles += result.cycles;
+                                if result.stop_reason == StopReason::Watchpoint {
+                                    self.print_watchpoint_hit();
+                                    start_debugger = true;
+                                }
+                            }
+                            Err(e) => {
+                                println!("{}", e);
+                                start_debugger = true;
+                            }
+                        }
+                    }
 
-                    while self.emulated_cycles < target_emulated_cycles && !start_debugger {
-                        let (_, trigger_watchpoint) = self.step(&mut video_frame_sink, &mut audio_frame_sink);
-                        if trigger_watchpoint || (self.breakpoints.len() != 0 && self.breakpoints.contains(&self.virtual_boy.cpu.reg_pc())) {
-                            start_debugger = true;
+                    while self.emulated_cycles < target_emulated_cycles && !start_debugger && !is_gdb_stopped {
+                        match self.step(&mut video_frame_sink, &mut audio_frame_sink) {
+                            Ok((_, trigger_watchpoint)) => {
+                                if self.gdb.is_some() {
+                                    is_gdb_stopped = self.gdb_instruction_executed(trigger_watchpoint);
+                                } else if trigger_watchpoint {
+                                    self.print_watchpoint_hit();
+                                    start_debugger = true;
+                                } else if self.check_breakpoint() || self.check_finished() {
+                                    start_debugger = true;
+                                }
+                            }
+                            Err(e) => {
+                                println!("{}", e);
+                                is_gdb_stopped = self.gdb_emulation_error();
+                                start_debugger = !is_gdb_stopped;
+                            }
                         }
                     }
 
+                    if is_gdb_stopped {
+                        // Hold emulated time still until GDB lets the target run again
+                        self.emulated_cycles = target_emulated_cycles;
+                    }
+
                     if start_debugger {
                         self.start_debugger();
                     }
@@ -148,6 +253,9 @@ impl Emulator {
                     // We only want to update the key state when a frame is actually pushed
                     // Otherwise some games break.
                     self.read_input_keys();
+                    if !is_rewinding {
+                        self.rewind_buffer.frame(&self.virtual_boy);
+                    }
                     if self.window.is_key_pressed(Key::F12, KeyRepeat::No) {
                         self.start_debugger();
                     }
@@ -160,33 +268,254 @@ impl Emulator {
         }
     }
 
-    fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
-        let ret = self.virtual_boy.step(video_frame_sink, audio_frame_sink);
+    // Breakpoints, finish, GDB and tracing look at the CPU after every instruction, the
+    //  link cable has to be kept in sync with the other side, and movies apply input on
+    //  the exact instruction a frame is emitted, so none of them can use run_cycles
+    fn needs_stepping(&self) -> bool {
+        !self.breakpoints.is_empty() || self.finish_depth.is_some() || self.gdb.is_some() || self.trace.is_some() || self.link.is_some() || self.movie.is_some()
+    }
+
+    fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(u32, bool), EmulationError> {
+        let mut video_frame_sink = FrameDetectingSink::new(video_frame_sink);
+        let ret = self.virtual_boy.step(&mut video_frame_sink, audio_frame_sink)?;
 
         self.emulated_cycles += ret.0 as u64;
 
-        ret
+        self.trace_instruction(video_frame_sink.frame_emitted());
+
+        if video_frame_sink.frame_emitted() {
+            self.movie_frame();
+        }
+
+        let link_error = match self.link {
+            Some(ref mut link) => link.cycles(ret.0, &mut self.virtual_boy).err(),
+            None => None,
+        };
+        if let Some(e) = link_error {
+            println!("Link cable disconnected: {}", e);
+            self.link = None;
+            self.virtual_boy.interconnect.with_com_port(|com_port| com_port.set_is_connected(false));
+        }
+
+        Ok(ret)
+    }
+
+    // Returns true if there's a breakpoint at the current PC and its condition (if any) holds
+    fn check_breakpoint(&mut self) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        let breakpoint = match self.breakpoints.get_mut(&self.virtual_boy.cpu.reg_pc()) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+
+        let is_hit = match breakpoint.condition {
+            Some(ref condition) => match condition.evaluate(&mut self.virtual_boy, &self.labels) {
+                Ok(value) => value != 0,
+                Err(e) => {
+                    println!("Couldn't evaluate breakpoint condition: {}", e);
+                    true
+                }
+            },
+            None => true,
+        };
+
+        if is_hit {
+            breakpoint.hit_count += 1;
+        }
+
+        is_hit
+    }
+
+    fn check_finished(&self) -> bool {
+        match self.finish_depth {
+            Some(depth) => self.virtual_boy.cpu.call_stack().depth() <= depth,
+            None => false,
+        }
+    }
+
+    fn print_watchpoint_hit(&self) {
+        if let Some(hit) = self.virtual_boy.cpu.watchpoint_hit() {
+            println!("{}", hit);
+        }
+    }
+
+    fn poll_gdb(&mut self) -> GdbControl {
+        let result = match self.gdb {
+            Some(ref mut gdb) => gdb.poll(&mut self.virtual_boy),
+            None => return GdbControl::Running,
+        };
+
+        match result {
+            Ok(GdbControl::Detached) => {
+                println!("GDB detached");
+                self.gdb = None;
+                GdbControl::Running
+            }
+            Ok(control) => control,
+            Err(e) => {
+                self.gdb_disconnected(e);
+                GdbControl::Running
+            }
+        }
+    }
+
+    // Returns true if GDB has stopped the target
+    fn gdb_instruction_executed(&mut self, trigger_watchpoint: bool) -> bool {
+        let result = match self.gdb {
+            Some(ref mut gdb) => gdb.instruction_executed(&mut self.virtual_boy, trigger_watchpoint),
+            None => return false,
+        };
+
+        result.unwrap_or_else(|e| {
+            self.gdb_disconnected(e);
+            false
+        })
+    }
+
+    // Returns true if GDB has stopped the target
+    fn gdb_emulation_error(&mut self) -> bool {
+        let result = match self.gdb {
+            Some(ref mut gdb) => gdb.emulation_error(&mut self.virtual_boy),
+            None => return false,
+        };
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                self.gdb_disconnected(e);
+                false
+            }
+        }
+    }
+
+    fn gdb_disconnected(&mut self, e: io::Error) {
+        println!("GDB disconnected: {}", e);
+        if let Some(mut gdb) = self.gdb.take() {
+            gdb.detach(&mut self.virtual_boy);
+        }
     }
 
     fn read_input_keys(&mut self) {
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::A, self.window.is_key_down(Key::F));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::B, self.window.is_key_down(Key::H));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::Start, self.window.is_key_down(Key::Enter));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::Select, self.window.is_key_down(Key::Space));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::L, self.window.is_key_down(Key::E));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::R, self.window.is_key_down(Key::U));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::LeftDPadUp, self.window.is_key_down(Key::W));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::LeftDPadDown, self.window.is_key_down(Key::S));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::LeftDPadLeft, self.window.is_key_down(Key::A));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::LeftDPadRight, self.window.is_key_down(Key::D));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::RightDPadUp, self.window.is_key_down(Key::I));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::RightDPadDown, self.window.is_key_down(Key::K));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::RightDPadLeft, self.window.is_key_down(Key::J));
-        self.virtual_boy.interconnect.game_pad.set_button_pressed(Button::RightDPadRight, self.window.is_key_down(Key::L));
+        match self.movie {
+            Some(Movie::Recording { ref mut input, .. }) => *input = self.key_bindings.read_buttons(&self.window),
+            Some(Movie::Playing(_)) => {}
+            None => self.key_bindings.read_input_keys(&self.window, &mut self.virtual_boy.interconnect.game_pad),
+        }
+    }
+
+    fn movie_frame(&mut self) {
+        let result = match self.movie {
+            Some(Movie::Recording { ref mut recorder, input }) => {
+                self.virtual_boy.interconnect.game_pad.set_buttons(input);
+                recorder.frame(input).map(|_| true)
+            }
+            Some(Movie::Playing(ref mut player)) => {
+                match player.frame() {
+                    Ok(Some(buttons)) => {
+                        self.virtual_boy.interconnect.game_pad.set_buttons(buttons);
+                        Ok(true)
+                    }
+                    Ok(None) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            }
+            None => return,
+        };
+
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("Movie playback finished");
+                self.movie = None;
+            }
+            Err(e) => {
+                println!("Movie stopped: {}", e);
+                self.movie = None;
+            }
+        }
+    }
+
+    fn trace_instruction(&mut self, frame_emitted: bool) {
+        let result = match self.trace {
+            Some(ref mut trace) => {
+                let result = match self.virtual_boy.cpu.trace_record() {
+                    Some(record) => trace.logger.log(record),
+                    None => Ok(()),
+                };
+                if frame_emitted {
+                    trace.logger.frame();
+                }
+                result.map(|_| trace.logger.is_finished())
+            }
+            None => return,
+        };
+
+        match result {
+            Ok(false) => {}
+            Ok(true) => {
+                println!("Trace finished");
+                self.stop_trace();
+            }
+            Err(e) => {
+                println!("Trace stopped: {}", e);
+                self.stop_trace();
+            }
+        }
+    }
+
+    fn start_trace(&mut self, args: &TraceArgs) {
+        let addr_range = match args.range {
+            Some((ref start, ref end)) => {
+                let start = start.evaluate(&mut self.virtual_boy, &self.labels);
+                let end = end.evaluate(&mut self.virtual_boy, &self.labels);
+                match (start, end) {
+                    (Ok(start), Ok(end)) => Some((start.min(end), start.max(end))),
+                    (Err(e), _) | (_, Err(e)) => {
+                        println!("{}", e);
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let file = match File::create(&args.path) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("Couldn't create trace file {}: {}", args.path, e);
+                return;
+            }
+        };
+
+        self.stop_trace();
+
+        let filter = TraceFilter {
+            addr_range: addr_range,
+            frames: args.frames,
+        };
+        self.trace = Some(Trace {
+            path: args.path.clone(),
+            logger: TraceLogger::new(BufWriter::new(file), filter),
+        });
+        self.virtual_boy.set_tracing(true);
+        println!("Tracing to {}", args.path);
+    }
+
+    fn stop_trace(&mut self) {
+        if let Some(mut trace) = self.trace.take() {
+            if let Err(e) = trace.logger.flush() {
+                println!("Couldn't write trace file {}: {}", trace.path, e);
+            }
+        }
+        self.virtual_boy.set_tracing(false);
     }
 
     fn start_debugger(&mut self) {
         self.mode = Mode::Debugging;
+        self.finish_depth = None;
 
         self.cursor = self.virtual_boy.cpu.reg_pc();
         self.disassemble_instruction();
@@ -226,38 +555,72 @@ impl Emulator {
                 },
                 Ok(Command::Step(count)) => {
                     for _ in 0..count {
-                        self.step(video_frame_sink, audio_frame_sink);
+                        if let Err(e) = self.step(video_frame_sink, audio_frame_sink) {
+                            println!("{}", e);
+                            break;
+                        }
+                        self.print_watchpoint_hit();
                         self.cursor = self.virtual_boy.cpu.reg_pc();
                         self.disassemble_instruction();
                     }
                 }
-                Ok(Command::Continue) => {
-                    self.mode = Mode::Running;
-                    self.time_source_start_time_ns = self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+                Ok(Command::Next) => {
+                    let depth = self.virtual_boy.cpu.call_stack().depth();
+                    match self.step(video_frame_sink, audio_frame_sink) {
+                        Ok((_, trigger_watchpoint)) => {
+                            self.print_watchpoint_hit();
+                            if !trigger_watchpoint && self.virtual_boy.cpu.call_stack().depth() > depth {
+                                // Stepped into a call (or an exception was taken); run until it returns
+                                self.finish_depth = Some(depth);
+                                self.resume();
+                            } else {
+                                self.cursor = self.virtual_boy.cpu.reg_pc();
+                                self.disassemble_instruction();
+                            }
+                        }
+                        Err(e) => println!("{}", e),
+                    }
                 }
-                Ok(Command::Goto(addr)) => {
-                    self.cursor = addr;
+                Ok(Command::Finish) => {
+                    let depth = self.virtual_boy.cpu.call_stack().depth();
+                    if depth > 0 {
+                        self.finish_depth = Some(depth - 1);
+                        self.resume();
+                    } else {
+                        println!("Not inside a call");
+                    }
                 }
-                Ok(Command::ShowMem(addr)) => {
-                    if let Some(addr) = addr {
-                        self.cursor = addr;
+                Ok(Command::Backtrace) => {
+                    let mut pc = self.virtual_boy.cpu.reg_pc();
+                    let frames = self.virtual_boy.cpu.call_stack().frames();
+                    for (index, frame) in frames.iter().rev().enumerate() {
+                        println!("#{:<3} 0x{:08x} in {}", index, pc, self.describe_frame(frame));
+                        pc = frame.call_site;
                     }
-
-                    self.print_labels_at_cursor();
-
-                    const NUM_ROWS: u32 = 16;
-                    const NUM_COLS: u32 = 16;
-                    for _ in 0..NUM_ROWS {
-                        print!("0x{:08x}  ", self.cursor);
-                        for x in 0..NUM_COLS {
-                            let byte = self.virtual_boy.interconnect.read_byte(self.cursor);
-                            self.cursor = self.cursor.wrapping_add(1);
-                            print!("{:02x}", byte);
-                            if x < NUM_COLS - 1 {
-                                print!(" ");
+                    println!("#{:<3} 0x{:08x}", frames.len(), pc);
+                }
+                Ok(Command::Continue) => {
+                    self.resume();
+                }
+                Ok(Command::Goto(ref addr)) => {
+                    match addr.evaluate(&mut self.virtual_boy, &self.labels) {
+                        Ok(addr) => self.cursor = addr,
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                Ok(Command::ShowMem(ref addr)) => {
+                    let addr = match *addr {
+                        Some(ref addr) => addr.evaluate(&mut self.virtual_boy, &self.labels).map(Some),
+                        None => Ok(None),
+                    };
+                    match addr {
+                        Ok(addr) => {
+                            if let Some(addr) = addr {
+                                self.cursor = addr;
                             }
+                            self.show_mem();
                         }
-                        println!();
+                        Err(e) => println!("{}", e),
                     }
                 }
                 Ok(Command::Disassemble(count)) => {
@@ -265,6 +628,23 @@ impl Emulator {
                         self.cursor = self.disassemble_instruction();
                     }
                 }
+                Ok(Command::Assemble(addr, ref source)) => {
+                    match assemble(source, addr) {
+                        Ok(assembly) => {
+                            for (offset, &byte) in assembly.bytes.iter().enumerate() {
+                                self.virtual_boy.interconnect.patch_byte(addr.wrapping_add(offset as u32), byte);
+                            }
+                            // Make sure the CPU doesn't keep running the old code out of its cache
+                            self.virtual_boy.cpu.cache.clear_entries(0, 128);
+
+                            self.cursor = addr;
+                            while self.cursor.wrapping_sub(addr) < assembly.bytes.len() as u32 {
+                                self.cursor = self.disassemble_instruction();
+                            }
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
                 Ok(Command::Label) => {
                     for (name, addr) in self.labels.iter() {
                         println!(".{}: 0x{:08x}", name, addr);
@@ -279,31 +659,95 @@ impl Emulator {
                     }
                 }
                 Ok(Command::Breakpoint) => {
-                    for addr in self.breakpoints.iter() {
-                        println!("* 0x{:08x}", addr);
+                    for (addr, breakpoint) in self.breakpoints.iter() {
+                        print!("* 0x{:08x} (hit {} times)", addr, breakpoint.hit_count);
+                        match breakpoint.condition {
+                            Some(ref condition) => println!(" if {}", condition),
+                            None => println!(),
+                        }
                     }
                 }
-                Ok(Command::AddBreakpoint(addr)) => {
-                    self.breakpoints.insert(addr);
+                Ok(Command::AddBreakpoint(ref args)) => {
+                    match args.addr.evaluate(&mut self.virtual_boy, &self.labels) {
+                        Ok(addr) => {
+                            self.breakpoints.insert(addr, Breakpoint {
+                                condition: args.condition.clone(),
+                                hit_count: 0,
+                            });
+                        }
+                        Err(e) => println!("{}", e),
+                    }
                 }
-                Ok(Command::RemoveBreakpoint(addr)) => {
-                    if !self.breakpoints.remove(&addr) {
-                        println!("Breakpoint at 0x{:08x} does not exist", addr);
+                Ok(Command::RemoveBreakpoint(ref addr)) => {
+                    match addr.evaluate(&mut self.virtual_boy, &self.labels) {
+                        Ok(addr) => {
+                            if self.breakpoints.remove(&addr).is_none() {
+                                println!("Breakpoint at 0x{:08x} does not exist", addr);
+                            }
+                        }
+                        Err(e) => println!("{}", e),
                     }
                 }
                 Ok(Command::Watchpoint) => {
-                    for addr in self.virtual_boy.cpu.watchpoints.iter() {
-                        println!("* 0x{:08x}", addr);
+                    for watchpoint in self.virtual_boy.cpu.watchpoints.iter() {
+                        println!("* {}", watchpoint);
+                    }
+                }
+                Ok(Command::AddWatchpoint(ref args)) => {
+                    let start = args.start.evaluate(&mut self.virtual_boy, &self.labels);
+                    let end = match args.end {
+                        Some(ref end) => end.evaluate(&mut self.virtual_boy, &self.labels).map(Some),
+                        None => Ok(None),
+                    };
+                    match (start, end) {
+                        (Ok(start), Ok(end)) => {
+                            let watchpoint = Watchpoint::range(start, end.unwrap_or(start), args.kind);
+                            self.virtual_boy.cpu.watchpoints.push(watchpoint);
+                        }
+                        (Err(e), _) | (_, Err(e)) => println!("{}", e),
+                    }
+                }
+                Ok(Command::RemoveWatchpoint(ref addr)) => {
+                    match addr.evaluate(&mut self.virtual_boy, &self.labels) {
+                        Ok(addr) => {
+                            // Removes every watchpoint covering the address
+                            let watchpoints = &mut self.virtual_boy.cpu.watchpoints;
+                            let num_watchpoints = watchpoints.len();
+                            watchpoints.retain(|watchpoint| !watchpoint.contains(addr));
+                            if watchpoints.len() == num_watchpoints {
+                                println!("Watchpoint at 0x{:08x} does not exist", addr);
+                            }
+                        }
+                        Err(e) => println!("{}", e),
                     }
                 }
-                Ok(Command::AddWatchpoint(addr)) => {
-                    self.virtual_boy.cpu.watchpoints.insert(addr);
+                Ok(Command::Trace(None)) => {
+                    match self.trace {
+                        Some(ref trace) => println!("Tracing to {} (frame {})", trace.path, trace.logger.frames()),
+                        None => println!("Not tracing"),
+                    }
                 }
-                Ok(Command::RemoveWatchpoint(addr)) => {
-                    if !self.virtual_boy.cpu.watchpoints.remove(&addr) {
-                        println!("Watchpoint at 0x{:08x} does not exist", addr);
+                Ok(Command::Trace(Some(ref args))) => self.start_trace(args),
+                Ok(Command::TraceOff) => {
+                    if self.trace.is_some() {
+                        self.stop_trace();
+                        println!("Trace stopped");
+                    } else {
+                        println!("Not tracing");
                     }
                 }
+                Ok(Command::LowBattery(value)) => {
+                    let game_pad = &mut self.virtual_boy.interconnect.game_pad;
+                    let is_low_battery = value.unwrap_or(!game_pad.is_low_battery());
+                    game_pad.set_low_battery(is_low_battery);
+                    println!("Low battery: {}", if is_low_battery { "on" } else { "off" });
+                }
+                Ok(Command::PadConnected(value)) => {
+                    let game_pad = &mut self.virtual_boy.interconnect.game_pad;
+                    let is_connected = value.unwrap_or(!game_pad.is_connected());
+                    game_pad.set_connected(is_connected);
+                    println!("Pad connected: {}", if is_connected { "on" } else { "off" });
+                }
                 Ok(Command::Exit) => {
                     return true;
                 }
@@ -323,6 +767,42 @@ impl Emulator {
         return false;
     }
 
+    fn resume(&mut self) {
+        self.mode = Mode::Running;
+        self.time_source_start_time_ns = self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+    }
+
+    // Names a frame after the label at its entry point, if there is one
+    fn describe_frame(&self, frame: &Frame) -> String {
+        let name = match self.labels.iter().filter(|x| *x.1 == frame.entry).map(|x| x.0).min() {
+            Some(name) => format!(".{}", name),
+            None => format!("0x{:08x}", frame.entry),
+        };
+        match frame.kind {
+            FrameKind::Call => name,
+            FrameKind::Exception(code) => format!("{} (exception 0x{:04x})", name, code),
+        }
+    }
+
+    fn show_mem(&mut self) {
+        self.print_labels_at_cursor();
+
+        const NUM_ROWS: u32 = 16;
+        const NUM_COLS: u32 = 16;
+        for _ in 0..NUM_ROWS {
+            print!("0x{:08x}  ", self.cursor);
+            for x in 0..NUM_COLS {
+                let byte = self.virtual_boy.interconnect.read_byte(self.cursor);
+                self.cursor = self.cursor.wrapping_add(1);
+                print!("{:02x}", byte);
+                if x < NUM_COLS - 1 {
+                    print!(" ");
+                }
+            }
+            println!();
+        }
+    }
+
     fn print_cursor(&self) {
         print!("(vb-rs 0x{:08x}) > ", self.cursor);
         stdout().flush().unwrap();
@@ -331,7 +811,7 @@ impl Emulator {
     fn disassemble_instruction(&mut self) -> u32 {
         self.print_labels_at_cursor();
 
-        if self.breakpoints.contains(&self.cursor) {
+        if self.breakpoints.contains_key(&self.cursor) {
             print!("* ");
         } else {
             print!("  ");
@@ -339,95 +819,23 @@ impl Emulator {
 
         print!("0x{:08x}  ", self.cursor);
 
-        let first_halfword = self.virtual_boy.interconnect.read_halfword(self.cursor);
-        let mut next_cursor = self.cursor.wrapping_add(2);
-        print!("{:02x}{:02x}", first_halfword & 0xff, first_halfword >> 8);
-
-        let opcode = Opcode::from_halfword(first_halfword);
-        let instruction_format = opcode.instruction_format();
+        let mut bytes = [0; 4];
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.virtual_boy.interconnect.read_byte(self.cursor.wrapping_add(offset as u32));
+        }
+        // Four bytes always hold a whole instruction
+        let instruction = disassemble(&bytes, self.cursor).unwrap();
 
-        let second_halfword = if instruction_format.has_second_halfword() {
-            let second_halfword = self.virtual_boy.interconnect.read_halfword(next_cursor);
-            print!("{:02x}{:02x}", second_halfword & 0xff, second_halfword >> 8);
-            next_cursor = next_cursor.wrapping_add(2);
-            second_halfword
-        } else {
+        for byte in bytes[..instruction.size as usize].iter() {
+            print!("{:02x}", byte);
+        }
+        if instruction.size == 2 {
             print!("    ");
-            0
-        };
-
-        print!("    ");
-
-        match instruction_format {
-            InstructionFormat::I => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-                if opcode == Opcode::Jmp {
-                    println!("jmp [r{}]", reg1);
-                } else {
-                    println!("{} r{}, r{}", opcode, reg1, reg2);
-                }
-            }
-            InstructionFormat::II => {
-                let imm5 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-                match opcode {
-                    Opcode::BitString => {
-                        let bit_string_op = opcode.bit_string_op(imm5);
-
-                        println!("{}", bit_string_op);
-                    }
-                    Opcode::Cli | Opcode::Reti | Opcode::Halt | Opcode::Sei => println!("{}", opcode),
-                    Opcode::Ldsr => println!("{} r{}, {}", opcode, reg2, opcode.system_register(imm5)),
-                    Opcode::Stsr => println!("{} {}, r{}", opcode, opcode.system_register(imm5), reg2),
-                    Opcode::MovImm | Opcode::AddImm5 | Opcode::CmpImm => println!("{} {}, r{}", opcode, (((imm5 as i32) << 27) >> 27), reg2),
-                    _ => println!("{} {}, r{}", opcode, imm5, reg2)
-                }
-            }
-            InstructionFormat::III => {
-                let disp9 = first_halfword & 0x01ff;
-                let disp = (disp9 as u32) | if disp9 & 0x0100 == 0 { 0x00000000 } else { 0xfffffe00 };
-                let target = self.cursor.wrapping_add(disp);
-                println!("{} {:#x} (0x{:08x})", opcode, disp9, target);
-            }
-            InstructionFormat::IV => {
-                let disp26 = (((first_halfword as u32) & 0x03ff) << 16) | (second_halfword as u32);
-                let disp = disp26 | if disp26 & 0x02000000 == 0 { 0x00000000 } else { 0xfc000000 };
-                let target = self.cursor.wrapping_add(disp);
-                println!("{} {} (0x{:08x})", opcode, disp26 as i32, target);
-            }
-            InstructionFormat::V => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let imm16 = second_halfword;
-
-                println!("{} {:#x}, r{}, r{}", opcode, imm16, reg1, reg2);
-            }
-            InstructionFormat::VI => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let disp16 = second_halfword as i16;
-
-                match opcode {
-                    Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Outb | Opcode::Outh | Opcode::Outw => println!("{} r{}, {}[r{}]", opcode, reg2, disp16, reg1),
-                    _ => println!("{} {}[r{}], r{}", opcode, disp16, reg1, reg2),
-                }
-            }
-            InstructionFormat::VII => {
-                let reg1 = (first_halfword & 0x1f) as u32;
-                let reg2 = ((first_halfword >> 5) & 0x1f) as u32;
-
-                let subop_bits = second_halfword >> 10;
-
-                let subop = opcode.subop(subop_bits);
-
-                println!("{} r{}, r{}", subop, reg1, reg2);
-            }
         }
 
-        next_cursor
+        println!("    {}", instruction);
+
+        instruction.next_addr()
     }
 
     fn print_labels_at_cursor(&mut self) {
@@ -0,0 +1,306 @@
+use minifb::{Key, Window};
+
+use rustual_boy_core::game_pad::{Button, GamePad};
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const BUTTONS: [(Button, &'static str); 14] = [
+    (Button::A, "a"),
+    (Button::B, "b"),
+    (Button::Start, "start"),
+    (Button::Select, "select"),
+    (Button::L, "l"),
+    (Button::R, "r"),
+    (Button::LeftDPadUp, "left_d_pad_up"),
+    (Button::LeftDPadDown, "left_d_pad_down"),
+    (Button::LeftDPadLeft, "left_d_pad_left"),
+    (Button::LeftDPadRight, "left_d_pad_right"),
+    (Button::RightDPadUp, "right_d_pad_up"),
+    (Button::RightDPadDown, "right_d_pad_down"),
+    (Button::RightDPadLeft, "right_d_pad_left"),
+    (Button::RightDPadRight, "right_d_pad_right"),
+];
+
+// Keys the frontend itself responds to, which can't be bound to buttons
+const RESERVED_KEYS: [Key; 3] = [Key::Escape, Key::Backspace, Key::F12];
+
+/// Which keyboard keys press each game pad button. Any of a button's keys being held presses it.
+pub struct KeyBindings {
+    bindings: Vec<(Button, Vec<Key>)>,
+}
+
+impl KeyBindings {
+    /// Loads bindings from a file, see `parse` for the format
+    pub fn load<P: AsRef<Path>>(file_name: P) -> Result<KeyBindings, Cow<'static, str>> {
+        let file_name = file_name.as_ref();
+        let mut contents = String::new();
+        File::open(file_name)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Couldn't read key bindings file {}: {}", file_name.display(), e))?;
+
+        KeyBindings::parse(&contents)
+            .map_err(|e| format!("Invalid key bindings file {}: {}", file_name.display(), e).into())
+    }
+
+    /// Parses bindings from a small subset of TOML: each line is either blank, a `#` comment,
+    /// or a button name assigned a key name or an array of key names, eg.
+    /// `right_d_pad_up = ["I", "Up"]`. Buttons that aren't mentioned keep their default keys.
+    /// As in TOML, a button can only be assigned once; a key can be bound to several buttons.
+    pub fn parse(s: &str) -> Result<KeyBindings, Cow<'static, str>> {
+        let mut key_bindings = KeyBindings::default();
+        let mut assigned_lines: Vec<(Button, usize)> = Vec::new();
+
+        for (index, line) in s.lines().enumerate() {
+            let line_number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let button_name = parts.next().unwrap().trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(format!("line {}: expected `<button> = <keys>`", line_number).into()),
+            };
+
+            let button = match BUTTONS.iter().find(|x| x.1 == button_name) {
+                Some(&(button, _)) => button,
+                None => return Err(format!("line {}: unknown button \"{}\" (expected one of: {})", line_number, button_name, button_names()).into()),
+            };
+            if let Some(&(_, first_line_number)) = assigned_lines.iter().find(|x| x.0 == button) {
+                return Err(format!("line {}: button \"{}\" was already bound on line {}", line_number, button_name, first_line_number).into());
+            }
+            assigned_lines.push((button, line_number));
+
+            let key_names = parse_key_names(value).map_err(|e| format!("line {}: {}", line_number, e))?;
+            let mut keys = Vec::new();
+            for key_name in key_names {
+                let key = match key_from_name(key_name) {
+                    Some(key) => key,
+                    None => return Err(format!("line {}: unknown key \"{}\"", line_number, key_name).into()),
+                };
+                if RESERVED_KEYS.contains(&key) {
+                    return Err(format!("line {}: key \"{}\" is reserved by the emulator", line_number, key_name).into());
+                }
+                keys.push(key);
+            }
+
+            for binding in key_bindings.bindings.iter_mut() {
+                if binding.0 == button {
+                    binding.1 = keys.clone();
+                }
+            }
+        }
+
+        Ok(key_bindings)
+    }
+
+    /// Updates every button on `game_pad` from the keys currently held down in `window`
+    pub fn read_input_keys(&self, window: &Window, game_pad: &mut GamePad) {
+        for &(button, ref keys) in self.bindings.iter() {
+            game_pad.set_button_pressed(button, keys.iter().any(|key| window.is_key_down(*key)));
+        }
+    }
+
+    /// The buttons whose keys are currently held down in `window`, laid out like `GamePad::buttons`
+    pub fn read_buttons(&self, window: &Window) -> u16 {
+        let mut game_pad = GamePad::new();
+        self.read_input_keys(window, &mut game_pad);
+        game_pad.buttons()
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            bindings: vec![
+                (Button::A, vec![Key::F]),
+                (Button::B, vec![Key::H]),
+                (Button::Start, vec![Key::Enter]),
+                (Button::Select, vec![Key::Space]),
+                (Button::L, vec![Key::E]),
+                (Button::R, vec![Key::U]),
+                (Button::LeftDPadUp, vec![Key::W]),
+                (Button::LeftDPadDown, vec![Key::S]),
+                (Button::LeftDPadLeft, vec![Key::A]),
+                (Button::LeftDPadRight, vec![Key::D]),
+                (Button::RightDPadUp, vec![Key::I]),
+                (Button::RightDPadDown, vec![Key::K]),
+                (Button::RightDPadLeft, vec![Key::J]),
+                (Button::RightDPadRight, vec![Key::L]),
+            ],
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    // Key names never contain `#`, so there's no need to worry about quoting here
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn parse_key_names(value: &str) -> Result<Vec<&str>, String> {
+    let items = if value.starts_with('[') {
+        if !value.ends_with(']') {
+            return Err("expected `]` at the end of the key list".into());
+        }
+        let inner = value[1..value.len() - 1].trim();
+        if inner.is_empty() {
+            Vec::new()
+        } else {
+            inner.split(',').map(|item| item.trim()).filter(|item| !item.is_empty()).collect()
+        }
+    } else {
+        vec![value]
+    };
+
+    items.into_iter().map(|item| {
+        if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+            Ok(&item[1..item.len() - 1])
+        } else {
+            Err(format!("expected a quoted key name, found `{}`", item))
+        }
+    }).collect()
+}
+
+fn button_names() -> String {
+    BUTTONS.iter().map(|x| x.1).collect::<Vec<_>>().join(", ")
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    Some(match name {
+        "0" => Key::Key0, "1" => Key::Key1, "2" => Key::Key2, "3" => Key::Key3, "4" => Key::Key4,
+        "5" => Key::Key5, "6" => Key::Key6, "7" => Key::Key7, "8" => Key::Key8, "9" => Key::Key9,
+
+        "A" => Key::A, "B" => Key::B, "C" => Key::C, "D" => Key::D, "E" => Key::E, "F" => Key::F,
+        "G" => Key::G, "H" => Key::H, "I" => Key::I, "J" => Key::J, "K" => Key::K, "L" => Key::L,
+        "M" => Key::M, "N" => Key::N, "O" => Key::O, "P" => Key::P, "Q" => Key::Q, "R" => Key::R,
+        "S" => Key::S, "T" => Key::T, "U" => Key::U, "V" => Key::V, "W" => Key::W, "X" => Key::X,
+        "Y" => Key::Y, "Z" => Key::Z,
+
+        "F1" => Key::F1, "F2" => Key::F2, "F3" => Key::F3, "F4" => Key::F4, "F5" => Key::F5, "F6" => Key::F6,
+        "F7" => Key::F7, "F8" => Key::F8, "F9" => Key::F9, "F10" => Key::F10, "F11" => Key::F11, "F12" => Key::F12,
+
+        "Up" => Key::Up, "Down" => Key::Down, "Left" => Key::Left, "Right" => Key::Right,
+
+        "Apostrophe" => Key::Apostrophe,
+        "Backquote" => Key::Backquote,
+        "Backslash" => Key::Backslash,
+        "Comma" => Key::Comma,
+        "Equal" => Key::Equal,
+        "LeftBracket" => Key::LeftBracket,
+        "Minus" => Key::Minus,
+        "Period" => Key::Period,
+        "RightBracket" => Key::RightBracket,
+        "Semicolon" => Key::Semicolon,
+        "Slash" => Key::Slash,
+
+        "Backspace" => Key::Backspace,
+        "Delete" => Key::Delete,
+        "End" => Key::End,
+        "Enter" => Key::Enter,
+        "Escape" => Key::Escape,
+        "Home" => Key::Home,
+        "Insert" => Key::Insert,
+        "PageDown" => Key::PageDown,
+        "PageUp" => Key::PageUp,
+        "Space" => Key::Space,
+        "Tab" => Key::Tab,
+
+        "LeftShift" => Key::LeftShift, "RightShift" => Key::RightShift,
+        "LeftCtrl" => Key::LeftCtrl, "RightCtrl" => Key::RightCtrl,
+        "LeftAlt" => Key::LeftAlt, "RightAlt" => Key::RightAlt,
+
+        "NumPad0" => Key::NumPad0, "NumPad1" => Key::NumPad1, "NumPad2" => Key::NumPad2, "NumPad3" => Key::NumPad3,
+        "NumPad4" => Key::NumPad4, "NumPad5" => Key::NumPad5, "NumPad6" => Key::NumPad6, "NumPad7" => Key::NumPad7,
+        "NumPad8" => Key::NumPad8, "NumPad9" => Key::NumPad9,
+        "NumPadDot" => Key::NumPadDot,
+        "NumPadSlash" => Key::NumPadSlash,
+        "NumPadAsterisk" => Key::NumPadAsterisk,
+        "NumPadMinus" => Key::NumPadMinus,
+        "NumPadPlus" => Key::NumPadPlus,
+        "NumPadEnter" => Key::NumPadEnter,
+
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(key_bindings: &KeyBindings, button: Button) -> Vec<Key> {
+        key_bindings.bindings.iter().find(|x| x.0 == button).unwrap().1.clone()
+    }
+
+    #[test]
+    fn defaults() {
+        let key_bindings = KeyBindings::default();
+        assert_eq!(key_bindings.bindings.len(), BUTTONS.len());
+        for &(button, _) in BUTTONS.iter() {
+            let keys = keys(&key_bindings, button);
+            assert_eq!(keys.len(), 1);
+            assert!(!RESERVED_KEYS.contains(&keys[0]));
+        }
+        assert_eq!(keys(&key_bindings, Button::Start), vec![Key::Enter]);
+
+        // An empty file changes nothing
+        assert_eq!(KeyBindings::parse("").unwrap().bindings, key_bindings.bindings);
+    }
+
+    #[test]
+    fn example_file() {
+        let key_bindings = KeyBindings::parse(include_str!("../keys.example.toml")).unwrap();
+        assert_eq!(keys(&key_bindings, Button::A), vec![Key::F]);
+        assert_eq!(keys(&key_bindings, Button::RightDPadUp), vec![Key::I, Key::Up]);
+        assert_eq!(keys(&key_bindings, Button::RightDPadRight), vec![Key::L, Key::Right]);
+    }
+
+    #[test]
+    fn bindings() {
+        let key_bindings = KeyBindings::parse("
+            a = \"Space\"
+            start = [ \"Enter\", \"NumPadEnter\", ]
+            select = []
+            l = \"Space\"").unwrap();
+        assert_eq!(keys(&key_bindings, Button::A), vec![Key::Space]);
+        assert_eq!(keys(&key_bindings, Button::Start), vec![Key::Enter, Key::NumPadEnter]);
+        assert_eq!(keys(&key_bindings, Button::Select), vec![]);
+        assert_eq!(keys(&key_bindings, Button::L), vec![Key::Space]);
+        // Buttons that aren't mentioned keep their defaults
+        assert_eq!(keys(&key_bindings, Button::B), vec![Key::H]);
+    }
+
+    #[test]
+    fn comments() {
+        let key_bindings = KeyBindings::parse("
+            # a = \"Z\"
+            b = \"X\" # not \"H\"
+               # indented
+            r = [\"1\", \"2\"]#").unwrap();
+        assert_eq!(keys(&key_bindings, Button::A), vec![Key::F]);
+        assert_eq!(keys(&key_bindings, Button::B), vec![Key::X]);
+        assert_eq!(keys(&key_bindings, Button::R), vec![Key::Key1, Key::Key2]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |s: &str| KeyBindings::parse(s).err().unwrap().into_owned();
+
+        assert!(error("a = \"F\"\njump = \"J\"").starts_with("line 2: unknown button \"jump\""));
+        assert_eq!(error("a = \"Shift\""), "line 1: unknown key \"Shift\"");
+        assert_eq!(error("a = \"f\""), "line 1: unknown key \"f\"");
+        assert_eq!(error("a = \"Escape\""), "line 1: key \"Escape\" is reserved by the emulator");
+        assert_eq!(error("a = [\"F\", \"F12\"]"), "line 1: key \"F12\" is reserved by the emulator");
+        assert_eq!(error("a = \"F\"\n\na = \"G\""), "line 3: button \"a\" was already bound on line 1");
+        assert_eq!(error("a"), "line 1: expected `<button> = <keys>`");
+        assert_eq!(error("a = F"), "line 1: expected a quoted key name, found `F`");
+        assert_eq!(error("a = [\"F\""), "line 1: expected `]` at the end of the key list");
+    }
+}
@@ -19,18 +19,32 @@ mod logging;
 mod command;
 mod cpal_driver;
 mod emulator;
+mod key_bindings;
 mod system_time_source;
 mod wave_file_buffer_sink;
 
 use rustual_boy_core::rom::*;
 use rustual_boy_core::sram::*;
+use rustual_boy_core::virtual_boy::VirtualBoy;
 use rustual_boy_core::vsu::*;
+use rustual_boy_middleware::{GdbStub, GoldenRecord, MoviePlayer, MovieRecorder, NetworkLink, RewindBuffer};
 use cpal_driver::*;
 use emulator::*;
+use key_bindings::KeyBindings;
+
+use std::process;
 
 fn main() {
     let config = argparse::parse_args();
 
+    let key_bindings = match config.key_bindings_path {
+        Some(ref path) => KeyBindings::load(path).unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(1);
+        }),
+        None => KeyBindings::default(),
+    };
+
     logln!("Loading ROM file {}", config.rom_path);
 
     let rom = Rom::load(&config.rom_path).unwrap();
@@ -62,12 +76,63 @@ fn main() {
         }
     };
 
+    if let Some(ref golden_path) = config.golden_path {
+        process::exit(run_golden(&config, golden_path, rom, sram));
+    }
+
     let audio_driver = CpalDriver::new(SAMPLE_RATE, 100).unwrap();
 
     let audio_buffer_sink = audio_driver.sink();
     let time_source = audio_driver.time_source();
 
-    let mut emulator = Emulator::new(rom, sram, audio_buffer_sink, time_source);
+    let rewind_buffer = RewindBuffer::new(config.rewind_memory_budget, config.rewind_interval);
+
+    let link = if let Some(ref addr) = config.link_listen_addr {
+        logln!("Waiting for link cable connection on {}", addr);
+        Some(NetworkLink::listen(addr.as_str(), config.link_sync_period).unwrap_or_else(|e| {
+            println!("Couldn't connect link cable: {}", e);
+            process::exit(1);
+        }))
+    } else if let Some(ref addr) = config.link_connect_addr {
+        logln!("Connecting link cable to {}", addr);
+        Some(NetworkLink::connect(addr.as_str(), config.link_sync_period).unwrap_or_else(|e| {
+            println!("Couldn't connect link cable to {}: {}", addr, e);
+            process::exit(1);
+        }))
+    } else {
+        None
+    };
+
+    let gdb = config.gdb_port.map(|port| {
+        logln!("Waiting for GDB to connect on port {}", port);
+        GdbStub::listen(("127.0.0.1", port)).unwrap_or_else(|e| {
+            println!("Couldn't listen for GDB on port {}: {}", port, e);
+            process::exit(1);
+        })
+    });
+
+    let movie = if let Some(ref path) = config.record_path {
+        logln!("Recording movie to {}", path);
+        let recorder = MovieRecorder::create(path, &rom).unwrap_or_else(|e| {
+            println!("Couldn't create movie {}: {}", path, e);
+            process::exit(1);
+        });
+        Some(Movie::Recording {
+            recorder: recorder,
+            input: 0,
+        })
+    } else if let Some(ref path) = config.play_path {
+        logln!("Playing movie from {}", path);
+        Some(Movie::Playing(MoviePlayer::open(path, &rom).unwrap_or_else(|e| {
+            println!("Couldn't open movie {}: {}", path, e);
+            process::exit(1);
+        })))
+    } else {
+        None
+    };
+
+    let mut emulator = Emulator::new(rom, sram, audio_buffer_sink, time_source, rewind_buffer, link, gdb, movie, key_bindings);
+    emulator.virtual_boy.interconnect.set_bus_error_policy(config.bus_error_policy);
     emulator.run();
 
     if emulator.virtual_boy.interconnect.sram.size() > 0 {
@@ -75,3 +140,73 @@ fn main() {
         emulator.virtual_boy.interconnect.sram.save(config.sram_path).unwrap();
     }
 }
+
+// Runs headlessly against (or blesses) a golden record, returning the process exit code
+fn run_golden(config: &argparse::CommandLineConfig, golden_path: &str, rom: Rom, sram: Sram) -> i32 {
+    let golden = if config.bless {
+        None
+    } else {
+        match GoldenRecord::load(golden_path) {
+            Ok(golden) => Some(golden),
+            Err(e) => {
+                println!("Couldn't load golden record {}: {}", golden_path, e);
+                return 1;
+            }
+        }
+    };
+
+    let num_frames = match (config.golden_frames, golden.as_ref()) {
+        (Some(num_frames), _) => num_frames,
+        (None, Some(golden)) => golden.frames.len(),
+        (None, None) => {
+            println!("--frames is required when blessing a golden record");
+            return 1;
+        }
+    };
+
+    let mut movie = match config.play_path {
+        Some(ref path) => match MoviePlayer::open(path, &rom) {
+            Ok(movie) => Some(movie),
+            Err(e) => {
+                println!("Couldn't open movie {}: {}", path, e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let mut virtual_boy = VirtualBoy::new(rom, sram);
+    virtual_boy.interconnect.set_bus_error_policy(config.bus_error_policy);
+
+    logln!("Running {} frames headlessly", num_frames);
+    let record = match GoldenRecord::run(&mut virtual_boy, num_frames, movie.as_mut()) {
+        Ok(record) => record,
+        Err(e) => {
+            println!("Emulation failed: {}", e);
+            return 1;
+        }
+    };
+
+    match golden {
+        Some(golden) => match record.compare(&golden) {
+            Ok(()) => {
+                println!("Output matches golden record {}", golden_path);
+                0
+            }
+            Err(mismatch) => {
+                println!("Output doesn't match golden record {}: {}", golden_path, mismatch);
+                1
+            }
+        },
+        None => match record.save(golden_path) {
+            Ok(()) => {
+                println!("Wrote golden record {}", golden_path);
+                0
+            }
+            Err(e) => {
+                println!("Couldn't write golden record {}: {}", golden_path, e);
+                1
+            }
+        },
+    }
+}
@@ -0,0 +1,815 @@
+//! A small two-pass assembler for V810 code, used to write CPU tests inline and to
+//! patch code from the debugger.
+//!
+//! The syntax follows the disassembler's output:
+//!
+//! ```text
+//! ; Comments start with a semicolon
+//! start:                      ; Labels end with a colon
+//!     movhi hi(data), r0, r6  ; Format V: imm16, reg1, reg2
+//!     movea lo(data), r6, r6
+//!     ld.w 0[r6], r7          ; Format VI loads: disp16[reg1], reg2
+//!     st.w r7, 4[r6]          ; Format VI stores: reg2, disp16[reg1]
+//!     add 1, r7               ; Format II: imm5, reg2
+//!     add r7, r8              ; Format I: reg1, reg2
+//!     cmp 0, r7
+//!     bnz start               ; Format III/IV branches take the target address
+//!     setf nz, r9
+//!     ldsr r9, psw
+//!     addf.s r7, r8           ; Format VII: reg1, reg2
+//!     jmp [lp]
+//! data:
+//!     .word 0x12345678
+//! ```
+//!
+//! Registers are `r0` through `r31`, with `sp` (r3), `gp` (r4), `tp` (r5) and `lp`
+//! (r31) as aliases. Numbers can be decimal or `0x`-prefixed hex, and anywhere a
+//! number is expected an expression made of numbers, labels, `.` (the address of the
+//! current statement), `+`, `-`, `hi(...)` and `lo(...)` can be used. `hi` accounts
+//! for `lo` being sign extended, so `movhi hi(x)` followed by `movea lo(x)` loads `x`.
+//!
+//! Supported directives are `.org address` (pads forward with zeroes), `.align n`,
+//! `.fill count[, value]`, `.byte`, `.halfword`, `.word`, `.ascii "text"` and
+//! `.equ name, value`.
+
+use instruction::*;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Machine code produced by `assemble`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// Address of the first byte of `bytes`
+    pub origin: u32,
+    pub bytes: Vec<u8>,
+    /// Every label and `.equ` symbol, by name
+    pub symbols: HashMap<String, u32>,
+}
+
+impl Assembly {
+    /// Address just past the last assembled byte
+    pub fn end(&self) -> u32 {
+        self.origin.wrapping_add(self.bytes.len() as u32)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    /// 1-based line number in the source
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblerError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Assembles `source`, placing the first statement at `origin`
+pub fn assemble(source: &str, origin: u32) -> Result<Assembly, AssemblerError> {
+    let statements = parse(source)?;
+
+    // Pass 1: lay out every statement so all labels are known
+    let mut symbols = HashMap::new();
+    let mut addr = origin;
+    for statement in statements.iter() {
+        for label in statement.labels.iter() {
+            if symbols.insert(label.clone(), addr).is_some() {
+                return Err(error(statement.line, format!("label \"{}\" is defined more than once", label)));
+            }
+        }
+
+        if let Some(ref operation) = statement.operation {
+            if operation.mnemonic == ".equ" {
+                let (name, value) = equ(operation, &symbols, addr).map_err(|e| error(statement.line, e))?;
+                if symbols.insert(name.clone(), value).is_some() {
+                    return Err(error(statement.line, format!("symbol \"{}\" is defined more than once", name)));
+                }
+            }
+
+            let size = size(operation, &symbols, addr).map_err(|e| error(statement.line, e))?;
+            addr = addr.wrapping_add(size);
+        }
+    }
+
+    // Pass 2: encode
+    let mut bytes = Vec::new();
+    for statement in statements.iter() {
+        if let Some(ref operation) = statement.operation {
+            let addr = origin.wrapping_add(bytes.len() as u32);
+            let mut encoder = Encoder {
+                symbols: &symbols,
+                addr: addr,
+                bytes: &mut bytes,
+            };
+            encoder.encode(operation).map_err(|e| error(statement.line, e))?;
+        }
+    }
+
+    Ok(Assembly {
+        origin: origin,
+        bytes: bytes,
+        symbols: symbols,
+    })
+}
+
+struct Statement {
+    line: usize,
+    labels: Vec<String>,
+    operation: Option<Operation>,
+}
+
+struct Operation {
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+fn error(line: usize, message: String) -> AssemblerError {
+    AssemblerError {
+        line: line,
+        message: message,
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Statement>, AssemblerError> {
+    let mut statements = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut rest = strip_comment(line).trim();
+
+        let mut labels = Vec::new();
+        while let Some(colon_index) = rest.find(':') {
+            let label = rest[..colon_index].trim();
+            if !is_symbol_name(label) {
+                break;
+            }
+            labels.push(label.to_string());
+            rest = rest[colon_index + 1..].trim();
+        }
+
+        let operation = if rest.is_empty() {
+            None
+        } else {
+            let mnemonic_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let mnemonic = rest[..mnemonic_end].to_lowercase();
+            let operands = split_operands(rest[mnemonic_end..].trim()).map_err(|e| error(line_number, e))?;
+            Some(Operation {
+                mnemonic: mnemonic,
+                operands: operands,
+            })
+        };
+
+        statements.push(Statement {
+            line: line_number,
+            labels: labels,
+            operation: operation,
+        });
+    }
+
+    Ok(statements)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut is_in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => is_in_string = !is_in_string,
+            ';' if !is_in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn split_operands(s: &str) -> Result<Vec<String>, String> {
+    let mut operands = Vec::new();
+    if s.is_empty() {
+        return Ok(operands);
+    }
+
+    let mut depth = 0;
+    let mut is_in_string = false;
+    let mut start = 0;
+    for (index, c) in s.char_indices() {
+        match c {
+            '"' => is_in_string = !is_in_string,
+            '(' | '[' if !is_in_string => depth += 1,
+            ')' | ']' if !is_in_string => depth -= 1,
+            ',' if !is_in_string && depth == 0 => {
+                operands.push(s[start..index].trim().to_string());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if is_in_string {
+        return Err("unterminated string".into());
+    }
+    operands.push(s[start..].trim().to_string());
+
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err("empty operand".into());
+    }
+
+    Ok(operands)
+}
+
+fn is_symbol_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn equ(operation: &Operation, symbols: &HashMap<String, u32>, addr: u32) -> Result<(String, u32), String> {
+    expect_operands(operation, 2)?;
+    let name = &operation.operands[0];
+    if !is_symbol_name(name) {
+        return Err(format!("invalid symbol name \"{}\"", name));
+    }
+    let value = evaluate(&operation.operands[1], symbols, addr)?;
+    Ok((name.clone(), value))
+}
+
+// Only directives can have a size that depends on symbols, and those symbols must already be defined
+fn size(operation: &Operation, symbols: &HashMap<String, u32>, addr: u32) -> Result<u32, String> {
+    let operands = &operation.operands;
+    Ok(match operation.mnemonic.as_str() {
+        ".equ" => 0,
+        ".org" => {
+            expect_operands(operation, 1)?;
+            let target = evaluate(&operands[0], symbols, addr)?;
+            if target < addr {
+                return Err(format!(".org 0x{:08x} is behind the current address 0x{:08x}", target, addr));
+            }
+            target.wrapping_sub(addr)
+        }
+        ".align" => {
+            expect_operands(operation, 1)?;
+            let alignment = evaluate(&operands[0], symbols, addr)?;
+            if alignment == 0 || !alignment.is_power_of_two() {
+                return Err(format!("alignment must be a power of two, not {}", alignment));
+            }
+            addr.wrapping_neg() & (alignment - 1)
+        }
+        ".fill" => {
+            if operands.len() != 1 && operands.len() != 2 {
+                return Err(".fill takes a count and an optional value".into());
+            }
+            evaluate(&operands[0], symbols, addr)?
+        }
+        ".byte" => operands.len() as u32,
+        ".halfword" => operands.len() as u32 * 2,
+        ".word" => operands.len() as u32 * 4,
+        ".ascii" => {
+            expect_operands(operation, 1)?;
+            parse_string(&operands[0])?.len() as u32
+        }
+        mnemonic => {
+            if mnemonic.starts_with('.') {
+                return Err(format!("unknown directive \"{}\"", mnemonic));
+            }
+            match instruction_format(mnemonic, operands) {
+                Some(format) => if format.has_second_halfword() { 4 } else { 2 },
+                None => return Err(format!("unknown instruction \"{}\"", mnemonic)),
+            }
+        }
+    })
+}
+
+fn expect_operands(operation: &Operation, count: usize) -> Result<(), String> {
+    if operation.operands.len() != count {
+        return Err(format!("{} takes {} operand(s), found {}", operation.mnemonic, count, operation.operands.len()));
+    }
+    Ok(())
+}
+
+// Mnemonics shared between formats I and II are told apart by whether the first operand is a register
+fn instruction_format(mnemonic: &str, operands: &[String]) -> Option<InstructionFormat> {
+    let is_first_operand_register = operands.get(0).map(|operand| parse_register(operand).is_some()).unwrap_or(false);
+    Some(match mnemonic {
+        "mov" | "add" | "cmp" | "shl" | "shr" | "sar" =>
+            if is_first_operand_register { InstructionFormat::I } else { InstructionFormat::II },
+        "sub" | "jmp" | "mul" | "div" | "mulu" | "divu" | "or" | "and" | "xor" | "not" => InstructionFormat::I,
+        "setf" | "cli" | "trap" | "reti" | "halt" | "ldsr" | "stsr" | "sei" => InstructionFormat::II,
+        "orbsu" | "andbsu" | "xorbsu" | "movbsu" | "ornbsu" | "andnbsu" | "xornbsu" | "notbsu" => InstructionFormat::II,
+        "bv" | "bc" | "bl" | "bz" | "be" | "bnh" | "bn" | "br" | "blt" | "ble" |
+        "bnv" | "bnc" | "bnl" | "bnz" | "bne" | "bh" | "bp" | "nop" | "bge" | "bgt" => InstructionFormat::III,
+        "jr" | "jal" => InstructionFormat::IV,
+        "movea" | "addi" | "ori" | "andi" | "xori" | "movhi" => InstructionFormat::V,
+        "ld.b" | "ld.h" | "ld.w" | "st.b" | "st.h" | "st.w" |
+        "in.b" | "in.h" | "in.w" | "out.b" | "out.h" | "out.w" => InstructionFormat::VI,
+        "cmpf.s" | "cvt.ws" | "cvt.sw" | "addf.s" | "subf.s" | "mulf.s" | "divf.s" |
+        "xb" | "xh" | "rev" | "trnc.sw" | "mpyhw" => InstructionFormat::VII,
+        _ => return None,
+    })
+}
+
+struct Encoder<'a> {
+    symbols: &'a HashMap<String, u32>,
+    addr: u32,
+    bytes: &'a mut Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    fn encode(&mut self, operation: &Operation) -> Result<(), String> {
+        let operands = &operation.operands;
+        match operation.mnemonic.as_str() {
+            ".equ" => {}
+            ".org" | ".align" => {
+                let size = size(operation, self.symbols, self.addr)?;
+                self.fill(size, 0);
+            }
+            ".fill" => {
+                let count = self.evaluate(&operands[0])?;
+                let value = match operands.get(1) {
+                    Some(value) => self.evaluate_sized(value, 8)? as u8,
+                    None => 0,
+                };
+                self.fill(count, value);
+            }
+            ".byte" => {
+                for operand in operands.iter() {
+                    let value = self.evaluate_sized(operand, 8)?;
+                    self.bytes.push(value as u8);
+                }
+            }
+            ".halfword" => {
+                for operand in operands.iter() {
+                    let value = self.evaluate_sized(operand, 16)?;
+                    self.push_halfword(value as u16);
+                }
+            }
+            ".word" => {
+                for operand in operands.iter() {
+                    let value = self.evaluate(operand)?;
+                    self.push_halfword(value as u16);
+                    self.push_halfword((value >> 16) as u16);
+                }
+            }
+            ".ascii" => {
+                let bytes = parse_string(&operands[0])?;
+                self.bytes.extend_from_slice(&bytes);
+            }
+            mnemonic => self.encode_instruction(mnemonic, operands)?,
+        }
+        Ok(())
+    }
+
+    fn encode_instruction(&mut self, mnemonic: &str, operands: &[String]) -> Result<(), String> {
+        match instruction_format(mnemonic, operands).unwrap() {
+            InstructionFormat::I => {
+                let opcode_bits = match mnemonic {
+                    "mov" => OPCODE_BITS_MOV_REG,
+                    "add" => OPCODE_BITS_ADD_REG,
+                    "sub" => OPCODE_BITS_SUB,
+                    "cmp" => OPCODE_BITS_CMP_REG,
+                    "shl" => OPCODE_BITS_SHL_REG,
+                    "shr" => OPCODE_BITS_SHR_REG,
+                    "jmp" => OPCODE_BITS_JMP,
+                    "sar" => OPCODE_BITS_SAR_REG,
+                    "mul" => OPCODE_BITS_MUL,
+                    "div" => OPCODE_BITS_DIV,
+                    "mulu" => OPCODE_BITS_MUL_U,
+                    "divu" => OPCODE_BITS_DIV_U,
+                    "or" => OPCODE_BITS_OR,
+                    "and" => OPCODE_BITS_AND,
+                    "xor" => OPCODE_BITS_XOR,
+                    _ => OPCODE_BITS_NOT,
+                };
+                let (reg1, reg2) = if mnemonic == "jmp" {
+                    check_count(mnemonic, operands, 1)?;
+                    let operand = operands[0].trim();
+                    if !operand.starts_with('[') || !operand.ends_with(']') {
+                        return Err(format!("expected [reg], found \"{}\"", operand));
+                    }
+                    (register(&operand[1..operand.len() - 1])?, 0)
+                } else {
+                    check_count(mnemonic, operands, 2)?;
+                    (register(&operands[0])?, register(&operands[1])?)
+                };
+                self.push_halfword(format_i(opcode_bits, reg1, reg2));
+            }
+            InstructionFormat::II => self.encode_format_ii(mnemonic, operands)?,
+            InstructionFormat::III => {
+                let cond_bits = match mnemonic {
+                    "bv" => OPCODE_BITS_BCOND_BV,
+                    "bc" | "bl" => OPCODE_BITS_BCOND_BC,
+                    "bz" | "be" => OPCODE_BITS_BCOND_BZ,
+                    "bnh" => OPCODE_BITS_BCOND_BNH,
+                    "bn" => OPCODE_BITS_BCOND_BN,
+                    "br" => OPCODE_BITS_BCOND_BR,
+                    "blt" => OPCODE_BITS_BCOND_BLT,
+                    "ble" => OPCODE_BITS_BCOND_BLE,
+                    "bnv" => OPCODE_BITS_BCOND_BNV,
+                    "bnc" | "bnl" => OPCODE_BITS_BCOND_BNC,
+                    "bnz" | "bne" => OPCODE_BITS_BCOND_BNZ,
+                    "bh" => OPCODE_BITS_BCOND_BH,
+                    "bp" => OPCODE_BITS_BCOND_BP,
+                    "nop" => OPCODE_BITS_BCOND_NOP,
+                    "bge" => OPCODE_BITS_BCOND_BGE,
+                    _ => OPCODE_BITS_BCOND_BGT,
+                };
+                let disp = if mnemonic == "nop" {
+                    check_count(mnemonic, operands, 0)?;
+                    0
+                } else {
+                    check_count(mnemonic, operands, 1)?;
+                    self.branch_displacement(&operands[0], 9)?
+                };
+                self.push_halfword((OPCODE_BITS_BCOND_PREFIX << 13) | (cond_bits << 9) | ((disp as u16) & 0x01ff));
+            }
+            InstructionFormat::IV => {
+                check_count(mnemonic, operands, 1)?;
+                let opcode_bits = if mnemonic == "jr" { OPCODE_BITS_JR } else { OPCODE_BITS_JAL };
+                let disp = self.branch_displacement(&operands[0], 26)? as u32;
+                self.push_halfword((opcode_bits << 10) | (((disp >> 16) as u16) & 0x03ff));
+                self.push_halfword(disp as u16);
+            }
+            InstructionFormat::V => {
+                check_count(mnemonic, operands, 3)?;
+                let opcode_bits = match mnemonic {
+                    "movea" => OPCODE_BITS_MOVEA,
+                    "addi" => OPCODE_BITS_ADD_IMM_16,
+                    "ori" => OPCODE_BITS_OR_I,
+                    "andi" => OPCODE_BITS_AND_I,
+                    "xori" => OPCODE_BITS_XOR_I,
+                    _ => OPCODE_BITS_MOVHI,
+                };
+                let imm16 = self.evaluate_sized(&operands[0], 16)?;
+                let reg1 = register(&operands[1])?;
+                let reg2 = register(&operands[2])?;
+                self.push_halfword(format_i(opcode_bits, reg1, reg2));
+                self.push_halfword(imm16 as u16);
+            }
+            InstructionFormat::VI => {
+                check_count(mnemonic, operands, 2)?;
+                let (opcode_bits, is_store) = match mnemonic {
+                    "ld.b" => (OPCODE_BITS_LDB, false),
+                    "ld.h" => (OPCODE_BITS_LDH, false),
+                    "ld.w" => (OPCODE_BITS_LDW, false),
+                    "st.b" => (OPCODE_BITS_STB, true),
+                    "st.h" => (OPCODE_BITS_STH, true),
+                    "st.w" => (OPCODE_BITS_STW, true),
+                    "in.b" => (OPCODE_BITS_INB, false),
+                    "in.h" => (OPCODE_BITS_INH, false),
+                    "in.w" => (OPCODE_BITS_INW, false),
+                    "out.b" => (OPCODE_BITS_OUTB, true),
+                    "out.h" => (OPCODE_BITS_OUTH, true),
+                    _ => (OPCODE_BITS_OUTW, true),
+                };
+                let (memory_operand, reg2_operand) = if is_store {
+                    (&operands[1], &operands[0])
+                } else {
+                    (&operands[0], &operands[1])
+                };
+                let (disp16, reg1) = self.memory_operand(memory_operand)?;
+                let reg2 = register(reg2_operand)?;
+                self.push_halfword(format_i(opcode_bits, reg1, reg2));
+                self.push_halfword(disp16 as u16);
+            }
+            InstructionFormat::VII => {
+                check_count(mnemonic, operands, 2)?;
+                let subop_bits = match mnemonic {
+                    "cmpf.s" => OPCODE_BITS_SUB_OP_CMPF_S,
+                    "cvt.ws" => OPCODE_BITS_SUB_OP_CVT_WS,
+                    "cvt.sw" => OPCODE_BITS_SUB_OP_CVT_SW,
+                    "addf.s" => OPCODE_BITS_SUB_OP_ADDF_S,
+                    "subf.s" => OPCODE_BITS_SUB_OP_SUBF_S,
+                    "mulf.s" => OPCODE_BITS_SUB_OP_MULF_S,
+                    "divf.s" => OPCODE_BITS_SUB_OP_DIVF_S,
+                    "xb" => OPCODE_BITS_SUB_OP_XB,
+                    "xh" => OPCODE_BITS_SUB_OP_XH,
+                    "rev" => OPCODE_BITS_SUB_OP_REV,
+                    "trnc.sw" => OPCODE_BITS_SUB_OP_TRNC_SW,
+                    _ => OPCODE_BITS_SUB_OP_MPYHW,
+                };
+                let reg1 = register(&operands[0])?;
+                let reg2 = register(&operands[1])?;
+                self.push_halfword(format_i(OPCODE_BITS_EXTENDED, reg1, reg2));
+                self.push_halfword(subop_bits << 10);
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_format_ii(&mut self, mnemonic: &str, operands: &[String]) -> Result<(), String> {
+        let (opcode_bits, imm5, reg2) = match mnemonic {
+            "cli" | "reti" | "halt" | "sei" => {
+                check_count(mnemonic, operands, 0)?;
+                let opcode_bits = match mnemonic {
+                    "cli" => OPCODE_BITS_CLI,
+                    "reti" => OPCODE_BITS_RETI,
+                    "halt" => OPCODE_BITS_HALT,
+                    _ => OPCODE_BITS_SEI,
+                };
+                (opcode_bits, 0, 0)
+            }
+            "orbsu" | "andbsu" | "xorbsu" | "movbsu" | "ornbsu" | "andnbsu" | "xornbsu" | "notbsu" => {
+                check_count(mnemonic, operands, 0)?;
+                let bit_string_op = match mnemonic {
+                    "orbsu" => OPCODE_BITS_BIT_STRING_OP_ORBSU,
+                    "andbsu" => OPCODE_BITS_BIT_STRING_OP_ANDBSU,
+                    "xorbsu" => OPCODE_BITS_BIT_STRING_OP_XORBSU,
+                    "movbsu" => OPCODE_BITS_BIT_STRING_OP_MOVBSU,
+                    "ornbsu" => OPCODE_BITS_BIT_STRING_OP_ORNBSU,
+                    "andnbsu" => OPCODE_BITS_BIT_STRING_OP_ANDNBSU,
+                    "xornbsu" => OPCODE_BITS_BIT_STRING_OP_XORNBSU,
+                    _ => OPCODE_BITS_BIT_STRING_OP_NOTBSU,
+                };
+                (OPCODE_BITS_BIT_STRING, bit_string_op, 0)
+            }
+            "trap" => {
+                check_count(mnemonic, operands, 1)?;
+                (OPCODE_BITS_TRAP, self.evaluate_unsigned(&operands[0], 5)?, 0)
+            }
+            "setf" => {
+                check_count(mnemonic, operands, 2)?;
+                let condition = match condition(&operands[0]) {
+                    Some(condition) => condition,
+                    None => self.evaluate_unsigned(&operands[0], 4)?,
+                };
+                (OPCODE_BITS_SETF, condition, register(&operands[1])?)
+            }
+            "ldsr" => {
+                check_count(mnemonic, operands, 2)?;
+                (OPCODE_BITS_LDSR, self.system_register(&operands[1])?, register(&operands[0])?)
+            }
+            "stsr" => {
+                check_count(mnemonic, operands, 2)?;
+                (OPCODE_BITS_STSR, self.system_register(&operands[0])?, register(&operands[1])?)
+            }
+            _ => {
+                check_count(mnemonic, operands, 2)?;
+                let (opcode_bits, is_signed) = match mnemonic {
+                    "mov" => (OPCODE_BITS_MOV_IMM, true),
+                    "add" => (OPCODE_BITS_ADD_IMM_5, true),
+                    "cmp" => (OPCODE_BITS_CMP_IMM, true),
+                    "shl" => (OPCODE_BITS_SHL_IMM, false),
+                    "shr" => (OPCODE_BITS_SHR_IMM, false),
+                    _ => (OPCODE_BITS_SAR_IMM, false),
+                };
+                let imm5 = if is_signed {
+                    self.evaluate_signed(&operands[0], 5)?
+                } else {
+                    self.evaluate_unsigned(&operands[0], 5)?
+                };
+                (opcode_bits, imm5, register(&operands[1])?)
+            }
+        };
+        self.push_halfword(format_i(opcode_bits, imm5 & 0x1f, reg2));
+        Ok(())
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<u32, String> {
+        evaluate(expression, self.symbols, self.addr)
+    }
+
+    // Accepts values that fit in `bits` bits either signed or unsigned
+    fn evaluate_sized(&self, expression: &str, bits: u32) -> Result<u32, String> {
+        let value = self.evaluate(expression)?;
+        let signed = value as i32;
+        if (signed >= -(1 << (bits - 1)) && signed < 0) || value < (1 << bits) {
+            Ok(value & ((1 << bits) - 1))
+        } else {
+            Err(format!("value {} doesn't fit in {} bits", signed, bits))
+        }
+    }
+
+    fn evaluate_signed(&self, expression: &str, bits: u32) -> Result<u32, String> {
+        let value = self.evaluate(expression)? as i32;
+        if value >= -(1 << (bits - 1)) && value < (1 << (bits - 1)) {
+            Ok((value as u32) & ((1 << bits) - 1))
+        } else {
+            Err(format!("value {} doesn't fit in {} signed bits", value, bits))
+        }
+    }
+
+    fn evaluate_unsigned(&self, expression: &str, bits: u32) -> Result<u32, String> {
+        let value = self.evaluate(expression)?;
+        if value < (1 << bits) {
+            Ok(value)
+        } else {
+            Err(format!("value {} doesn't fit in {} unsigned bits", value, bits))
+        }
+    }
+
+    fn branch_displacement(&self, target: &str, bits: u32) -> Result<i32, String> {
+        let disp = self.evaluate(target)?.wrapping_sub(self.addr) as i32;
+        if disp & 1 != 0 {
+            return Err(format!("branch target {} isn't halfword aligned", target));
+        }
+        if disp < -(1 << (bits - 1)) || disp >= (1 << (bits - 1)) {
+            return Err(format!("branch target {} is out of range", target));
+        }
+        Ok(disp)
+    }
+
+    fn memory_operand(&self, operand: &str) -> Result<(u32, u32), String> {
+        let operand = operand.trim();
+        let bracket_index = match operand.rfind('[') {
+            Some(index) if operand.ends_with(']') => index,
+            _ => return Err(format!("expected disp[reg], found \"{}\"", operand)),
+        };
+        let disp = operand[..bracket_index].trim();
+        let disp16 = if disp.is_empty() { 0 } else { self.evaluate_sized(disp, 16)? };
+        let reg1 = register(&operand[bracket_index + 1..operand.len() - 1])?;
+        Ok((disp16, reg1))
+    }
+
+    fn system_register(&self, operand: &str) -> Result<u32, String> {
+        Ok(match operand.to_lowercase().as_str() {
+            "eipc" => OPCODE_SYSTEM_REGISTER_ID_EIPC,
+            "eipsw" => OPCODE_SYSTEM_REGISTER_ID_EIPSW,
+            "fepc" => OPCODE_SYSTEM_REGISTER_ID_FEPC,
+            "fepsw" => OPCODE_SYSTEM_REGISTER_ID_FEPSW,
+            "ecr" => OPCODE_SYSTEM_REGISTER_ID_ECR,
+            "psw" => OPCODE_SYSTEM_REGISTER_ID_PSW,
+            "chcw" => OPCODE_SYSTEM_REGISTER_ID_CHCW,
+            _ => self.evaluate_unsigned(operand, 5)?,
+        })
+    }
+
+    fn fill(&mut self, count: u32, value: u8) {
+        for _ in 0..count {
+            self.bytes.push(value);
+        }
+    }
+
+    fn push_halfword(&mut self, value: u16) {
+        self.bytes.push(value as u8);
+        self.bytes.push((value >> 8) as u8);
+    }
+}
+
+fn format_i(opcode_bits: u16, reg1: u32, reg2: u32) -> u16 {
+    (opcode_bits << 10) | ((reg2 as u16) << 5) | (reg1 as u16)
+}
+
+fn check_count(mnemonic: &str, operands: &[String], count: usize) -> Result<(), String> {
+    if operands.len() != count {
+        return Err(format!("{} takes {} operand(s), found {}", mnemonic, count, operands.len()));
+    }
+    Ok(())
+}
+
+fn parse_register(operand: &str) -> Option<u32> {
+    let operand = operand.trim().to_lowercase();
+    match operand.as_str() {
+        "sp" => return Some(3),
+        "gp" => return Some(4),
+        "tp" => return Some(5),
+        "lp" => return Some(31),
+        _ => {}
+    }
+    if !operand.starts_with('r') {
+        return None;
+    }
+    match operand[1..].parse::<u32>() {
+        Ok(index) if index < 32 && !operand[1..].starts_with('+') => Some(index),
+        _ => None,
+    }
+}
+
+fn register(operand: &str) -> Result<u32, String> {
+    parse_register(operand).ok_or_else(|| format!("expected a register, found \"{}\"", operand.trim()))
+}
+
+fn condition(operand: &str) -> Option<u32> {
+    Some(match operand.trim().to_lowercase().as_str() {
+        "v" => OPCODE_CONDITION_BITS_V,
+        "c" | "l" => OPCODE_CONDITION_BITS_C,
+        "z" | "e" => OPCODE_CONDITION_BITS_Z,
+        "nh" => OPCODE_CONDITION_BITS_NH,
+        "n" => OPCODE_CONDITION_BITS_N,
+        "t" => OPCODE_CONDITION_BITS_T,
+        "lt" => OPCODE_CONDITION_BITS_LT,
+        "le" => OPCODE_CONDITION_BITS_LE,
+        "nv" => OPCODE_CONDITION_BITS_NV,
+        "nc" | "nl" => OPCODE_CONDITION_BITS_NC,
+        "nz" | "ne" => OPCODE_CONDITION_BITS_NZ,
+        "h" => OPCODE_CONDITION_BITS_H,
+        "p" => OPCODE_CONDITION_BITS_P,
+        "f" => OPCODE_CONDITION_BITS_F,
+        "ge" => OPCODE_CONDITION_BITS_GE,
+        "gt" => OPCODE_CONDITION_BITS_GT,
+        _ => return None,
+    })
+}
+
+fn parse_string(operand: &str) -> Result<Vec<u8>, String> {
+    let operand = operand.trim();
+    if operand.len() < 2 || !operand.starts_with('"') || !operand.ends_with('"') {
+        return Err(format!("expected a quoted string, found {}", operand));
+    }
+
+    let mut bytes = Vec::new();
+    let mut chars = operand[1..operand.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                Some(c) => return Err(format!("unknown escape sequence \\{}", c)),
+                None => return Err("string ends with a backslash".into()),
+            }
+        } else {
+            c
+        };
+        let mut buffer = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Evaluates an expression such as `label + 4` or `hi(data)`, with `.` standing for `addr`
+fn evaluate(expression: &str, symbols: &HashMap<String, u32>, addr: u32) -> Result<u32, String> {
+    let expression = expression.trim();
+    if expression.is_empty() {
+        return Err("expected an expression".into());
+    }
+
+    // Split on the last top-level + or - that's a binary operator, so terms are applied left to right
+    let mut depth = 0;
+    let mut split = None;
+    let mut previous = None;
+    for (index, c) in expression.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '+' | '-' if depth == 0 => {
+                let is_binary = match previous {
+                    Some(p) => p != '+' && p != '-' && p != '(',
+                    None => false,
+                };
+                if is_binary {
+                    split = Some(index);
+                }
+            }
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            previous = Some(c);
+        }
+    }
+
+    if let Some(index) = split {
+        let lhs = evaluate(&expression[..index], symbols, addr)?;
+        let rhs = evaluate(&expression[index + 1..], symbols, addr)?;
+        return Ok(if expression[index..].starts_with('+') {
+            lhs.wrapping_add(rhs)
+        } else {
+            lhs.wrapping_sub(rhs)
+        });
+    }
+
+    if expression.starts_with('-') {
+        return Ok(evaluate(&expression[1..], symbols, addr)?.wrapping_neg());
+    }
+    if expression.starts_with('+') {
+        return evaluate(&expression[1..], symbols, addr);
+    }
+
+    if expression.ends_with(')') {
+        if let Some(paren_index) = expression.find('(') {
+            let inner = evaluate(&expression[paren_index + 1..expression.len() - 1], symbols, addr)?;
+            return match expression[..paren_index].trim() {
+                "" => Ok(inner),
+                "hi" => Ok(inner.wrapping_add(0x8000) >> 16),
+                "lo" => Ok(inner & 0xffff),
+                function => Err(format!("unknown function \"{}\"", function)),
+            };
+        }
+    }
+
+    if expression == "." {
+        return Ok(addr);
+    }
+
+    if expression.starts_with("0x") || expression.starts_with("0X") {
+        return u32::from_str_radix(&expression[2..], 16).map_err(|_| format!("invalid number \"{}\"", expression));
+    }
+    if expression.chars().next().unwrap().is_digit(10) {
+        return expression.parse::<u32>().map_err(|_| format!("invalid number \"{}\"", expression));
+    }
+
+    match symbols.get(expression) {
+        Some(&value) => Ok(value),
+        None if is_symbol_name(expression) => Err(format!("undefined symbol \"{}\"", expression)),
+        None => Err(format!("invalid expression \"{}\"", expression)),
+    }
+}
@@ -0,0 +1,101 @@
+// Deep recursion (or code that calls without ever returning) shouldn't grow the stack forever
+const MAX_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Entered with `jal`, left with `jmp [lp]`
+    Call,
+    /// An interrupt or exception with the given exception code, left with `reti`
+    Exception(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Address of the called function or exception handler
+    pub entry: u32,
+    /// Address of the `jal`, or of the instruction an exception interrupted
+    pub call_site: u32,
+    /// Where execution continues once the frame returns
+    pub return_addr: u32,
+}
+
+/// A best-effort record of the calls and exceptions the CPU is currently inside of,
+/// built by watching `jal`, `jmp [lp]`, exception entries and `reti` as they execute.
+///
+/// Returns that don't match a frame are ignored, and frames that are never returned
+/// from (eg. when code jumps out of a function some other way) stay until an outer
+/// frame returns past them.
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+        }
+    }
+
+    /// The frames from outermost to innermost
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn call(&mut self, call_site: u32, entry: u32) {
+        self.push(Frame {
+            kind: FrameKind::Call,
+            entry: entry,
+            call_site: call_site,
+            return_addr: call_site.wrapping_add(4),
+        });
+    }
+
+    /// Pops the innermost call returning to `return_addr`, along with any calls inside
+    /// of it. Returns never leave an exception handler.
+    pub fn ret(&mut self, return_addr: u32) {
+        let index = self.frames.iter().rev()
+            .take_while(|frame| frame.kind == FrameKind::Call)
+            .position(|frame| frame.return_addr == return_addr);
+        if let Some(index) = index {
+            let depth = self.frames.len() - 1 - index;
+            self.frames.truncate(depth);
+        }
+    }
+
+    pub fn enter_exception(&mut self, exception_code: u16, restore_pc: u32, handler: u32) {
+        self.push(Frame {
+            kind: FrameKind::Exception(exception_code),
+            entry: handler,
+            call_site: restore_pc,
+            return_addr: restore_pc,
+        });
+    }
+
+    /// Pops the innermost exception frame, along with any calls made by its handler
+    pub fn return_from_exception(&mut self) {
+        let exception = self.frames.iter().rposition(|frame| match frame.kind {
+            FrameKind::Exception(_) => true,
+            FrameKind::Call => false,
+        });
+        if let Some(depth) = exception {
+            self.frames.truncate(depth);
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() >= MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+}
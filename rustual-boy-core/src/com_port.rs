@@ -1,10 +1,33 @@
+use save_state::*;
+
+use std::io::{self, Read, Write};
+
+// 20mhz / 100khz = 200 clocks
+const TRANSFER_BIT_PERIOD: u32 = 200;
+
+const TRANSFER_PERIOD: u32 = TRANSFER_BIT_PERIOD * 8;
+
 pub struct ComPort {
     cdtr: u8,
     cdrr: u8,
 
     c_stat: bool,
+    c_int_inh: bool,
+    c_clk_sel_external: bool,
+    c_int: bool,
+
+    cc_int_inh: bool,
+    cc_smp: bool,
+    cc_sig_rising: bool,
+    cc_wr: bool,
+    cc_rd: bool,
+    cc_int: bool,
 
     transfer_bit_index: u32,
+    transfer_counter: u32,
+    is_awaiting_exchange: bool,
+
+    is_connected: bool,
 }
 
 impl ComPort {
@@ -14,33 +37,122 @@ impl ComPort {
             cdrr: 0,
 
             c_stat: false,
+            c_int_inh: true,
+            c_clk_sel_external: false,
+            c_int: false,
+
+            cc_int_inh: true,
+            cc_smp: false,
+            cc_sig_rising: false,
+            cc_wr: true,
+            cc_rd: true,
+            cc_int: false,
 
             transfer_bit_index: 0,
+            transfer_counter: 0,
+            is_awaiting_exchange: false,
+
+            is_connected: false,
         }
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_u8(w, self.cdtr)?;
+        write_u8(w, self.cdrr)?;
+
+        write_bool(w, self.c_stat)?;
+        write_bool(w, self.c_int_inh)?;
+        write_bool(w, self.c_clk_sel_external)?;
+        write_bool(w, self.c_int)?;
+
+        write_bool(w, self.cc_int_inh)?;
+        write_bool(w, self.cc_smp)?;
+        write_bool(w, self.cc_sig_rising)?;
+        write_bool(w, self.cc_wr)?;
+        write_bool(w, self.cc_rd)?;
+        write_bool(w, self.cc_int)?;
+
+        write_u32(w, self.transfer_bit_index)?;
+        write_u32(w, self.transfer_counter)?;
+        write_bool(w, self.is_awaiting_exchange)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.cdtr = read_u8(r)?;
+        self.cdrr = read_u8(r)?;
+
+        self.c_stat = read_bool(r)?;
+        self.c_int_inh = read_bool(r)?;
+        self.c_clk_sel_external = read_bool(r)?;
+        self.c_int = read_bool(r)?;
+
+        self.cc_int_inh = read_bool(r)?;
+        self.cc_smp = read_bool(r)?;
+        self.cc_sig_rising = read_bool(r)?;
+        self.cc_wr = read_bool(r)?;
+        self.cc_rd = read_bool(r)?;
+        self.cc_int = read_bool(r)?;
+
+        self.transfer_bit_index = read_u32(r)?;
+        self.transfer_counter = read_u32(r)?;
+        self.is_awaiting_exchange = read_bool(r)?;
+
+        Ok(())
+    }
+
     pub fn read_ccr(&self) -> u8 {
-        logln!(Log::Ic, "WARNING: Read from CCR not fully implemented");
         0b01101001 |
+        (if self.c_int_inh { 1 } else { 0 } << 7) |
+        (if self.c_clk_sel_external { 1 } else { 0 } << 4) |
         if self.c_stat { 1 << 1 } else { 0 }
     }
 
     pub fn write_ccr(&mut self, value: u8) {
-        logln!(Log::Ic, "WARNING: Write to CCR not yet implemented (value: 0x{:02x})", value);
+        self.c_int_inh = (value & 0x80) != 0;
+        if self.c_int_inh {
+            self.c_int = false;
+        }
+
+        // The clock source can't be changed in the middle of a transfer
+        if !self.c_stat {
+            self.c_clk_sel_external = (value & 0x10) != 0;
+        }
+
         if (value & 0x04) != 0 && !self.c_stat {
             self.cdrr = 0;
             self.c_stat = true;
+            self.c_int = false;
             self.transfer_bit_index = 7;
+
+            if !self.c_clk_sel_external {
+                self.transfer_counter = 0;
+            }
         }
     }
 
     pub fn read_ccsr(&self) -> u8 {
-        logln!(Log::Ic, "WARNING: Read from CCSR not yet implemented");
-        0
+        0b01100100 |
+        (if self.cc_int_inh { 1 } else { 0 } << 7) |
+        (if self.cc_smp { 1 } else { 0 } << 4) |
+        (if self.cc_sig_rising { 1 } else { 0 } << 3) |
+        (if self.cc_wr { 1 } else { 0 } << 1) |
+        if self.cc_rd { 1 } else { 0 }
     }
 
     pub fn write_ccsr(&mut self, value: u8) {
-        logln!(Log::Ic, "WARNING: Write to CCSR not yet implemented (value: 0x{:02x})", value);
+        self.cc_int_inh = (value & 0x80) != 0;
+        if self.cc_int_inh {
+            self.cc_int = false;
+        }
+        self.cc_smp = (value & 0x10) != 0;
+        self.cc_sig_rising = (value & 0x08) != 0;
+        self.cc_wr = (value & 0x02) != 0;
+
+        // With nothing on the other end, the COMCNT line just follows what we drive
+        if !self.is_connected {
+            let level = self.cc_wr;
+            self.set_comcnt_level(level);
+        }
     }
 
     pub fn read_cdtr(&self) -> u8 {
@@ -55,9 +167,57 @@ impl ComPort {
         self.cdrr
     }
 
-    // TODO: This covers the case where the VB is slave only, and doesn't properly emulate any possible timing errors that might occur.
+    /// Whether anything is plugged in to the link port. While disconnected,
+    /// transfers clocked by this unit complete on their own, receiving 0xff.
+    pub fn is_connected(&self) -> bool {
+        self.is_connected
+    }
+
+    pub fn set_is_connected(&mut self, is_connected: bool) {
+        self.is_connected = is_connected;
+    }
+
+    /// The level this unit is driving on the COMCNT line
+    pub fn comcnt_output(&self) -> bool {
+        self.cc_wr
+    }
+
+    /// Updates the level seen on the COMCNT line, raising an interrupt on the edge selected by CC-SIG
+    pub fn set_comcnt_level(&mut self, level: bool) {
+        let is_selected_edge = level != self.cc_rd && level == self.cc_sig_rising;
+        self.cc_rd = level;
+
+        if is_selected_edge && !self.cc_int_inh {
+            self.cc_int = true;
+        }
+    }
+
+    /// True once this unit has clocked out a whole byte as master and is waiting for
+    /// the other end of the cable to exchange it with `exchange_master_transfer`
+    pub fn is_awaiting_exchange(&self) -> bool {
+        self.is_awaiting_exchange
+    }
+
+    /// Clocks each bit of this (master) unit's transfer through `slave_clock_bit`,
+    /// which is given our outgoing bit and returns the incoming one, then completes it.
+    pub fn exchange_master_transfer<F>(&mut self, mut slave_clock_bit: F) where F: FnMut(u32) -> u32 {
+        if !self.is_awaiting_exchange {
+            return;
+        }
+
+        let mut received = 0;
+        for bit_index in (0..8).rev() {
+            let bit = ((self.cdtr >> bit_index) & 1) as u32;
+            received |= (slave_clock_bit(bit) << bit_index) as u8;
+        }
+
+        self.is_awaiting_exchange = false;
+        self.complete_transfer(received);
+    }
+
+    // TODO: This doesn't properly emulate any possible timing errors that might occur.
     pub fn transfer_slave_clock_bit(&mut self, bit: u32) -> u32 {
-        if !self.c_stat {
+        if !self.c_stat || !self.c_clk_sel_external {
             return 0;
         }
 
@@ -65,13 +225,63 @@ impl ComPort {
         let ret = ((self.cdtr >> self.transfer_bit_index) & 1) as u32;
 
         if self.transfer_bit_index == 0 {
-            self.c_stat = false;
-
-            // TODO: Emulate link port interrupt (if applicable)
+            let received = self.cdrr;
+            self.complete_transfer(received);
         } else {
             self.transfer_bit_index -= 1;
         }
 
         ret
     }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.c_int || self.cc_int
+    }
+
+    /// Number of cycles that can pass before a transfer clocked by this unit finishes
+    pub fn cycles_until_next_event(&self) -> u32 {
+        if !self.is_clocking_transfer() {
+            return u32::max_value();
+        }
+
+        TRANSFER_PERIOD.saturating_sub(self.transfer_counter + 1)
+    }
+
+    /// Advances a transfer clocked by this unit. `cycles` must not be more than `cycles_until_next_event()`.
+    pub fn skip_cycles(&mut self, cycles: u32) {
+        if self.is_clocking_transfer() {
+            self.transfer_counter += cycles;
+        }
+    }
+
+    pub fn cycles(&mut self, cycles: u32) {
+        if !self.is_clocking_transfer() {
+            return;
+        }
+
+        self.transfer_counter += cycles;
+        if self.transfer_counter >= TRANSFER_PERIOD {
+            self.transfer_counter = 0;
+
+            if self.is_connected {
+                self.is_awaiting_exchange = true;
+            } else {
+                // Nothing drives the data line, so it reads as all ones
+                self.complete_transfer(0xff);
+            }
+        }
+    }
+
+    fn is_clocking_transfer(&self) -> bool {
+        self.c_stat && !self.c_clk_sel_external && !self.is_awaiting_exchange
+    }
+
+    fn complete_transfer(&mut self, received: u8) {
+        self.cdrr = received;
+        self.c_stat = false;
+
+        if !self.c_int_inh {
+            self.c_int = true;
+        }
+    }
 }
@@ -0,0 +1,104 @@
+use instruction::*;
+use wram::WRAM_SIZE;
+
+/// Previously decoded instructions in Game Pak ROM and WRAM, indexed by halfword offset,
+/// so that the CPU doesn't have to fetch and decode them again. Entries that overlap WRAM
+/// are invalidated whenever WRAM is written, and ROM entries when ROM is patched.
+pub struct DecodeCache {
+    rom: Box<[Option<DecodedInstruction>]>,
+    rom_mask: u32,
+    wram: Box<[Option<DecodedInstruction>]>,
+
+    hits: u64,
+    misses: u64,
+}
+
+impl DecodeCache {
+    pub fn new(rom_size: usize) -> DecodeCache {
+        DecodeCache {
+            rom: vec![None; rom_size / 2].into_boxed_slice(),
+            rom_mask: (rom_size as u32) - 1,
+            wram: vec![None; WRAM_SIZE / 2].into_boxed_slice(),
+
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn rom(&self, offset: u32) -> Option<DecodedInstruction> {
+        self.rom[((offset & self.rom_mask) >> 1) as usize]
+    }
+
+    pub fn insert_rom(&mut self, offset: u32, instruction: DecodedInstruction) {
+        self.rom[((offset & self.rom_mask) >> 1) as usize] = Some(instruction);
+    }
+
+    pub fn invalidate_rom(&mut self, offset: u32) {
+        // A 32-bit instruction starting at the previous halfword overlaps this one as well
+        let index = ((offset & self.rom_mask) >> 1) as usize;
+        let previous_index = (index + self.rom.len() - 1) % self.rom.len();
+        self.rom[index] = None;
+        self.rom[previous_index] = None;
+    }
+
+    pub fn wram(&self, offset: u32) -> Option<DecodedInstruction> {
+        self.wram[wram_index(offset)]
+    }
+
+    pub fn insert_wram(&mut self, offset: u32, instruction: DecodedInstruction) {
+        self.wram[wram_index(offset)] = Some(instruction);
+    }
+
+    pub fn invalidate_wram(&mut self, offset: u32) {
+        let index = wram_index(offset);
+        let previous_index = (index + self.wram.len() - 1) % self.wram.len();
+        self.wram[index] = None;
+        self.wram[previous_index] = None;
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.rom.iter_mut() {
+            *entry = None;
+        }
+        self.clear_wram();
+    }
+
+    pub fn clear_wram(&mut self) {
+        for entry in self.wram.iter_mut() {
+            *entry = None;
+        }
+    }
+
+    pub fn record_lookup(&mut self, is_hit: bool) {
+        if is_hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+pub fn is_32_bit_instruction(first_halfword: u16) -> bool {
+    if first_halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
+        return false;
+    }
+
+    match first_halfword >> 10 {
+        OPCODE_BITS_MOVEA | OPCODE_BITS_ADD_IMM_16 | OPCODE_BITS_JR | OPCODE_BITS_JAL |
+        OPCODE_BITS_OR_I | OPCODE_BITS_AND_I | OPCODE_BITS_XOR_I | OPCODE_BITS_MOVHI |
+        OPCODE_BITS_LDB | OPCODE_BITS_LDH | OPCODE_BITS_LDW |
+        OPCODE_BITS_STB | OPCODE_BITS_STH | OPCODE_BITS_STW |
+        OPCODE_BITS_INB | OPCODE_BITS_INH | OPCODE_BITS_INW |
+        OPCODE_BITS_OUTB | OPCODE_BITS_OUTH | OPCODE_BITS_OUTW |
+        OPCODE_BITS_EXTENDED => true,
+        _ => false,
+    }
+}
+
+fn wram_index(offset: u32) -> usize {
+    ((offset as usize) & (WRAM_SIZE - 1)) >> 1
+}
@@ -0,0 +1,194 @@
+use instruction::*;
+
+use std::fmt;
+
+/// What an instruction does: a plain opcode, or one of the sub-operations selected by
+/// a bit string or extended (format VII) instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mnemonic {
+    Opcode(Opcode),
+    BitString(BitStringOp),
+    SubOp(SubOp),
+    /// Not a valid instruction; its first halfword is kept as an operand
+    Invalid,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Mnemonic::Opcode(ref opcode) => write!(f, "{}", opcode),
+            &Mnemonic::BitString(ref bit_string_op) => write!(f, "{}", bit_string_op),
+            &Mnemonic::SubOp(ref subop) => write!(f, "{}", subop),
+            &Mnemonic::Invalid => write!(f, ".halfword"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A general purpose register, `r0` through `r31`
+    Reg(usize),
+    /// The address held in a register, as in `jmp [r31]`
+    RegIndirect(usize),
+    SystemReg(SystemRegister),
+    /// A 5-bit immediate, already sign extended for instructions that treat it as signed
+    Imm5(i32),
+    Imm16(u16),
+    /// `disp16[reg1]` addressing used by loads, stores and port I/O
+    Memory { disp: i16, base: usize },
+    /// A relative branch or jump; `target` is the absolute address it goes to. `disp` is
+    /// shown in signed decimal for both format III and IV, where the old debugger showed
+    /// format III's raw 9-bit field in hex.
+    Branch { disp: i32, target: u32 },
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Operand::Reg(reg) => write!(f, "r{}", reg),
+            &Operand::RegIndirect(reg) => write!(f, "[r{}]", reg),
+            &Operand::SystemReg(ref system_register) => write!(f, "{}", system_register),
+            &Operand::Imm5(imm5) => write!(f, "{}", imm5),
+            &Operand::Imm16(imm16) => write!(f, "{:#x}", imm16),
+            &Operand::Memory { disp, base } => write!(f, "{}[r{}]", disp, base),
+            &Operand::Branch { disp, target } => write!(f, "{} (0x{:08x})", disp, target),
+        }
+    }
+}
+
+/// A single decoded instruction. Displays the same way the debugger shows it, eg.
+/// `ld.w -4[r3], r10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub addr: u32,
+    /// Length in bytes, either 2 or 4
+    pub size: u32,
+    pub mnemonic: Mnemonic,
+    /// Operands in the order they're written in assembly
+    pub operands: Vec<Operand>,
+}
+
+impl DecodedInstruction {
+    /// Address of the instruction that follows this one in memory
+    pub fn next_addr(&self) -> u32 {
+        self.addr.wrapping_add(self.size)
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (index, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if index == 0 { " " } else { ", " }, operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which is located at `addr`.
+///
+/// Passing 4 bytes is always enough; any extra bytes are ignored. Returns `None` if
+/// `bytes` ends before the instruction does. Unassigned opcodes decode as a 2-byte
+/// `Mnemonic::Invalid`.
+pub fn disassemble(bytes: &[u8], addr: u32) -> Option<DecodedInstruction> {
+    let first_halfword = read_halfword(bytes, 0)?;
+
+    let opcode = match Opcode::try_from_halfword(first_halfword) {
+        Some(opcode) => opcode,
+        None => return Some(invalid(first_halfword, addr)),
+    };
+    let instruction_format = opcode.instruction_format();
+
+    let (size, second_halfword) = if instruction_format.has_second_halfword() {
+        (4, read_halfword(bytes, 2)?)
+    } else {
+        (2, 0)
+    };
+
+    let reg1 = (first_halfword & 0x1f) as usize;
+    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
+
+    let (mnemonic, operands) = match instruction_format {
+        InstructionFormat::I => {
+            if opcode == Opcode::Jmp {
+                (Mnemonic::Opcode(opcode), vec![Operand::RegIndirect(reg1)])
+            } else {
+                (Mnemonic::Opcode(opcode), vec![Operand::Reg(reg1), Operand::Reg(reg2)])
+            }
+        }
+        InstructionFormat::II => {
+            let imm5 = (first_halfword & 0x1f) as u32;
+            match opcode {
+                Opcode::BitString => match opcode.try_bit_string_op(imm5) {
+                    Some(bit_string_op) => (Mnemonic::BitString(bit_string_op), Vec::new()),
+                    None => return Some(invalid(first_halfword, addr)),
+                },
+                Opcode::Cli | Opcode::Reti | Opcode::Halt | Opcode::Sei => (Mnemonic::Opcode(opcode), Vec::new()),
+                Opcode::Trap => (Mnemonic::Opcode(opcode), vec![Operand::Imm5(imm5 as i32)]),
+                Opcode::Ldsr => (Mnemonic::Opcode(opcode), vec![Operand::Reg(reg2), Operand::SystemReg(opcode.system_register(imm5))]),
+                Opcode::Stsr => (Mnemonic::Opcode(opcode), vec![Operand::SystemReg(opcode.system_register(imm5)), Operand::Reg(reg2)]),
+                Opcode::MovImm | Opcode::AddImm5 | Opcode::CmpImm => (Mnemonic::Opcode(opcode), vec![Operand::Imm5(((imm5 as i32) << 27) >> 27), Operand::Reg(reg2)]),
+                _ => (Mnemonic::Opcode(opcode), vec![Operand::Imm5(imm5 as i32), Operand::Reg(reg2)]),
+            }
+        }
+        InstructionFormat::III => {
+            let disp = ((first_halfword as i32) << 23) >> 23;
+            (Mnemonic::Opcode(opcode), vec![branch(addr, disp)])
+        }
+        InstructionFormat::IV => {
+            let disp26 = (((first_halfword as u32) & 0x03ff) << 16) | (second_halfword as u32);
+            let disp = ((disp26 << 6) as i32) >> 6;
+            (Mnemonic::Opcode(opcode), vec![branch(addr, disp)])
+        }
+        InstructionFormat::V => {
+            (Mnemonic::Opcode(opcode), vec![Operand::Imm16(second_halfword), Operand::Reg(reg1), Operand::Reg(reg2)])
+        }
+        InstructionFormat::VI => {
+            let memory = Operand::Memory {
+                disp: second_halfword as i16,
+                base: reg1,
+            };
+            match opcode {
+                Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Outb | Opcode::Outh | Opcode::Outw => (Mnemonic::Opcode(opcode), vec![Operand::Reg(reg2), memory]),
+                _ => (Mnemonic::Opcode(opcode), vec![memory, Operand::Reg(reg2)]),
+            }
+        }
+        InstructionFormat::VII => {
+            let subop_bits = second_halfword >> 10;
+            match opcode.try_subop(subop_bits) {
+                Some(subop) => (Mnemonic::SubOp(subop), vec![Operand::Reg(reg1), Operand::Reg(reg2)]),
+                None => return Some(invalid(first_halfword, addr)),
+            }
+        }
+    };
+
+    Some(DecodedInstruction {
+        addr: addr,
+        size: size,
+        mnemonic: mnemonic,
+        operands: operands,
+    })
+}
+
+fn read_halfword(bytes: &[u8], offset: usize) -> Option<u16> {
+    if bytes.len() < offset + 2 {
+        return None;
+    }
+    Some((bytes[offset] as u16) | ((bytes[offset + 1] as u16) << 8))
+}
+
+fn branch(addr: u32, disp: i32) -> Operand {
+    Operand::Branch {
+        disp: disp,
+        target: addr.wrapping_add(disp as u32),
+    }
+}
+
+fn invalid(first_halfword: u16, addr: u32) -> DecodedInstruction {
+    DecodedInstruction {
+        addr: addr,
+        size: 2,
+        mnemonic: Mnemonic::Invalid,
+        operands: vec![Operand::Imm16(first_halfword)],
+    }
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessWidth {
+    Byte,
+    Halfword,
+    /// Word accesses take two bus cycles, so bus errors are never reported with this width
+    Word,
+}
+
+impl fmt::Display for AccessWidth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            AccessWidth::Byte => "byte",
+            AccessWidth::Halfword => "halfword",
+            AccessWidth::Word => "word",
+        })
+    }
+}
+
+/// An access to an address that isn't decoded by anything on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusError {
+    pub addr: u32,
+    pub kind: AccessKind,
+    pub width: AccessWidth,
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        };
+        write!(f, "{} {} at unmapped addr 0x{:08x}", kind, self.width, self.addr)
+    }
+}
+
+/// An error that stopped emulation. `pc` is always the address of the
+/// instruction that caused it, which hasn't been retired: the registers and memory are
+/// left as they were before it, so emulation can resume by retrying it. The exception
+/// is a bit string instruction, which keeps the bits it processed before the error and
+/// updates its registers to match, just like when it's interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulationError {
+    BusError { pc: u32, bus_error: BusError },
+}
+
+impl EmulationError {
+    pub fn pc(&self) -> u32 {
+        match *self {
+            EmulationError::BusError { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulationError::BusError { pc, ref bus_error } => write!(f, "Bus error: {} (pc: 0x{:08x})", bus_error, pc),
+        }
+    }
+}
+
+impl Error for EmulationError {
+    fn description(&self) -> &str {
+        match *self {
+            EmulationError::BusError { .. } => "Bus error",
+        }
+    }
+}
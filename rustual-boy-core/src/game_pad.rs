@@ -1,3 +1,11 @@
+use save_state::*;
+
+use std::io::{self, Read, Write};
+
+// The pad shifts out its 16 bits of button data over roughly 32us (20mhz * 32us = 640 clocks)
+const HARDWARE_READ_PERIOD: u32 = 640;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Button {
     A,
     B,
@@ -30,6 +38,22 @@ pub struct GamePad {
     right_d_pad_down_pressed: bool,
     right_d_pad_left_pressed: bool,
     right_d_pad_right_pressed: bool,
+
+    is_low_battery: bool,
+    is_connected: bool,
+
+    scr_k_int_inh: bool,
+    scr_para_si: bool,
+    scr_soft_ck: bool,
+    scr_s_abt_dis: bool,
+
+    is_hardware_reading: bool,
+    hardware_read_counter: u32,
+    key_int: bool,
+
+    is_latched: bool,
+    sdlr: u8,
+    sdhr: u8,
 }
 
 impl GamePad {
@@ -49,41 +73,169 @@ impl GamePad {
             right_d_pad_down_pressed: false,
             right_d_pad_left_pressed: false,
             right_d_pad_right_pressed: false,
+
+            is_low_battery: false,
+            is_connected: true,
+
+            scr_k_int_inh: true,
+            scr_para_si: false,
+            scr_soft_ck: false,
+            scr_s_abt_dis: false,
+
+            is_hardware_reading: false,
+            hardware_read_counter: 0,
+            key_int: false,
+
+            is_latched: false,
+            sdlr: 0,
+            sdhr: 0,
         }
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bool(w, self.a_pressed)?;
+        write_bool(w, self.b_pressed)?;
+        write_bool(w, self.start_pressed)?;
+        write_bool(w, self.select_pressed)?;
+        write_bool(w, self.l_pressed)?;
+        write_bool(w, self.r_pressed)?;
+        write_bool(w, self.left_d_pad_up_pressed)?;
+        write_bool(w, self.left_d_pad_down_pressed)?;
+        write_bool(w, self.left_d_pad_left_pressed)?;
+        write_bool(w, self.left_d_pad_right_pressed)?;
+        write_bool(w, self.right_d_pad_up_pressed)?;
+        write_bool(w, self.right_d_pad_down_pressed)?;
+        write_bool(w, self.right_d_pad_left_pressed)?;
+        write_bool(w, self.right_d_pad_right_pressed)?;
+
+        write_bool(w, self.scr_k_int_inh)?;
+        write_bool(w, self.scr_para_si)?;
+        write_bool(w, self.scr_soft_ck)?;
+        write_bool(w, self.scr_s_abt_dis)?;
+
+        write_bool(w, self.is_hardware_reading)?;
+        write_u32(w, self.hardware_read_counter)?;
+        write_bool(w, self.key_int)?;
+
+        write_bool(w, self.is_latched)?;
+        write_u8(w, self.sdlr)?;
+        write_u8(w, self.sdhr)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.a_pressed = read_bool(r)?;
+        self.b_pressed = read_bool(r)?;
+        self.start_pressed = read_bool(r)?;
+        self.select_pressed = read_bool(r)?;
+        self.l_pressed = read_bool(r)?;
+        self.r_pressed = read_bool(r)?;
+        self.left_d_pad_up_pressed = read_bool(r)?;
+        self.left_d_pad_down_pressed = read_bool(r)?;
+        self.left_d_pad_left_pressed = read_bool(r)?;
+        self.left_d_pad_right_pressed = read_bool(r)?;
+        self.right_d_pad_up_pressed = read_bool(r)?;
+        self.right_d_pad_down_pressed = read_bool(r)?;
+        self.right_d_pad_left_pressed = read_bool(r)?;
+        self.right_d_pad_right_pressed = read_bool(r)?;
+
+        self.scr_k_int_inh = read_bool(r)?;
+        self.scr_para_si = read_bool(r)?;
+        self.scr_soft_ck = read_bool(r)?;
+        self.scr_s_abt_dis = read_bool(r)?;
+
+        self.is_hardware_reading = read_bool(r)?;
+        self.hardware_read_counter = read_u32(r)?;
+        self.key_int = read_bool(r)?;
+
+        self.is_latched = read_bool(r)?;
+        self.sdlr = read_u8(r)?;
+        self.sdhr = read_u8(r)?;
+
+        Ok(())
+    }
+
     pub fn read_scr(&self) -> u8 {
-        logln!(Log::GamePad, "WARNING: Read SCR not yet implemented");
-        0
+        0b01001000 |
+        (if self.scr_k_int_inh { 1 } else { 0 } << 7) |
+        (if self.scr_para_si { 1 } else { 0 } << 5) |
+        (if self.scr_soft_ck { 1 } else { 0 } << 4) |
+        (if self.is_hardware_reading { 1 } else { 0 } << 1) |
+        if self.scr_s_abt_dis { 1 } else { 0 }
     }
 
     pub fn write_scr(&mut self, value: u8) {
-        logln!(Log::GamePad, "WARNING: Write SCR not yet implemented (value: 0x{:02x})", value);
+        self.scr_k_int_inh = (value & 0x80) != 0;
+        if self.scr_k_int_inh {
+            self.key_int = false;
+        }
+        self.scr_para_si = (value & 0x20) != 0;
+        self.scr_soft_ck = (value & 0x10) != 0;
+        self.scr_s_abt_dis = (value & 0x01) != 0;
+
+        if self.scr_para_si {
+            logln!(Log::GamePad, "WARNING: Software controller reads not yet implemented");
+        }
+
+        if self.scr_s_abt_dis {
+            // Abort any read in progress; the data registers keep their previous contents
+            self.is_hardware_reading = false;
+        } else if (value & 0x04) != 0 && !self.is_hardware_reading {
+            self.is_hardware_reading = true;
+            self.hardware_read_counter = 0;
+        }
     }
 
+    /// Button data latched by the last completed hardware read. Until the first one
+    /// completes, this reads the pad directly, so software that polls without starting
+    /// a read still sees the buttons and the signature bit.
     pub fn read_sdlr(&self) -> u8 {
-        let version = 1;
-        // TODO: Would be cool to be able to toggle this at startup/runtime :)
-        let low_battery = false;
-        (if self.right_d_pad_right_pressed { 1 } else { 0 } << 7) |
-        (if self.right_d_pad_up_pressed { 1 } else { 0 } << 6) |
-        (if self.l_pressed { 1 } else { 0 } << 5) |
-        (if self.r_pressed { 1 } else { 0 } << 4) |
-        (if self.b_pressed { 1 } else { 0 } << 3) |
-        (if self.a_pressed { 1 } else { 0 } << 2) |
-        (version << 1) |
-        if low_battery { 1 } else { 0 }
+        if self.is_latched { self.sdlr } else { self.pad_sdlr() }
     }
 
     pub fn read_sdhr(&self) -> u8 {
-        (if self.right_d_pad_down_pressed { 1 } else { 0 } << 7) |
-        (if self.right_d_pad_left_pressed { 1 } else { 0 } << 6) |
-        (if self.select_pressed { 1 } else { 0 } << 5) |
-        (if self.start_pressed { 1 } else { 0 } << 4) |
-        (if self.left_d_pad_up_pressed { 1 } else { 0 } << 3) |
-        (if self.left_d_pad_down_pressed { 1 } else { 0 } << 2) |
-        (if self.left_d_pad_left_pressed { 1 } else { 0 } << 1) |
-        if self.left_d_pad_right_pressed { 1 } else { 0 }
+        if self.is_latched { self.sdhr } else { self.pad_sdhr() }
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.key_int
+    }
+
+    /// Number of cycles that can pass before a hardware read in progress completes
+    pub fn cycles_until_next_event(&self) -> u32 {
+        if !self.is_hardware_reading {
+            return u32::max_value();
+        }
+
+        HARDWARE_READ_PERIOD.saturating_sub(self.hardware_read_counter + 1)
+    }
+
+    /// Advances a hardware read in progress. `cycles` must not be more than `cycles_until_next_event()`.
+    pub fn skip_cycles(&mut self, cycles: u32) {
+        if self.is_hardware_reading {
+            self.hardware_read_counter += cycles;
+        }
+    }
+
+    pub fn cycles(&mut self, cycles: u32) {
+        if !self.is_hardware_reading {
+            return;
+        }
+
+        self.hardware_read_counter += cycles;
+        if self.hardware_read_counter >= HARDWARE_READ_PERIOD {
+            self.is_hardware_reading = false;
+            self.hardware_read_counter = 0;
+
+            self.is_latched = true;
+            self.sdlr = self.pad_sdlr();
+            self.sdhr = self.pad_sdhr();
+
+            // Any pressed button raises the key interrupt
+            let is_any_pressed = (self.sdlr & 0xfc) != 0 || self.sdhr != 0;
+            if is_any_pressed && !self.scr_k_int_inh {
+                self.key_int = true;
+            }
+        }
     }
 
     pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
@@ -104,4 +256,79 @@ impl GamePad {
             Button::RightDPadRight => self.right_d_pad_right_pressed = pressed,
         }
     }
+
+    /// The pressed state of every button, laid out as the pad reports it in SDHR (high byte)
+    /// and SDLR (low byte), with the signature and low battery bits cleared
+    pub fn buttons(&self) -> u16 {
+        (if self.right_d_pad_down_pressed { 1 } else { 0 } << 15) |
+        (if self.right_d_pad_left_pressed { 1 } else { 0 } << 14) |
+        (if self.select_pressed { 1 } else { 0 } << 13) |
+        (if self.start_pressed { 1 } else { 0 } << 12) |
+        (if self.left_d_pad_up_pressed { 1 } else { 0 } << 11) |
+        (if self.left_d_pad_down_pressed { 1 } else { 0 } << 10) |
+        (if self.left_d_pad_left_pressed { 1 } else { 0 } << 9) |
+        (if self.left_d_pad_right_pressed { 1 } else { 0 } << 8) |
+        (if self.right_d_pad_right_pressed { 1 } else { 0 } << 7) |
+        (if self.right_d_pad_up_pressed { 1 } else { 0 } << 6) |
+        (if self.l_pressed { 1 } else { 0 } << 5) |
+        (if self.r_pressed { 1 } else { 0 } << 4) |
+        (if self.b_pressed { 1 } else { 0 } << 3) |
+        (if self.a_pressed { 1 } else { 0 } << 2)
+    }
+
+    /// Sets the pressed state of every button from a value laid out like `buttons()` returns
+    pub fn set_buttons(&mut self, buttons: u16) {
+        self.right_d_pad_down_pressed = (buttons & (1 << 15)) != 0;
+        self.right_d_pad_left_pressed = (buttons & (1 << 14)) != 0;
+        self.select_pressed = (buttons & (1 << 13)) != 0;
+        self.start_pressed = (buttons & (1 << 12)) != 0;
+        self.left_d_pad_up_pressed = (buttons & (1 << 11)) != 0;
+        self.left_d_pad_down_pressed = (buttons & (1 << 10)) != 0;
+        self.left_d_pad_left_pressed = (buttons & (1 << 9)) != 0;
+        self.left_d_pad_right_pressed = (buttons & (1 << 8)) != 0;
+        self.right_d_pad_right_pressed = (buttons & (1 << 7)) != 0;
+        self.right_d_pad_up_pressed = (buttons & (1 << 6)) != 0;
+        self.l_pressed = (buttons & (1 << 5)) != 0;
+        self.r_pressed = (buttons & (1 << 4)) != 0;
+        self.b_pressed = (buttons & (1 << 3)) != 0;
+        self.a_pressed = (buttons & (1 << 2)) != 0;
+    }
+
+    /// Whether the pad reports a low battery in the next hardware read
+    pub fn is_low_battery(&self) -> bool {
+        self.is_low_battery
+    }
+
+    pub fn set_low_battery(&mut self, is_low_battery: bool) {
+        self.is_low_battery = is_low_battery;
+    }
+
+    /// Whether a pad is plugged in. While disconnected, hardware reads return all zeroes,
+    /// including the signature bit games use to detect the pad.
+    pub fn is_connected(&self) -> bool {
+        self.is_connected
+    }
+
+    pub fn set_connected(&mut self, is_connected: bool) {
+        self.is_connected = is_connected;
+    }
+
+    fn pad_sdlr(&self) -> u8 {
+        if !self.is_connected {
+            return 0;
+        }
+
+        let version = 1;
+        (self.buttons() as u8) |
+        (version << 1) |
+        if self.is_low_battery { 1 } else { 0 }
+    }
+
+    fn pad_sdhr(&self) -> u8 {
+        if !self.is_connected {
+            return 0;
+        }
+
+        (self.buttons() >> 8) as u8
+    }
 }
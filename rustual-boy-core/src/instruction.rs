@@ -43,6 +43,7 @@ pub const OPCODE_BITS_SHL_IMM: u16 = 0b010100;
 pub const OPCODE_BITS_SHR_IMM: u16 = 0b010101;
 pub const OPCODE_BITS_CLI: u16 = 0b010110;
 pub const OPCODE_BITS_SAR_IMM: u16 = 0b010111;
+pub const OPCODE_BITS_TRAP: u16 = 0b011000;
 pub const OPCODE_BITS_RETI: u16 = 0b011001;
 pub const OPCODE_BITS_HALT: u16 = 0b011010;
 pub const OPCODE_BITS_LDSR: u16 = 0b011100;
@@ -118,7 +119,7 @@ pub const OPCODE_CONDITION_BITS_F: u32 = 0x0d;
 pub const OPCODE_CONDITION_BITS_GE: u32 = 0x0e;
 pub const OPCODE_CONDITION_BITS_GT: u32 = 0x0f;
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     MovReg,
     AddReg,
@@ -144,6 +145,7 @@ pub enum Opcode {
     ShrImm,
     Cli,
     SarImm,
+    Trap,
     Reti,
     Halt,
     Ldsr,
@@ -191,7 +193,15 @@ pub enum Opcode {
 
 impl Opcode {
     pub fn from_halfword(halfword: u16) -> Opcode {
-        if halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
+        match Opcode::try_from_halfword(halfword) {
+            Some(opcode) => opcode,
+            None => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", halfword >> 10, halfword),
+        }
+    }
+
+    /// Like `from_halfword`, but returns `None` for unassigned opcodes instead of panicking
+    pub fn try_from_halfword(halfword: u16) -> Option<Opcode> {
+        Some(if halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
             let cond_bits = (halfword >> 9) & 0x0f;
             match cond_bits {
                 OPCODE_BITS_BCOND_BV => Opcode::Bv,
@@ -239,6 +249,7 @@ impl Opcode {
                 OPCODE_BITS_SHR_IMM => Opcode::ShrImm,
                 OPCODE_BITS_CLI => Opcode::Cli,
                 OPCODE_BITS_SAR_IMM => Opcode::SarImm,
+                OPCODE_BITS_TRAP => Opcode::Trap,
                 OPCODE_BITS_RETI => Opcode::Reti,
                 OPCODE_BITS_HALT => Opcode::Halt,
                 OPCODE_BITS_LDSR => Opcode::Ldsr,
@@ -266,9 +277,9 @@ impl Opcode {
                 OPCODE_BITS_OUTH => Opcode::Outh,
                 OPCODE_BITS_EXTENDED => Opcode::Extended,
                 OPCODE_BITS_OUTW => Opcode::Outw,
-                _ => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", opcode_bits, halfword),
+                _ => return None,
             }
-        }
+        })
     }
 
     pub fn instruction_format(&self) -> InstructionFormat {
@@ -297,6 +308,7 @@ impl Opcode {
             &Opcode::ShrImm => InstructionFormat::II,
             &Opcode::Cli => InstructionFormat::II,
             &Opcode::SarImm => InstructionFormat::II,
+            &Opcode::Trap => InstructionFormat::II,
             &Opcode::Reti => InstructionFormat::II,
             &Opcode::Halt => InstructionFormat::II,
             &Opcode::Ldsr => InstructionFormat::II,
@@ -344,7 +356,14 @@ impl Opcode {
     }
 
     pub fn bit_string_op(&self, bit_string_op: u32) -> BitStringOp {
-        match bit_string_op {
+        match self.try_bit_string_op(bit_string_op) {
+            Some(op) => op,
+            None => panic!("Unrecognized bit string op: {:05b}", bit_string_op),
+        }
+    }
+
+    pub fn try_bit_string_op(&self, bit_string_op: u32) -> Option<BitStringOp> {
+        Some(match bit_string_op {
             OPCODE_BITS_BIT_STRING_OP_ORBSU => BitStringOp::Orbsu,
             OPCODE_BITS_BIT_STRING_OP_ANDBSU => BitStringOp::Andbsu,
             OPCODE_BITS_BIT_STRING_OP_XORBSU => BitStringOp::Xorbsu,
@@ -353,12 +372,19 @@ impl Opcode {
             OPCODE_BITS_BIT_STRING_OP_ANDNBSU => BitStringOp::Andnbsu,
             OPCODE_BITS_BIT_STRING_OP_XORNBSU => BitStringOp::Xornbsu,
             OPCODE_BITS_BIT_STRING_OP_NOTBSU => BitStringOp::Notbsu,
-            _ => panic!("Unrecognized bit string op: {:05b}", bit_string_op)
-        }
+            _ => return None,
+        })
     }
 
     pub fn subop(&self, subop: u16) -> SubOp {
-        match subop {
+        match self.try_subop(subop) {
+            Some(subop) => subop,
+            None => panic!("Unrecognized subop bits: {:06b}", subop),
+        }
+    }
+
+    pub fn try_subop(&self, subop: u16) -> Option<SubOp> {
+        Some(match subop {
             OPCODE_BITS_SUB_OP_CMPF_S => SubOp::CmpfS,
             OPCODE_BITS_SUB_OP_CVT_WS => SubOp::CvtWs,
             OPCODE_BITS_SUB_OP_CVT_SW => SubOp::CvtSw,
@@ -371,8 +397,8 @@ impl Opcode {
             OPCODE_BITS_SUB_OP_REV => SubOp::Rev,
             OPCODE_BITS_SUB_OP_TRNC_SW => SubOp::TrncSw,
             OPCODE_BITS_SUB_OP_MPYHW => SubOp::Mpyhw,
-            _ => panic!("Unrecognized subop bits: {:06b}", subop),
-        }
+            _ => return None,
+        })
     }
 
     pub fn system_register(&self, imm5: u32) -> SystemRegister {
@@ -410,6 +436,7 @@ impl fmt::Display for Opcode {
             &Opcode::Not => "not",
             &Opcode::Setf => "setf",
             &Opcode::Cli => "cli",
+            &Opcode::Trap => "trap",
             &Opcode::Reti => "reti",
             &Opcode::Halt => "halt",
             &Opcode::Ldsr => "ldsr",
@@ -458,6 +485,7 @@ impl fmt::Display for Opcode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstructionFormat {
     I,
     II,
@@ -482,6 +510,70 @@ impl InstructionFormat {
     }
 }
 
+/// An instruction with its opcode, format and operands extracted from its halfwords, as
+/// executed by `V810::step`. Operands the instruction's format doesn't have are 0, and so
+/// is `second_halfword` for 16-bit instructions. Register indices are kept as `u8`s so
+/// that caching one of these per instruction in ROM stays reasonably cheap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub first_halfword: u16,
+    pub second_halfword: u16,
+    /// `None` for unassigned opcodes, which are decoded as format I instructions
+    pub opcode: Option<Opcode>,
+    pub format: InstructionFormat,
+    /// reg1 for formats I, V, VI and VII; the 5-bit immediate for format II
+    pub reg1: u8,
+    pub reg2: u8,
+    /// The sign-extended branch displacement of formats III and IV, with bit 0 cleared
+    pub disp: u32,
+    /// The immediate or displacement of formats V and VI
+    pub imm16: u16,
+    /// The sub-opcode of format VII
+    pub subop: u16,
+}
+
+impl DecodedInstruction {
+    pub fn new(first_halfword: u16, second_halfword: u16) -> DecodedInstruction {
+        let opcode = Opcode::try_from_halfword(first_halfword);
+        let format = match opcode {
+            Some(opcode) => opcode.instruction_format(),
+            None => InstructionFormat::I,
+        };
+        let second_halfword = if format.has_second_halfword() { second_halfword } else { 0 };
+
+        let (reg1, reg2) = match format {
+            InstructionFormat::III | InstructionFormat::IV => (0, 0),
+            _ => ((first_halfword & 0x1f) as u8, ((first_halfword >> 5) & 0x1f) as u8),
+        };
+        let disp = match format {
+            InstructionFormat::III => ((((first_halfword as i16) << 7) >> 7) as u32) & 0xfffffffe,
+            InstructionFormat::IV => ((((((first_halfword as i16) << 6) >> 6) as u32) << 16) | (second_halfword as u32)) & 0xfffffffe,
+            _ => 0,
+        };
+        let imm16 = match format {
+            InstructionFormat::V | InstructionFormat::VI => second_halfword,
+            _ => 0,
+        };
+        let subop = match format {
+            InstructionFormat::VII => second_halfword >> 10,
+            _ => 0,
+        };
+
+        DecodedInstruction {
+            first_halfword: first_halfword,
+            second_halfword: second_halfword,
+            opcode: opcode,
+            format: format,
+            reg1: reg1,
+            reg2: reg2,
+            disp: disp,
+            imm16: imm16,
+            subop: subop,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BitStringOp {
     Orbsu,
     Andbsu,
@@ -509,6 +601,7 @@ impl fmt::Display for BitStringOp {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SubOp {
     CmpfS,
     CvtWs,
@@ -544,6 +637,7 @@ impl fmt::Display for SubOp {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SystemRegister {
     Eipc,
     Eipsw,
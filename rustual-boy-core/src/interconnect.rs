@@ -1,14 +1,33 @@
 use com_port::*;
+use decode_cache::*;
+use emulation_error::*;
 use game_pad::*;
+use instruction::DecodedInstruction;
 use mem_map::*;
 use rom::*;
+use save_state::*;
 use sinks::*;
 use sram::*;
 use timer::*;
+use trace::*;
 use vip::*;
 use vsu::*;
 use wram::*;
 
+use std::io::{self, Read, Write};
+use std::mem;
+
+/// What to do when the CPU accesses an address nothing is mapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusErrorPolicy {
+    /// Reads return the given value, writes are ignored
+    OpenBus(u16),
+    /// Same as `OpenBus`, but each access is logged as well
+    Log(u16),
+    /// The access is recorded and the CPU stops at the faulting instruction
+    Stop,
+}
+
 pub struct Interconnect {
     rom: Rom,
     wram: Wram,
@@ -17,12 +36,34 @@ pub struct Interconnect {
     vsu: Vsu,
     timer: Timer,
     pub game_pad: GamePad,
-    pub com_port: ComPort,
+    com_port: ComPort,
+
+    bus_error_policy: BusErrorPolicy,
+    bus_error: Option<BusError>,
+
+    wcr_rom_1_wait: bool,
+    wcr_expansion_1_wait: bool,
+    wait_cycles: u32,
+
+    decode_cache: DecodeCache,
+    is_decode_cache_enabled: bool,
+
+    is_tracing_accesses: bool,
+    memory_accesses: Vec<MemoryAccess>,
+
+    // Cycles that haven't been applied to the link port, timer, VIP and VSU yet. These are
+    //  only applied when something is due to happen or when one of them is accessed, which
+    //  is much cheaper than stepping them after every instruction.
+    pending_cycles: u32,
+    next_event_cycles: u32,
+    is_skipping_events: bool,
 }
 
 impl Interconnect {
     pub fn new(rom: Rom, sram: Sram) -> Interconnect {
-        Interconnect {
+        let decode_cache = DecodeCache::new(rom.size());
+
+        let mut ret = Interconnect {
             rom: rom,
             wram: Wram::new(),
             sram: sram,
@@ -31,12 +72,350 @@ impl Interconnect {
             timer: Timer::new(),
             game_pad: GamePad::new(),
             com_port: ComPort::new(),
+
+            bus_error_policy: BusErrorPolicy::Stop,
+            bus_error: None,
+
+            wcr_rom_1_wait: false,
+            wcr_expansion_1_wait: false,
+            wait_cycles: 0,
+
+            decode_cache: decode_cache,
+            is_decode_cache_enabled: true,
+
+            is_tracing_accesses: false,
+            memory_accesses: Vec::new(),
+
+            pending_cycles: 0,
+            next_event_cycles: 0,
+            is_skipping_events: true,
+        };
+        ret.schedule_next_event();
+        ret
+    }
+
+    pub fn bus_error_policy(&self) -> BusErrorPolicy {
+        self.bus_error_policy
+    }
+
+    pub fn set_bus_error_policy(&mut self, bus_error_policy: BusErrorPolicy) {
+        self.bus_error_policy = bus_error_policy;
+    }
+
+    pub fn com_port(&self) -> &ComPort {
+        &self.com_port
+    }
+
+    /// Gives `f` the link port, to exchange the state of the cable with whatever is on the
+    /// other end. Pending cycles are applied first and the next event is rescheduled after,
+    /// so anything the exchange starts or finishes is seen by the event scheduler.
+    pub fn with_com_port<F, T>(&mut self, f: F) -> T where F: FnOnce(&mut ComPort) -> T {
+        self.apply_pending_cycles();
+        let ret = f(&mut self.com_port);
+        self.schedule_next_event();
+        ret
+    }
+
+    /// With event skipping disabled, the link port, game pad, timer, VIP and VSU are stepped
+    /// after every instruction instead of only when something is due to happen, and a halted
+    /// CPU waits one cycle at a time. Emulation is the same either way (just much slower
+    /// without skipping); this exists to check that it is. Skipping is enabled by default.
+    pub fn set_event_skipping(&mut self, enabled: bool) {
+        self.apply_pending_cycles();
+        self.is_skipping_events = enabled;
+        self.schedule_next_event();
+    }
+
+    /// True if a bus error has been recorded under `BusErrorPolicy::Stop` and not taken yet
+    pub fn has_bus_error(&self) -> bool {
+        self.bus_error.is_some()
+    }
+
+    /// Returns (and clears) the first bus error recorded under `BusErrorPolicy::Stop`
+    pub fn take_bus_error(&mut self) -> Option<BusError> {
+        self.bus_error.take()
+    }
+
+    /// Starts or stops recording every read and write for `take_memory_accesses`
+    pub fn set_access_tracing(&mut self, enabled: bool) {
+        self.is_tracing_accesses = enabled;
+        self.memory_accesses.clear();
+    }
+
+    /// Returns (and clears) the accesses recorded since the last call
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        mem::replace(&mut self.memory_accesses, Vec::new())
+    }
+
+    /// Returns (and clears) the wait cycles accumulated by memory accesses since the last call
+    pub fn take_wait_cycles(&mut self) -> u32 {
+        let ret = self.wait_cycles;
+        self.wait_cycles = 0;
+        ret
+    }
+
+    /// Turns the cache of decoded instructions used by `cached_instruction` on or off (it's
+    /// on by default). It only saves host work, so turning it off never changes the results.
+    pub fn set_decode_cache_enabled(&mut self, enabled: bool) {
+        self.is_decode_cache_enabled = enabled;
+        if !enabled {
+            self.decode_cache.clear();
         }
     }
 
-    pub fn read_byte(&mut self, addr: u32) -> u8 {
+    /// Returns how many instructions were found in the decode cache, and how many had to be
+    /// decoded instead, since power-on
+    pub fn decode_cache_stats(&self) -> (u64, u64) {
+        self.decode_cache.stats()
+    }
+
+    /// Returns the previously decoded instruction at `addr`, charging the same fetch wait
+    /// cycles as reading its halfwords with `read_halfword` would (see `access_wait_cycles`).
+    pub fn cached_instruction(&mut self, addr: u32) -> Option<DecodedInstruction> {
+        let cached = self.lookup_cached_instruction(addr);
+        self.record_decode_cache_lookup(cached.is_some());
+
+        cached.map(|instruction| {
+            let addr = addr & 0x07ffffff;
+            let num_fetches = if instruction.format.has_second_halfword() { 2 } else { 1 };
+            self.wait_cycles += self.access_wait_cycles(addr) * num_fetches;
+            instruction
+        })
+    }
+
+    /// Like `cached_instruction`, but for an instruction whose halfwords were already
+    /// fetched some other way (eg. from the instruction cache, which may not match memory):
+    /// the cached instruction is only returned if it was decoded from the same halfwords, and
+    /// no wait cycles are charged.
+    pub fn cached_instruction_matching(&mut self, addr: u32, first_halfword: u16, second_halfword: u16) -> Option<DecodedInstruction> {
+        let cached = self.lookup_cached_instruction(addr).and_then(|instruction| {
+            if instruction.first_halfword == first_halfword && instruction.second_halfword == second_halfword {
+                Some(instruction)
+            } else {
+                None
+            }
+        });
+        self.record_decode_cache_lookup(cached.is_some());
+        cached
+    }
+
+    /// Remembers an instruction decoded from halfwords fetched from `addr` for
+    /// `cached_instruction`. Only instructions in Game Pak ROM and WRAM are kept, and only
+    /// if memory still holds the halfwords they were decoded from (halfwords served by the
+    /// instruction cache may be stale).
+    pub fn insert_cached_instruction(&mut self, addr: u32, instruction: DecodedInstruction) {
+        if !self.is_decode_cache_enabled {
+            return;
+        }
+
+        let addr = addr & 0x07ffffff;
+
+        // Instructions that straddle the end of a region aren't worth the trouble
+        let is_32_bit = instruction.format.has_second_halfword();
+        if is_32_bit && (addr | 0x00ffffff) - addr < 3 {
+            return;
+        }
+
+        let matches = |first_halfword: u16, second_halfword: u16| {
+            first_halfword == instruction.first_halfword && (!is_32_bit || second_halfword == instruction.second_halfword)
+        };
+
+        match addr {
+            WRAM_START ... WRAM_END => {
+                let offset = addr - WRAM_START;
+                if matches(self.wram.read_halfword(offset), self.wram.read_halfword(offset + 2)) {
+                    self.decode_cache.insert_wram(offset, instruction);
+                }
+            }
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => {
+                let offset = addr - GAME_PAK_ROM_START;
+                if matches(self.rom.read_halfword(offset), self.rom.read_halfword(offset + 2)) {
+                    self.decode_cache.insert_rom(offset, instruction);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn lookup_cached_instruction(&self, addr: u32) -> Option<DecodedInstruction> {
+        if !self.is_decode_cache_enabled {
+            return None;
+        }
+
         let addr = addr & 0x07ffffff;
         match addr {
+            WRAM_START ... WRAM_END => self.decode_cache.wram(addr - WRAM_START),
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => self.decode_cache.rom(addr - GAME_PAK_ROM_START),
+            _ => None,
+        }
+    }
+
+    fn record_decode_cache_lookup(&mut self, is_hit: bool) {
+        if self.is_decode_cache_enabled {
+            self.decode_cache.record_lookup(is_hit);
+        }
+    }
+
+    // Wait states for a single 16-bit bus cycle to `addr`, added on top of the instruction
+    //  cycle counts in `V810::step`. Those counts come from NEC's V810 Family User's Manual
+    //  ("Instruction Execution Clock Count"), which assumes every access is a single bus
+    //  cycle with no waits. The Virtual Boy's data bus is only 16 bits wide, so word accesses
+    //  are split into two halfword bus cycles, each of which pays these waits; that's the only
+    //  extra cost of the split.
+    //
+    // Instruction fetches are bus cycles too, and this is the one place their timing is
+    //  defined: with the instruction cache disabled every fetched halfword pays these waits
+    //  (whether or not it actually comes from the decode cache, which only saves host work).
+    //  With the cache enabled, hits cost nothing and a miss pays for the two halfwords of the
+    //  subblock it fills.
+    //
+    // Game Pak ROM and expansion accesses take 2 waits, or 1 when selected in WCR, as described
+    //  in the Sacred Tech Scroll (Planet Virtual Boy's hardware documentation, "Wait Control
+    //  Register"). WRAM has no waits. The values for the VIP, VSU, hardware registers and Game
+    //  Pak RAM aren't documented anywhere we know of; they're estimates until someone measures
+    //  them on hardware.
+    fn access_wait_cycles(&self, addr: u32) -> u32 {
+        match addr {
+            VIP_START ... VIP_END => self.vip.access_wait_cycles(addr - VIP_START),
+            VSU_START ... VSU_END => 1,
+            CCR ... SCR => 1,
+            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => if self.wcr_expansion_1_wait { 1 } else { 2 },
+            WRAM_START ... WRAM_END => 0,
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END => 1,
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => if self.wcr_rom_1_wait { 1 } else { 2 },
+            _ => 0,
+        }
+    }
+
+    /// Whether anything on the bus decodes `addr`, ie. whether accessing it is not a bus error
+    pub fn is_mapped(addr: u32) -> bool {
+        match addr & 0x07ffffff {
+            VIP_START ... VIP_END | VSU_START ... VSU_END |
+            CCR | CCSR | CDTR | CDRR | SDLR | SDHR | TLR | THR | TCR | WCR | SCR |
+            GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END | WRAM_START ... WRAM_END |
+            GAME_PAK_RAM_START ... GAME_PAK_RAM_END | GAME_PAK_ROM_START ... GAME_PAK_ROM_END => true,
+            _ => false,
+        }
+    }
+
+    fn is_scheduled_addr(addr: u32) -> bool {
+        match addr {
+            VIP_START ... VIP_END | VSU_START ... VSU_END | CCR | CCSR | CDTR | CDRR | SDLR | SDHR | TLR | THR | TCR | SCR => true,
+            _ => false,
+        }
+    }
+
+    // Pending cycles never cross an event, so they can simply be skipped
+    fn apply_pending_cycles(&mut self) {
+        let cycles = self.pending_cycles;
+        if cycles > 0 {
+            self.pending_cycles = 0;
+            self.next_event_cycles -= cycles;
+
+            self.com_port.skip_cycles(cycles);
+            self.game_pad.skip_cycles(cycles);
+            self.timer.skip_cycles(cycles);
+            self.vip.skip_cycles(cycles);
+            self.vsu.skip_cycles(cycles);
+        }
+    }
+
+    fn schedule_next_event(&mut self) {
+        if !self.is_skipping_events {
+            self.next_event_cycles = 0;
+            return;
+        }
+
+        self.next_event_cycles = self.com_port.cycles_until_next_event()
+            .min(self.game_pad.cycles_until_next_event())
+            .min(self.timer.cycles_until_next_event())
+            .min(self.vip.cycles_until_next_event())
+            .min(self.vsu.cycles_until_next_event());
+    }
+
+    fn read_wcr(&self) -> u8 {
+        0xfc |
+        (if self.wcr_expansion_1_wait { 0x02 } else { 0 }) |
+        (if self.wcr_rom_1_wait { 0x01 } else { 0 })
+    }
+
+    fn write_wcr(&mut self, value: u8) {
+        self.wcr_rom_1_wait = (value & 0x01) != 0;
+        self.wcr_expansion_1_wait = (value & 0x02) != 0;
+        logln!(Log::Ic, "WCR written: 0x{:02x}", value);
+        logln!(Log::Ic, " Game Pak ROM Waits: {}", if self.wcr_rom_1_wait { 1 } else { 2 });
+        logln!(Log::Ic, " Game Pak Expansion Waits: {}", if self.wcr_expansion_1_wait { 1 } else { 2 });
+    }
+
+    fn trace_access(&mut self, addr: u32, kind: AccessKind, width: AccessWidth, value: u32) {
+        if self.is_tracing_accesses {
+            self.memory_accesses.push(MemoryAccess {
+                addr: addr,
+                kind: kind,
+                width: width,
+                value: value,
+            });
+        }
+    }
+
+    fn unmapped_access(&mut self, addr: u32, kind: AccessKind, width: AccessWidth) -> u16 {
+        let bus_error = BusError {
+            addr: addr,
+            kind: kind,
+            width: width,
+        };
+
+        match self.bus_error_policy {
+            BusErrorPolicy::OpenBus(value) => value,
+            BusErrorPolicy::Log(value) => {
+                logln!(Log::Ic, "WARNING: Bus error: {}", bus_error);
+                value
+            }
+            BusErrorPolicy::Stop => {
+                if self.bus_error.is_none() {
+                    self.bus_error = Some(bus_error);
+                }
+                0
+            }
+        }
+    }
+
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        self.wram.save_state(w)?;
+        self.sram.save_state(w)?;
+        self.vip.save_state(w)?;
+        self.vsu.save_state(w)?;
+        self.timer.save_state(w)?;
+        self.game_pad.save_state(w)?;
+        self.com_port.save_state(w)?;
+        write_u8(w, self.read_wcr())?;
+        write_u32(w, self.pending_cycles)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.wram.load_state(r)?;
+        self.decode_cache.clear_wram();
+        self.sram.load_state(r)?;
+        self.vip.load_state(r)?;
+        self.vsu.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.game_pad.load_state(r)?;
+        self.com_port.load_state(r)?;
+        let wcr = read_u8(r)?;
+        self.write_wcr(wcr);
+        self.pending_cycles = read_u32(r)?;
+        self.schedule_next_event();
+        Ok(())
+    }
+
+    pub fn read_byte(&mut self, addr: u32) -> u8 {
+        let addr = addr & 0x07ffffff;
+        self.wait_cycles += self.access_wait_cycles(addr);
+        if Interconnect::is_scheduled_addr(addr) {
+            self.apply_pending_cycles();
+        }
+
+        let value = match addr {
             VIP_START ... VIP_END => self.vip.read_byte(addr - VIP_START),
             VSU_START ... VSU_END => self.vsu.read_byte(addr - VSU_START),
             CCR => self.com_port.read_ccr(),
@@ -48,10 +427,7 @@ impl Interconnect {
             TLR => self.timer.read_tlr(),
             THR => self.timer.read_thr(),
             TCR => self.timer.read_tcr(),
-            WCR => {
-                logln!(Log::Ic, "WARNING: Read byte from WCR not yet implemented");
-                0
-            }
+            WCR => self.read_wcr(),
             SCR => self.game_pad.read_scr(),
             GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
                 logln!(Log::Ic, "WARNING: Read byte from Game Pak Expansion not yet implemented (addr: 0x{:08x})", addr - GAME_PAK_EXPANSION_START);
@@ -60,14 +436,23 @@ impl Interconnect {
             WRAM_START ... WRAM_END => self.wram.read_byte(addr - WRAM_START),
             GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.read_byte(addr - GAME_PAK_RAM_START),
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => self.rom.read_byte(addr - GAME_PAK_ROM_START),
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
-        }
+            _ => self.unmapped_access(addr, AccessKind::Read, AccessWidth::Byte) as _
+        };
+
+        self.trace_access(addr, AccessKind::Read, AccessWidth::Byte, value as _);
+
+        value
     }
 
     pub fn read_halfword(&mut self, addr: u32) -> u16 {
         let addr = addr & 0x07ffffff;
         let addr = addr & 0xfffffffe;
-        match addr {
+        self.wait_cycles += self.access_wait_cycles(addr);
+        if Interconnect::is_scheduled_addr(addr) {
+            self.apply_pending_cycles();
+        }
+
+        let value = match addr {
             VIP_START ... VIP_END => self.vip.read_halfword(addr - VIP_START),
             VSU_START ... VSU_END => self.vsu.read_halfword(addr - VSU_START),
             CCR => self.com_port.read_ccr() as _,
@@ -79,10 +464,7 @@ impl Interconnect {
             TLR => self.timer.read_tlr() as _,
             THR => self.timer.read_thr() as _,
             TCR => self.timer.read_tcr() as _,
-            WCR => {
-                logln!(Log::Ic, "Read halfword from WCR not yet implemented");
-                0
-            }
+            WCR => self.read_wcr() as _,
             SCR => self.game_pad.read_scr() as _,
             GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
                 logln!(Log::Ic, "WARNING: Read halfword from Game Pak Expansion not yet implemented (addr: 0x{:08x})", addr - GAME_PAK_EXPANSION_START);
@@ -91,12 +473,22 @@ impl Interconnect {
             WRAM_START ... WRAM_END => self.wram.read_halfword(addr - WRAM_START),
             GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.read_halfword(addr - GAME_PAK_RAM_START),
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => self.rom.read_halfword(addr - GAME_PAK_ROM_START),
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
-        }
+            _ => self.unmapped_access(addr, AccessKind::Read, AccessWidth::Halfword)
+        };
+
+        self.trace_access(addr, AccessKind::Read, AccessWidth::Halfword, value as _);
+
+        value
     }
 
     pub fn write_byte(&mut self, addr: u32, value: u8) {
         let addr = addr & 0x07ffffff;
+        self.wait_cycles += self.access_wait_cycles(addr);
+        let is_scheduled_addr = Interconnect::is_scheduled_addr(addr);
+        if is_scheduled_addr {
+            self.apply_pending_cycles();
+        }
+
         match addr {
             VIP_START ... VIP_END => self.vip.write_byte(addr - VIP_START, value),
             VSU_START ... VSU_END => self.vsu.write_byte(addr - VSU_START, value),
@@ -115,27 +507,78 @@ impl Interconnect {
             TLR => self.timer.write_tlr(value),
             THR => self.timer.write_thr(value),
             TCR => self.timer.write_tcr(value),
-            WCR => {
-                logln!(Log::Ic, "WCR (0x{:08x}) written: 0x{:02x}", addr, value);
-                logln!(Log::Ic, " Game Pak ROM Waits: {}", if value & 0x01 == 0 { 2 } else { 1 });
-                logln!(Log::Ic, " Game Pak Expansion Waits: {}", if value & 0x02 == 0 { 2 } else { 1 });
-            }
+            WCR => self.write_wcr(value),
             SCR => self.game_pad.write_scr(value),
             GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
                 logln!(Log::Ic, "WARNING: Write byte to Game Pak Expansion not yet implemented (addr: 0x{:08x}, value: 0x{:02x})", addr - GAME_PAK_EXPANSION_START, value);
             }
-            WRAM_START ... WRAM_END => self.wram.write_byte(addr - WRAM_START, value),
+            WRAM_START ... WRAM_END => {
+                self.wram.write_byte(addr - WRAM_START, value);
+                self.decode_cache.invalidate_wram(addr - WRAM_START);
+            }
             GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.write_byte(addr - GAME_PAK_RAM_START, value),
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => {
                 logln!(Log::Ic, "WARNING: Attempted write to Game Pak ROM at 0x{:08x}", addr - GAME_PAK_ROM_START);
             }
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => {
+                self.unmapped_access(addr, AccessKind::Write, AccessWidth::Byte);
+            }
+        }
+
+        self.trace_access(addr, AccessKind::Write, AccessWidth::Byte, value as _);
+
+        if is_scheduled_addr {
+            self.schedule_next_event();
+        }
+    }
+
+    /// Writes a byte on behalf of a debugger: Game Pak ROM is patched rather than left
+    /// alone, and no wait cycles are charged.
+    pub fn patch_byte(&mut self, addr: u32, value: u8) {
+        let addr = addr & 0x07ffffff;
+        match addr {
+            GAME_PAK_ROM_START ... GAME_PAK_ROM_END => {
+                self.rom.write_byte(addr - GAME_PAK_ROM_START, value);
+                self.decode_cache.invalidate_rom(addr - GAME_PAK_ROM_START);
+            }
+            _ => {
+                let wait_cycles = self.wait_cycles;
+                self.write_byte(addr, value);
+                self.wait_cycles = wait_cycles;
+            }
         }
     }
 
+    /// Reads a byte on behalf of a debugger: no wait cycles are charged, and reading
+    /// unmapped memory isn't recorded as a bus error. Nor is the access traced.
+    pub fn peek_byte(&mut self, addr: u32) -> u8 {
+        let (wait_cycles, bus_error, num_accesses) = (self.wait_cycles, self.bus_error, self.memory_accesses.len());
+        let value = self.read_byte(addr);
+        self.wait_cycles = wait_cycles;
+        self.bus_error = bus_error;
+        self.memory_accesses.truncate(num_accesses);
+        value
+    }
+
+    /// Reads a halfword the same way as `peek_byte`
+    pub fn peek_halfword(&mut self, addr: u32) -> u16 {
+        let (wait_cycles, bus_error, num_accesses) = (self.wait_cycles, self.bus_error, self.memory_accesses.len());
+        let value = self.read_halfword(addr);
+        self.wait_cycles = wait_cycles;
+        self.bus_error = bus_error;
+        self.memory_accesses.truncate(num_accesses);
+        value
+    }
+
     pub fn write_halfword(&mut self, addr: u32, value: u16) {
         let addr = addr & 0x07ffffff;
         let addr = addr & 0xfffffffe;
+        self.wait_cycles += self.access_wait_cycles(addr);
+        let is_scheduled_addr = Interconnect::is_scheduled_addr(addr);
+        if is_scheduled_addr {
+            self.apply_pending_cycles();
+        }
+
         match addr {
             VIP_START ... VIP_END => self.vip.write_halfword(addr - VIP_START, value),
             VSU_START ... VSU_END => self.vsu.write_halfword(addr - VSU_START, value),
@@ -154,34 +597,69 @@ impl Interconnect {
             TLR => self.timer.write_tlr(value as _),
             THR => self.timer.write_thr(value as _),
             TCR => self.timer.write_tcr(value as _),
-            WCR => {
-                logln!(Log::Ic, "WARNING: Write halfword to WCR not yet implemented (value: 0x{:04x})", value);
-            }
+            WCR => self.write_wcr(value as _),
             SCR => self.game_pad.write_scr(value as _),
             GAME_PAK_EXPANSION_START ... GAME_PAK_EXPANSION_END => {
                 logln!(Log::Ic, "WARNING: Write halfword to Game Pak Expansion not yet implemented (addr: 0x{:08x}, value: 0x{:04x})", addr - GAME_PAK_EXPANSION_START, value);
             }
-            WRAM_START ... WRAM_END => self.wram.write_halfword(addr - WRAM_START, value),
+            WRAM_START ... WRAM_END => {
+                self.wram.write_halfword(addr - WRAM_START, value);
+                self.decode_cache.invalidate_wram(addr - WRAM_START);
+            }
             GAME_PAK_RAM_START ... GAME_PAK_RAM_END => self.sram.write_halfword(addr - GAME_PAK_RAM_START, value),
             GAME_PAK_ROM_START ... GAME_PAK_ROM_END => {
                 logln!(Log::Ic, "WARNING: Attempted write to Game Pak ROM at 0x{:08x}", addr - GAME_PAK_ROM_START);
             }
-            _ => panic!("Unrecognized addr: 0x{:08x}", addr)
+            _ => {
+                self.unmapped_access(addr, AccessKind::Write, AccessWidth::Halfword);
+            }
         }
+
+        self.trace_access(addr, AccessKind::Write, AccessWidth::Halfword, value as _);
+
+        if is_scheduled_addr {
+            self.schedule_next_event();
+        }
+    }
+
+    /// Number of cycles `cycles` can be called with before the link port, timer, VIP or VSU does
+    /// something (such as raising an interrupt) on the last of them
+    pub fn cycles_until_next_event(&self) -> u32 {
+        self.next_event_cycles - self.pending_cycles + 1
     }
 
     pub fn cycles(&mut self, cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Option<u16> {
+        self.pending_cycles += cycles;
+        if self.pending_cycles > self.next_event_cycles {
+            let cycles = self.pending_cycles;
+            self.pending_cycles = 0;
+
+            self.com_port.cycles(cycles);
+            self.game_pad.cycles(cycles);
+            self.timer.cycles(cycles);
+            self.vip.cycles(cycles, video_frame_sink);
+            self.vsu.cycles(cycles, audio_frame_sink);
+
+            self.schedule_next_event();
+        }
+
         let mut interrupt = None;
 
-        if self.timer.cycles(cycles) {
+        if self.game_pad.interrupt_pending() {
+            interrupt = Some(0xfe00);
+        }
+
+        if self.timer.interrupt_pending() {
             interrupt = Some(0xfe10);
         }
 
-        if self.vip.cycles(cycles, video_frame_sink) {
-            interrupt = Some(0xfe40);
+        if self.com_port.interrupt_pending() {
+            interrupt = Some(0xfe30);
         }
 
-        self.vsu.cycles(cycles, audio_frame_sink);
+        if self.vip.interrupt_pending() {
+            interrupt = Some(0xfe40);
+        }
 
         interrupt
     }
@@ -1,22 +1,31 @@
 extern crate encoding;
 
+mod decode_cache;
 #[macro_use]
 mod logging;
 mod mem_map;
+mod save_state;
 
+pub mod assembler;
+pub mod call_stack;
 pub mod com_port;
+pub mod disassembler;
+pub mod emulation_error;
 pub mod game_pad;
 pub mod instruction;
 pub mod interconnect;
+pub mod link_cable;
 pub mod rom;
 pub mod sinks;
 pub mod sram;
 pub mod time_source;
 pub mod timer;
+pub mod trace;
 pub mod v810;
 pub mod vip;
 pub mod virtual_boy;
 pub mod vsu;
+pub mod watchpoint;
 pub mod wram;
 
 pub use rom::*;
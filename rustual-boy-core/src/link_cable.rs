@@ -0,0 +1,91 @@
+use emulation_error::*;
+use sinks::*;
+use virtual_boy::*;
+
+// 20mhz / (1s / 10us) = 200 clocks, the length of a single bit on the link port
+const LOCKSTEP_PERIOD: u64 = 200;
+
+/// Two `VirtualBoy`s with their link ports connected to each other. Both units
+/// are run in lockstep, and the state of the cable is exchanged between them
+/// every `LOCKSTEP_PERIOD` cycles.
+pub struct LinkCable {
+    pub a: VirtualBoy,
+    pub b: VirtualBoy,
+
+    // Number of cycles each unit has run past the last sync point
+    overshoot_a: u64,
+    overshoot_b: u64,
+}
+
+impl LinkCable {
+    pub fn new(mut a: VirtualBoy, mut b: VirtualBoy) -> LinkCable {
+        a.interconnect.with_com_port(|com_port| com_port.set_is_connected(true));
+        b.interconnect.with_com_port(|com_port| com_port.set_is_connected(true));
+
+        LinkCable {
+            a: a,
+            b: b,
+
+            overshoot_a: 0,
+            overshoot_b: 0,
+        }
+    }
+
+    /// Unplugs the cable, returning both units
+    pub fn disconnect(mut self) -> (VirtualBoy, VirtualBoy) {
+        self.a.interconnect.with_com_port(|com_port| com_port.set_is_connected(false));
+        self.b.interconnect.with_com_port(|com_port| com_port.set_is_connected(false));
+
+        (self.a, self.b)
+    }
+
+    /// Runs both units for (at least) `num_cycles` cycles. Watchpoints don't
+    /// stop emulation here.
+    pub fn run_cycles(&mut self, num_cycles: u64,
+                      a_video_frame_sink: &mut Sink<VideoFrame>, a_audio_frame_sink: &mut Sink<AudioFrame>,
+                      b_video_frame_sink: &mut Sink<VideoFrame>, b_audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(), EmulationError> {
+        let mut cycles = 0;
+        while cycles < num_cycles {
+            self.overshoot_a = run_slice(&mut self.a, self.overshoot_a, a_video_frame_sink, a_audio_frame_sink)?;
+            self.overshoot_b = run_slice(&mut self.b, self.overshoot_b, b_video_frame_sink, b_audio_frame_sink)?;
+            cycles += LOCKSTEP_PERIOD;
+
+            self.sync();
+        }
+
+        Ok(())
+    }
+
+    fn sync(&mut self) {
+        let b_interconnect = &mut self.b.interconnect;
+        self.a.interconnect.with_com_port(|a_com_port| b_interconnect.with_com_port(|b_com_port| {
+            // COMCNT is open-drain, so either unit can pull it low
+            let level = a_com_port.comcnt_output() && b_com_port.comcnt_output();
+            a_com_port.set_comcnt_level(level);
+            b_com_port.set_comcnt_level(level);
+
+            if a_com_port.is_awaiting_exchange() {
+                a_com_port.exchange_master_transfer(|bit| b_com_port.transfer_slave_clock_bit(bit));
+            }
+
+            if b_com_port.is_awaiting_exchange() {
+                b_com_port.exchange_master_transfer(|bit| a_com_port.transfer_slave_clock_bit(bit));
+            }
+        }));
+    }
+}
+
+// Runs one lockstep period, minus whatever the last one overshot by. Returns the new overshoot.
+fn run_slice(virtual_boy: &mut VirtualBoy, overshoot: u64, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<u64, EmulationError> {
+    if overshoot >= LOCKSTEP_PERIOD {
+        return Ok(overshoot - LOCKSTEP_PERIOD);
+    }
+
+    let target_cycles = LOCKSTEP_PERIOD - overshoot;
+    let mut cycles = 0;
+    while cycles < target_cycles {
+        cycles += virtual_boy.run_cycles(target_cycles - cycles, video_frame_sink, audio_frame_sink)?.cycles;
+    }
+
+    Ok(cycles - target_cycles)
+}
@@ -62,6 +62,15 @@ impl Rom {
         }
     }
 
+    /// Patches the loaded ROM image. The Game Pak itself is read-only, so this is
+    /// only meant for debugging.
+    pub fn write_byte(&mut self, addr: u32, value: u8) {
+        let addr = self.mask_addr(addr);
+        unsafe {
+            *self.bytes_ptr.offset(addr as _) = value;
+        }
+    }
+
     fn mask_addr(&self, addr: u32) -> u32 {
         let mask = (self.bytes.len() - 1) as u32;
         addr & mask
@@ -0,0 +1,65 @@
+use std::io::{self, Read, Write, Error, ErrorKind};
+
+// All multi-byte values are stored little-endian, matching the rest of the machine.
+
+pub fn write_u8(w: &mut Write, value: u8) -> io::Result<()> {
+    w.write_all(&[value])
+}
+
+pub fn write_bool(w: &mut Write, value: bool) -> io::Result<()> {
+    write_u8(w, if value { 1 } else { 0 })
+}
+
+pub fn write_u16(w: &mut Write, value: u16) -> io::Result<()> {
+    w.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+pub fn write_u32(w: &mut Write, value: u32) -> io::Result<()> {
+    write_u16(w, value as _)?;
+    write_u16(w, (value >> 16) as _)
+}
+
+pub fn write_u64(w: &mut Write, value: u64) -> io::Result<()> {
+    write_u32(w, value as _)?;
+    write_u32(w, (value >> 32) as _)
+}
+
+pub fn write_bytes(w: &mut Write, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(bytes)
+}
+
+pub fn read_u8(r: &mut Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn read_bool(r: &mut Read) -> io::Result<bool> {
+    match read_u8(r)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(Error::new(ErrorKind::InvalidData, "Invalid bool value in save state")),
+    }
+}
+
+pub fn read_u16(r: &mut Read) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok((buf[0] as u16) | ((buf[1] as u16) << 8))
+}
+
+pub fn read_u32(r: &mut Read) -> io::Result<u32> {
+    let low = read_u16(r)? as u32;
+    let high = read_u16(r)? as u32;
+    Ok(low | (high << 16))
+}
+
+pub fn read_u64(r: &mut Read) -> io::Result<u64> {
+    let low = read_u32(r)? as u64;
+    let high = read_u32(r)? as u64;
+    Ok(low | (high << 32))
+}
+
+pub fn read_bytes(r: &mut Read, bytes: &mut [u8]) -> io::Result<()> {
+    r.read_exact(bytes)
+}
@@ -17,3 +17,31 @@ pub type VideoFrame = (Box<[u8]>, Box<[u8]>);
 
 /// A frame of audio (left, right).
 pub type AudioFrame = (i16, i16);
+
+/// Forwards video frames to an inner sink, noting whether one was emitted.
+/// Useful for stepping the CPU until the end of a frame.
+pub struct FrameDetectingSink<'a> {
+    inner: &'a mut Sink<VideoFrame>,
+    frame_emitted: bool,
+}
+
+impl<'a> FrameDetectingSink<'a> {
+    pub fn new(inner: &'a mut Sink<VideoFrame>) -> FrameDetectingSink<'a> {
+        FrameDetectingSink {
+            inner: inner,
+            frame_emitted: false,
+        }
+    }
+
+    /// True if a frame has been appended since this sink was created
+    pub fn frame_emitted(&self) -> bool {
+        self.frame_emitted
+    }
+}
+
+impl<'a> Sink<VideoFrame> for FrameDetectingSink<'a> {
+    fn append(&mut self, frame: VideoFrame) {
+        self.frame_emitted = true;
+        self.inner.append(frame);
+    }
+}
@@ -1,3 +1,5 @@
+use save_state::*;
+
 use std::io::{self, Read, Write, Error, ErrorKind};
 use std::fs::File;
 use std::path::Path;
@@ -55,6 +57,31 @@ impl Sram {
         self.size
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_u32(w, self.size as _)?;
+        write_bytes(w, &self.bytes[..self.size])
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        let size = read_u32(r)? as usize;
+        if size != 0 && (size < MIN_SRAM_SIZE || size > MAX_SRAM_SIZE || !size.is_power_of_two()) {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid SRAM size in save state"));
+        }
+
+        if size > self.bytes.len() {
+            self.bytes = vec![0xff; MAX_SRAM_SIZE].into_boxed_slice();
+            self.bytes_ptr = self.bytes.as_mut_ptr();
+        }
+
+        read_bytes(r, &mut self.bytes[..size])?;
+        for byte in self.bytes[size..].iter_mut() {
+            *byte = 0xff;
+        }
+        self.size = size;
+
+        Ok(())
+    }
+
     pub fn read_byte(&mut self, addr: u32) -> u8 {
         let addr = self.mask_addr(addr);
         unsafe {
@@ -1,3 +1,7 @@
+use save_state::*;
+
+use std::io::{self, Read, Write};
+
 // 20mhz / (1s / 100us) = 2000
 const LARGE_INTERVAL_PERIOD: u32 = 2000;
 
@@ -81,31 +85,153 @@ impl Timer {
         self.counter = self.reload;
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bool(w, match self.t_clk_sel {
+            Interval::Large => false,
+            Interval::Small => true,
+        })?;
+        write_bool(w, self.tim_z_int)?;
+        write_bool(w, self.z_stat)?;
+        write_bool(w, self.t_enb)?;
+        write_u16(w, self.reload)?;
+        write_u16(w, self.counter)?;
+
+        write_u32(w, self.tick_counter)?;
+        write_bool(w, self.zero_interrupt)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.t_clk_sel = if read_bool(r)? { Interval::Small } else { Interval::Large };
+        self.tim_z_int = read_bool(r)?;
+        self.z_stat = read_bool(r)?;
+        self.t_enb = read_bool(r)?;
+        self.reload = read_u16(r)?;
+        self.counter = read_u16(r)?;
+
+        self.tick_counter = read_u32(r)?;
+        self.zero_interrupt = read_bool(r)?;
+
+        Ok(())
+    }
+
+    /// Number of cycles that can pass before the timer next ticks
+    pub fn cycles_until_next_event(&self) -> u32 {
+        if !self.t_enb {
+            return u32::max_value();
+        }
+
+        self.tick_period().saturating_sub(self.tick_counter + 1)
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.zero_interrupt
+    }
+
     pub fn cycles(&mut self, cycles: u32) -> bool {
         if self.t_enb {
-            for _ in 0..cycles {
-                let tick_period = match self.t_clk_sel {
-                    Interval::Large => LARGE_INTERVAL_PERIOD,
-                    Interval::Small => SMALL_INTERVAL_PERIOD,
-                };
-                self.tick_counter += 1;
-                if self.tick_counter >= tick_period {
-                    self.tick_counter = 0;
-
-                    self.counter = match self.counter {
-                        0 => {
-                            self.z_stat = true;
-                            if self.tim_z_int {
-                                self.zero_interrupt = true;
-                            }
-                            self.reload
-                        }
-                        _ => self.counter - 1
-                    };
+            let mut remaining_cycles = cycles;
+            while remaining_cycles > 0 {
+                // Skip straight to the cycle where the next tick happens
+                let skipped_cycles = self.cycles_until_next_event().min(remaining_cycles);
+                self.skip_cycles(skipped_cycles);
+                remaining_cycles -= skipped_cycles;
+
+                if remaining_cycles > 0 {
+                    self.cycle();
+                    remaining_cycles -= 1;
                 }
             }
         }
 
         self.zero_interrupt
     }
+
+    // The original cycle-by-cycle loop, kept as a reference for `cycles` to be checked against
+    #[cfg(test)]
+    fn reference_cycles(&mut self, cycles: u32) -> bool {
+        if self.t_enb {
+            for _ in 0..cycles {
+                self.cycle();
+            }
+        }
+
+        self.zero_interrupt
+    }
+
+    /// Advances the timer without ticking. `cycles` must not be more than `cycles_until_next_event()`.
+    pub fn skip_cycles(&mut self, cycles: u32) {
+        if self.t_enb {
+            self.tick_counter += cycles;
+        }
+    }
+
+    fn tick_period(&self) -> u32 {
+        match self.t_clk_sel {
+            Interval::Large => LARGE_INTERVAL_PERIOD,
+            Interval::Small => SMALL_INTERVAL_PERIOD,
+        }
+    }
+
+    fn cycle(&mut self) {
+        let tick_period = self.tick_period();
+        self.tick_counter += 1;
+        if self.tick_counter >= tick_period {
+            self.tick_counter = 0;
+
+            self.counter = match self.counter {
+                0 => {
+                    self.z_stat = true;
+                    if self.tim_z_int {
+                        self.zero_interrupt = true;
+                    }
+                    self.reload
+                }
+                _ => self.counter - 1
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Chosen to land both on and either side of tick boundaries
+    const CHUNK_SIZES: [u32; 13] = [1, 2, 3, 7, 399, 400, 401, 1999, 2000, 2001, 4800, 65536, 100000];
+
+    fn state(timer: &Timer) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        timer.save_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn configure(timer: &mut Timer, reload: u16, tcr: u8) {
+        timer.write_tcr(0x04);
+        timer.write_tlr(reload as _);
+        timer.write_thr((reload >> 8) as _);
+        timer.write_tcr(tcr);
+    }
+
+    #[test]
+    fn cycles_matches_reference() {
+        let (mut timer, mut reference) = (Timer::new(), Timer::new());
+
+        // Small and large intervals, with and without the zero interrupt, and disabled
+        for &(reload, tcr) in [(0, 0x19), (1, 0x19), (5, 0x09), (300, 0x11), (2, 0x01), (3, 0x18)].iter() {
+            configure(&mut timer, reload, tcr);
+            configure(&mut reference, reload, tcr);
+
+            for &chunk_size in CHUNK_SIZES.iter() {
+                let interrupt = timer.cycles(chunk_size);
+                assert_eq!(interrupt, reference.reference_cycles(chunk_size));
+                assert_eq!(state(&timer), state(&reference));
+
+                // Acknowledge the interrupt like a handler would
+                if interrupt {
+                    timer.write_tcr(tcr | 0x04);
+                    reference.write_tcr(tcr | 0x04);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,84 @@
+use emulation_error::{AccessKind, AccessWidth};
+use instruction::SystemRegister;
+
+use std::fmt;
+
+/// A single access made over the bus, as recorded by `Interconnect` while access
+/// tracing is enabled. `addr` is the address as seen on the bus, with the upper
+/// address bits already dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u32,
+    pub kind: AccessKind,
+    pub width: AccessWidth,
+    pub value: u32,
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, arrow) = match self.kind {
+            AccessKind::Read => ("read", "->"),
+            AccessKind::Write => ("write", "<-"),
+        };
+        match self.width {
+            AccessWidth::Byte => write!(f, "{} byte [0x{:08x}] {} 0x{:02x}", kind, self.addr, arrow, self.value),
+            AccessWidth::Halfword => write!(f, "{} halfword [0x{:08x}] {} 0x{:04x}", kind, self.addr, arrow, self.value),
+            AccessWidth::Word => write!(f, "{} word [0x{:08x}] {} 0x{:08x}", kind, self.addr, arrow, self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceRegister {
+    Gpr(usize),
+    System(SystemRegister),
+}
+
+impl fmt::Display for TraceRegister {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TraceRegister::Gpr(index) => write!(f, "r{}", index),
+            TraceRegister::System(ref system_register) => write!(f, "{}", system_register),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: TraceRegister,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: 0x{:08x} -> 0x{:08x}", self.register, self.old_value, self.new_value)
+    }
+}
+
+/// Everything a single instruction did, recorded by `V810::step` while tracing is
+/// enabled (see `VirtualBoy::set_tracing`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub first_halfword: u16,
+    /// Only meaningful for 32-bit instructions
+    pub second_halfword: u16,
+    /// Registers the instruction changed, not counting `pc`. This includes the system
+    /// registers changed by any exception it raised.
+    pub register_changes: Vec<RegisterChange>,
+    /// Bus accesses made by the instruction, not counting the instruction fetch
+    pub memory_accesses: Vec<MemoryAccess>,
+}
+
+impl TraceRecord {
+    /// The instruction's bytes as they're laid out in memory, eg. for `disassemble`
+    pub fn bytes(&self) -> [u8; 4] {
+        [
+            self.first_halfword as u8,
+            (self.first_halfword >> 8) as u8,
+            self.second_halfword as u8,
+            (self.second_halfword >> 8) as u8,
+        ]
+    }
+}
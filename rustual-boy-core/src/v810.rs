@@ -1,14 +1,21 @@
+use call_stack::*;
+use decode_cache::is_32_bit_instruction;
+use emulation_error::*;
 use instruction::*;
 use interconnect::*;
+use save_state::*;
+use trace::*;
+use watchpoint::*;
 
-use std::collections::HashSet;
 use std::fmt;
+use std::io::{self, Read, Write};
 
 #[derive(Copy, Clone, Default)]
 pub struct CacheEntry {
     pub tag: u32,
     pub base_addr: u32,
     pub subblock_valid: [bool; 2],
+    pub subblock_data: [u32; 2],
 }
 
 impl fmt::Display for CacheEntry {
@@ -67,36 +74,105 @@ impl Cache {
         return self.is_enabled;
     }
 
+    // Hits are served straight from the cache without touching the bus. A miss fills the
+    //  whole 4-byte subblock containing `addr` (fetch timing is described in
+    //  `Interconnect::access_wait_cycles`).
     pub fn read_halfword(&mut self, interconnect: &mut Interconnect, addr: u32) -> (u16, CacheResult) {
-        let halfword = interconnect.read_halfword(addr);
-
         if !self.is_enabled {
-            return (halfword, CacheResult::Disabled);
+            return (interconnect.read_halfword(addr), CacheResult::Disabled);
         }
 
         let byte_offset = (addr & 0x07) as usize;
         let entry = ((addr >> 3) & 0x7f) as usize;
         let tag = addr >> 10;
         let subblock = if byte_offset >= 4 { 1 } else { 0 };
+        let shift = (addr & 0x02) * 8;
 
         if self.entries[entry].tag == tag {
             if self.entries[entry].subblock_valid[subblock] {
                 self.hits += 1;
+                let halfword = (self.entries[entry].subblock_data[subblock] >> shift) as u16;
                 return (halfword, CacheResult::Hit);
             }
-            self.entries[entry].subblock_valid[subblock] = true;
-            self.misses += 1;
-            return (halfword, CacheResult::Miss);
         } else {
             self.entries[entry].tag = tag;
             self.entries[entry].subblock_valid = [false; 2];
-            self.entries[entry].subblock_valid[subblock] = true;
             self.entries[entry].base_addr = addr & 0xfffffff8;
-            self.misses += 1;
-            return (halfword, CacheResult::Miss);
+        }
+
+        let data = read_word(interconnect, addr & 0xfffffffc);
+        self.entries[entry].subblock_data[subblock] = data;
+        self.entries[entry].subblock_valid[subblock] = true;
+        self.misses += 1;
+        ((data >> shift) as u16, CacheResult::Miss)
+    }
+
+    // Dump layout (matching the V810 CHCW ICD operation): each entry's two data words
+    //  (1024 bytes total), followed by one word per entry with the tag in bits 10-31 and
+    //  the subblock valid bits in bits 8 and 9.
+    pub fn dump(&self, interconnect: &mut Interconnect, addr: u32) {
+        for (index, entry) in self.entries.iter().enumerate() {
+            let data_addr = addr.wrapping_add((index as u32) * 8);
+            write_word(interconnect, data_addr, entry.subblock_data[0]);
+            write_word(interconnect, data_addr.wrapping_add(4), entry.subblock_data[1]);
+
+            let tag_addr = addr.wrapping_add(1024 + (index as u32) * 4);
+            let tag_word =
+                (entry.tag << 10) |
+                (if entry.subblock_valid[1] { 1 << 9 } else { 0 }) |
+                (if entry.subblock_valid[0] { 1 << 8 } else { 0 });
+            write_word(interconnect, tag_addr, tag_word);
         }
     }
 
+    pub fn restore(&mut self, interconnect: &mut Interconnect, addr: u32) {
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            let data_addr = addr.wrapping_add((index as u32) * 8);
+            entry.subblock_data[0] = read_word(interconnect, data_addr);
+            entry.subblock_data[1] = read_word(interconnect, data_addr.wrapping_add(4));
+
+            let tag_addr = addr.wrapping_add(1024 + (index as u32) * 4);
+            let tag_word = read_word(interconnect, tag_addr);
+            entry.tag = tag_word >> 10;
+            entry.subblock_valid = [(tag_word >> 8) & 0x01 != 0, (tag_word >> 9) & 0x01 != 0];
+            entry.base_addr = (tag_word & 0xfffffc00) | ((index as u32) << 3);
+        }
+    }
+
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_u64(w, self.hits)?;
+        write_u64(w, self.misses)?;
+        write_bool(w, self.is_enabled)?;
+
+        for entry in self.entries.iter() {
+            write_u32(w, entry.tag)?;
+            write_u32(w, entry.base_addr)?;
+            write_bool(w, entry.subblock_valid[0])?;
+            write_bool(w, entry.subblock_valid[1])?;
+            write_u32(w, entry.subblock_data[0])?;
+            write_u32(w, entry.subblock_data[1])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.hits = read_u64(r)?;
+        self.misses = read_u64(r)?;
+        self.is_enabled = read_bool(r)?;
+
+        for entry in self.entries.iter_mut() {
+            entry.tag = read_u32(r)?;
+            entry.base_addr = read_u32(r)?;
+            entry.subblock_valid[0] = read_bool(r)?;
+            entry.subblock_valid[1] = read_bool(r)?;
+            entry.subblock_data[0] = read_u32(r)?;
+            entry.subblock_data[1] = read_u32(r)?;
+        }
+
+        Ok(())
+    }
+
     pub fn entry(&self, entry: usize) -> CacheEntry {
         return self.entries[entry];
     }
@@ -106,6 +182,16 @@ impl Cache {
     }
 }
 
+const TRACED_SYSTEM_REGISTERS: [(u32, SystemRegister); 7] = [
+    (OPCODE_SYSTEM_REGISTER_ID_EIPC, SystemRegister::Eipc),
+    (OPCODE_SYSTEM_REGISTER_ID_EIPSW, SystemRegister::Eipsw),
+    (OPCODE_SYSTEM_REGISTER_ID_FEPC, SystemRegister::Fepc),
+    (OPCODE_SYSTEM_REGISTER_ID_FEPSW, SystemRegister::Fepsw),
+    (OPCODE_SYSTEM_REGISTER_ID_ECR, SystemRegister::Ecr),
+    (OPCODE_SYSTEM_REGISTER_ID_PSW, SystemRegister::Psw),
+    (OPCODE_SYSTEM_REGISTER_ID_CHCW, SystemRegister::Chcw),
+];
+
 pub struct V810 {
     reg_pc: u32,
 
@@ -114,7 +200,7 @@ pub struct V810 {
 
     reg_eipc: u32,
     reg_eipsw: u32,
-    reg_ecr: u16,
+    reg_ecr: u32,
     reg_fepc: u32,
     reg_fepsw: u32,
 
@@ -138,7 +224,13 @@ pub struct V810 {
 
     pub cache: Cache,
 
-    pub watchpoints: HashSet<u32>,
+    call_stack: CallStack,
+
+    pub watchpoints: Vec<Watchpoint>,
+    watchpoint_hit: Option<WatchpointHit>,
+
+    is_tracing: bool,
+    trace_record: Option<TraceRecord>,
 }
 
 impl V810 {
@@ -179,7 +271,13 @@ impl V810 {
 
             cache: Cache::new(),
 
-            watchpoints: HashSet::new(),
+            call_stack: CallStack::new(),
+
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+
+            is_tracing: false,
+            trace_record: None,
         }
     }
 
@@ -187,6 +285,35 @@ impl V810 {
         self.reg_pc
     }
 
+    pub fn set_reg_pc(&mut self, value: u32) {
+        self.reg_pc = value & 0xfffffffe;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// The first watchpoint hit by the last call to `step`, if any
+    pub fn watchpoint_hit(&self) -> Option<WatchpointHit> {
+        self.watchpoint_hit
+    }
+
+    /// Enables recording a `TraceRecord` for each instruction. Memory accesses are only
+    /// included if the interconnect is tracing them too (see `VirtualBoy::set_tracing`).
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.is_tracing = enabled;
+        self.trace_record = None;
+    }
+
+    /// What the instruction executed by the last call to `step` did, if tracing is enabled
+    pub fn trace_record(&self) -> Option<&TraceRecord> {
+        self.trace_record.as_ref()
+    }
+
     pub fn reg_gpr(&self, index: usize) -> u32 {
         unsafe {
             let reg_ptr = self.reg_gpr_ptr.offset(index as _);
@@ -194,7 +321,8 @@ impl V810 {
         }
     }
 
-    fn set_reg_gpr(&mut self, index: usize, value: u32) {
+    /// Writes to r0 are ignored, as on hardware
+    pub fn set_reg_gpr(&mut self, index: usize, value: u32) {
         if index != 0 {
             unsafe {
                 let reg_ptr = self.reg_gpr_ptr.offset(index as _);
@@ -230,10 +358,65 @@ impl V810 {
         self.reg_eipsw
     }
 
-    pub fn reg_ecr(&self) -> u16 {
+    pub fn reg_ecr(&self) -> u32 {
         self.reg_ecr
     }
 
+    pub fn reg_fepc(&self) -> u32 {
+        self.reg_fepc
+    }
+
+    pub fn reg_fepsw(&self) -> u32 {
+        self.reg_fepsw
+    }
+
+    /// Reads a system register by its `stsr` id. Unrecognized registers read as 0.
+    pub fn reg_system(&self, id: u32) -> u32 {
+        match id {
+            OPCODE_SYSTEM_REGISTER_ID_EIPC => self.reg_eipc,
+            OPCODE_SYSTEM_REGISTER_ID_EIPSW => self.reg_eipsw,
+            OPCODE_SYSTEM_REGISTER_ID_FEPC => self.reg_fepc,
+            OPCODE_SYSTEM_REGISTER_ID_FEPSW => self.reg_fepsw,
+            OPCODE_SYSTEM_REGISTER_ID_ECR => self.reg_ecr,
+            OPCODE_SYSTEM_REGISTER_ID_PSW => self.reg_psw(),
+            OPCODE_SYSTEM_REGISTER_ID_CHCW => {
+                match self.cache.is_enabled() {
+                    true => 2,
+                    false => 0,
+                }
+            }
+            _ => {
+                logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", id);
+                0
+            }
+        }
+    }
+
+    /// Writes a system register by its `ldsr` id. CHCW commands need access to memory,
+    /// so they're only carried out by `ldsr` itself; here they (and writes to
+    /// unrecognized registers) are ignored.
+    pub fn set_reg_system(&mut self, id: u32, value: u32) {
+        match id {
+            OPCODE_SYSTEM_REGISTER_ID_EIPC => {
+                self.reg_eipc = value & 0xfffffffe;
+            }
+            OPCODE_SYSTEM_REGISTER_ID_EIPSW => {
+                self.reg_eipsw = value & 0x000ff3ff;
+            }
+            OPCODE_SYSTEM_REGISTER_ID_FEPC => {
+                self.reg_fepc = value & 0xfffffffe;
+            }
+            OPCODE_SYSTEM_REGISTER_ID_FEPSW => {
+                self.reg_fepsw = value & 0x000ff3ff;
+            }
+            OPCODE_SYSTEM_REGISTER_ID_ECR => {
+                self.reg_ecr = value as _;
+            }
+            OPCODE_SYSTEM_REGISTER_ID_PSW => self.set_reg_psw(value),
+            _ => logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", id),
+        }
+    }
+
     pub fn reg_psw(&self) -> u32 {
         (if self.psw_zero { 1 << 0 } else { 0 }) |
         (if self.psw_sign { 1 << 1 } else { 0 }) |
@@ -270,666 +453,806 @@ impl V810 {
         self.psw_interrupt_mask_level = (value >> 16) & 0x0f;
     }
 
-    pub fn step(&mut self, interconnect: &mut Interconnect) -> (u32, bool) {
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_u32(w, self.reg_pc)?;
+
+        for index in 0..32 {
+            write_u32(w, self.reg_gpr(index))?;
+        }
+
+        write_u32(w, self.reg_eipc)?;
+        write_u32(w, self.reg_eipsw)?;
+        write_u32(w, self.reg_ecr)?;
+        write_u32(w, self.reg_fepc)?;
+        write_u32(w, self.reg_fepsw)?;
+
+        write_u32(w, self.reg_psw())?;
+
+        write_bool(w, self.is_halted)?;
+
+        self.cache.save_state(w)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        // Calls aren't part of the saved state, so there's no telling which ones are active
+        self.call_stack.clear();
+
+        self.reg_pc = read_u32(r)?;
+
+        for index in 0..32 {
+            let value = read_u32(r)?;
+            self.set_reg_gpr(index, value);
+        }
+
+        self.reg_eipc = read_u32(r)?;
+        self.reg_eipsw = read_u32(r)?;
+        self.reg_ecr = read_u32(r)?;
+        self.reg_fepc = read_u32(r)?;
+        self.reg_fepsw = read_u32(r)?;
+
+        let psw = read_u32(r)?;
+        self.set_reg_psw(psw);
+
+        self.is_halted = read_bool(r)?;
+
+        self.cache.load_state(r)
+    }
+
+    pub fn step(&mut self, interconnect: &mut Interconnect) -> Result<(u32, bool), EmulationError> {
+        self.watchpoint_hit = None;
+        self.trace_record = None;
+
         if self.is_halted {
-            return (1, false);
+            return Ok((1, false));
         }
 
+        // Any bus error recorded outside of instruction execution (eg. by a debugger peeking at memory) isn't ours
+        interconnect.take_bus_error();
+        interconnect.take_wait_cycles();
+
         let original_pc = self.reg_pc;
 
-        let (first_halfword, _) = self.cache.read_halfword(interconnect, original_pc);
+        let registers_before = if self.is_tracing { Some(self.trace_registers()) } else { None };
+
+        let instruction = self.fetch_instruction(interconnect, original_pc);
+        let (first_halfword, second_halfword) = (instruction.first_halfword, instruction.second_halfword);
+        if self.is_tracing {
+            // Fetches aren't part of what the instruction itself accessed
+            interconnect.take_memory_accesses();
+        }
         let mut next_pc = original_pc.wrapping_add(2);
 
         let mut num_cycles = 1;
         let mut trigger_watchpoint = false;
+        let mut illegal_instruction = false;
+        let mut fp_exception = None;
 
-        if first_halfword >> 13 == OPCODE_BITS_BCOND_PREFIX {
-            let cond_bits = (first_halfword >> 9) & 0x0f;
-            let take_branch = match cond_bits {
-                OPCODE_BITS_BCOND_BV => self.psw_overflow,
-                OPCODE_BITS_BCOND_BC => self.psw_carry,
-                OPCODE_BITS_BCOND_BZ => self.psw_zero,
-                OPCODE_BITS_BCOND_BNH => self.psw_carry || self.psw_zero,
-                OPCODE_BITS_BCOND_BN => self.psw_sign,
-                OPCODE_BITS_BCOND_BR => true,
-                OPCODE_BITS_BCOND_BLT => self.psw_sign != self.psw_overflow,
-                OPCODE_BITS_BCOND_BLE => (self.psw_sign != self.psw_overflow) || self.psw_zero,
-                OPCODE_BITS_BCOND_BNV => !self.psw_overflow,
-                OPCODE_BITS_BCOND_BNC => !self.psw_carry,
-                OPCODE_BITS_BCOND_BNZ => !self.psw_zero,
-                OPCODE_BITS_BCOND_BH => !(self.psw_carry || self.psw_zero),
-                OPCODE_BITS_BCOND_BP => !self.psw_sign,
-                OPCODE_BITS_BCOND_NOP => false,
-                OPCODE_BITS_BCOND_BGE => !(self.psw_sign != self.psw_overflow),
-                OPCODE_BITS_BCOND_BGT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
-                _ => panic!("Unrecognized cond bits: {:04b} (halfword: 0b{:016b})", cond_bits, first_halfword)
-            };
+        macro_rules! format_i {
+            ($f:expr) => ({
+                let reg1 = instruction.reg1 as usize;
+                let reg2 = instruction.reg2 as usize;
 
-            if take_branch {
-                let disp = ((((first_halfword as i16) << 7) >> 7) as u32) & 0xfffffffe;
-                next_pc = self.reg_pc.wrapping_add(disp);
-                num_cycles = 3;
-            }
-        } else {
-            macro_rules! format_i {
-                ($f:expr) => ({
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
+                $f(reg1, reg2);
+            });
+        }
 
-                    $f(reg1, reg2);
-                });
-            }
+        macro_rules! format_ii {
+            ($f:expr) => ({
+                let imm5 = instruction.reg1 as u32;
+                let reg2 = instruction.reg2 as usize;
+                $f(imm5, reg2);
+            })
+        }
 
-            macro_rules! format_ii {
-                ($f:expr) => ({
-                    let imm5 = (first_halfword & 0x1f) as u32;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-                    $f(imm5, reg2);
-                })
-            }
+        macro_rules! format_iii {
+            ($cond:expr) => ({
+                if $cond {
+                    next_pc = self.reg_pc.wrapping_add(instruction.disp);
+                    num_cycles = 3;
+                }
+            })
+        }
 
-            macro_rules! format_iv {
-                ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
+        macro_rules! format_iv {
+            ($f:expr) => ({
+                next_pc = next_pc.wrapping_add(2);
 
-                    let disp = ((((((first_halfword as i16) << 6) >> 6) as u32) << 16) | (second_halfword as u32)) & 0xfffffffe;
-                    let target = self.reg_pc.wrapping_add(disp);
-                    $f(target);
-                })
-            }
+                let target = self.reg_pc.wrapping_add(instruction.disp);
+                $f(target);
+            })
+        }
 
-            macro_rules! format_v {
-                ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
+        macro_rules! format_v {
+            ($f:expr) => ({
+                next_pc = next_pc.wrapping_add(2);
 
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-                    let imm16 = second_halfword;
-                    $f(reg1, reg2, imm16);
-                })
-            }
+                let reg1 = instruction.reg1 as usize;
+                let reg2 = instruction.reg2 as usize;
+                let imm16 = instruction.imm16;
+                $f(reg1, reg2, imm16);
+            })
+        }
 
-            macro_rules! format_vi {
-                ($f:expr) => ({
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
+        macro_rules! format_vi {
+            ($f:expr) => ({
+                next_pc = next_pc.wrapping_add(2);
 
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-                    let disp16 = second_halfword as i16;
-                    $f(reg1, reg2, disp16);
-                })
-            }
+                let reg1 = instruction.reg1 as usize;
+                let reg2 = instruction.reg2 as usize;
+                let disp16 = instruction.imm16 as i16;
+                let reg2_before = self.reg_gpr(reg2);
+                $f(reg1, reg2, disp16);
 
-            let opcode_bits = first_halfword >> 10;
-            match opcode_bits {
-                OPCODE_BITS_MOV_REG => format_i!(|reg1, reg2| {
-                    let value = self.reg_gpr(reg1);
-                    self.set_reg_gpr(reg2, value);
-                }),
-                OPCODE_BITS_ADD_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    self.add(lhs, rhs, reg2);
-                }),
-                OPCODE_BITS_SUB => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.sub_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_CMP_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    self.sub_and_set_flags(lhs, rhs);
-                }),
-                OPCODE_BITS_SHL_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.shl_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_SHR_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.shr_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_JMP => format_i!(|reg1, _| {
-                    next_pc = self.reg_gpr(reg1) & 0xfffffffe;
-                    num_cycles = 3;
-                }),
-                OPCODE_BITS_SAR_REG => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = self.sar_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_MUL => format_i!(|reg1, reg2| {
-                    let lhs = (self.reg_gpr(reg2) as i32) as i64;
-                    let rhs = (self.reg_gpr(reg1) as i32) as i64;
-                    let res = (lhs * rhs) as u64;
-                    let res_low = res as u32;
-                    let res_high = (res >> 32) as u32;
-                    let overflow = res != ((res_low as i32) as u64);
-                    self.set_reg_gpr(30, res_high);
-                    self.set_reg_gpr(reg2, res_low);
-                    self.set_zero_sign_flags(res_low);
-                    self.psw_overflow = overflow;
-                    num_cycles = 13;
-                }),
-                OPCODE_BITS_DIV => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    if rhs == 0 {
-                        next_pc = self.enter_exception(0xff80);
-                    } else {
-                        let (res, rem, overflow) = if lhs == 0x80000000 && rhs == 0xffffffff {
-                            (lhs, 0, true)
-                        } else {
-                            let lhs = lhs as i32;
-                            let rhs = rhs as i32;
-                            let res = (lhs / rhs) as u32;
-                            let rem = (lhs % rhs) as u32;
-                            (res, rem, false)
-                        };
-                        self.set_reg_gpr(30, rem);
-                        self.set_reg_gpr(reg2, res);
-                        self.set_zero_sign_flags(res);
-                        self.psw_overflow = overflow;
-                    }
-                    num_cycles = 38;
-                }),
-                OPCODE_BITS_MUL_U => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2) as u64;
-                    let rhs = self.reg_gpr(reg1) as u64;
-                    let res = lhs * rhs;
-                    let res_low = res as u32;
-                    let res_high = (res >> 32) as u32;
-                    let overflow = res != (res_low as u64);
-                    self.set_reg_gpr(30, res_high);
-                    self.set_reg_gpr(reg2, res_low);
-                    self.set_zero_sign_flags(res_low);
-                    self.psw_overflow = overflow;
-                    num_cycles = 13;
-                }),
-                OPCODE_BITS_DIV_U => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    if rhs == 0 {
-                        next_pc = self.enter_exception(0xff80);
+                // A load that hit a bus error mustn't clobber its destination, so that
+                //  the instruction can be retried
+                if interconnect.has_bus_error() {
+                    self.set_reg_gpr(reg2, reg2_before);
+                }
+            })
+        }
+
+        match instruction.opcode {
+            Some(Opcode::Bv) => format_iii!(self.psw_overflow),
+            Some(Opcode::Bc) => format_iii!(self.psw_carry),
+            Some(Opcode::Bz) => format_iii!(self.psw_zero),
+            Some(Opcode::Bnh) => format_iii!(self.psw_carry || self.psw_zero),
+            Some(Opcode::Bn) => format_iii!(self.psw_sign),
+            Some(Opcode::Br) => format_iii!(true),
+            Some(Opcode::Blt) => format_iii!(self.psw_sign != self.psw_overflow),
+            Some(Opcode::Ble) => format_iii!((self.psw_sign != self.psw_overflow) || self.psw_zero),
+            Some(Opcode::Bnv) => format_iii!(!self.psw_overflow),
+            Some(Opcode::Bnc) => format_iii!(!self.psw_carry),
+            Some(Opcode::Bnz) => format_iii!(!self.psw_zero),
+            Some(Opcode::Bh) => format_iii!(!(self.psw_carry || self.psw_zero)),
+            Some(Opcode::Bp) => format_iii!(!self.psw_sign),
+            Some(Opcode::Nop) => format_iii!(false),
+            Some(Opcode::Bge) => format_iii!(!(self.psw_sign != self.psw_overflow)),
+            Some(Opcode::Bgt) => format_iii!(!((self.psw_sign != self.psw_overflow) || self.psw_zero)),
+            Some(Opcode::MovReg) => format_i!(|reg1, reg2| {
+                let value = self.reg_gpr(reg1);
+                self.set_reg_gpr(reg2, value);
+            }),
+            Some(Opcode::AddReg) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                self.add(lhs, rhs, reg2);
+            }),
+            Some(Opcode::Sub) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.sub_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::CmpReg) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                self.sub_and_set_flags(lhs, rhs);
+            }),
+            Some(Opcode::ShlReg) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.shl_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::ShrReg) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.shr_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::Jmp) => format_i!(|reg1, _| {
+                next_pc = self.reg_gpr(reg1) & 0xfffffffe;
+                if reg1 == 31 {
+                    self.call_stack.ret(next_pc);
+                }
+                num_cycles = 3;
+            }),
+            Some(Opcode::SarReg) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = self.sar_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::Mul) => format_i!(|reg1, reg2| {
+                let lhs = (self.reg_gpr(reg2) as i32) as i64;
+                let rhs = (self.reg_gpr(reg1) as i32) as i64;
+                let res = (lhs * rhs) as u64;
+                let res_low = res as u32;
+                let res_high = (res >> 32) as u32;
+                let overflow = res != ((res_low as i32) as u64);
+                self.set_reg_gpr(30, res_high);
+                self.set_reg_gpr(reg2, res_low);
+                self.set_zero_sign_flags(res_low);
+                self.psw_overflow = overflow;
+                num_cycles = 13;
+            }),
+            Some(Opcode::Div) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                if rhs == 0 {
+                    next_pc = self.raise_exception(interconnect, 0xff80, original_pc);
+                } else {
+                    let (res, rem, overflow) = if lhs == 0x80000000 && rhs == 0xffffffff {
+                        (lhs, 0, true)
                     } else {
-                        let res = lhs / rhs;
-                        let rem = lhs % rhs;
-                        self.set_reg_gpr(30, rem);
-                        self.set_reg_gpr(reg2, res);
-                        self.set_zero_sign_flags(res);
-                        self.psw_overflow = false;
-                    }
-                    num_cycles = 36;
-                }),
-                OPCODE_BITS_OR => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = lhs | rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_AND => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = lhs & rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_XOR => format_i!(|reg1, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = self.reg_gpr(reg1);
-                    let res = lhs ^ rhs;
+                        let lhs = lhs as i32;
+                        let rhs = rhs as i32;
+                        let res = (lhs / rhs) as u32;
+                        let rem = (lhs % rhs) as u32;
+                        (res, rem, false)
+                    };
+                    self.set_reg_gpr(30, rem);
                     self.set_reg_gpr(reg2, res);
                     self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_NOT => format_i!(|reg1, reg2| {
-                    let res = !self.reg_gpr(reg1);
+                    self.psw_overflow = overflow;
+                }
+                num_cycles = 38;
+            }),
+            Some(Opcode::MulU) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2) as u64;
+                let rhs = self.reg_gpr(reg1) as u64;
+                let res = lhs * rhs;
+                let res_low = res as u32;
+                let res_high = (res >> 32) as u32;
+                let overflow = res != (res_low as u64);
+                self.set_reg_gpr(30, res_high);
+                self.set_reg_gpr(reg2, res_low);
+                self.set_zero_sign_flags(res_low);
+                self.psw_overflow = overflow;
+                num_cycles = 13;
+            }),
+            Some(Opcode::DivU) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                if rhs == 0 {
+                    next_pc = self.raise_exception(interconnect, 0xff80, original_pc);
+                } else {
+                    let res = lhs / rhs;
+                    let rem = lhs % rhs;
+                    self.set_reg_gpr(30, rem);
                     self.set_reg_gpr(reg2, res);
                     self.set_zero_sign_flags(res);
                     self.psw_overflow = false;
-                }),
-                OPCODE_BITS_MOV_IMM => format_ii!(|imm5, reg2| {
-                    let value = sign_extend_imm5(imm5);
-                    self.set_reg_gpr(reg2, value);
-                }),
-                OPCODE_BITS_ADD_IMM_5 => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = sign_extend_imm5(imm5);
-                    self.add(lhs, rhs, reg2);
-                }),
-                OPCODE_BITS_SETF => format_ii!(|imm5, reg2| {
-                    let set = match imm5 & 0x0f {
-                        OPCODE_CONDITION_BITS_V => self.psw_overflow,
-                        OPCODE_CONDITION_BITS_C => self.psw_carry,
-                        OPCODE_CONDITION_BITS_Z => self.psw_zero,
-                        OPCODE_CONDITION_BITS_NH => self.psw_carry || self.psw_zero,
-                        OPCODE_CONDITION_BITS_N => self.psw_sign,
-                        OPCODE_CONDITION_BITS_T => true,
-                        OPCODE_CONDITION_BITS_LT => self.psw_sign != self.psw_overflow,
-                        OPCODE_CONDITION_BITS_LE => (self.psw_sign != self.psw_overflow) || self.psw_zero,
-                        OPCODE_CONDITION_BITS_NV => !self.psw_overflow,
-                        OPCODE_CONDITION_BITS_NC => !self.psw_carry,
-                        OPCODE_CONDITION_BITS_NZ => !self.psw_zero,
-                        OPCODE_CONDITION_BITS_H => !(self.psw_carry || self.psw_zero),
-                        OPCODE_CONDITION_BITS_P => !self.psw_sign,
-                        OPCODE_CONDITION_BITS_F => false,
-                        OPCODE_CONDITION_BITS_GE => !(self.psw_sign != self.psw_overflow),
-                        OPCODE_CONDITION_BITS_GT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
-                        _ => panic!("Unrecognized condition: {}", imm5),
-                    };
-                    self.set_reg_gpr(reg2, if set { 1 } else { 0 });
-                }),
-                OPCODE_BITS_CMP_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = sign_extend_imm5(imm5);
-                    self.sub_and_set_flags(lhs, rhs);
-                }),
-                OPCODE_BITS_SHL_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = imm5;
-                    let res = self.shl_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_SHR_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = imm5;
-                    let res = self.shr_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_CLI => format_ii!(|_, _| {
-                    self.psw_interrupt_disable = false;
-
-                    num_cycles = 12;
-                }),
-                OPCODE_BITS_SAR_IMM => format_ii!(|imm5, reg2| {
-                    let lhs = self.reg_gpr(reg2);
-                    let rhs = imm5;
-                    let res = self.sar_and_set_flags(lhs, rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_RETI => format_ii!(|_, _| {
-                    next_pc = self.return_from_exception();
-                    num_cycles = 10;
-                }),
-                OPCODE_BITS_HALT => format_ii!(|_, _| {
-                    next_pc = original_pc;
-                    self.is_halted = true;
-                }),
-                OPCODE_BITS_LDSR => format_ii!(|imm5, reg2| {
-                    let value = self.reg_gpr(reg2);
-                    match imm5 {
-                        OPCODE_SYSTEM_REGISTER_ID_EIPC => {
-                            self.reg_eipc = value & 0xfffffffe;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_EIPSW => {
-                            self.reg_eipsw = value & 0x000ff3ff;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_FEPC => {
-                            self.reg_fepc = value & 0xfffffffe;
-                        }
-                        OPCODE_SYSTEM_REGISTER_ID_FEPSW => {
-                            self.reg_fepsw = value & 0x000ff3ff;
+                }
+                num_cycles = 36;
+            }),
+            Some(Opcode::Or) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = lhs | rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::And) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = lhs & rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::Xor) => format_i!(|reg1, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = self.reg_gpr(reg1);
+                let res = lhs ^ rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::Not) => format_i!(|reg1, reg2| {
+                let res = !self.reg_gpr(reg1);
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::MovImm) => format_ii!(|imm5, reg2| {
+                let value = sign_extend_imm5(imm5);
+                self.set_reg_gpr(reg2, value);
+            }),
+            Some(Opcode::AddImm5) => format_ii!(|imm5, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = sign_extend_imm5(imm5);
+                self.add(lhs, rhs, reg2);
+            }),
+            Some(Opcode::Setf) => format_ii!(|imm5, reg2| {
+                let set = match imm5 & 0x0f {
+                    OPCODE_CONDITION_BITS_V => self.psw_overflow,
+                    OPCODE_CONDITION_BITS_C => self.psw_carry,
+                    OPCODE_CONDITION_BITS_Z => self.psw_zero,
+                    OPCODE_CONDITION_BITS_NH => self.psw_carry || self.psw_zero,
+                    OPCODE_CONDITION_BITS_N => self.psw_sign,
+                    OPCODE_CONDITION_BITS_T => true,
+                    OPCODE_CONDITION_BITS_LT => self.psw_sign != self.psw_overflow,
+                    OPCODE_CONDITION_BITS_LE => (self.psw_sign != self.psw_overflow) || self.psw_zero,
+                    OPCODE_CONDITION_BITS_NV => !self.psw_overflow,
+                    OPCODE_CONDITION_BITS_NC => !self.psw_carry,
+                    OPCODE_CONDITION_BITS_NZ => !self.psw_zero,
+                    OPCODE_CONDITION_BITS_H => !(self.psw_carry || self.psw_zero),
+                    OPCODE_CONDITION_BITS_P => !self.psw_sign,
+                    OPCODE_CONDITION_BITS_F => false,
+                    OPCODE_CONDITION_BITS_GE => !(self.psw_sign != self.psw_overflow),
+                    OPCODE_CONDITION_BITS_GT => !((self.psw_sign != self.psw_overflow) || self.psw_zero),
+                    _ => panic!("Unrecognized condition: {}", imm5),
+                };
+                self.set_reg_gpr(reg2, if set { 1 } else { 0 });
+            }),
+            Some(Opcode::CmpImm) => format_ii!(|imm5, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = sign_extend_imm5(imm5);
+                self.sub_and_set_flags(lhs, rhs);
+            }),
+            Some(Opcode::ShlImm) => format_ii!(|imm5, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5;
+                let res = self.shl_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::ShrImm) => format_ii!(|imm5, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5;
+                let res = self.shr_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::Cli) => format_ii!(|_, _| {
+                self.psw_interrupt_disable = false;
+
+                num_cycles = 12;
+            }),
+            Some(Opcode::SarImm) => format_ii!(|imm5, reg2| {
+                let lhs = self.reg_gpr(reg2);
+                let rhs = imm5;
+                let res = self.sar_and_set_flags(lhs, rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::Trap) => format_ii!(|imm5, _| {
+                next_pc = self.raise_exception(interconnect, 0xffa0 + (imm5 as u16), next_pc);
+                num_cycles = 15;
+            }),
+            Some(Opcode::Reti) => format_ii!(|_, _| {
+                next_pc = self.return_from_exception();
+                num_cycles = 10;
+            }),
+            Some(Opcode::Halt) => format_ii!(|_, _| {
+                next_pc = original_pc;
+                self.is_halted = true;
+            }),
+            Some(Opcode::Ldsr) => format_ii!(|imm5, reg2| {
+                let value = self.reg_gpr(reg2);
+                match imm5 {
+                    OPCODE_SYSTEM_REGISTER_ID_CHCW => {
+                        let enable = (value >> 1) & 0x01 == 1;
+                        if enable != self.cache.is_enabled() {
+                            logln!(Log::Cpu, "ldsr chcw cache enable changed to {}", enable);
+                            self.cache.set_is_enabled(enable);
                         }
-                        OPCODE_SYSTEM_REGISTER_ID_ECR => {
-                            self.reg_ecr = value as _;
+
+                        if value & 0x01 == 1 {
+                            let entry_count = ((value >> 8) & 0xfff) as usize;
+                            let entry_start = (value >> 20) as usize;
+                            logln!(Log::Cpu, "ldsr chcw request to clear cache for start entry: {}, entry count: {}", entry_start, entry_count);
+                            self.cache.clear_entries(entry_start, entry_count);
+                        } else if (value >> 4) & 0x01 == 1 {
+                            let addr = value & 0xffffff00;
+                            logln!(Log::Cpu, "ldsr chcw request to dump instruction cache to 0x{:08x}", addr);
+                            self.cache.dump(interconnect, addr);
+                        } else if (value >> 5) & 0x01 == 1 {
+                            let addr = value & 0xffffff00;
+                            logln!(Log::Cpu, "ldsr chcw request to restore instruction cache from 0x{:08x}", addr);
+                            self.cache.restore(interconnect, addr);
                         }
-                        OPCODE_SYSTEM_REGISTER_ID_PSW => self.set_reg_psw(value),
-                        OPCODE_SYSTEM_REGISTER_ID_CHCW => {
-                            logln!(Log::Cpu, "WARNING: ldsr chcw not fully implemented (value: 0x{:08x})", value);
-                            let enable = (value >> 1) & 0x01 == 1;
-                            if enable != self.cache.is_enabled() {
-                                logln!(Log::Cpu, "ldsr chcw cache enable changed to {}", enable);
-                                self.cache.set_is_enabled(enable);
+                    }
+                    _ => self.set_reg_system(imm5, value),
+                }
+            }),
+            Some(Opcode::Stsr) => format_ii!(|imm5, reg2| {
+                let value = self.reg_system(imm5);
+                self.set_reg_gpr(reg2, value);
+            }),
+            Some(Opcode::Sei) => format_ii!(|_, _| {
+                self.psw_interrupt_disable = true;
+
+                num_cycles = 12;
+            }),
+            Some(Opcode::BitString) => format_ii!(|imm5, _| {
+                macro_rules! bsu {
+                    ($f:expr) => ({
+                        let mut src_word_addr = self.reg_gpr(30) & 0xfffffffc;
+                        let mut dst_word_addr = self.reg_gpr(29) & 0xfffffffc;
+                        let mut src_bit_offset = self.reg_gpr(27) & 0x1f;
+                        let mut dst_bit_offset = self.reg_gpr(26) & 0x1f;
+                        let mut num_bits = self.reg_gpr(28);
+
+                        while num_bits > 0 {
+                            let src_word = read_word(interconnect, src_word_addr);
+                            trigger_watchpoint |= self.check_watchpoints(interconnect, src_word_addr, AccessKind::Read, AccessWidth::Word, src_word);
+                            let dst_word = read_word(interconnect, dst_word_addr);
+                            trigger_watchpoint |= self.check_watchpoints(interconnect, dst_word_addr, AccessKind::Read, AccessWidth::Word, dst_word);
+                            // Stop at a bus error without processing the current bit. The
+                            //  registers are left pointing at it, just like when the
+                            //  instruction is interrupted, so retrying it picks up from there.
+                            if interconnect.has_bus_error() {
+                                break;
+                            }
+                            let src_bit = (src_word >> src_bit_offset) & 0x01;
+                            let dst_bit = (dst_word >> dst_bit_offset) & 0x01;
+                            let res_bit = $f(src_bit, dst_bit) & 0x01;
+                            let dst_bit_mask = !(1 << dst_bit_offset);
+                            let res_word = (dst_word & dst_bit_mask) | (res_bit << dst_bit_offset);
+                            trigger_watchpoint |= self.check_watchpoints(interconnect, dst_word_addr, AccessKind::Write, AccessWidth::Word, res_word);
+                            write_word(interconnect, dst_word_addr, res_word);
+                            if interconnect.has_bus_error() {
+                                break;
                             }
 
-                            if value & 0x01 == 1 {
-                                let entry_count = ((value >> 8) & 0x7ffff) as usize;
-                                let entry_start = (value >> 20) as usize;
-                                logln!(Log::Cpu, "ldsr chcw request to clear cache for start entry: {}, entry count: {}", entry_start, entry_count);
-                                self.cache.clear_entries(entry_start, entry_count);
-                            } else if (value >> 4) & 0x01 == 1 {
-                                let addr = value & 0xffffff00;
-                                logln!(Log::Cpu, "WARNING: ldsr chcw request to dump instruction cache to 0x{:08x} not implemented yet", addr);
-                            } else if (value >> 5) & 0x01 == 1 {
-                                let addr = value & 0xffffff00;
-                                logln!(Log::Cpu, "WARNING: ldsr chcw request to restore instruction cache from 0x{:08x} not implemented yet", addr);
+                            src_bit_offset += 1;
+                            if src_bit_offset >= 32 {
+                                src_bit_offset = 0;
+                                src_word_addr += 4;
                             }
-                        }
-                        _ => logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", imm5),
-                    }
-                }),
-                OPCODE_BITS_STSR => format_ii!(|imm5, reg2| {
-                    let value = match imm5 {
-                        OPCODE_SYSTEM_REGISTER_ID_EIPC => self.reg_eipc,
-                        OPCODE_SYSTEM_REGISTER_ID_EIPSW => self.reg_eipsw,
-                        OPCODE_SYSTEM_REGISTER_ID_FEPC => self.reg_fepc,
-                        OPCODE_SYSTEM_REGISTER_ID_FEPSW => self.reg_fepsw,
-                        OPCODE_SYSTEM_REGISTER_ID_ECR => self.reg_ecr as _,
-                        OPCODE_SYSTEM_REGISTER_ID_PSW => self.reg_psw(),
-                        OPCODE_SYSTEM_REGISTER_ID_CHCW => {
-                            logln!(Log::Cpu, "WARNING: stsr chcw not fully implemented");
-                            match self.cache.is_enabled() {
-                                true => 2,
-                                false => 0,
+                            dst_bit_offset += 1;
+                            if dst_bit_offset >= 32 {
+                                dst_bit_offset = 0;
+                                dst_word_addr += 4;
                             }
+
+                            num_bits -= 1;
                         }
-                        _ => {
-                            logln!(Log::Cpu, "WARNING: Unrecognized system register: {}", imm5);
-                            0
-                        }
-                    };
-                    self.set_reg_gpr(reg2, value);
-                }),
-                OPCODE_BITS_SEI => format_ii!(|_, _| {
-                    self.psw_interrupt_disable = true;
-
-                    num_cycles = 12;
-                }),
-                OPCODE_BITS_BIT_STRING => format_ii!(|imm5, _| {
-                    macro_rules! bsu {
-                        ($f:expr) => ({
-                            let mut src_word_addr = self.reg_gpr(30) & 0xfffffffc;
-                            let mut dst_word_addr = self.reg_gpr(29) & 0xfffffffc;
-                            let mut src_bit_offset = self.reg_gpr(27) & 0x1f;
-                            let mut dst_bit_offset = self.reg_gpr(26) & 0x1f;
-                            let mut num_bits = self.reg_gpr(28);
-
-                            while num_bits > 0 {
-                                let src_word = read_word(interconnect, src_word_addr);
-                                let dst_word = read_word(interconnect, dst_word_addr);
-                                let src_bit = (src_word >> src_bit_offset) & 0x01;
-                                let dst_bit = (dst_word >> dst_bit_offset) & 0x01;
-                                let res_bit = $f(src_bit, dst_bit) & 0x01;
-                                let dst_bit_mask = !(1 << dst_bit_offset);
-                                let res_word = (dst_word & dst_bit_mask) | (res_bit << dst_bit_offset);
-                                write_word(interconnect, dst_word_addr, res_word);
-
-                                src_bit_offset += 1;
-                                if src_bit_offset >= 32 {
-                                    src_bit_offset = 0;
-                                    src_word_addr += 4;
-                                }
-                                dst_bit_offset += 1;
-                                if dst_bit_offset >= 32 {
-                                    dst_bit_offset = 0;
-                                    dst_word_addr += 4;
-                                }
-
-                                num_bits -= 1;
-                            }
 
-                            self.set_reg_gpr(30, src_word_addr);
-                            self.set_reg_gpr(29, dst_word_addr);
-                            self.set_reg_gpr(27, src_bit_offset);
-                            self.set_reg_gpr(26, dst_bit_offset);
-                            self.set_reg_gpr(28, num_bits);
-                        });
-                    }
+                        self.set_reg_gpr(30, src_word_addr);
+                        self.set_reg_gpr(29, dst_word_addr);
+                        self.set_reg_gpr(27, src_bit_offset);
+                        self.set_reg_gpr(26, dst_bit_offset);
+                        self.set_reg_gpr(28, num_bits);
+                    });
+                }
 
-                    match imm5 {
-                        OPCODE_BITS_BIT_STRING_OP_ORBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit | dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_ANDBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit & dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_XORBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit ^ dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_MOVBSU => bsu!(|src_bit: u32, _| src_bit),
-                        OPCODE_BITS_BIT_STRING_OP_ORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit | dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_ANDNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit & dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_XORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit ^ dst_bit),
-                        OPCODE_BITS_BIT_STRING_OP_NOTBSU => bsu!(|src_bit: u32, _| !src_bit),
-                        _ => panic!("Unrecognized bit string op: {:05b}", imm5)
-                    }
-                }),
-                OPCODE_BITS_MOVEA => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = (imm16 as i16) as u32;
-                    let res = lhs.wrapping_add(rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_ADD_IMM_16 => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = (imm16 as i16) as u32;
-                    self.add(lhs, rhs, reg2);
-                }),
-                OPCODE_BITS_JR => format_iv!(|target| {
-                    next_pc = target;
-                    num_cycles = 3;
-                }),
-                OPCODE_BITS_JAL => format_iv!(|target| {
-                    self.set_reg_gpr(31, next_pc);
-                    next_pc = target;
-                    num_cycles = 3;
-                }),
-                OPCODE_BITS_OR_I => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = imm16 as u32;
-                    let res = lhs | rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_AND_I => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = imm16 as u32;
-                    let res = lhs & rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_XOR_I => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = imm16 as u32;
-                    let res = lhs ^ rhs;
-                    self.set_reg_gpr(reg2, res);
-                    self.set_zero_sign_flags(res);
-                    self.psw_overflow = false;
-                }),
-                OPCODE_BITS_MOVHI => format_v!(|reg1, reg2, imm16| {
-                    let lhs = self.reg_gpr(reg1);
-                    let rhs = (imm16 as u32) << 16;
-                    let res = lhs.wrapping_add(rhs);
-                    self.set_reg_gpr(reg2, res);
-                }),
-                OPCODE_BITS_LDB => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = (interconnect.read_byte(addr) as i8) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_LDH => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = (interconnect.read_halfword(addr) as i16) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_LDW | OPCODE_BITS_INW => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffc;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = read_word(interconnect, addr);
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_STB | OPCODE_BITS_OUTB => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = self.reg_gpr(reg2) as u8;
-                    interconnect.write_byte(addr, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_STH | OPCODE_BITS_OUTH => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = self.reg_gpr(reg2) as u16;
-                    interconnect.write_halfword(addr, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_STW | OPCODE_BITS_OUTW => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffc;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = self.reg_gpr(reg2);
-                    write_word(interconnect, addr, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_INB => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = interconnect.read_byte(addr) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_INH => format_vi!(|reg1, reg2, disp16| {
-                    let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
-                    let addr = addr & 0xfffffffe;
-                    trigger_watchpoint |= self.check_watchpoints(addr);
-                    let value = interconnect.read_halfword(addr) as u32;
-                    self.set_reg_gpr(reg2, value);
-                    num_cycles = 4;
-                }),
-                OPCODE_BITS_EXTENDED => {
-                    let (second_halfword, _) = self.cache.read_halfword(interconnect, next_pc);
-                    next_pc = next_pc.wrapping_add(2);
-
-                    let reg1 = (first_halfword & 0x1f) as usize;
-                    let reg2 = ((first_halfword >> 5) & 0x1f) as usize;
-
-                    let subop_bits = second_halfword >> 10;
-
-                    match subop_bits {
-                        OPCODE_BITS_SUB_OP_CMPF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
+                match imm5 {
+                    OPCODE_BITS_BIT_STRING_OP_ORBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit | dst_bit),
+                    OPCODE_BITS_BIT_STRING_OP_ANDBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit & dst_bit),
+                    OPCODE_BITS_BIT_STRING_OP_XORBSU => bsu!(|src_bit: u32, dst_bit: u32| src_bit ^ dst_bit),
+                    OPCODE_BITS_BIT_STRING_OP_MOVBSU => bsu!(|src_bit: u32, _| src_bit),
+                    OPCODE_BITS_BIT_STRING_OP_ORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit | dst_bit),
+                    OPCODE_BITS_BIT_STRING_OP_ANDNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit & dst_bit),
+                    OPCODE_BITS_BIT_STRING_OP_XORNBSU => bsu!(|src_bit: u32, dst_bit: u32| !src_bit ^ dst_bit),
+                    OPCODE_BITS_BIT_STRING_OP_NOTBSU => bsu!(|src_bit: u32, _| !src_bit),
+                    _ => illegal_instruction = true,
+                }
+            }),
+            Some(Opcode::Movea) => format_v!(|reg1, reg2, imm16| {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = (imm16 as i16) as u32;
+                let res = lhs.wrapping_add(rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::AddImm16) => format_v!(|reg1, reg2, imm16| {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = (imm16 as i16) as u32;
+                self.add(lhs, rhs, reg2);
+            }),
+            Some(Opcode::Jr) => format_iv!(|target| {
+                next_pc = target;
+                num_cycles = 3;
+            }),
+            Some(Opcode::Jal) => format_iv!(|target| {
+                self.set_reg_gpr(31, next_pc);
+                self.call_stack.call(original_pc, target);
+                next_pc = target;
+                num_cycles = 3;
+            }),
+            Some(Opcode::OrI) => format_v!(|reg1, reg2, imm16| {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = imm16 as u32;
+                let res = lhs | rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::AndI) => format_v!(|reg1, reg2, imm16| {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = imm16 as u32;
+                let res = lhs & rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::XorI) => format_v!(|reg1, reg2, imm16| {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = imm16 as u32;
+                let res = lhs ^ rhs;
+                self.set_reg_gpr(reg2, res);
+                self.set_zero_sign_flags(res);
+                self.psw_overflow = false;
+            }),
+            Some(Opcode::Movhi) => format_v!(|reg1, reg2, imm16| {
+                let lhs = self.reg_gpr(reg1);
+                let rhs = (imm16 as u32) << 16;
+                let res = lhs.wrapping_add(rhs);
+                self.set_reg_gpr(reg2, res);
+            }),
+            Some(Opcode::Ldb) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let value = interconnect.read_byte(addr);
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Read, AccessWidth::Byte, value as u32);
+                let value = (value as i8) as u32;
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Ldh) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffe;
+                let value = interconnect.read_halfword(addr);
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Read, AccessWidth::Halfword, value as u32);
+                let value = (value as i16) as u32;
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Ldw) | Some(Opcode::Inw) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffc;
+                let value = read_word(interconnect, addr);
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Read, AccessWidth::Word, value);
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Stb) | Some(Opcode::Outb) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let value = self.reg_gpr(reg2) as u8;
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Write, AccessWidth::Byte, value as u32);
+                interconnect.write_byte(addr, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Sth) | Some(Opcode::Outh) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffe;
+                let value = self.reg_gpr(reg2) as u16;
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Write, AccessWidth::Halfword, value as u32);
+                interconnect.write_halfword(addr, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Stw) | Some(Opcode::Outw) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffc;
+                let value = self.reg_gpr(reg2);
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Write, AccessWidth::Word, value);
+                write_word(interconnect, addr, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Inb) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let value = interconnect.read_byte(addr) as u32;
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Read, AccessWidth::Byte, value);
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Inh) => format_vi!(|reg1, reg2, disp16| {
+                let addr = self.reg_gpr(reg1).wrapping_add(disp16 as u32);
+                let addr = addr & 0xfffffffe;
+                let value = interconnect.read_halfword(addr) as u32;
+                trigger_watchpoint |= self.check_watchpoints(interconnect, addr, AccessKind::Read, AccessWidth::Halfword, value);
+                self.set_reg_gpr(reg2, value);
+                num_cycles = 4;
+            }),
+            Some(Opcode::Extended) => {
+                next_pc = next_pc.wrapping_add(2);
+
+                let reg1 = instruction.reg1 as usize;
+                let reg2 = instruction.reg2 as usize;
+
+                match instruction.subop {
+                    OPCODE_BITS_SUB_OP_CMPF_S => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        if self.check_reserved_operands(lhs, rhs) {
+                            fp_exception = Some(0xff60);
+                        } else {
                             let value = lhs - rhs;
 
                             self.set_fp_flags(value);
+                        }
 
-                            num_cycles = 10;
+                        num_cycles = 10;
+                    }
+                    OPCODE_BITS_SUB_OP_CVT_WS => {
+                        let original = self.reg_gpr(reg1) as i32;
+                        let value = original as f32;
+                        if (value as f64) != (original as f64) {
+                            self.psw_fp_precision_degredation = true;
                         }
-                        OPCODE_BITS_SUB_OP_CVT_WS => {
-                            let value = (self.reg_gpr(reg1) as i32) as f32;
-                            self.set_reg_gpr_float(reg2, value);
+                        self.set_reg_gpr_float(reg2, value);
 
-                            self.set_fp_flags(value);
+                        self.set_fp_flags(value);
 
-                            num_cycles = 16;
-                        }
-                        OPCODE_BITS_SUB_OP_CVT_SW => {
-                            let value = (self.reg_gpr_float(reg1).round() as i32) as u32;
-                            self.set_reg_gpr(reg2, value);
+                        num_cycles = 16;
+                    }
+                    OPCODE_BITS_SUB_OP_CVT_SW => {
+                        let original = self.reg_gpr_float(reg1);
+                        fp_exception = self.float_to_int(reg2, original, original.round());
 
-                            self.psw_overflow = false;
-                            self.set_zero_sign_flags(value);
+                        num_cycles = 14;
+                    }
+                    OPCODE_BITS_SUB_OP_ADDF_S => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        fp_exception = self.float_arithmetic(FloatOp::Add, reg2, lhs, rhs);
 
-                            num_cycles = 14;
-                        }
-                        OPCODE_BITS_SUB_OP_ADDF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs + rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                        num_cycles = 28;
+                    }
+                    OPCODE_BITS_SUB_OP_SUBF_S => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        fp_exception = self.float_arithmetic(FloatOp::Sub, reg2, lhs, rhs);
 
-                            self.set_fp_flags(value);
+                        num_cycles = 28;
+                    }
+                    OPCODE_BITS_SUB_OP_MULF_S => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        fp_exception = self.float_arithmetic(FloatOp::Mul, reg2, lhs, rhs);
 
-                            num_cycles = 28;
-                        }
-                        OPCODE_BITS_SUB_OP_SUBF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs - rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                        num_cycles = 30;
+                    }
+                    OPCODE_BITS_SUB_OP_DIVF_S => {
+                        let lhs = self.reg_gpr_float(reg2);
+                        let rhs = self.reg_gpr_float(reg1);
+                        fp_exception = self.float_arithmetic(FloatOp::Div, reg2, lhs, rhs);
 
-                            self.set_fp_flags(value);
+                        num_cycles = 44;
+                    }
+                    OPCODE_BITS_SUB_OP_XB => {
+                        let original = self.reg_gpr(reg2);
+                        let value = (original & 0xffff0000) | ((original & 0x0000ff00) >> 8) | ((original & 0x000000ff) << 8);
+                        self.set_reg_gpr(reg2, value);
 
-                            num_cycles = 28;
+                        num_cycles = 6;
+                    }
+                    OPCODE_BITS_SUB_OP_XH => {
+                        let original = self.reg_gpr(reg2);
+                        let value = (original >> 16) | ((original & 0xffff) << 16);
+                        self.set_reg_gpr(reg2, value);
+                    }
+                    OPCODE_BITS_SUB_OP_REV => {
+                        let original = self.reg_gpr(reg1);
+                        let mut value: u32 = 0;
+                        for x in 0..32 {
+                            value = (value << 1) | ((original >> x) & 0x01);
                         }
-                        OPCODE_BITS_SUB_OP_MULF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs * rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                        self.set_reg_gpr(reg2, value);
 
-                            self.set_fp_flags(value);
+                        num_cycles = 22;
+                    }
+                    OPCODE_BITS_SUB_OP_TRNC_SW => {
+                        let original = self.reg_gpr_float(reg1);
+                        fp_exception = self.float_to_int(reg2, original, original.trunc());
 
-                            num_cycles = 30;
-                        }
-                        OPCODE_BITS_SUB_OP_DIVF_S => {
-                            let lhs = self.reg_gpr_float(reg2);
-                            let rhs = self.reg_gpr_float(reg1);
-                            let value = lhs / rhs;
-                            self.set_reg_gpr_float(reg2, value);
+                        num_cycles = 14;
+                    }
+                    OPCODE_BITS_SUB_OP_MPYHW => {
+                        let lhs = self.reg_gpr(reg2) as i32;
+                        let rhs = ((self.reg_gpr(reg1) as i32) << 15) >> 15;
+                        let value = (lhs * rhs) as u32;
+                        self.set_reg_gpr(reg2, value);
 
-                            self.set_fp_flags(value);
+                        num_cycles = 9;
+                    }
+                    _ => illegal_instruction = true,
+                }
+            }
+            None => illegal_instruction = true,
+        }
 
-                            num_cycles = 44;
-                        }
-                        OPCODE_BITS_SUB_OP_XB => {
-                            let original = self.reg_gpr(reg2);
-                            let value = (original & 0xffff0000) | ((original & 0x0000ff00) >> 8) | ((original & 0x000000ff) << 8);
-                            self.set_reg_gpr(reg2, value);
+        if let Some(bus_error) = interconnect.take_bus_error() {
+            self.reg_pc = original_pc;
+            return Err(EmulationError::BusError {
+                pc: original_pc,
+                bus_error: bus_error,
+            });
+        }
 
-                            num_cycles = 6;
-                        }
-                        OPCODE_BITS_SUB_OP_XH => {
-                            let original = self.reg_gpr(reg2);
-                            let value = (original >> 16) | ((original & 0xffff) << 16);
-                            self.set_reg_gpr(reg2, value);
-                        }
-                        OPCODE_BITS_SUB_OP_REV => {
-                            let original = self.reg_gpr(reg1);
-                            let mut value: u32 = 0;
-                            for x in 0..32 {
-                                value = (value << 1) | ((original >> x) & 0x01);
-                            }
-                            self.set_reg_gpr(reg2, value);
+        if illegal_instruction {
+            logln!(Log::Cpu, "Illegal instruction at 0x{:08x} (halfword: 0b{:016b})", original_pc, first_halfword);
+            next_pc = self.raise_exception(interconnect, 0xff90, original_pc);
+        }
 
-                            num_cycles = 22;
-                        }
-                        OPCODE_BITS_SUB_OP_TRNC_SW => {
-                            let value = (self.reg_gpr_float(reg1).trunc() as i32) as u32;
-                            self.set_reg_gpr(reg2, value);
+        if let Some(exception_code) = fp_exception {
+            next_pc = self.raise_exception(interconnect, exception_code, original_pc);
+        }
 
-                            self.psw_overflow = false;
-                            self.set_zero_sign_flags(value);
+        self.reg_pc = next_pc;
 
-                            num_cycles = 14;
-                        }
-                        OPCODE_BITS_SUB_OP_MPYHW => {
-                            let lhs = self.reg_gpr(reg2) as i32;
-                            let rhs = ((self.reg_gpr(reg1) as i32) << 15) >> 15;
-                            let value = (lhs * rhs) as u32;
-                            self.set_reg_gpr(reg2, value);
+        if let Some(registers_before) = registers_before {
+            let registers_after = self.trace_registers();
+            self.trace_record = Some(TraceRecord {
+                pc: original_pc,
+                first_halfword: first_halfword,
+                second_halfword: second_halfword,
+                register_changes: registers_before.iter().zip(registers_after.iter())
+                    .filter(|&(before, after)| before.1 != after.1)
+                    .map(|(&(register, old_value), &(_, new_value))| RegisterChange {
+                        register: register,
+                        old_value: old_value,
+                        new_value: new_value,
+                    })
+                    .collect(),
+                memory_accesses: interconnect.take_memory_accesses(),
+            });
+        }
 
-                            num_cycles = 9;
-                        }
-                        _ => panic!("Unrecognized subop bits: {:06b}", subop_bits)
-                    }
-                }
-                _ => panic!("Unrecognized opcode bits: {:06b} (halfword: 0b{:016b})", opcode_bits, first_halfword),
+        num_cycles += interconnect.take_wait_cycles();
+
+        Ok((num_cycles, trigger_watchpoint))
+    }
+
+    fn trace_registers(&self) -> Vec<(TraceRegister, u32)> {
+        let gprs = (1..32).map(|index| (TraceRegister::Gpr(index), self.reg_gpr(index)));
+        let system_registers = TRACED_SYSTEM_REGISTERS.iter()
+            .map(|&(id, system_register)| (TraceRegister::System(system_register), self.reg_system(id)));
+        gprs.chain(system_registers).collect()
+    }
+
+    // Instructions fetched from ROM and WRAM are decoded once and remembered by the
+    //  interconnect until that memory is written. With the instruction cache disabled, a
+    //  remembered instruction is used without going through the bus at all (but still costs
+    //  the same cycles). With it enabled, the halfwords have to come from the cache itself,
+    //  since its contents may no longer match memory, so only decoding them is skipped.
+    fn fetch_instruction(&mut self, interconnect: &mut Interconnect, addr: u32) -> DecodedInstruction {
+        if !self.cache.is_enabled() {
+            if let Some(instruction) = interconnect.cached_instruction(addr) {
+                return instruction;
             }
         }
 
-        self.reg_pc = next_pc;
+        let (first_halfword, _) = self.cache.read_halfword(interconnect, addr);
+        let second_halfword = if is_32_bit_instruction(first_halfword) {
+            self.cache.read_halfword(interconnect, addr.wrapping_add(2)).0
+        } else {
+            0
+        };
+
+        if self.cache.is_enabled() {
+            if let Some(instruction) = interconnect.cached_instruction_matching(addr, first_halfword, second_halfword) {
+                return instruction;
+            }
+        }
 
-        (num_cycles, trigger_watchpoint)
+        let instruction = DecodedInstruction::new(first_halfword, second_halfword);
+        interconnect.insert_cached_instruction(addr, instruction);
+        instruction
     }
 
-    fn check_watchpoints(&self, addr: u32) -> bool {
-        self.watchpoints.len() != 0 && self.watchpoints.contains(&addr)
+    // Reads are checked after the value is read, and writes before the value is written,
+    //  so `value` is always the new value and the old one can still be peeked from memory
+    fn check_watchpoints(&mut self, interconnect: &mut Interconnect, addr: u32, kind: AccessKind, width: AccessWidth, value: u32) -> bool {
+        if self.watchpoints.is_empty() {
+            return false;
+        }
+
+        let watchpoint = match self.watchpoints.iter().find(|watchpoint| watchpoint.matches(addr, kind, width)) {
+            Some(&watchpoint) => watchpoint,
+            None => return false,
+        };
+
+        if self.watchpoint_hit.is_none() {
+            let old_value = match kind {
+                AccessKind::Read => value,
+                AccessKind::Write => match width {
+                    AccessWidth::Byte => interconnect.peek_byte(addr) as u32,
+                    AccessWidth::Halfword => interconnect.peek_halfword(addr) as u32,
+                    AccessWidth::Word => (interconnect.peek_halfword(addr) as u32) | ((interconnect.peek_halfword(addr + 2) as u32) << 16),
+                },
+            };
+            self.watchpoint_hit = Some(WatchpointHit {
+                watchpoint: watchpoint,
+                pc: self.reg_pc,
+                addr: addr,
+                kind: kind,
+                width: width,
+                old_value: old_value,
+                new_value: value,
+            });
+        }
+
+        true
     }
 
     fn add(&mut self, lhs: u32, rhs: u32, reg2: usize) {
@@ -996,6 +1319,94 @@ impl V810 {
         self.psw_sign = (value & 0x80000000) != 0;
     }
 
+    // Sets the (sticky) reserved operand flag if either operand is NaN, infinite or denormal
+    fn check_reserved_operands(&mut self, lhs: f32, rhs: f32) -> bool {
+        if is_reserved_operand(lhs) || is_reserved_operand(rhs) {
+            self.psw_fp_reserved_operand = true;
+            return true;
+        }
+
+        false
+    }
+
+    // Returns the code of the FPU exception to raise, if any. The result is only written if there isn't one.
+    fn float_arithmetic(&mut self, op: FloatOp, reg2: usize, lhs: f32, rhs: f32) -> Option<u16> {
+        if self.check_reserved_operands(lhs, rhs) {
+            return Some(0xff60);
+        }
+
+        if op == FloatOp::Div && rhs == 0.0 {
+            if lhs == 0.0 {
+                self.psw_fp_invalid_operation = true;
+                return Some(0xff70);
+            }
+
+            self.psw_fp_zero_division = true;
+            return Some(0xff68);
+        }
+
+        let mut value = match op {
+            FloatOp::Add => lhs + rhs,
+            FloatOp::Sub => lhs - rhs,
+            FloatOp::Mul => lhs * rhs,
+            FloatOp::Div => lhs / rhs,
+        };
+
+        if value.is_infinite() {
+            self.psw_fp_overflow = true;
+            self.psw_fp_precision_degredation = true;
+            return Some(0xff64);
+        }
+
+        // Operands are all normal (or zero) here, so the f64 product of two of them is exact
+        let is_inexact = match op {
+            FloatOp::Add => two_sum_error(lhs, rhs, value) != 0.0,
+            FloatOp::Sub => two_sum_error(lhs, -rhs, value) != 0.0,
+            FloatOp::Mul => (value as f64) != (lhs as f64) * (rhs as f64),
+            FloatOp::Div => (value as f64) * (rhs as f64) != (lhs as f64),
+        };
+
+        if (value != 0.0 && !value.is_normal()) || (value == 0.0 && is_inexact) {
+            // Denormal results aren't supported, so these are flushed to zero
+            self.psw_fp_underflow = true;
+            self.psw_fp_precision_degredation = true;
+            value = if value.is_sign_negative() { -0.0 } else { 0.0 };
+        } else if is_inexact {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        self.set_reg_gpr_float(reg2, value);
+
+        self.set_fp_flags(value);
+
+        None
+    }
+
+    // Shared by cvt.sw and trnc.sw, which only differ in how `rounded` is computed
+    fn float_to_int(&mut self, reg2: usize, original: f32, rounded: f32) -> Option<u16> {
+        if is_reserved_operand(original) {
+            self.psw_fp_reserved_operand = true;
+            return Some(0xff60);
+        }
+
+        if rounded < -2147483648.0 || rounded >= 2147483648.0 {
+            self.psw_fp_invalid_operation = true;
+            return Some(0xff70);
+        }
+
+        if rounded != original {
+            self.psw_fp_precision_degredation = true;
+        }
+
+        let value = (rounded as i32) as u32;
+        self.set_reg_gpr(reg2, value);
+
+        self.psw_overflow = false;
+        self.set_zero_sign_flags(value);
+
+        None
+    }
+
     fn set_fp_flags(&mut self, value: f32) {
         self.psw_carry = value.is_sign_negative();
         self.psw_overflow = false;
@@ -1017,35 +1428,94 @@ impl V810 {
             interrupt_level += 1;
         }
 
-        self.reg_pc = self.enter_exception(exception_code);
+        let restore_pc = if self.is_halted {
+            self.reg_pc.wrapping_add(2)
+        } else {
+            self.reg_pc
+        };
+        self.is_halted = false;
+
+        self.reg_pc = self.enter_exception(exception_code, restore_pc);
 
         self.psw_interrupt_mask_level = interrupt_level;
     }
 
-    fn enter_exception(&mut self, exception_code: u16) -> u32 {
-        logln!(Log::Cpu, "Entering exception (code: 0x{:04x})", exception_code);
-        // TODO: Duplexed exception
-        self.reg_eipc = self.reg_pc;
-        if self.is_halted {
-            self.reg_eipc = self.reg_eipc.wrapping_add(2);
-            self.is_halted = false;
+    // Exceptions raised by instructions can't be masked, so unlike interrupts they may end up
+    //  being duplexed or fatal. Returns the next pc.
+    fn raise_exception(&mut self, interconnect: &mut Interconnect, exception_code: u16, restore_pc: u32) -> u32 {
+        if self.psw_nmi_pending {
+            logln!(Log::Cpu, "Fatal exception (code: 0x{:04x})", exception_code);
+            write_word(interconnect, 0x00000000, 0xffff0000 | (exception_code as u32));
+            write_word(interconnect, 0x00000004, self.reg_psw());
+            write_word(interconnect, 0x00000008, restore_pc);
+            // Only a reset gets us out of here; interrupts are ignored while NP is set
+            self.is_halted = true;
+            return restore_pc;
         }
-        self.reg_eipsw = self.reg_psw();
-        self.reg_ecr = exception_code;
-        self.psw_exception_pending = true;
+
+        self.enter_exception(exception_code, restore_pc)
+    }
+
+    fn enter_exception(&mut self, exception_code: u16, restore_pc: u32) -> u32 {
+        let handler = if self.psw_exception_pending {
+            logln!(Log::Cpu, "Entering duplexed exception (code: 0x{:04x})", exception_code);
+            self.reg_fepc = restore_pc;
+            self.reg_fepsw = self.reg_psw();
+            self.reg_ecr = (self.reg_ecr & 0x0000ffff) | ((exception_code as u32) << 16);
+            self.psw_nmi_pending = true;
+            0xffffffd0
+        } else {
+            logln!(Log::Cpu, "Entering exception (code: 0x{:04x})", exception_code);
+            self.reg_eipc = restore_pc;
+            self.reg_eipsw = self.reg_psw();
+            self.reg_ecr = (self.reg_ecr & 0xffff0000) | (exception_code as u32);
+            self.psw_exception_pending = true;
+            0xffff0000 | ((exception_code as u32) & 0xfff0)
+        };
         self.psw_interrupt_disable = true;
         self.psw_address_trap_enable = false;
-        0xffff0000 | (exception_code as u32)
+
+        self.call_stack.enter_exception(exception_code, restore_pc, handler);
+
+        handler
     }
 
     fn return_from_exception(&mut self) -> u32 {
-        logln!(Log::Cpu, "Returning from exception (code: 0x{:04x})", self.reg_ecr);
-        let psw = self.reg_eipsw;
-        self.set_reg_psw(psw);
-        self.reg_eipc
+        self.call_stack.return_from_exception();
+
+        if self.psw_nmi_pending {
+            logln!(Log::Cpu, "Returning from duplexed exception (code: 0x{:04x})", self.reg_ecr >> 16);
+            let psw = self.reg_fepsw;
+            self.set_reg_psw(psw);
+            self.reg_fepc
+        } else {
+            logln!(Log::Cpu, "Returning from exception (code: 0x{:04x})", self.reg_ecr & 0xffff);
+            let psw = self.reg_eipsw;
+            self.set_reg_psw(psw);
+            self.reg_eipc
+        }
     }
 }
 
+#[derive(PartialEq, Eq)]
+enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+fn is_reserved_operand(value: f32) -> bool {
+    value.is_nan() || value.is_infinite() || (value != 0.0 && !value.is_normal())
+}
+
+// Knuth's TwoSum: the exact rounding error of `sum = lhs + rhs`
+fn two_sum_error(lhs: f32, rhs: f32, sum: f32) -> f32 {
+    let rhs_virtual = sum - lhs;
+    let lhs_virtual = sum - rhs_virtual;
+    (lhs - lhs_virtual) + (rhs - rhs_virtual)
+}
+
 fn sign_extend_imm5(imm5: u32) -> u32 {
     (((imm5 as i32) << 27) >> 27) as _
 }
@@ -1,9 +1,12 @@
 mod mem_map;
 
+use save_state::*;
 use sinks::*;
 
 use self::mem_map::*;
 
+use std::io::{self, Read, Write, Error, ErrorKind};
+
 const FRAMEBUFFER_RESOLUTION_X: u32 = 384;
 const FRAMEBUFFER_RESOLUTION_Y: u32 = 256;
 
@@ -58,7 +61,7 @@ enum ObjGroup {
 }
 
 pub struct Vip {
-    _vram: Box<[u8]>,
+    vram: Box<[u8]>,
     vram_ptr: *mut u8,
 
     display_state: DisplayState,
@@ -128,7 +131,7 @@ impl Vip {
         let vram_ptr = vram.as_mut_ptr();
 
         Vip {
-            _vram: vram,
+            vram: vram,
             vram_ptr: vram_ptr,
 
             display_state: DisplayState::Idle,
@@ -211,6 +214,16 @@ impl Vip {
         (if self.reg_intenb_xpend { 1 } else { 0 } << 14)
     }
 
+    /// Extra CPU cycles spent waiting on the VIP for an access to `addr`. These are
+    /// estimates (VRAM being quicker to reach than the registers); see
+    /// `Interconnect::access_wait_cycles`.
+    pub fn access_wait_cycles(&self, addr: u32) -> u32 {
+        match addr & 0x0007ffff {
+            VRAM_START ... VRAM_END => 1,
+            _ => 2,
+        }
+    }
+
     pub fn read_byte(&self, addr: u32) -> u8 {
         let addr = addr & 0x0007ffff;
         match addr {
@@ -506,84 +519,282 @@ impl Vip {
         }
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bytes(w, &self.vram)?;
+
+        write_u8(w, match self.display_state {
+            DisplayState::Idle => 0,
+            DisplayState::LeftFramebuffer => 1,
+            DisplayState::RightFramebuffer => 2,
+            DisplayState::Finished => 3,
+        })?;
+
+        write_bool(w, self.drawing_state == DrawingState::Drawing)?;
+
+        write_bool(w, self.reg_intpnd_lfbend)?;
+        write_bool(w, self.reg_intpnd_rfbend)?;
+        write_bool(w, self.reg_intpnd_gamestart)?;
+        write_bool(w, self.reg_intpnd_framestart)?;
+        write_bool(w, self.reg_intpnd_sbhit)?;
+        write_bool(w, self.reg_intpnd_xpend)?;
+
+        write_bool(w, self.reg_intenb_lfbend)?;
+        write_bool(w, self.reg_intenb_rfbend)?;
+        write_bool(w, self.reg_intenb_gamestart)?;
+        write_bool(w, self.reg_intenb_framestart)?;
+        write_bool(w, self.reg_intenb_sbhit)?;
+        write_bool(w, self.reg_intenb_xpend)?;
+
+        write_bool(w, self.reg_dpctrl_disp)?;
+        write_bool(w, self.reg_dpctrl_synce)?;
+
+        write_bool(w, self.reg_xpctrl_xpen)?;
+        write_u32(w, self.reg_xpctrl_sbcount)?;
+        write_u32(w, self.reg_xpctrl_sbcmp)?;
+        write_bool(w, self.reg_xpctrl_sbout)?;
+
+        write_u32(w, self.reg_frmcyc)?;
+
+        write_u8(w, self.reg_brta)?;
+        write_u8(w, self.reg_brtb)?;
+        write_u8(w, self.reg_brtc)?;
+
+        write_u16(w, self.reg_spt0)?;
+        write_u16(w, self.reg_spt1)?;
+        write_u16(w, self.reg_spt2)?;
+        write_u16(w, self.reg_spt3)?;
+
+        write_u8(w, self.reg_gplt0)?;
+        write_u8(w, self.reg_gplt1)?;
+        write_u8(w, self.reg_gplt2)?;
+        write_u8(w, self.reg_gplt3)?;
+
+        write_u8(w, self.reg_jplt0)?;
+        write_u8(w, self.reg_jplt1)?;
+        write_u8(w, self.reg_jplt2)?;
+        write_u8(w, self.reg_jplt3)?;
+
+        write_u8(w, self.reg_bkcol)?;
+
+        write_u32(w, self.display_frame_eighth_clock_counter)?;
+        write_u32(w, self.display_frame_eighth_counter)?;
+
+        write_u32(w, self.drawing_block_counter)?;
+        write_u32(w, self.drawing_sbout_counter)?;
+
+        write_u32(w, self.fclk)?;
+
+        write_bool(w, self.display_first_framebuffers)?;
+        write_u8(w, self.last_bkcol)?;
+
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        read_bytes(r, &mut self.vram)?;
+
+        self.display_state = match read_u8(r)? {
+            0 => DisplayState::Idle,
+            1 => DisplayState::LeftFramebuffer,
+            2 => DisplayState::RightFramebuffer,
+            3 => DisplayState::Finished,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid VIP display state in save state")),
+        };
+
+        self.drawing_state = if read_bool(r)? { DrawingState::Drawing } else { DrawingState::Idle };
+
+        self.reg_intpnd_lfbend = read_bool(r)?;
+        self.reg_intpnd_rfbend = read_bool(r)?;
+        self.reg_intpnd_gamestart = read_bool(r)?;
+        self.reg_intpnd_framestart = read_bool(r)?;
+        self.reg_intpnd_sbhit = read_bool(r)?;
+        self.reg_intpnd_xpend = read_bool(r)?;
+
+        self.reg_intenb_lfbend = read_bool(r)?;
+        self.reg_intenb_rfbend = read_bool(r)?;
+        self.reg_intenb_gamestart = read_bool(r)?;
+        self.reg_intenb_framestart = read_bool(r)?;
+        self.reg_intenb_sbhit = read_bool(r)?;
+        self.reg_intenb_xpend = read_bool(r)?;
+
+        self.reg_dpctrl_disp = read_bool(r)?;
+        self.reg_dpctrl_synce = read_bool(r)?;
+
+        self.reg_xpctrl_xpen = read_bool(r)?;
+        self.reg_xpctrl_sbcount = read_u32(r)?;
+        self.reg_xpctrl_sbcmp = read_u32(r)?;
+        self.reg_xpctrl_sbout = read_bool(r)?;
+
+        self.reg_frmcyc = read_u32(r)?;
+
+        self.reg_brta = read_u8(r)?;
+        self.reg_brtb = read_u8(r)?;
+        self.reg_brtc = read_u8(r)?;
+
+        self.reg_spt0 = read_u16(r)?;
+        self.reg_spt1 = read_u16(r)?;
+        self.reg_spt2 = read_u16(r)?;
+        self.reg_spt3 = read_u16(r)?;
+
+        self.reg_gplt0 = read_u8(r)?;
+        self.reg_gplt1 = read_u8(r)?;
+        self.reg_gplt2 = read_u8(r)?;
+        self.reg_gplt3 = read_u8(r)?;
+
+        self.reg_jplt0 = read_u8(r)?;
+        self.reg_jplt1 = read_u8(r)?;
+        self.reg_jplt2 = read_u8(r)?;
+        self.reg_jplt3 = read_u8(r)?;
+
+        self.reg_bkcol = read_u8(r)?;
+
+        self.display_frame_eighth_clock_counter = read_u32(r)?;
+        self.display_frame_eighth_counter = read_u32(r)?;
+
+        self.drawing_block_counter = read_u32(r)?;
+        self.drawing_sbout_counter = read_u32(r)?;
+
+        self.fclk = read_u32(r)?;
+
+        self.display_first_framebuffers = read_bool(r)?;
+        self.last_bkcol = read_u8(r)?;
+
+        Ok(())
+    }
+
+    /// Number of cycles that can pass before the VIP next does anything other than count
+    pub fn cycles_until_next_event(&self) -> u32 {
+        let mut ret = DISPLAY_FRAME_EIGHTH_PERIOD.saturating_sub(self.display_frame_eighth_clock_counter + 1);
+
+        if let DrawingState::Drawing = self.drawing_state {
+            ret = ret.min(DRAWING_BLOCK_PERIOD.saturating_sub(self.drawing_block_counter + 1));
+
+            if self.reg_xpctrl_sbout {
+                ret = ret.min(DRAWING_SBOUT_PERIOD.saturating_sub(self.drawing_sbout_counter + 1));
+            }
+        }
+
+        ret
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        (self.reg_intpnd() & self.reg_intenb()) != 0
+    }
+
     pub fn cycles(&mut self, cycles: u32, video_frame_sink: &mut Sink<VideoFrame>) -> bool {
+        let mut remaining_cycles = cycles;
+        while remaining_cycles > 0 {
+            // Skip straight to the cycle where the next event happens
+            let skipped_cycles = self.cycles_until_next_event().min(remaining_cycles);
+            self.skip_cycles(skipped_cycles);
+            remaining_cycles -= skipped_cycles;
+
+            if remaining_cycles > 0 {
+                self.cycle(video_frame_sink);
+                remaining_cycles -= 1;
+            }
+        }
+
+        // Always raise any pending interrupts if the corresponding interrupts are enabled
+        self.interrupt_pending()
+    }
+
+    // The original cycle-by-cycle loop, kept as a reference for `cycles` to be checked against
+    #[cfg(test)]
+    fn reference_cycles(&mut self, cycles: u32, video_frame_sink: &mut Sink<VideoFrame>) -> bool {
         for _ in 0..cycles {
-            self.display_frame_eighth_clock_counter += 1;
-            if self.display_frame_eighth_clock_counter >= DISPLAY_FRAME_EIGHTH_PERIOD {
-                self.display_frame_eighth_clock_counter = 0;
+            self.cycle(video_frame_sink);
+        }
 
-                self.display_frame_eighth_counter = match self.display_frame_eighth_counter {
-                    7 => 0,
-                    _ => self.display_frame_eighth_counter + 1
-                };
+        self.interrupt_pending()
+    }
 
-                match self.display_frame_eighth_counter {
-                    0 => {
-                        self.frame_clock();
-                    }
-                    1 => {
-                        self.display(video_frame_sink);
+    /// Advances the VIP without any events happening. `cycles` must not be more than `cycles_until_next_event()`.
+    pub fn skip_cycles(&mut self, cycles: u32) {
+        self.display_frame_eighth_clock_counter += cycles;
 
-                        if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
-                            self.begin_left_framebuffer_display_process();
-                        }
+        if let DrawingState::Drawing = self.drawing_state {
+            self.drawing_block_counter += cycles;
+
+            if self.reg_xpctrl_sbout {
+                self.drawing_sbout_counter += cycles;
+            }
+        }
+    }
+
+    fn cycle(&mut self, video_frame_sink: &mut Sink<VideoFrame>) {
+        self.display_frame_eighth_clock_counter += 1;
+        if self.display_frame_eighth_clock_counter >= DISPLAY_FRAME_EIGHTH_PERIOD {
+            self.display_frame_eighth_clock_counter = 0;
+
+            self.display_frame_eighth_counter = match self.display_frame_eighth_counter {
+                7 => 0,
+                _ => self.display_frame_eighth_counter + 1
+            };
+
+            match self.display_frame_eighth_counter {
+                0 => {
+                    self.frame_clock();
+                }
+                1 => {
+                    self.display(video_frame_sink);
+
+                    if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
+                        self.begin_left_framebuffer_display_process();
                     }
-                    3 => {
-                        if self.reg_dpctrl_disp {
-                            if let DisplayState::LeftFramebuffer = self.display_state {
-                                self.end_left_framebuffer_display_process();
-                            }
+                }
+                3 => {
+                    if self.reg_dpctrl_disp {
+                        if let DisplayState::LeftFramebuffer = self.display_state {
+                            self.end_left_framebuffer_display_process();
                         }
                     }
-                    5 => {
-                        if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
-                            self.begin_right_framebuffer_display_process();
-                        }
+                }
+                5 => {
+                    if self.reg_dpctrl_disp && self.reg_dpctrl_synce {
+                        self.begin_right_framebuffer_display_process();
                     }
-                    7 => {
-                        if self.reg_dpctrl_disp {
-                            if let DisplayState::RightFramebuffer = self.display_state {
-                                self.reg_intpnd_rfbend = true;
-                            }
-
-                            self.end_display_process();
+                }
+                7 => {
+                    if self.reg_dpctrl_disp {
+                        if let DisplayState::RightFramebuffer = self.display_state {
+                            self.reg_intpnd_rfbend = true;
                         }
+
+                        self.end_display_process();
                     }
-                    _ => {}
                 }
+                _ => {}
             }
+        }
 
-            if let DrawingState::Drawing = self.drawing_state {
-                self.drawing_block_counter += 1;
-                if self.drawing_block_counter >= DRAWING_BLOCK_PERIOD {
-                    self.drawing_block_counter = 0;
+        if let DrawingState::Drawing = self.drawing_state {
+            self.drawing_block_counter += 1;
+            if self.drawing_block_counter >= DRAWING_BLOCK_PERIOD {
+                self.drawing_block_counter = 0;
 
-                    if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT {
-                        self.end_drawing_block();
+                if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT {
+                    self.end_drawing_block();
 
-                        if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT - 1 {
-                            self.reg_xpctrl_sbcount += 1;
-                            if self.reg_xpctrl_xpen {
-                                self.begin_drawing_block();
-                            }
-                        } else {
-                            self.end_drawing_process();
-                            self.reg_intpnd_xpend = true;
+                    if self.reg_xpctrl_sbcount < DRAWING_BLOCK_COUNT - 1 {
+                        self.reg_xpctrl_sbcount += 1;
+                        if self.reg_xpctrl_xpen {
+                            self.begin_drawing_block();
                         }
+                    } else {
+                        self.end_drawing_process();
+                        self.reg_intpnd_xpend = true;
                     }
                 }
+            }
 
-                if self.reg_xpctrl_sbout {
-                    self.drawing_sbout_counter += 1;
-                    if self.drawing_sbout_counter >= DRAWING_SBOUT_PERIOD {
-                        self.reg_xpctrl_sbout = false;
-                    }
+            if self.reg_xpctrl_sbout {
+                self.drawing_sbout_counter += 1;
+                if self.drawing_sbout_counter >= DRAWING_SBOUT_PERIOD {
+                    self.reg_xpctrl_sbout = false;
                 }
             }
         }
-
-        // Always raise any pending interrupts if the corresponding interrupts are enabled
-        (self.reg_intpnd() & self.reg_intenb()) != 0
     }
 
     fn frame_clock(&mut self) {
@@ -1136,3 +1347,88 @@ impl Vip {
         video_frame_sink.append((left_buffer, right_buffer));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Chosen to land both on and either side of display and drawing block boundaries
+    const CHUNK_SIZES: [u32; 14] = [1, 2, 3, 7, 1119, 1120, 1121, 49999, 50000, 50001, 120000, 199999, 400000, 400001];
+
+    const WORLD_31: u32 = WINDOW_ATTRIBS_START + 31 * 32;
+
+    struct Frames(Vec<VideoFrame>);
+
+    impl Sink<VideoFrame> for Frames {
+        fn append(&mut self, frame: VideoFrame) {
+            self.0.push(frame);
+        }
+    }
+
+    fn state(vip: &Vip) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        vip.save_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn configure(vip: &mut Vip) {
+        // Fill VRAM with noise, so both the framebuffers and anything drawn from the
+        //  characters and background maps have something to show
+        let mut seed = 0x1234_5678u32;
+        for addr in (VRAM_START..VRAM_END).filter(|addr| addr % 2 == 0) {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            vip.write_halfword(addr, (seed >> 16) as _);
+        }
+
+        // A single normal background world covering the top-left of the screen
+        for addr in (WINDOW_ATTRIBS_START..WINDOW_ATTRIBS_END).filter(|addr| addr % 2 == 0) {
+            vip.write_halfword(addr, 0);
+        }
+        vip.write_halfword(WORLD_31, 0xc000);
+        vip.write_halfword(WORLD_31 + 14, 200);
+        vip.write_halfword(WORLD_31 + 16, 100);
+        vip.write_halfword(WORLD_31 - 32, 0x0040);
+
+        vip.write_halfword(BRTA, 0x20);
+        vip.write_halfword(BRTB, 0x40);
+        vip.write_halfword(BRTC, 0x20);
+        vip.write_halfword(GPLT0, 0xe4);
+        vip.write_halfword(BKCOL, 0x02);
+        vip.write_halfword(INTENB, 0x601e);
+    }
+
+    #[test]
+    fn cycles_matches_reference() {
+        let (mut vip, mut reference) = (Vip::new(), Vip::new());
+        configure(&mut vip);
+        configure(&mut reference);
+        let (mut frames, mut reference_frames) = (Frames(Vec::new()), Frames(Vec::new()));
+
+        // Display only; display and drawing every other frame; drawing only; then everything off
+        for &(dpctrl, xpctrl, frmcyc) in [(0x0202, 0x0000, 0), (0x0202, 0x0002, 1), (0x0000, 0x0002, 0), (0x0000, 0x0000, 0)].iter() {
+            for vip in [&mut vip, &mut reference].iter_mut() {
+                vip.write_halfword(DPCTRL, dpctrl);
+                vip.write_halfword(XPCTRL, xpctrl);
+                vip.write_halfword(FRMCYC, frmcyc);
+            }
+
+            for &chunk_size in CHUNK_SIZES.iter() {
+                let interrupt = vip.cycles(chunk_size, &mut frames);
+                assert_eq!(interrupt, reference.reference_cycles(chunk_size, &mut reference_frames));
+                assert_eq!(state(&vip), state(&reference));
+                assert!(frames.0 == reference_frames.0);
+
+                // Acknowledge the interrupts like a handler would
+                if interrupt {
+                    let intpnd = vip.read_halfword(INTPND);
+                    vip.write_halfword(INTCLR, intpnd);
+                    reference.write_halfword(INTCLR, intpnd);
+                }
+            }
+        }
+
+        // Make sure the display and drawing processes actually produced something
+        assert!(frames.0.len() > 4);
+        assert!(frames.0.iter().any(|frame| frame.0.iter().any(|&pixel| pixel != 0)));
+    }
+}
@@ -1,9 +1,35 @@
+use emulation_error::*;
 use sinks::*;
 use rom::*;
 use sram::*;
 use interconnect::*;
+use save_state::*;
 use v810::*;
 
+use std::io::{self, Read, Write, Error, ErrorKind};
+
+const SAVE_STATE_MAGIC: &'static [u8; 4] = b"RBSS";
+const SAVE_STATE_VERSION: u32 = 8;
+
+/// Why a call to `run_frame` or `run_cycles` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The VIP emitted a video frame
+    FrameCompleted,
+    /// The requested number of cycles has been emulated
+    CyclesElapsed,
+    /// A CPU watchpoint was hit
+    Watchpoint,
+}
+
+/// The outcome of a call to `run_frame` or `run_cycles`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameResult {
+    /// Number of CPU cycles emulated
+    pub cycles: u64,
+    pub stop_reason: StopReason,
+}
+
 pub struct VirtualBoy {
     pub interconnect: Interconnect,
     pub cpu: V810,
@@ -17,13 +43,130 @@ impl VirtualBoy {
         }
     }
 
-    pub fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> (u32, bool) {
-        let ret = self.cpu.step(&mut self.interconnect);
+    /// Enables or disables instruction tracing, including memory accesses. While enabled,
+    /// `cpu.trace_record()` describes the instruction executed by each `step`.
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.cpu.set_tracing(enabled);
+        self.interconnect.set_access_tracing(enabled);
+    }
+
+    /// Writes a snapshot of the entire machine (excluding ROM) to `w`.
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bytes(w, SAVE_STATE_MAGIC)?;
+        write_u32(w, SAVE_STATE_VERSION)?;
+
+        self.save_machine_state(w)
+    }
+
+    /// Restores a snapshot previously written by `save_state`. The ROM loaded at the time
+    /// of the snapshot is expected to be the one currently loaded. If the snapshot can't be
+    /// loaded, an error is returned and the machine is left as it was.
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        let mut magic = [0; 4];
+        read_bytes(r, &mut magic)?;
+        if &magic != SAVE_STATE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid save state magic"));
+        }
+
+        let version = read_u32(r)?;
+        if version != SAVE_STATE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported save state version: {}", version)));
+        }
+
+        // Loading overwrites the machine piece by piece, so keep the current state around
+        //  to put back if the snapshot turns out to be truncated or corrupt partway through
+        let mut previous_state = Vec::new();
+        self.save_machine_state(&mut previous_state)?;
+        if let Err(e) = self.load_machine_state(r) {
+            self.load_machine_state(&mut &previous_state[..]).expect("Couldn't restore the previous state");
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn save_machine_state(&self, w: &mut Write) -> io::Result<()> {
+        self.cpu.save_state(w)?;
+        self.interconnect.save_state(w)
+    }
+
+    fn load_machine_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.cpu.load_state(r)?;
+        self.interconnect.load_state(r)
+    }
+
+    /// Runs until the VIP emits a video frame (or a watchpoint is hit). The frame is
+    /// appended to `video_frame_sink` before this returns. Emulation errors are passed
+    /// through from `step`.
+    pub fn run_frame(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<FrameResult, EmulationError> {
+        let mut video_frame_sink = FrameDetectingSink::new(video_frame_sink);
+        let mut cycles = 0;
+
+        loop {
+            let (step_cycles, trigger_watchpoint) = self.step(&mut video_frame_sink, audio_frame_sink)?;
+            cycles += step_cycles as u64;
+
+            if trigger_watchpoint {
+                return Ok(FrameResult {
+                    cycles: cycles,
+                    stop_reason: StopReason::Watchpoint,
+                });
+            }
+
+            if video_frame_sink.frame_emitted() {
+                return Ok(FrameResult {
+                    cycles: cycles,
+                    stop_reason: StopReason::FrameCompleted,
+                });
+            }
+        }
+    }
+
+    /// Runs for at least `num_cycles` cycles (or until a watchpoint is hit). Since
+    /// instructions aren't interrupted, this may overshoot by a few cycles; the number
+    /// of cycles actually emulated is returned. While the CPU is halted, this never
+    /// overshoots.
+    pub fn run_cycles(&mut self, num_cycles: u64, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<FrameResult, EmulationError> {
+        let mut cycles = 0;
+
+        while cycles < num_cycles {
+            let max_halted_cycles = (num_cycles - cycles).min(u32::max_value() as u64) as u32;
+            let (step_cycles, trigger_watchpoint) = self.step_with_limit(max_halted_cycles, video_frame_sink, audio_frame_sink)?;
+            cycles += step_cycles as u64;
+
+            if trigger_watchpoint {
+                return Ok(FrameResult {
+                    cycles: cycles,
+                    stop_reason: StopReason::Watchpoint,
+                });
+            }
+        }
+
+        Ok(FrameResult {
+            cycles: cycles,
+            stop_reason: StopReason::CyclesElapsed,
+        })
+    }
+
+    /// Executes a single instruction. While the CPU is halted, this instead skips
+    /// straight to the next cycle on which an interrupt could be raised.
+    pub fn step(&mut self, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(u32, bool), EmulationError> {
+        self.step_with_limit(u32::max_value(), video_frame_sink, audio_frame_sink)
+    }
+
+    fn step_with_limit(&mut self, max_halted_cycles: u32, video_frame_sink: &mut Sink<VideoFrame>, audio_frame_sink: &mut Sink<AudioFrame>) -> Result<(u32, bool), EmulationError> {
+        let ret = if self.cpu.is_halted() {
+            // Interrupts are only raised by scheduled events, so nothing can wake the CPU before then
+            let cycles = self.interconnect.cycles_until_next_event().min(max_halted_cycles);
+            (cycles.max(1), false)
+        } else {
+            self.cpu.step(&mut self.interconnect)?
+        };
 
         if let Some(exception_code) = self.interconnect.cycles(ret.0, video_frame_sink, audio_frame_sink) {
             self.cpu.request_interrupt(exception_code);
         }
 
-        ret
+        Ok(ret)
     }
 }
@@ -1,9 +1,12 @@
 mod mem_map;
 
+use save_state::*;
 use sinks::*;
 
 use self::mem_map::*;
 
+use std::io::{self, Read, Write};
+
 // Docs claim the sample rate is 41.7khz, but my calculations indicate it should be 41666.66hz repeating
 //  (see SAMPLE_CLOCK_PERIOD calculation below), so we take the nearest whole-number sample rate to that.
 //  Note that the documentation rounds values in a lot of places, so that's probably what happened here.
@@ -46,6 +49,26 @@ struct IntReg {
 }
 
 impl IntReg {
+    fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bool(w, self.output_enable)?;
+        write_bool(w, self.interval_data)?;
+        write_u32(w, self.interval_counter_setting_values)?;
+
+        write_u32(w, self.interval_counter)?;
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.output_enable = read_bool(r)?;
+        self.interval_data = read_bool(r)?;
+        self.interval_counter_setting_values = read_u32(r)?;
+
+        self.interval_counter = read_u32(r)?;
+
+        Ok(())
+    }
+
     fn write(&mut self, value: u8) {
         self.output_enable = (value & 0x80) != 0;
         self.interval_data = (value & 0x20) != 0;
@@ -73,6 +96,20 @@ struct LrvReg {
 }
 
 impl LrvReg {
+    fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_u32(w, self.left)?;
+        write_u32(w, self.right)?;
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.left = read_u32(r)?;
+        self.right = read_u32(r)?;
+
+        Ok(())
+    }
+
     fn write(&mut self, value: u8) {
         self.left = (value >> 4) as _;
         self.right = (value & 0x0f) as _;
@@ -94,6 +131,36 @@ struct Envelope {
 }
 
 impl Envelope {
+    fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_u32(w, self.reg_data_reload)?;
+        write_bool(w, self.reg_data_direction)?;
+        write_u32(w, self.reg_data_step_interval)?;
+
+        write_bool(w, self.reg_control_repeat)?;
+        write_bool(w, self.reg_control_enable)?;
+
+        write_u32(w, self.level)?;
+
+        write_u32(w, self.envelope_counter)?;
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.reg_data_reload = read_u32(r)?;
+        self.reg_data_direction = read_bool(r)?;
+        self.reg_data_step_interval = read_u32(r)?;
+
+        self.reg_control_repeat = read_bool(r)?;
+        self.reg_control_enable = read_bool(r)?;
+
+        self.level = read_u32(r)?;
+
+        self.envelope_counter = read_u32(r)?;
+
+        Ok(())
+    }
+
     fn write_data_reg(&mut self, value: u8) {
         self.reg_data_reload = (value >> 4) as _;
         self.reg_data_direction = (value & 0x08) != 0;
@@ -153,6 +220,42 @@ struct StandardSound {
 }
 
 impl StandardSound {
+    fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        self.reg_int.save_state(w)?;
+
+        self.reg_lrv.save_state(w)?;
+
+        write_u32(w, self.fql)?;
+        write_u32(w, self.fqh)?;
+
+        self.envelope.save_state(w)?;
+
+        write_u32(w, self.ram)?;
+
+        write_u32(w, self.frequency_counter)?;
+        write_u32(w, self.phase)?;
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.reg_int.load_state(r)?;
+
+        self.reg_lrv.load_state(r)?;
+
+        self.fql = read_u32(r)?;
+        self.fqh = read_u32(r)?;
+
+        self.envelope.load_state(r)?;
+
+        self.ram = read_u32(r)?;
+
+        self.frequency_counter = read_u32(r)?;
+        self.phase = read_u32(r)?;
+
+        Ok(())
+    }
+
     fn write_int(&mut self, value: u8) {
         self.reg_int.write(value);
 
@@ -188,6 +291,14 @@ impl StandardSound {
         self.ram = (value & 0x07) as _;
     }
 
+    fn frequency_clocks(&mut self, num_clocks: u32) {
+        let period = 2048 - ((self.fqh << 8) | self.fql);
+        let num_steps = advance_counter(&mut self.frequency_counter, period, num_clocks);
+        self.phase = (self.phase + num_steps) & (NUM_WAVEFORM_DATA_WORDS - 1);
+    }
+
+    // The original single-clock step, kept as a reference for `frequency_clocks` to be checked against
+    #[cfg(test)]
     fn frequency_clock(&mut self) {
         self.frequency_counter += 1;
         if self.frequency_counter >= 2048 - ((self.fqh << 8) | self.fql) {
@@ -254,6 +365,74 @@ struct SweepModSound {
 }
 
 impl SweepModSound {
+    fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        self.reg_int.save_state(w)?;
+
+        self.reg_lrv.save_state(w)?;
+
+        write_u32(w, self.fql)?;
+        write_u32(w, self.fqh)?;
+        write_u32(w, self.frequency_low)?;
+        write_u32(w, self.frequency_high)?;
+        write_u32(w, self.next_frequency_low)?;
+        write_u32(w, self.next_frequency_high)?;
+
+        self.envelope.save_state(w)?;
+
+        write_bool(w, self.reg_sweep_mod_enable)?;
+        write_bool(w, self.reg_mod_repeat)?;
+        write_bool(w, self.reg_function)?;
+
+        write_bool(w, self.reg_sweep_mod_base_interval)?;
+        write_u32(w, self.reg_sweep_mod_interval)?;
+        write_bool(w, self.reg_sweep_direction)?;
+        write_u32(w, self.reg_sweep_shift_amount)?;
+
+        write_u32(w, self.ram)?;
+
+        write_u32(w, self.frequency_counter)?;
+        write_u32(w, self.phase)?;
+
+        write_u32(w, self.sweep_mod_counter)?;
+        write_u32(w, self.mod_phase)?;
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.reg_int.load_state(r)?;
+
+        self.reg_lrv.load_state(r)?;
+
+        self.fql = read_u32(r)?;
+        self.fqh = read_u32(r)?;
+        self.frequency_low = read_u32(r)?;
+        self.frequency_high = read_u32(r)?;
+        self.next_frequency_low = read_u32(r)?;
+        self.next_frequency_high = read_u32(r)?;
+
+        self.envelope.load_state(r)?;
+
+        self.reg_sweep_mod_enable = read_bool(r)?;
+        self.reg_mod_repeat = read_bool(r)?;
+        self.reg_function = read_bool(r)?;
+
+        self.reg_sweep_mod_base_interval = read_bool(r)?;
+        self.reg_sweep_mod_interval = read_u32(r)?;
+        self.reg_sweep_direction = read_bool(r)?;
+        self.reg_sweep_shift_amount = read_u32(r)?;
+
+        self.ram = read_u32(r)?;
+
+        self.frequency_counter = read_u32(r)?;
+        self.phase = read_u32(r)?;
+
+        self.sweep_mod_counter = read_u32(r)?;
+        self.mod_phase = read_u32(r)?;
+
+        Ok(())
+    }
+
     fn write_int(&mut self, value: u8) {
         self.reg_int.write(value);
 
@@ -303,6 +482,14 @@ impl SweepModSound {
         self.ram = (value & 0x07) as _;
     }
 
+    fn frequency_clocks(&mut self, num_clocks: u32) {
+        let period = 2048 - ((self.frequency_high << 8) | self.frequency_low);
+        let num_steps = advance_counter(&mut self.frequency_counter, period, num_clocks);
+        self.phase = (self.phase + num_steps) & (NUM_WAVEFORM_DATA_WORDS - 1);
+    }
+
+    // The original single-clock step, kept as a reference for `frequency_clocks` to be checked against
+    #[cfg(test)]
     fn frequency_clock(&mut self) {
         self.frequency_counter += 1;
         if self.frequency_counter >= 2048 - ((self.frequency_high << 8) | self.frequency_low) {
@@ -399,6 +586,44 @@ struct NoiseSound {
 }
 
 impl NoiseSound {
+    fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        self.reg_int.save_state(w)?;
+
+        self.reg_lrv.save_state(w)?;
+
+        write_u32(w, self.fql)?;
+        write_u32(w, self.fqh)?;
+
+        self.envelope.save_state(w)?;
+
+        write_u32(w, self.reg_noise_control)?;
+
+        write_u32(w, self.frequency_counter)?;
+        write_u32(w, self.shift)?;
+        write_u32(w, self.output)?;
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        self.reg_int.load_state(r)?;
+
+        self.reg_lrv.load_state(r)?;
+
+        self.fql = read_u32(r)?;
+        self.fqh = read_u32(r)?;
+
+        self.envelope.load_state(r)?;
+
+        self.reg_noise_control = read_u32(r)?;
+
+        self.frequency_counter = read_u32(r)?;
+        self.shift = read_u32(r)?;
+        self.output = read_u32(r)?;
+
+        Ok(())
+    }
+
     fn write_int(&mut self, value: u8) {
         self.reg_int.write(value);
 
@@ -431,37 +656,51 @@ impl NoiseSound {
         self.envelope.write_control_reg(value);
     }
 
+    fn noise_clocks(&mut self, num_clocks: u32) {
+        let period = 2048 - ((self.fqh << 8) | self.fql);
+        let num_steps = advance_counter(&mut self.frequency_counter, period, num_clocks);
+        for _ in 0..num_steps {
+            self.shift_clock();
+        }
+    }
+
+    // The original single-clock step, kept as a reference for `noise_clocks` to be checked against
+    #[cfg(test)]
     fn noise_clock(&mut self) {
         self.frequency_counter += 1;
         if self.frequency_counter >= 2048 - ((self.fqh << 8) | self.fql) {
             self.frequency_counter = 0;
 
-            let lhs = self.shift >> 7;
-
-            let rhs_bit_index = match self.reg_noise_control {
-                0 => 14,
-                1 => 10,
-                2 => 13,
-                3 => 4,
-                4 => 8,
-                5 => 6,
-                6 => 9,
-                _ => 11
-            };
-            let rhs = self.shift >> rhs_bit_index;
-
-            let xor_bit = (lhs ^ rhs) & 0x01;
-
-            self.shift = ((self.shift << 1) | xor_bit) & 0x7fff;
-
-            let output_bit = (!xor_bit) & 0x01;
-            self.output = match output_bit {
-                0 => 0,
-                _ => 0x3f
-            };
+            self.shift_clock();
         }
     }
 
+    fn shift_clock(&mut self) {
+        let lhs = self.shift >> 7;
+
+        let rhs_bit_index = match self.reg_noise_control {
+            0 => 14,
+            1 => 10,
+            2 => 13,
+            3 => 4,
+            4 => 8,
+            5 => 6,
+            6 => 9,
+            _ => 11
+        };
+        let rhs = self.shift >> rhs_bit_index;
+
+        let xor_bit = (lhs ^ rhs) & 0x01;
+
+        self.shift = ((self.shift << 1) | xor_bit) & 0x7fff;
+
+        let output_bit = (!xor_bit) & 0x01;
+        self.output = match output_bit {
+            0 => 0,
+            _ => 0x3f
+        };
+    }
+
     fn output(&self) -> u32 {
         self.output
     }
@@ -522,6 +761,50 @@ impl Vsu {
         }
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bytes(w, &self.waveform_data)?;
+        for &value in self.mod_data.iter() {
+            write_u8(w, value as _)?;
+        }
+
+        self.sound1.save_state(w)?;
+        self.sound2.save_state(w)?;
+        self.sound3.save_state(w)?;
+        self.sound4.save_state(w)?;
+        self.sound5.save_state(w)?;
+        self.sound6.save_state(w)?;
+
+        write_u32(w, self.duration_clock_counter)?;
+        write_u32(w, self.envelope_clock_counter)?;
+        write_u32(w, self.frequency_clock_counter)?;
+        write_u32(w, self.sweep_mod_clock_counter)?;
+        write_u32(w, self.noise_clock_counter)?;
+        write_u32(w, self.sample_clock_counter)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        read_bytes(r, &mut self.waveform_data)?;
+        for value in self.mod_data.iter_mut() {
+            *value = read_u8(r)? as _;
+        }
+
+        self.sound1.load_state(r)?;
+        self.sound2.load_state(r)?;
+        self.sound3.load_state(r)?;
+        self.sound4.load_state(r)?;
+        self.sound5.load_state(r)?;
+        self.sound6.load_state(r)?;
+
+        self.duration_clock_counter = read_u32(r)?;
+        self.envelope_clock_counter = read_u32(r)?;
+        self.frequency_clock_counter = read_u32(r)?;
+        self.sweep_mod_clock_counter = read_u32(r)?;
+        self.noise_clock_counter = read_u32(r)?;
+        self.sample_clock_counter = read_u32(r)?;
+
+        Ok(())
+    }
+
     pub fn read_byte(&self, addr: u32) -> u8 {
         logln!(Log::Vsu, "WARNING: Attempted read byte from VSU (addr: 0x{:08x})", addr);
 
@@ -627,7 +910,35 @@ impl Vsu {
         self.write_byte(addr, value as _);
     }
 
+    /// Number of cycles that can pass before the VSU next does anything other than
+    /// count or clock its sound sources' frequency counters
+    pub fn cycles_until_next_event(&self) -> u32 {
+        let sweep_mod_clock_period = self.sweep_mod_clock_period();
+
+        DURATION_CLOCK_PERIOD.saturating_sub(self.duration_clock_counter + 1)
+            .min(ENVELOPE_CLOCK_PERIOD.saturating_sub(self.envelope_clock_counter + 1))
+            .min(sweep_mod_clock_period.saturating_sub(self.sweep_mod_clock_counter + 1))
+            .min(SAMPLE_CLOCK_PERIOD.saturating_sub(self.sample_clock_counter + 1))
+    }
+
     pub fn cycles(&mut self, num_cycles: u32, audio_frame_sink: &mut Sink<AudioFrame>) {
+        let mut remaining_cycles = num_cycles;
+        while remaining_cycles > 0 {
+            // Skip straight to the cycle where the next event happens
+            let skipped_cycles = self.cycles_until_next_event().min(remaining_cycles);
+            self.skip_cycles(skipped_cycles);
+            remaining_cycles -= skipped_cycles;
+
+            if remaining_cycles > 0 {
+                self.cycle(audio_frame_sink);
+                remaining_cycles -= 1;
+            }
+        }
+    }
+
+    // The original cycle-by-cycle loop, kept as a reference for `cycles` to be checked against
+    #[cfg(test)]
+    fn reference_cycles(&mut self, num_cycles: u32, audio_frame_sink: &mut Sink<AudioFrame>) {
         for _ in 0..num_cycles {
             self.duration_clock_counter += 1;
             if self.duration_clock_counter >= DURATION_CLOCK_PERIOD {
@@ -691,6 +1002,91 @@ impl Vsu {
         }
     }
 
+    fn sweep_mod_clock_period(&self) -> u32 {
+        match self.sound5.reg_sweep_mod_base_interval {
+            false => SWEEP_MOD_SMALL_PERIOD,
+            true => SWEEP_MOD_LARGE_PERIOD
+        }
+    }
+
+    /// Advances the VSU without any events happening. `num_cycles` must not be more than `cycles_until_next_event()`.
+    pub fn skip_cycles(&mut self, num_cycles: u32) {
+        self.duration_clock_counter += num_cycles;
+        self.envelope_clock_counter += num_cycles;
+        self.sweep_mod_clock_counter += num_cycles;
+        self.sample_clock_counter += num_cycles;
+
+        let num_frequency_clocks = advance_counter(&mut self.frequency_clock_counter, FREQUENCY_CLOCK_PERIOD, num_cycles);
+        if num_frequency_clocks > 0 {
+            self.sound1.frequency_clocks(num_frequency_clocks);
+            self.sound2.frequency_clocks(num_frequency_clocks);
+            self.sound3.frequency_clocks(num_frequency_clocks);
+            self.sound4.frequency_clocks(num_frequency_clocks);
+            self.sound5.frequency_clocks(num_frequency_clocks);
+        }
+
+        let num_noise_clocks = advance_counter(&mut self.noise_clock_counter, NOISE_CLOCK_PERIOD, num_cycles);
+        if num_noise_clocks > 0 {
+            self.sound6.noise_clocks(num_noise_clocks);
+        }
+    }
+
+    fn cycle(&mut self, audio_frame_sink: &mut Sink<AudioFrame>) {
+        self.duration_clock_counter += 1;
+        if self.duration_clock_counter >= DURATION_CLOCK_PERIOD {
+            self.duration_clock_counter = 0;
+
+            self.sound1.reg_int.duration_clock();
+            self.sound2.reg_int.duration_clock();
+            self.sound3.reg_int.duration_clock();
+            self.sound4.reg_int.duration_clock();
+            self.sound5.reg_int.duration_clock();
+            self.sound6.reg_int.duration_clock();
+        }
+
+        self.envelope_clock_counter += 1;
+        if self.envelope_clock_counter >= ENVELOPE_CLOCK_PERIOD {
+            self.envelope_clock_counter = 0;
+
+            self.sound1.envelope.envelope_clock();
+            self.sound2.envelope.envelope_clock();
+            self.sound3.envelope.envelope_clock();
+            self.sound4.envelope.envelope_clock();
+            self.sound5.envelope.envelope_clock();
+            self.sound6.envelope.envelope_clock();
+        }
+
+        // The frequency and noise clocks don't interact with anything else, so they
+        //  can be advanced the same way as when skipping
+        let num_frequency_clocks = advance_counter(&mut self.frequency_clock_counter, FREQUENCY_CLOCK_PERIOD, 1);
+        if num_frequency_clocks > 0 {
+            self.sound1.frequency_clocks(num_frequency_clocks);
+            self.sound2.frequency_clocks(num_frequency_clocks);
+            self.sound3.frequency_clocks(num_frequency_clocks);
+            self.sound4.frequency_clocks(num_frequency_clocks);
+            self.sound5.frequency_clocks(num_frequency_clocks);
+        }
+
+        self.sweep_mod_clock_counter += 1;
+        if self.sweep_mod_clock_counter >= self.sweep_mod_clock_period() {
+            self.sweep_mod_clock_counter = 0;
+
+            self.sound5.sweep_mod_clock(&self.mod_data);
+        }
+
+        let num_noise_clocks = advance_counter(&mut self.noise_clock_counter, NOISE_CLOCK_PERIOD, 1);
+        if num_noise_clocks > 0 {
+            self.sound6.noise_clocks(num_noise_clocks);
+        }
+
+        self.sample_clock_counter += 1;
+        if self.sample_clock_counter >= SAMPLE_CLOCK_PERIOD {
+            self.sample_clock_counter = 0;
+
+            self.sample_clock(audio_frame_sink);
+        }
+    }
+
     fn sample_clock(&mut self, audio_frame_sink: &mut Sink<AudioFrame>) {
         let mut acc_left = 0;
         let mut acc_right = 0;
@@ -744,3 +1140,111 @@ impl Vsu {
         self.sound6.reg_int.output_enable
     }
 }
+
+// Equivalent to `num_clocks` iterations of incrementing `counter` and resetting it to 0
+//  once it reaches `period`. Returns the number of times it was reset.
+fn advance_counter(counter: &mut u32, period: u32, num_clocks: u32) -> u32 {
+    let clocks_until_reset = period.saturating_sub(*counter).max(1);
+    if num_clocks < clocks_until_reset {
+        *counter += num_clocks;
+        return 0;
+    }
+
+    let remaining_clocks = num_clocks - clocks_until_reset;
+    *counter = remaining_clocks % period;
+    1 + remaining_clocks / period
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Chosen to land both on and either side of the various clocks' boundaries
+    const CHUNK_SIZES: [u32; 15] = [1, 2, 3, 7, 39, 40, 41, 479, 480, 481, 19201, 76799, 76800, 307201, 500000];
+
+    const SOUND_REGS: [(u32, u32, u32, u32, u32, u32); 6] = [
+        (S1INT, S1LRV, S1FQL, S1FQH, S1EV0, S1EV1),
+        (S2INT, S2LRV, S2FQL, S2FQH, S2EV0, S2EV1),
+        (S3INT, S3LRV, S3FQL, S3FQH, S3EV0, S3EV1),
+        (S4INT, S4LRV, S4FQL, S4FQH, S4EV0, S4EV1),
+        (S5INT, S5LRV, S5FQL, S5FQH, S5EV0, S5EV1),
+        (S6INT, S6LRV, S6FQL, S6FQH, S6EV0, S6EV1),
+    ];
+
+    struct Samples(Vec<AudioFrame>);
+
+    impl Sink<AudioFrame> for Samples {
+        fn append(&mut self, frame: AudioFrame) {
+            self.0.push(frame);
+        }
+    }
+
+    fn state(vsu: &Vsu) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        vsu.save_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn configure(vsu: &mut Vsu) {
+        let mut seed = 0x1234_5678u32;
+        for addr in (WAVEFORM_DATA_0_START..MOD_DATA_END).filter(|addr| addr % 4 == 0) {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            vsu.write_byte(addr, (seed >> 16) as _);
+        }
+
+        for (index, &(int, lrv, fql, fqh, ev0, ev1)) in SOUND_REGS.iter().enumerate() {
+            let index = index as u8;
+            vsu.write_byte(lrv, 0xff - index * 0x11);
+            vsu.write_byte(fql, 0x33 * index);
+            vsu.write_byte(fqh, index);
+            // Envelopes of different step intervals and directions, some repeating
+            vsu.write_byte(ev0, 0xf0 | (index & 0x01) << 3 | index);
+            vsu.write_byte(ev1, 0x01 | (index & 0x02));
+            // Half of the sounds stop after a few duration clocks
+            vsu.write_byte(int, 0x80 | (index & 0x01) << 5 | index);
+        }
+        vsu.write_byte(S1RAM, 0);
+        vsu.write_byte(S2RAM, 1);
+        vsu.write_byte(S3RAM, 2);
+        vsu.write_byte(S4RAM, 3);
+        vsu.write_byte(S5RAM, 4);
+
+        // Repeated modulation on sound 5, and a different tap for the noise
+        vsu.write_byte(S5EV1, 0x71);
+        vsu.write_byte(S5SWP, 0x10);
+        vsu.write_byte(S6EV1, 0x31);
+    }
+
+    fn reconfigure(vsu: &mut Vsu) {
+        // Shorten the frequency periods below the counters' current values, switch sound 5
+        //  to sweeping at the large interval and restart it
+        vsu.write_byte(S1FQL, 0xff);
+        vsu.write_byte(S1FQH, 0x07);
+        vsu.write_byte(S6FQL, 0xf0);
+        vsu.write_byte(S6FQH, 0x07);
+        vsu.write_byte(S5EV1, 0x41);
+        vsu.write_byte(S5SWP, 0x9a);
+        vsu.write_byte(S5INT, 0x80);
+    }
+
+    #[test]
+    fn cycles_matches_reference() {
+        let (mut vsu, mut reference) = (Vsu::new(), Vsu::new());
+        let (mut samples, mut reference_samples) = (Samples(Vec::new()), Samples(Vec::new()));
+
+        for &phase in [configure as fn(&mut Vsu), reconfigure].iter() {
+            phase(&mut vsu);
+            phase(&mut reference);
+
+            for &chunk_size in CHUNK_SIZES.iter() {
+                vsu.cycles(chunk_size, &mut samples);
+                reference.reference_cycles(chunk_size, &mut reference_samples);
+                assert_eq!(state(&vsu), state(&reference));
+                assert!(samples.0 == reference_samples.0);
+            }
+        }
+
+        // Make sure the sounds were actually audible
+        assert!(samples.0.iter().any(|&(left, right)| left != 0 && right != 0));
+    }
+}
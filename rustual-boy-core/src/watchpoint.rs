@@ -0,0 +1,114 @@
+use emulation_error::{AccessKind, AccessWidth};
+
+use std::fmt;
+
+/// Which kinds of access trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointKind {
+    Read,
+    Write,
+    /// Both reads and writes
+    Access,
+}
+
+impl WatchpointKind {
+    fn matches(&self, kind: AccessKind) -> bool {
+        match (*self, kind) {
+            (WatchpointKind::Access, _) |
+            (WatchpointKind::Read, AccessKind::Read) |
+            (WatchpointKind::Write, AccessKind::Write) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for WatchpointKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            WatchpointKind::Read => "read",
+            WatchpointKind::Write => "write",
+            WatchpointKind::Access => "access",
+        })
+    }
+}
+
+/// Stops emulation when the CPU accesses any byte from `start` to `end` (inclusive).
+///
+/// Addresses are compared as the CPU computes them, before the bus drops the upper
+/// address bits, so a watchpoint doesn't catch accesses through mirrors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub end: u32,
+    pub kind: WatchpointKind,
+}
+
+impl Watchpoint {
+    /// A watchpoint on a single byte
+    pub fn new(addr: u32, kind: WatchpointKind) -> Watchpoint {
+        Watchpoint::range(addr, addr, kind)
+    }
+
+    /// A watchpoint on all bytes between `start` and `end`, in either order
+    pub fn range(start: u32, end: u32, kind: WatchpointKind) -> Watchpoint {
+        Watchpoint {
+            start: start.min(end),
+            end: start.max(end),
+            kind: kind,
+        }
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && addr <= self.end
+    }
+
+    /// Whether an access of `width` bytes starting at `addr` triggers this watchpoint
+    pub fn matches(&self, addr: u32, kind: AccessKind, width: AccessWidth) -> bool {
+        let access_end = addr.saturating_add(width_bytes(width) - 1);
+        self.kind.matches(kind) && addr <= self.end && access_end >= self.start
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{} 0x{:08x}", self.kind, self.start)
+        } else {
+            write!(f, "{} 0x{:08x}-0x{:08x}", self.kind, self.start, self.end)
+        }
+    }
+}
+
+/// The access that triggered a watchpoint. For reads, `old_value` and `new_value` are
+/// both the value that was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub watchpoint: Watchpoint,
+    /// Address of the instruction that made the access
+    pub pc: u32,
+    pub addr: u32,
+    pub kind: AccessKind,
+    pub width: AccessWidth,
+    pub old_value: u32,
+    pub new_value: u32,
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = (width_bytes(self.width) * 2) as usize;
+        match self.kind {
+            AccessKind::Read => write!(f, "Watchpoint ({}) hit: read {} at 0x{:08x}: 0x{:0width$x} (pc: 0x{:08x})",
+                                       self.watchpoint, self.width, self.addr, self.new_value, self.pc, width = digits),
+            AccessKind::Write => write!(f, "Watchpoint ({}) hit: write {} at 0x{:08x}: 0x{:0width$x} -> 0x{:0width$x} (pc: 0x{:08x})",
+                                        self.watchpoint, self.width, self.addr, self.old_value, self.new_value, self.pc, width = digits),
+        }
+    }
+}
+
+fn width_bytes(width: AccessWidth) -> u32 {
+    match width {
+        AccessWidth::Byte => 1,
+        AccessWidth::Halfword => 2,
+        AccessWidth::Word => 4,
+    }
+}
@@ -1,7 +1,11 @@
+use save_state::*;
+
+use std::io::{self, Read, Write};
+
 pub const WRAM_SIZE: usize = 65536;
 
 pub struct Wram {
-    _bytes: Box<[u8]>,
+    bytes: Box<[u8]>,
     bytes_ptr: *mut u8,
 }
 
@@ -11,7 +15,7 @@ impl Wram {
         let bytes_ptr = bytes.as_mut_ptr();
 
         Wram {
-            _bytes: bytes,
+            bytes: bytes,
             bytes_ptr: bytes_ptr,
         }
     }
@@ -48,6 +52,14 @@ impl Wram {
         }
     }
 
+    pub fn save_state(&self, w: &mut Write) -> io::Result<()> {
+        write_bytes(w, &self.bytes)
+    }
+
+    pub fn load_state(&mut self, r: &mut Read) -> io::Result<()> {
+        read_bytes(r, &mut self.bytes)
+    }
+
     fn mask_addr(&self, addr: u32) -> u32 {
         let mask = (WRAM_SIZE - 1) as u32;
         addr & mask
@@ -0,0 +1,99 @@
+extern crate rustual_boy_core;
+
+use rustual_boy_core::assembler::*;
+use rustual_boy_core::instruction::*;
+
+fn halfwords(source: &str) -> Vec<u16> {
+    let assembly = assemble(source, 0x07000000).unwrap();
+    assembly.bytes.chunks(2).map(|x| (x[0] as u16) | ((x[1] as u16) << 8)).collect()
+}
+
+fn error_line(source: &str) -> usize {
+    assemble(source, 0x07000000).unwrap_err().line
+}
+
+#[test]
+fn format_i() {
+    assert_eq!(halfwords("mov r1, r2"), vec![0x0041]);
+    assert_eq!(halfwords("add r31, r30"), vec![0x07df]);
+    assert_eq!(halfwords("not r0, sp"), vec![0x3c60]);
+    assert_eq!(halfwords("jmp [lp]"), vec![0x181f]);
+}
+
+#[test]
+fn format_ii() {
+    assert_eq!(halfwords("mov 2, r1"), vec![0x4022]);
+    assert_eq!(halfwords("add -1, r1"), vec![0x443f]);
+    assert_eq!(halfwords("shl 31, r2"), vec![0x505f]);
+    assert_eq!(halfwords("setf nz, r3"), vec![0x486a]);
+    assert_eq!(halfwords("ldsr r1, chcw"), vec![0x7038]);
+    assert_eq!(halfwords("stsr psw, r2"), vec![0x7445]);
+    assert_eq!(halfwords("trap 0x1f"), vec![0x601f]);
+    assert_eq!(halfwords("halt\ncli\nsei\nreti"), vec![0x6800, 0x5800, 0x7800, 0x6400]);
+    assert_eq!(halfwords("movbsu"), vec![0x7c0b]);
+}
+
+#[test]
+fn format_iii_and_iv_branches() {
+    assert_eq!(halfwords("br ."), vec![0x8a00]);
+    assert_eq!(halfwords("nop"), vec![0x9a00]);
+    assert_eq!(halfwords("loop: nop\nbnz loop"), vec![0x9a00, 0x95fe]);
+    assert_eq!(halfwords("jr target\nnop\ntarget:"), vec![0xa800, 0x0006, 0x9a00]);
+    assert_eq!(halfwords("back: jal back"), vec![0xac00, 0x0000]);
+    assert_eq!(halfwords("nop\njr 0x06fffffe"), vec![0x9a00, 0xabff, 0xfffc]);
+}
+
+#[test]
+fn format_v_vi_vii() {
+    assert_eq!(halfwords("movhi 0x1234, r0, r6"), vec![0xbcc0, 0x1234]);
+    assert_eq!(halfwords("movea -1, r6, r7"), vec![0xa0e6, 0xffff]);
+    assert_eq!(halfwords("ld.b 0x10[r1], r2"), vec![0xc041, 0x0010]);
+    assert_eq!(halfwords("st.w r2, [r1]"), vec![0xdc41, 0x0000]);
+    assert_eq!(halfwords("out.h r2, -2[r1]"), vec![0xf441, 0xfffe]);
+    assert_eq!(halfwords("addf.s r1, r2"), vec![0xf841, 0x1000]);
+    assert_eq!(halfwords("mpyhw r3, r4"), vec![0xf883, 0x3000]);
+}
+
+#[test]
+fn encodings_decode_back_to_the_same_opcodes() {
+    let source = "mov r1, r2\nmov 1, r2\nsetf z, r1\nbge .\njal .\nori 1, r1, r2\nin.w 0[r1], r2\ncvt.sw r1, r2";
+    let expected = [Opcode::MovReg, Opcode::MovImm, Opcode::Setf, Opcode::Bge, Opcode::Jal, Opcode::OrI, Opcode::Inw, Opcode::Extended];
+
+    let halfwords = halfwords(source);
+    let mut index = 0;
+    for opcode in expected.iter() {
+        let decoded = Opcode::from_halfword(halfwords[index]);
+        assert!(decoded == *opcode);
+        index += if decoded.instruction_format().has_second_halfword() { 2 } else { 1 };
+    }
+    assert_eq!(index, halfwords.len());
+}
+
+#[test]
+fn hi_and_lo_load_any_address() {
+    let assembly = assemble("movhi hi(data), r0, r6\nmovea lo(data), r6, r6\n.org 0x0700fffc\ndata: .word 0", 0x07000000).unwrap();
+    let data = assembly.symbols["data"];
+    let hi = (assembly.bytes[2] as u32) | ((assembly.bytes[3] as u32) << 8);
+    let lo = (assembly.bytes[6] as u32) | ((assembly.bytes[7] as u32) << 8);
+    assert_eq!((hi << 16).wrapping_add(((lo as i16) as i32) as u32), data);
+}
+
+#[test]
+fn directives() {
+    let assembly = assemble(".equ value, 0x1234\n.byte 1, -1\n.align 4\n.halfword value\n.word value + 1\n.ascii \"a;b\"\n.fill 2, 0xff\n.org 0x07000020", 0x07000000).unwrap();
+    assert_eq!(&assembly.bytes[..16], &[0x01, 0xff, 0x00, 0x00, 0x34, 0x12, 0x35, 0x12, 0x00, 0x00, b'a', b';', b'b', 0xff, 0xff, 0x00][..]);
+    assert_eq!(assembly.end(), 0x07000020);
+    assert_eq!(assembly.symbols["value"], 0x1234);
+}
+
+#[test]
+fn errors_report_their_line() {
+    assert_eq!(error_line("nop\nfrob r1"), 2);
+    assert_eq!(error_line("nop\n\nbr nowhere"), 3);
+    assert_eq!(error_line("a:\na:"), 2);
+    assert_eq!(error_line("mov 16, r1"), 1);
+    assert_eq!(error_line("mov r1"), 1);
+    assert_eq!(error_line("ld.w r1, r2"), 1);
+    assert_eq!(error_line("br far\n.fill 256\nfar:"), 1);
+    assert_eq!(error_line(".org 0x07000010\nnop\n.org 0x07000000"), 3);
+}
@@ -0,0 +1,88 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::emulation_error::*;
+use rustual_boy_core::interconnect::{BusErrorPolicy, Interconnect};
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+// Steps until an instruction stops with an error
+fn run_to_error(virtual_boy: &mut VirtualBoy) -> EmulationError {
+    for _ in 0..1000 {
+        if let Err(error) = virtual_boy.step(&mut NullSink, &mut NullSink) {
+            return error;
+        }
+    }
+    panic!("No error");
+}
+
+#[test]
+fn load_is_retried() {
+    let (assembly, mut virtual_boy) = boot("
+        movhi 0x0300, r0, r1
+        movea 0x1234, r0, r2
+    fault:
+        ld.w 0[r1], r2");
+
+    let error = run_to_error(&mut virtual_boy);
+    let fault = assembly.symbols["fault"];
+    assert_eq!(error, EmulationError::BusError {
+        pc: fault,
+        bus_error: BusError { addr: 0x03000000, kind: AccessKind::Read, width: AccessWidth::Halfword },
+    });
+    assert_eq!(virtual_boy.cpu.reg_pc(), fault);
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 0x1234);
+
+    // Nothing has been committed, so the instruction can be retried with the bus open
+    virtual_boy.interconnect.set_bus_error_policy(BusErrorPolicy::OpenBus(0xabcd));
+    step(&mut virtual_boy);
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 0xabcdabcd);
+}
+
+#[test]
+fn bit_string_keeps_progress() {
+    // Copies 64 bits from the end of VSU (which reads as 0) into WRAM, running into the
+    //  unmapped halfword after CCR halfway through
+    let (assembly, mut virtual_boy) = boot("
+        movhi 0x0500, r0, r1
+        mov -1, r2
+        st.w r2, 0[r1]
+        st.w r2, 4[r1]
+        movhi 0x0200, r0, r30
+        add -4, r30
+        mov r1, r29
+        mov 0, r27
+        mov 0, r26
+        movea 64, r0, r28
+    fault:
+        movbsu");
+
+    let error = run_to_error(&mut virtual_boy);
+    assert_eq!(error.pc(), assembly.symbols["fault"]);
+    assert_eq!(virtual_boy.cpu.reg_pc(), assembly.symbols["fault"]);
+
+    // The first word was copied, and the registers point at the bit that faulted
+    assert_eq!(virtual_boy.interconnect.peek_halfword(0x05000000), 0);
+    assert_eq!(virtual_boy.interconnect.peek_halfword(0x05000004), 0xffff);
+    assert_eq!(virtual_boy.cpu.reg_gpr(30), 0x02000000);
+    assert_eq!(virtual_boy.cpu.reg_gpr(29), 0x05000004);
+    assert_eq!(virtual_boy.cpu.reg_gpr(27), 0);
+    assert_eq!(virtual_boy.cpu.reg_gpr(28), 32);
+}
+
+#[test]
+fn is_mapped_matches_bus_errors() {
+    let (_, mut virtual_boy) = boot("");
+    let addrs = [
+        0x00000000, 0x00ffffff, 0x01000000, 0x01ffffff, 0x02000000, 0x02000001, 0x02000010,
+        0x02000028, 0x0200002c, 0x03000000, 0x04000000, 0x05000000, 0x05ffffff, 0x06000000,
+        0x07000000, 0x07ffffff, 0x0a000000, 0xfffffff0,
+    ];
+    for &addr in addrs.iter() {
+        virtual_boy.interconnect.read_byte(addr);
+        let is_bus_error = virtual_boy.interconnect.take_bus_error().is_some();
+        assert_eq!(Interconnect::is_mapped(addr), !is_bus_error, "{:#010x}", addr);
+    }
+}
@@ -0,0 +1,107 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::call_stack::*;
+
+const PROGRAM: &'static str = "
+    start:
+        ldsr r0, psw            ; clear NP so the trap isn't fatal
+    main:
+        jal outer
+        halt
+    outer:
+        mov lp, r10
+        jal inner
+    after_inner:
+        mov r10, lp
+        jmp [lp]
+    inner:
+        trap 0
+    after_trap:
+        jmp [lp]
+
+        .org 0xffffffa0
+    trap_handler:
+        reti
+";
+
+#[test]
+fn calls_and_exceptions() {
+    let (assembly, mut virtual_boy) = boot(PROGRAM);
+    let symbol = |name: &str| assembly.symbols[name];
+
+    run_to(&mut virtual_boy, symbol("trap_handler"));
+    assert_eq!(virtual_boy.cpu.call_stack().frames(), &[
+        Frame {
+            kind: FrameKind::Call,
+            entry: symbol("outer"),
+            call_site: symbol("main"),
+            return_addr: symbol("main") + 4,
+        },
+        Frame {
+            kind: FrameKind::Call,
+            entry: symbol("inner"),
+            call_site: symbol("after_inner") - 4,
+            return_addr: symbol("after_inner"),
+        },
+        Frame {
+            kind: FrameKind::Exception(0xffa0),
+            entry: symbol("trap_handler"),
+            call_site: symbol("after_trap"),
+            return_addr: symbol("after_trap"),
+        },
+    ][..]);
+
+    run_to(&mut virtual_boy, symbol("after_trap"));
+    assert_eq!(virtual_boy.cpu.call_stack().depth(), 2);
+
+    run_to(&mut virtual_boy, symbol("after_inner"));
+    assert_eq!(virtual_boy.cpu.call_stack().depth(), 1);
+
+    run_to(&mut virtual_boy, symbol("main") + 4);
+    assert_eq!(virtual_boy.cpu.call_stack().depth(), 0);
+}
+
+#[test]
+fn loading_state_clears_the_stack() {
+    let (assembly, mut virtual_boy) = boot(PROGRAM);
+
+    let mut state = Vec::new();
+    virtual_boy.save_state(&mut state).unwrap();
+
+    run_to(&mut virtual_boy, assembly.symbols["inner"]);
+    assert_eq!(virtual_boy.cpu.call_stack().depth(), 2);
+
+    virtual_boy.load_state(&mut &state[..]).unwrap();
+    assert_eq!(virtual_boy.cpu.call_stack().depth(), 0);
+}
+
+#[test]
+fn unmatched_returns() {
+    let mut call_stack = CallStack::new();
+    call_stack.call(0x07000000, 0x07001000);
+    call_stack.enter_exception(0xfe40, 0x07001010, 0xfffffe40);
+    call_stack.call(0xfffffe40, 0x07002000);
+    call_stack.call(0x07002000, 0x07003000);
+
+    // A return to somewhere that wasn't called from is ignored
+    call_stack.ret(0x07005000);
+    assert_eq!(call_stack.depth(), 4);
+
+    // Returning from an outer call drops the ones inside it
+    call_stack.ret(0xfffffe44);
+    assert_eq!(call_stack.depth(), 2);
+
+    // Returns can't leave an exception handler, but `reti` drops its calls
+    call_stack.call(0xfffffe44, 0x07002000);
+    call_stack.ret(0x07000004);
+    assert_eq!(call_stack.depth(), 3);
+    call_stack.return_from_exception();
+    assert_eq!(call_stack.depth(), 1);
+
+    call_stack.ret(0x07000004);
+    assert_eq!(call_stack.depth(), 0);
+}
@@ -0,0 +1,179 @@
+//! Fixture shared by the tests that run assembly programs on a whole `VirtualBoy`.
+//! The middleware's tests use it too, by path.
+
+#![allow(dead_code)]
+
+use rustual_boy_core::assembler::{assemble, Assembly};
+use rustual_boy_core::rom::Rom;
+use rustual_boy_core::sinks::{AudioFrame, Sink, VideoFrame};
+use rustual_boy_core::sram::Sram;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+pub const ROM_START: u32 = 0xfffff000;
+
+// Enough for any of the test programs; running out means a program never halted
+const MAX_STEPS: u32 = 100000;
+
+pub struct NullSink;
+
+impl<T> Sink<T> for NullSink {
+    fn append(&mut self, _: T) {}
+}
+
+pub struct VideoFrames(pub Vec<VideoFrame>);
+
+impl Sink<VideoFrame> for VideoFrames {
+    fn append(&mut self, frame: VideoFrame) {
+        self.0.push(frame);
+    }
+}
+
+pub struct AudioFrames(pub Vec<AudioFrame>);
+
+impl Sink<AudioFrame> for AudioFrames {
+    fn append(&mut self, frame: AudioFrame) {
+        self.0.push(frame);
+    }
+}
+
+/// Wraps `program` in a 4KB ROM starting at `ROM_START`, followed by a `halt` and a reset
+/// vector that jumps to the start of the program, and boots it. Programs can place their
+/// own code further up in the ROM (eg. exception handlers) with `.org`.
+pub fn boot(program: &str) -> (Assembly, VirtualBoy) {
+    let source = format!("{}\n    halt\n.org 0xfffffff0\n    jr {:#x}\n.fill 12", program, ROM_START);
+    let assembly = assemble(&source, ROM_START).unwrap();
+    let rom = Rom::from_bytes(&assembly.bytes).unwrap();
+    let virtual_boy = VirtualBoy::new(rom, Sram::new());
+    (assembly, virtual_boy)
+}
+
+/// A program that runs `setup` with r1 pointing at the hardware registers, then waits for the
+/// link port interrupt. Its handler stores the exception code in r5, CCR in r6, CCSR in r7 and
+/// CDRR in r8, and sets r10 when it's done.
+pub fn link_port_program(setup: &str) -> String {
+    format!("
+        movhi 0x0200, r0, r1
+        ldsr r0, psw
+{}
+        halt
+
+.org 0xfffffe30
+        stsr ecr, r5
+        ld.b 0x00[r1], r6
+        andi 0xff, r6, r6
+        ld.b 0x04[r1], r7
+        andi 0xff, r7, r7
+        ld.b 0x0c[r1], r8
+        andi 0xff, r8, r8
+        mov 1, r10
+        halt", setup)
+}
+
+/// Steps one instruction, returning whether a watchpoint was triggered
+pub fn step(virtual_boy: &mut VirtualBoy) -> bool {
+    virtual_boy.step(&mut NullSink, &mut NullSink).unwrap().1
+}
+
+/// Steps until the CPU halts, calling `on_step` after each instruction
+pub fn run_until_halt_with<F: FnMut(&mut VirtualBoy, bool)>(virtual_boy: &mut VirtualBoy, mut on_step: F) {
+    for _ in 0..MAX_STEPS {
+        if virtual_boy.cpu.is_halted() {
+            return;
+        }
+        let trigger_watchpoint = step(virtual_boy);
+        on_step(virtual_boy, trigger_watchpoint);
+    }
+    panic!("Program didn't halt");
+}
+
+pub fn run_until_halt(virtual_boy: &mut VirtualBoy) {
+    run_until_halt_with(virtual_boy, |_, _| {});
+}
+
+/// Steps until the PC reaches `addr`
+pub fn run_to(virtual_boy: &mut VirtualBoy, addr: u32) {
+    for _ in 0..MAX_STEPS {
+        step(virtual_boy);
+        if virtual_boy.cpu.reg_pc() == addr {
+            return;
+        }
+    }
+    panic!("Never reached 0x{:08x}", addr);
+}
+
+/// Halts waiting for interrupts from the timer (which changes the pitch of the first sound
+/// channel) and the VIP (which draws stripes into the framebuffers), so that any difference
+/// in event timing shows up in the output.
+pub const AUDIO_VIDEO_PROGRAM: &'static str = "
+        ; Waveform 0: a ramp, played on sound 1
+        movhi 0x0100, r0, r1
+        mov 0, r2
+        movea 32, r0, r3
+    wave:
+        st.b r2, 0[r1]
+        add 2, r2
+        add 4, r1
+        add -1, r3
+        bnz wave
+        movhi 0x0100, r0, r1
+        movea 0xff, r0, r2
+        st.b r2, 0x404[r1]      ; S1LRV
+        movea 0xf0, r0, r2
+        st.b r2, 0x410[r1]      ; S1EV0
+        movea 0x80, r0, r2
+        st.b r2, 0x400[r1]      ; S1INT
+
+        ; Timer: interrupt every 11 * 20us
+        movhi 0x0200, r0, r1
+        mov 10, r2
+        st.b r2, 0x18[r1]       ; TLR
+        st.b r0, 0x1c[r1]       ; THR
+        movea 0x19, r0, r2
+        st.b r2, 0x20[r1]       ; TCR: enabled, 20us, zero interrupt
+
+        ; VIP: display on, game start and frame start interrupts
+        movhi 0x0006, r0, r1
+        movea -0x800, r1, r1
+        movea 32, r0, r2
+        st.h r2, 0x24[r1]       ; BRTA
+        st.h r2, 0x26[r1]       ; BRTB
+        st.h r2, 0x28[r1]       ; BRTC
+        movea 0x302, r0, r2
+        st.h r2, 0x22[r1]       ; DPCTRL
+        movea 0x18, r0, r2
+        st.h r2, 0x02[r1]       ; INTENB
+
+        mov 0, r20              ; pitch
+        mov 0, r24              ; framebuffer offset
+        mov 0, r25              ; pattern
+        ldsr r0, psw
+    idle:
+        halt
+        br idle
+
+.org 0xfffffe10
+        ; Timer: bump the pitch
+        add 1, r20
+        movhi 0x0100, r0, r21
+        st.b r20, 0x408[r21]    ; S1FQL
+        movhi 0x0200, r0, r21
+        movea 0x1d, r0, r22
+        st.b r22, 0x20[r21]     ; TCR: clear the zero status
+        reti
+
+.org 0xfffffe40
+        ; VIP: draw a stripe of the pattern in r25 at the next column of every framebuffer
+        movhi 0x0006, r0, r26
+        movea -0x800, r26, r26
+        ld.h 0x00[r26], r27     ; INTPND
+        st.h r27, 0x04[r26]     ; INTCLR
+        movhi 0x1b1b, r25, r25
+        movea 0x1b1b, r25, r25
+        st.w r25, 0[r24]
+        movhi 1, r24, r27
+        st.w r25, 0[r27]
+        st.w r25, -0x8000[r27]
+        movhi 2, r24, r27
+        st.w r25, -0x8000[r27]
+        add 4, r24
+        reti";
@@ -0,0 +1,113 @@
+//! CPU tests written in assembly. Each program runs from reset until it halts.
+
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+fn run(program: &str) -> VirtualBoy {
+    let (_, mut virtual_boy) = boot(program);
+    run_until_halt(&mut virtual_boy);
+    virtual_boy
+}
+
+#[test]
+fn immediates() {
+    let virtual_boy = run("
+        mov -3, r1
+        add 5, r1
+        movhi hi(0x12348765), r0, r2
+        movea lo(0x12348765), r2, r2
+        ori 0xff00, r0, r3
+        andi 0x0ff0, r3, r4
+        xori 0xffff, r4, r5");
+
+    assert_eq!(virtual_boy.cpu.reg_gpr(1), 2);
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 0x12348765);
+    assert_eq!(virtual_boy.cpu.reg_gpr(3), 0xff00);
+    assert_eq!(virtual_boy.cpu.reg_gpr(4), 0x0f00);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 0xf0ff);
+}
+
+#[test]
+fn counting_loop() {
+    let virtual_boy = run("
+        mov 10, r1
+        mov r0, r2
+    loop:
+        add r1, r2
+        add -1, r1
+        bnz loop");
+
+    assert_eq!(virtual_boy.cpu.reg_gpr(1), 0);
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 55);
+}
+
+#[test]
+fn loads_and_stores() {
+    let virtual_boy = run("
+        movhi 0x0500, r0, r1
+        movea -2, r0, r2
+        st.w r2, 4[r1]
+        st.b r0, 4[r1]
+        ld.w 4[r1], r3
+        ld.b 5[r1], r4
+        ld.h 4[r1], r5");
+
+    assert_eq!(virtual_boy.cpu.reg_gpr(3), 0xffffff00);
+    assert_eq!(virtual_boy.cpu.reg_gpr(4), 0xffffffff);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 0xffffff00);
+}
+
+#[test]
+fn subroutine_call() {
+    let virtual_boy = run("
+        mov 1, r1
+        jal double
+        jal double
+        br done
+    double:
+        shl 1, r1
+        jmp [lp]
+    done:");
+
+    assert_eq!(virtual_boy.cpu.reg_gpr(1), 4);
+}
+
+#[test]
+fn conditions() {
+    let virtual_boy = run("
+        mov 1, r1
+        cmp 2, r1
+        setf lt, r2
+        setf gt, r3
+        setf nz, r4
+        setf z, r5");
+
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 1);
+    assert_eq!(virtual_boy.cpu.reg_gpr(3), 0);
+    assert_eq!(virtual_boy.cpu.reg_gpr(4), 1);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 0);
+}
+
+#[test]
+fn floating_point() {
+    let virtual_boy = run("
+        mov 3, r1
+        cvt.ws r1, r1
+        mov 4, r2
+        cvt.ws r2, r2
+        mulf.s r1, r2
+        movhi hi(0x3f400000), r0, r3
+        addf.s r3, r2
+        trnc.sw r2, r4
+        cvt.sw r2, r5");
+
+    // 3.0 * 4.0 + 0.75
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 12.75f32.to_bits());
+    assert_eq!(virtual_boy.cpu.reg_gpr(4), 12);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 13);
+}
@@ -0,0 +1,160 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use std::time::Instant;
+
+// Copies `routine` to WRAM and calls it 100 times, patching its immediate operand (the
+//  second halfword of a 32-bit instruction, which the decode cache has to notice) before
+//  each call
+const PROGRAM: &'static str = "
+        movhi 0x0500, r0, r10
+        movea routine, r0, r11
+        ld.w 0[r11], r12
+        st.w r12, 0[r10]
+        ld.w 4[r11], r12
+        st.w r12, 4[r10]
+        movea 100, r0, r1
+        mov 0, r5
+    loop:
+        st.h r1, 2[r10]
+        movea back, r0, r31
+        jmp [r10]
+    back:
+        add -1, r1
+        bnz loop
+        halt
+
+        .align 4
+    routine:
+        movea 0, r0, r6
+        add r6, r5
+        jmp [r31]";
+
+const ENABLE_INSTRUCTION_CACHE: &'static str = "
+        mov 2, r1
+        ldsr r1, chcw";
+
+const HOT_LOOP: &'static str = "
+        movea 1000, r0, r1
+    loop:
+        add -1, r1
+        bnz loop";
+
+// Runs `program`, returning the cycles each instruction took and the final registers
+fn run(program: &str, is_decode_cache_enabled: bool) -> (Vec<u32>, Vec<u32>) {
+    let (_, mut virtual_boy) = boot(program);
+    virtual_boy.interconnect.set_decode_cache_enabled(is_decode_cache_enabled);
+
+    let mut cycles = Vec::new();
+    while !virtual_boy.cpu.is_halted() {
+        cycles.push(virtual_boy.step(&mut NullSink, &mut NullSink).unwrap().0);
+    }
+    let registers = (0..32).map(|index| virtual_boy.cpu.reg_gpr(index)).collect::<Vec<_>>();
+    (cycles, registers)
+}
+
+#[test]
+fn same_results() {
+    let (cycles, registers) = run(PROGRAM, true);
+    assert_eq!(registers[5], 5050);
+    assert_eq!((cycles, registers), run(PROGRAM, false));
+
+    // With the instruction cache enabled, the routine keeps running from the cache, which
+    //  never sees the patches. The decode cache mustn't change that either.
+    let program = format!("{}\n{}", ENABLE_INSTRUCTION_CACHE, PROGRAM);
+    let (cycles, registers) = run(&program, true);
+    assert_eq!(registers[5], 100 * 100);
+    assert_eq!((cycles, registers), run(&program, false));
+}
+
+#[test]
+fn hot_loop_hits() {
+    for &(program, num_setup_instructions) in [(HOT_LOOP, 0), (&format!("{}\n{}", ENABLE_INSTRUCTION_CACHE, HOT_LOOP) as &str, 2)].iter() {
+        let (_, mut virtual_boy) = boot(program);
+        run_until_halt(&mut virtual_boy);
+        assert_eq!(virtual_boy.cpu.reg_gpr(1), 0);
+
+        // The reset vector's jr, movea, add, bnz and halt are each decoded once; the other
+        //  999 passes through the loop are served from the cache
+        let (hits, misses) = virtual_boy.interconnect.decode_cache_stats();
+        assert_eq!(misses, 5 + num_setup_instructions);
+        assert_eq!(hits, 999 * 2);
+    }
+}
+
+#[test]
+fn wram_writes_redecode() {
+    let (_, mut virtual_boy) = boot(PROGRAM);
+    run_until_halt(&mut virtual_boy);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 5050);
+
+    // Patching the routine invalidates its first instruction, which is decoded again on
+    //  each of the 100 calls. Everything else (the reset vector's jr, the 14 instructions
+    //  in ROM and the other 2 in WRAM) is only decoded once.
+    let (hits, misses) = virtual_boy.interconnect.decode_cache_stats();
+    assert_eq!(misses, 1 + 14 + 2 + 100);
+    let num_executed = 1 + 8 + 100 * (3 + 3 + 2) + 1;
+    assert_eq!(hits + misses, num_executed);
+}
+
+#[test]
+fn patched_rom_redecodes() {
+    let (assembly, mut virtual_boy) = boot("
+        mov 0, r5
+        movea 10, r0, r1
+    loop:
+        add 1, r5
+        add -1, r1
+        bnz loop
+    done:");
+    let (add, done) = (assembly.symbols["loop"], assembly.symbols["done"]);
+    run_to(&mut virtual_boy, done);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 10);
+    let (_, misses) = virtual_boy.interconnect.decode_cache_stats();
+
+    // Patch `add 1, r5` into `add 2, r5` and run the loop again
+    let low_byte = virtual_boy.interconnect.peek_byte(add);
+    virtual_boy.interconnect.patch_byte(add, (low_byte & !0x1f) | 2);
+    virtual_boy.cpu.set_reg_gpr(1, 10);
+    virtual_boy.cpu.set_reg_pc(add);
+    run_to(&mut virtual_boy, done);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5), 30);
+
+    // Only the patched instruction had to be decoded again
+    let (_, misses_after_patch) = virtual_boy.interconnect.decode_cache_stats();
+    assert_eq!(misses_after_patch - misses, 1);
+}
+
+// Not a correctness test; run with `cargo test --release -- --ignored --nocapture` to see
+//  how much time the decode cache saves
+#[test]
+#[ignore]
+fn faster() {
+    fn time(is_decode_cache_enabled: bool) -> u64 {
+        let (_, mut virtual_boy) = boot("
+            movea 100, r0, r3
+        outer:
+            movea 0x7fff, r0, r1
+        inner:
+            movhi 0, r0, r2
+            add -1, r1
+            bnz inner
+            add -1, r3
+            bnz outer");
+        virtual_boy.interconnect.set_decode_cache_enabled(is_decode_cache_enabled);
+
+        let start = Instant::now();
+        while !virtual_boy.cpu.is_halted() {
+            step(&mut virtual_boy);
+        }
+        let elapsed = start.elapsed();
+        elapsed.as_secs() * 1000000 + (elapsed.subsec_nanos() / 1000) as u64
+    }
+
+    let (with_cache, without_cache) = (time(true), time(false));
+    println!("with the decode cache: {}us, without: {}us", with_cache, without_cache);
+    assert!(with_cache < without_cache);
+}
@@ -0,0 +1,109 @@
+extern crate rustual_boy_core;
+
+use rustual_boy_core::assembler::*;
+use rustual_boy_core::disassembler::*;
+use rustual_boy_core::instruction::*;
+
+const ORIGIN: u32 = 0x07000000;
+
+fn disassemble_all(source: &str) -> Vec<String> {
+    let assembly = assemble(source, ORIGIN).unwrap();
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < assembly.bytes.len() {
+        let instruction = disassemble(&assembly.bytes[offset..], ORIGIN + offset as u32).unwrap();
+        offset += instruction.size as usize;
+        lines.push(instruction.to_string());
+    }
+    lines
+}
+
+#[test]
+fn formats_match_assembler_syntax() {
+    assert_eq!(disassemble_all("
+        mov r1, r2
+        jmp [lp]
+        mov -3, r4
+        shl 31, r2
+        ldsr r1, chcw
+        stsr psw, r2
+        trap 5
+        halt
+        movbsu
+        movea 0x1234, r0, r6
+        ld.w -4[sp], r10
+        st.h r7, 2[r6]
+        out.w r1, 0x10[r2]
+        addf.s r7, r8
+    "), vec![
+        "mov r1, r2",
+        "jmp [r31]",
+        "mov -3, r4",
+        "shl 31, r2",
+        "ldsr r1, chcw",
+        "stsr psw, r2",
+        "trap 5",
+        "halt",
+        "movbsu",
+        "movea 0x1234, r0, r6",
+        "ld.w -4[r3], r10",
+        "st.h r7, 2[r6]",
+        "out.w r1, 16[r2]",
+        "addf.s r7, r8",
+    ]);
+}
+
+#[test]
+fn branch_targets() {
+    let assembly = assemble("loop: nop\nbnz loop\njal loop\njr 0x07001000", ORIGIN).unwrap();
+
+    let bnz = disassemble(&assembly.bytes[2..], ORIGIN + 2).unwrap();
+    assert_eq!(bnz.mnemonic, Mnemonic::Opcode(Opcode::Bnz));
+    assert_eq!(bnz.operands, vec![Operand::Branch { disp: -2, target: ORIGIN }]);
+    assert_eq!(bnz.size, 2);
+    assert_eq!(bnz.next_addr(), ORIGIN + 4);
+    assert_eq!(bnz.to_string(), "bnz -2 (0x07000000)");
+
+    let jal = disassemble(&assembly.bytes[4..], ORIGIN + 4).unwrap();
+    assert_eq!(jal.operands, vec![Operand::Branch { disp: -4, target: ORIGIN }]);
+    assert_eq!(jal.size, 4);
+    assert_eq!(jal.to_string(), "jal -4 (0x07000000)");
+
+    let jr = disassemble(&assembly.bytes[8..], ORIGIN + 8).unwrap();
+    assert_eq!(jr.operands, vec![Operand::Branch { disp: 0xff8, target: 0x07001000 }]);
+}
+
+#[test]
+fn structured_operands() {
+    let instruction = disassemble(&[0x46, 0xcd, 0xfc, 0xff], ORIGIN).unwrap();
+    assert_eq!(instruction.mnemonic, Mnemonic::Opcode(Opcode::Ldw));
+    assert_eq!(instruction.operands, vec![Operand::Memory { disp: -4, base: 6 }, Operand::Reg(10)]);
+
+    let instruction = disassemble(&[0x38, 0x70], ORIGIN).unwrap();
+    assert_eq!(instruction.operands, vec![Operand::Reg(1), Operand::SystemReg(SystemRegister::Chcw)]);
+}
+
+#[test]
+fn invalid_opcodes() {
+    // Opcode 0b011011 is unassigned
+    let instruction = disassemble(&[0x00, 0x6c, 0x00, 0x00], ORIGIN).unwrap();
+    assert_eq!(instruction.mnemonic, Mnemonic::Invalid);
+    assert_eq!(instruction.size, 2);
+    assert_eq!(instruction.to_string(), ".halfword 0x6c00");
+
+    // Bit string op 0 doesn't exist
+    assert_eq!(disassemble(&[0x00, 0x7c], ORIGIN).unwrap().mnemonic, Mnemonic::Invalid);
+}
+
+#[test]
+fn truncated_instructions() {
+    assert_eq!(disassemble(&[], ORIGIN), None);
+    assert_eq!(disassemble(&[0x38], ORIGIN), None);
+
+    // movea needs its second halfword
+    assert_eq!(disassemble(&[0x20, 0xa0, 0x34], ORIGIN), None);
+    assert_eq!(disassemble(&[0x20, 0xa0, 0x34, 0x12], ORIGIN).unwrap().size, 4);
+
+    // Extra bytes after a 16-bit instruction aren't needed
+    assert_eq!(disassemble(&[0x38, 0x70, 0x00], ORIGIN).unwrap().size, 2);
+}
@@ -0,0 +1,59 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::sinks::{AudioFrame, VideoFrame};
+
+// 20MHz / 50Hz
+const FRAME_CYCLES: u64 = 400000;
+
+struct Run {
+    // (cycle, pc) of every instruction executed, which includes when each interrupt was taken
+    instructions: Vec<(u64, u32)>,
+    video_frames: Vec<VideoFrame>,
+    audio_frames: Vec<AudioFrame>,
+}
+
+fn run(is_skipping_events: bool) -> Run {
+    let (_, mut virtual_boy) = boot(AUDIO_VIDEO_PROGRAM);
+    virtual_boy.interconnect.set_event_skipping(is_skipping_events);
+
+    let mut run = Run {
+        instructions: Vec::new(),
+        video_frames: Vec::new(),
+        audio_frames: Vec::new(),
+    };
+    let mut video_frames = VideoFrames(Vec::new());
+    let mut audio_frames = AudioFrames(Vec::new());
+
+    let mut cycles = 0;
+    while cycles < 6 * FRAME_CYCLES {
+        if !virtual_boy.cpu.is_halted() {
+            run.instructions.push((cycles, virtual_boy.cpu.reg_pc()));
+        }
+        cycles += virtual_boy.step(&mut video_frames, &mut audio_frames).unwrap().0 as u64;
+    }
+
+    run.video_frames = video_frames.0;
+    run.audio_frames = audio_frames.0;
+    run
+}
+
+#[test]
+fn same_as_stepping_every_instruction() {
+    let skipping = run(true);
+    let stepping = run(false);
+
+    // Make sure the program actually exercised everything
+    assert!(skipping.video_frames.len() >= 2);
+    assert!(skipping.video_frames.iter().any(|&(ref left, _)| left.iter().any(|&pixel| pixel != 0)));
+    assert!(skipping.audio_frames.iter().any(|&frame| frame != (0, 0)));
+    assert!(skipping.instructions.iter().any(|&(_, pc)| pc == 0xfffffe10));
+    assert!(skipping.instructions.iter().any(|&(_, pc)| pc == 0xfffffe40));
+
+    assert!(skipping.instructions == stepping.instructions, "Instruction timing differs");
+    assert!(skipping.video_frames == stepping.video_frames, "Video differs");
+    assert!(skipping.audio_frames == stepping.audio_frames, "Audio differs");
+}
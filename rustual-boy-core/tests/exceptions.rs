@@ -0,0 +1,275 @@
+//! Exceptions raised by instructions: TRAP, illegal instructions, the FPU's exceptions,
+//! and what happens when one is raised while another is being handled
+
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+const PSW_FPR: u32 = 1 << 4;
+const PSW_FOV: u32 = 1 << 6;
+const PSW_FZD: u32 = 1 << 7;
+const PSW_FIV: u32 = 1 << 8;
+const PSW_FRO: u32 = 1 << 9;
+const PSW_ID: u32 = 1 << 12;
+const PSW_EP: u32 = 1 << 14;
+const PSW_NP: u32 = 1 << 15;
+
+const FPU_FLAGS: u32 = 0x3f0;
+
+// Each handler stores ECR in r20, EIPC in r21 and EIPSW in r22, and the high byte
+//  of its vector in r25, then halts
+const HANDLERS: &'static str = "
+.org 0xffffff60
+        stsr ecr, r20
+        stsr eipc, r21
+        mov 6, r25
+        stsr eipsw, r22
+        halt
+.org 0xffffff70
+        stsr ecr, r20
+        stsr eipc, r21
+        mov 7, r25
+        stsr eipsw, r22
+        halt
+.org 0xffffff90
+        stsr ecr, r20
+        stsr eipc, r21
+        mov 9, r25
+        stsr eipsw, r22
+        halt
+.org 0xffffffa0
+        stsr ecr, r20
+        stsr eipc, r21
+        mov 10, r25
+        stsr eipsw, r22
+        halt
+.org 0xffffffb0
+        stsr ecr, r20
+        stsr eipc, r21
+        mov 11, r25
+        stsr eipsw, r22
+        halt";
+
+// Runs `program` with the PSW cleared (the CPU resets with NP set, which would make any
+//  exception fatal) and returns the address of its `fault` label
+fn run(program: &str) -> (VirtualBoy, u32) {
+    let (assembly, mut virtual_boy) = boot(&format!("
+        ldsr r0, psw
+        mov 0, r25
+{}
+        halt
+{}", program, HANDLERS));
+    run_until_halt(&mut virtual_boy);
+    let fault = assembly.symbols.get("fault").cloned().unwrap_or(0);
+    (virtual_boy, fault)
+}
+
+fn float_bits(virtual_boy: &VirtualBoy, index: usize) -> f32 {
+    f32::from_bits(virtual_boy.cpu.reg_gpr(index))
+}
+
+#[test]
+fn trap_vectors() {
+    let (virtual_boy, fault) = run("
+    fault:
+        trap 3");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 10);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xffa3);
+    // Unlike other exceptions, TRAP returns to the instruction after it
+    assert_eq!(virtual_boy.cpu.reg_gpr(21), fault + 2);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22), 0);
+    assert_eq!(virtual_boy.cpu.reg_psw() & (PSW_EP | PSW_ID | PSW_NP), PSW_EP | PSW_ID);
+
+    let (virtual_boy, _) = run("
+        trap 0x1c");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 11);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xffbc);
+}
+
+#[test]
+fn trap_returns() {
+    let (_, mut virtual_boy) = boot("
+        ldsr r0, psw
+        mov 1, r1
+        trap 0
+        add 1, r1
+        halt
+
+.org 0xffffffa0
+        add 1, r1
+        reti");
+    run_until_halt(&mut virtual_boy);
+    assert_eq!(virtual_boy.cpu.reg_gpr(1), 3);
+    assert_eq!(virtual_boy.cpu.reg_psw() & (PSW_EP | PSW_ID), 0);
+}
+
+#[test]
+fn illegal_instructions() {
+    for &(instruction, name) in [
+        (".halfword 0x6c00", "opcode"),
+        (".halfword 0x7c1f", "bit string op"),
+        (".halfword 0xf800, 0xfc00", "subop"),
+    ].iter() {
+        let (virtual_boy, fault) = run(&format!("
+    fault:
+        {}", instruction));
+        assert_eq!(virtual_boy.cpu.reg_gpr(25), 9, "{}", name);
+        assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xff90, "{}", name);
+        assert_eq!(virtual_boy.cpu.reg_gpr(21), fault, "{}", name);
+    }
+}
+
+#[test]
+fn fpu_reserved_operand() {
+    let (virtual_boy, fault) = run("
+        movhi 0x7f80, r0, r1        ; +inf
+        mov 1, r2
+        cvt.ws r2, r2
+    fault:
+        addf.s r1, r2");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 6);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xff60);
+    assert_eq!(virtual_boy.cpu.reg_gpr(21), fault);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22) & FPU_FLAGS, PSW_FRO);
+    // The result isn't written
+    assert_eq!(float_bits(&virtual_boy, 2), 1.0);
+}
+
+#[test]
+fn fpu_overflow() {
+    let (virtual_boy, fault) = run("
+        movhi 0x7f00, r0, r1        ; 2^127
+    fault:
+        mulf.s r1, r1");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 6);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xff64);
+    assert_eq!(virtual_boy.cpu.reg_gpr(21), fault);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22) & FPU_FLAGS, PSW_FOV | PSW_FPR);
+    assert_eq!(virtual_boy.cpu.reg_gpr(1), 0x7f000000);
+}
+
+#[test]
+fn fpu_zero_division() {
+    let (virtual_boy, fault) = run("
+        movhi 0x3f80, r0, r1        ; 1.0
+        mov r0, r2
+    fault:
+        divf.s r2, r1");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 6);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xff68);
+    assert_eq!(virtual_boy.cpu.reg_gpr(21), fault);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22) & FPU_FLAGS, PSW_FZD);
+    assert_eq!(float_bits(&virtual_boy, 1), 1.0);
+}
+
+#[test]
+fn fpu_invalid_operation() {
+    // 0 / 0
+    let (virtual_boy, fault) = run("
+        mov r0, r1
+    fault:
+        divf.s r1, r1");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 7);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xff70);
+    assert_eq!(virtual_boy.cpu.reg_gpr(21), fault);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22) & FPU_FLAGS, PSW_FIV);
+
+    // Converting a float that doesn't fit in a word
+    let (virtual_boy, fault) = run("
+        movhi 0x4f40, r0, r1        ; 3221225472.0
+        mov 5, r2
+    fault:
+        trnc.sw r1, r2");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 7);
+    assert_eq!(virtual_boy.cpu.reg_ecr() & 0xffff, 0xff70);
+    assert_eq!(virtual_boy.cpu.reg_gpr(21), fault);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22) & FPU_FLAGS, PSW_FIV);
+    assert_eq!(virtual_boy.cpu.reg_gpr(2), 5);
+}
+
+#[test]
+fn fpu_flags_are_sticky() {
+    // Flags raised by an instruction that doesn't trap stay set until software clears them
+    let (virtual_boy, _) = run("
+        mov 1, r1
+        cvt.ws r1, r1
+        mov 3, r2
+        cvt.ws r2, r2
+        divf.s r2, r1               ; 1/3 is inexact
+        mov 2, r3
+        cvt.ws r3, r3
+        addf.s r3, r3");
+    assert_eq!(virtual_boy.cpu.reg_gpr(25), 0);
+    assert_eq!(virtual_boy.cpu.reg_psw() & FPU_FLAGS, PSW_FPR);
+}
+
+#[test]
+fn duplexed_exception() {
+    // The TRAP handler raises another exception, which is saved in FEPC/FEPSW and the
+    //  high half of ECR, and handled at 0xffffffd0. RETI from there goes back to the
+    //  first handler.
+    let (assembly, mut virtual_boy) = boot("
+        ldsr r0, psw
+    first:
+        trap 2
+        halt
+
+.org 0xffffffa0
+        trap 5
+    back:
+        stsr psw, r21
+        halt
+
+.org 0xffffffd0
+        stsr ecr, r20
+        stsr psw, r22
+        reti");
+    run_until_halt(&mut virtual_boy);
+
+    assert_eq!(virtual_boy.cpu.reg_gpr(20), 0xffa5ffa2);
+    assert_eq!(virtual_boy.cpu.reg_eipc(), assembly.symbols["first"] + 2);
+    assert_eq!(virtual_boy.cpu.reg_fepc(), assembly.symbols["back"]);
+    assert_eq!(virtual_boy.cpu.reg_fepsw() & (PSW_EP | PSW_ID | PSW_NP), PSW_EP | PSW_ID);
+    assert_eq!(virtual_boy.cpu.reg_gpr(22) & (PSW_EP | PSW_ID | PSW_NP), PSW_EP | PSW_ID | PSW_NP);
+    // RETI restored FEPSW, so the first handler carries on with just EP set
+    assert_eq!(virtual_boy.cpu.reg_gpr(21) & (PSW_EP | PSW_ID | PSW_NP), PSW_EP | PSW_ID);
+}
+
+#[test]
+fn fatal_exception() {
+    // An exception raised while NP is set stores its code, the PSW and the return
+    //  address at 0x00000000-0x0000000b and halts the CPU for good
+    let (assembly, mut virtual_boy) = boot("
+        ldsr r0, psw
+        trap 2
+        halt
+
+.org 0xffffffa0
+        trap 5
+        halt
+
+.org 0xffffffd0
+    fault:
+        trap 7
+        mov 1, r1");
+    virtual_boy.cpu.set_reg_gpr(1, 0);
+    run_until_halt(&mut virtual_boy);
+
+    let read_word = |virtual_boy: &mut VirtualBoy, addr: u32| {
+        (virtual_boy.interconnect.read_halfword(addr) as u32) | ((virtual_boy.interconnect.read_halfword(addr + 2) as u32) << 16)
+    };
+    let fault = assembly.symbols["fault"];
+    assert_eq!(read_word(&mut virtual_boy, 0x00000000), 0xffffffa7);
+    assert_eq!(read_word(&mut virtual_boy, 0x00000004) & (PSW_EP | PSW_ID | PSW_NP), PSW_EP | PSW_ID | PSW_NP);
+    assert_eq!(read_word(&mut virtual_boy, 0x00000008), fault + 2);
+
+    // Only a reset gets it going again
+    assert!(virtual_boy.cpu.is_halted());
+    virtual_boy.run_cycles(1000000, &mut NullSink, &mut NullSink).unwrap();
+    assert!(virtual_boy.cpu.is_halted());
+    assert_eq!(virtual_boy.cpu.reg_gpr(1), 0);
+}
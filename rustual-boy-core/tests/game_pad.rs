@@ -0,0 +1,168 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::game_pad::{Button, GamePad};
+
+const HARDWARE_READ_PERIOD: u32 = 640;
+
+const SCR_HW_SI: u8 = 0x04;
+const SCR_S_STAT: u8 = 0x02;
+const SCR_S_ABT_DIS: u8 = 0x01;
+
+// Runs a complete hardware read, returning the latched SDLR and SDHR
+fn hardware_read_data(game_pad: &mut GamePad) -> (u8, u8) {
+    game_pad.write_scr(0x80 | SCR_HW_SI);
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+    assert_eq!(game_pad.read_scr() & SCR_S_STAT, 0);
+    (game_pad.read_sdlr(), game_pad.read_sdhr())
+}
+
+#[test]
+fn live_data_before_first_read() {
+    // Until a hardware read completes, the data registers follow the pad
+    let mut game_pad = GamePad::new();
+    assert_eq!(game_pad.read_sdlr(), 0x02);
+    assert_eq!(game_pad.read_sdhr(), 0x00);
+
+    game_pad.set_button_pressed(Button::A, true);
+    game_pad.set_button_pressed(Button::Start, true);
+    assert_eq!(game_pad.read_sdlr(), 0x06);
+    assert_eq!(game_pad.read_sdhr(), 0x10);
+}
+
+#[test]
+fn hardware_read() {
+    let mut game_pad = GamePad::new();
+    game_pad.set_button_pressed(Button::B, true);
+    game_pad.write_scr(0x80 | SCR_HW_SI);
+    assert_eq!(game_pad.read_scr() & SCR_S_STAT, SCR_S_STAT);
+    assert_eq!(game_pad.cycles_until_next_event(), HARDWARE_READ_PERIOD - 1);
+
+    game_pad.cycles(HARDWARE_READ_PERIOD - 1);
+    assert_eq!(game_pad.read_scr() & SCR_S_STAT, SCR_S_STAT);
+    game_pad.cycles(1);
+    assert_eq!(game_pad.read_scr() & SCR_S_STAT, 0);
+    assert_eq!(game_pad.cycles_until_next_event(), u32::max_value());
+    assert_eq!(game_pad.read_sdlr(), 0x0a);
+
+    // The registers now hold the latched data, not what's pressed
+    game_pad.set_button_pressed(Button::B, false);
+    game_pad.set_button_pressed(Button::L, true);
+    assert_eq!(game_pad.read_sdlr(), 0x0a);
+
+    game_pad.write_scr(0x80 | SCR_HW_SI);
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+    assert_eq!(game_pad.read_sdlr(), 0x22);
+}
+
+#[test]
+fn abort() {
+    let mut game_pad = GamePad::new();
+    game_pad.set_button_pressed(Button::A, true);
+    game_pad.write_scr(0x80 | SCR_HW_SI);
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+
+    game_pad.set_button_pressed(Button::A, false);
+    game_pad.write_scr(0x80 | SCR_HW_SI);
+    game_pad.cycles(100);
+    game_pad.write_scr(0x80 | SCR_S_ABT_DIS);
+    assert_eq!(game_pad.read_scr() & SCR_S_STAT, 0);
+
+    // Aborting keeps the data from the last completed read
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+    assert_eq!(game_pad.read_sdlr(), 0x06);
+}
+
+#[test]
+fn low_battery() {
+    let mut game_pad = GamePad::new();
+    game_pad.set_button_pressed(Button::A, true);
+    game_pad.set_button_pressed(Button::Start, true);
+    assert_eq!(hardware_read_data(&mut game_pad), (0x06, 0x10));
+
+    game_pad.set_low_battery(true);
+    assert!(game_pad.is_low_battery());
+    assert_eq!(hardware_read_data(&mut game_pad), (0x07, 0x10));
+
+    game_pad.set_low_battery(false);
+    assert!(!game_pad.is_low_battery());
+    assert_eq!(hardware_read_data(&mut game_pad), (0x06, 0x10));
+}
+
+#[test]
+fn disconnected() {
+    let mut game_pad = GamePad::new();
+    game_pad.set_button_pressed(Button::A, true);
+    game_pad.set_button_pressed(Button::Start, true);
+    game_pad.set_low_battery(true);
+    assert_eq!(hardware_read_data(&mut game_pad), (0x07, 0x10));
+
+    // No buttons, no low battery and no signature bit
+    game_pad.set_connected(false);
+    assert!(!game_pad.is_connected());
+    assert_eq!(hardware_read_data(&mut game_pad), (0x00, 0x00));
+
+    game_pad.set_connected(true);
+    assert!(game_pad.is_connected());
+    assert_eq!(hardware_read_data(&mut game_pad), (0x07, 0x10));
+
+    game_pad.set_low_battery(false);
+    assert_eq!(hardware_read_data(&mut game_pad), (0x06, 0x10));
+}
+
+#[test]
+fn key_interrupt_enable() {
+    // Nothing pressed, so no interrupt
+    let mut game_pad = GamePad::new();
+    game_pad.write_scr(SCR_HW_SI);
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+    assert!(!game_pad.interrupt_pending());
+
+    // K-Int-Inh set
+    game_pad.set_button_pressed(Button::R, true);
+    game_pad.write_scr(0x80 | SCR_HW_SI);
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+    assert!(!game_pad.interrupt_pending());
+
+    game_pad.write_scr(SCR_HW_SI);
+    game_pad.cycles(HARDWARE_READ_PERIOD);
+    assert!(game_pad.interrupt_pending());
+
+    // Setting K-Int-Inh acknowledges it
+    game_pad.write_scr(0x80);
+    assert!(!game_pad.interrupt_pending());
+}
+
+#[test]
+fn key_interrupt() {
+    let (_, mut virtual_boy) = boot("
+        movhi 0x0200, r0, r1
+        ldsr r0, psw
+        mov 0, r10
+        mov 0x04, r2
+        st.b r2, 0x28[r1]       ; SCR: start a hardware read, key interrupt enabled
+    wait:
+        ld.b 0x28[r1], r3
+        andi 0x02, r3, r3       ; S-Stat
+        bnz wait
+        halt
+
+.org 0xfffffe00
+        stsr ecr, r5
+        ld.b 0x10[r1], r6
+        andi 0xff, r6, r6
+        ld.b 0x14[r1], r7
+        andi 0xff, r7, r7
+        mov 1, r10
+        halt");
+    virtual_boy.interconnect.game_pad.set_button_pressed(Button::LeftDPadUp, true);
+
+    run_until_halt(&mut virtual_boy);
+    assert_eq!(virtual_boy.cpu.reg_gpr(10), 1);
+    assert_eq!(virtual_boy.cpu.reg_gpr(5) & 0xffff, 0xfe00);
+    assert_eq!(virtual_boy.cpu.reg_gpr(6), 0x02);
+    assert_eq!(virtual_boy.cpu.reg_gpr(7), 0x08);
+}
@@ -0,0 +1,66 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::link_cable::LinkCable;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+// Runs both units until their interrupt handlers are done
+fn run(master_setup: &str, slave_setup: &str) -> (VirtualBoy, VirtualBoy) {
+    let (_, master) = boot(&link_port_program(master_setup));
+    let (_, slave) = boot(&link_port_program(slave_setup));
+    let mut cable = LinkCable::new(master, slave);
+
+    for _ in 0..100 {
+        cable.run_cycles(1000, &mut NullSink, &mut NullSink, &mut NullSink, &mut NullSink).unwrap();
+        if cable.a.cpu.reg_gpr(10) == 1 && cable.b.cpu.reg_gpr(10) == 1 {
+            return cable.disconnect();
+        }
+    }
+    panic!("No link port interrupt");
+}
+
+#[test]
+fn byte_exchange() {
+    let (master, slave) = run("
+        movea 0x5a, r0, r2
+        st.b r2, 0x08[r1]       ; CDTR
+        mov 0x04, r2
+        st.b r2, 0x00[r1]       ; CCR: start, internal clock, interrupt enabled", "
+        movea 0xa5, r0, r2
+        st.b r2, 0x08[r1]
+        movea 0x14, r0, r2
+        st.b r2, 0x00[r1]       ; CCR: start, external clock, interrupt enabled");
+
+    assert_eq!(master.cpu.reg_gpr(5) & 0xffff, 0xfe30);
+    assert_eq!(master.cpu.reg_gpr(8), 0xa5);
+    // C-Stat is clear once the transfer is done
+    assert_eq!(master.cpu.reg_gpr(6), 0x69);
+
+    assert_eq!(slave.cpu.reg_gpr(5) & 0xffff, 0xfe30);
+    assert_eq!(slave.cpu.reg_gpr(8), 0x5a);
+    assert_eq!(slave.cpu.reg_gpr(6), 0x79);
+}
+
+#[test]
+fn comcnt() {
+    // The master pulls COMCNT low once the slave is waiting for a falling edge. The line is
+    //  open-drain, so the slave sees it go low even though it's still driving it high.
+    let (master, slave) = run("
+        movea 1000, r0, r3
+    wait:
+        add -1, r3
+        bnz wait
+        mov 0, r2
+        st.b r2, 0x04[r1]       ; CCSR: drive low, interrupt on a falling edge", "
+        mov 0x02, r2
+        st.b r2, 0x04[r1]       ; CCSR: drive high, interrupt on a falling edge");
+
+    // CC-Rd (bit 0) is the level of the line, CC-Wr (bit 1) what each unit drives
+    assert_eq!(master.cpu.reg_gpr(5) & 0xffff, 0xfe30);
+    assert_eq!(master.cpu.reg_gpr(7), 0x64);
+    assert_eq!(slave.cpu.reg_gpr(5) & 0xffff, 0xfe30);
+    assert_eq!(slave.cpu.reg_gpr(7), 0x66);
+}
@@ -0,0 +1,92 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+fn save(virtual_boy: &VirtualBoy) -> Vec<u8> {
+    let mut state = Vec::new();
+    virtual_boy.save_state(&mut state).unwrap();
+    state
+}
+
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Runs `num_frames` frames, returning hashes of the video and audio emitted along the way
+fn run_frames(virtual_boy: &mut VirtualBoy, num_frames: u32) -> (u64, u64) {
+    let mut video_frames = VideoFrames(Vec::new());
+    let mut audio_frames = AudioFrames(Vec::new());
+    for _ in 0..num_frames {
+        virtual_boy.run_frame(&mut video_frames, &mut audio_frames).unwrap();
+    }
+
+    assert_eq!(video_frames.0.len(), num_frames as usize);
+    assert!(audio_frames.0.iter().any(|&frame| frame != (0, 0)));
+    (hash(&video_frames.0), hash(&audio_frames.0))
+}
+
+#[test]
+fn round_trip() {
+    let (_, mut virtual_boy) = boot(AUDIO_VIDEO_PROGRAM);
+    run_frames(&mut virtual_boy, 3);
+    let state = save(&virtual_boy);
+    let output = run_frames(&mut virtual_boy, 4);
+    let state_after = save(&virtual_boy);
+
+    // Loading the snapshot replays the same frames, both on the same machine and on a freshly
+    //  booted one, and ends up in the same state
+    let (_, fresh_virtual_boy) = boot(AUDIO_VIDEO_PROGRAM);
+    for mut restored in vec![virtual_boy, fresh_virtual_boy] {
+        restored.load_state(&mut &state[..]).unwrap();
+        assert!(save(&restored) == state);
+        assert_eq!(run_frames(&mut restored, 4), output);
+        assert!(save(&restored) == state_after);
+    }
+}
+
+#[test]
+fn bad_states_rejected() {
+    let (_, mut virtual_boy) = boot(AUDIO_VIDEO_PROGRAM);
+    run_frames(&mut virtual_boy, 1);
+    let state = save(&virtual_boy);
+    run_frames(&mut virtual_boy, 1);
+    let current_state = save(&virtual_boy);
+
+    let mut wrong_magic = state.clone();
+    wrong_magic[0] ^= 0xff;
+    let mut wrong_version = state.clone();
+    for byte in wrong_version[4..8].iter_mut() {
+        *byte ^= 0xff;
+    }
+
+    // Truncated in the header, in the CPU's state, halfway through and right at the end
+    let bad_states = vec![
+        wrong_magic,
+        wrong_version,
+        Vec::new(),
+        state[..6].to_vec(),
+        state[..20].to_vec(),
+        state[..state.len() / 2].to_vec(),
+        state[..state.len() - 1].to_vec(),
+    ];
+    for bad_state in bad_states {
+        assert!(virtual_boy.load_state(&mut &bad_state[..]).is_err());
+
+        // Nothing was loaded from the bad snapshot
+        assert!(save(&virtual_boy) == current_state);
+    }
+
+    // And the machine still runs as if nothing happened
+    let output = run_frames(&mut virtual_boy, 2);
+    virtual_boy.load_state(&mut &current_state[..]).unwrap();
+    assert_eq!(run_frames(&mut virtual_boy, 2), output);
+}
@@ -0,0 +1,147 @@
+//! Cycle counts returned by `VirtualBoy::step`, including memory wait states
+
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::assembler::assemble;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+const WRAM_CODE: u32 = 0x05000000;
+
+// Copies `program` into WRAM and runs it from there, returning the cycles each
+//  instruction took. WRAM has no wait states, so fetching the program adds nothing.
+fn cycles_in_wram(program: &str) -> Vec<u32> {
+    let (_, mut virtual_boy) = boot("");
+    let assembly = assemble(program, WRAM_CODE).unwrap();
+    load_wram(&mut virtual_boy, &assembly.bytes);
+    virtual_boy.cpu.set_reg_pc(WRAM_CODE);
+
+    let end = WRAM_CODE + assembly.bytes.len() as u32;
+    let mut cycles = Vec::new();
+    while virtual_boy.cpu.reg_pc() != end {
+        cycles.push(virtual_boy.step(&mut NullSink, &mut NullSink).unwrap().0);
+    }
+    cycles
+}
+
+// Boots `program` from ROM and runs it until it halts, returning the cycles each of its
+//  instructions took (leaving out the reset vector's jump and the final halt)
+fn cycles_in_rom(program: &str) -> Vec<u32> {
+    let (_, mut virtual_boy) = boot(program);
+    let mut cycles = Vec::new();
+    while !virtual_boy.cpu.is_halted() {
+        cycles.push(virtual_boy.step(&mut NullSink, &mut NullSink).unwrap().0);
+    }
+    cycles[1..cycles.len() - 1].to_vec()
+}
+
+fn load_wram(virtual_boy: &mut VirtualBoy, bytes: &[u8]) {
+    for (offset, &byte) in bytes.iter().enumerate() {
+        virtual_boy.interconnect.write_byte(WRAM_CODE + offset as u32, byte);
+    }
+}
+
+#[test]
+fn rom_wait_states() {
+    let cycles = cycles_in_wram("
+        movhi 0x0700, r0, r1
+        ld.b 0[r1], r2
+        ld.h 0[r1], r2
+        movhi 0x0200, r0, r3
+        mov 1, r4
+        st.b r4, 0x24[r3]       ; WCR: 1 wait for Game Pak ROM
+        ld.b 0[r1], r2
+        ld.h 0[r1], r2
+        mov 0, r4
+        st.b r4, 0x24[r3]
+        ld.h 0[r1], r2");
+
+    assert_eq!(cycles, vec![
+        1,
+        4 + 2,
+        4 + 2,
+        1,
+        1,
+        4 + 1,
+        4 + 1,
+        4 + 1,
+        1,
+        4 + 1,
+        4 + 2,
+    ]);
+}
+
+#[test]
+fn word_accesses() {
+    // Words go over the 16-bit bus as two halfwords, each paying the region's wait states
+    let cycles = cycles_in_wram("
+        movhi 0x0500, r0, r1
+        ld.w 0x100[r1], r2
+        st.w r2, 0x100[r1]
+        movhi 0x0700, r0, r3
+        ld.w 0[r3], r2
+        in.w 0[r3], r2
+        movhi 0x0200, r0, r4
+        mov 1, r5
+        st.b r5, 0x24[r4]
+        ld.w 0[r3], r2");
+
+    assert_eq!(cycles, vec![
+        1,
+        4,
+        4,
+        1,
+        4 + 2 * 2,
+        4 + 2 * 2,
+        1,
+        1,
+        4 + 1,
+        4 + 2 * 1,
+    ]);
+}
+
+#[test]
+fn instruction_fetches() {
+    // With the instruction cache disabled, each fetched halfword pays the region's wait
+    //  states, every time it's fetched
+    let fetch = "
+        mov r0, r0
+        movhi 0, r0, r0
+        movhi 0x0200, r0, r1
+        mov 1, r2
+        st.b r2, 0x24[r1]       ; WCR: 1 wait for Game Pak ROM
+        mov r0, r0
+        movhi 0, r0, r0";
+    assert_eq!(cycles_in_wram(fetch), vec![1, 1, 1, 1, 4 + 1, 1, 1]);
+    assert_eq!(cycles_in_rom(fetch), vec![
+        1 + 2,
+        1 + 2 * 2,
+        1 + 2 * 2,
+        1 + 2,
+        4 + 1 + 2 * 2,
+        1 + 1,
+        1 + 2 * 1,
+    ]);
+
+    let repeated = "
+        movea 2, r0, r1         ; 0xfffff000
+        movhi 0, r0, r0         ; 0xfffff004
+    loop:
+        add -1, r1              ; 0xfffff008
+        bnz loop                ; 0xfffff00a";
+    assert_eq!(cycles_in_rom(repeated)[2..], [1 + 2, 3 + 2, 1 + 2, 1 + 2]);
+
+    // With the cache enabled, a miss pays for filling a whole 4-byte subblock (so the mov
+    //  and the add pay for two halfwords each) and hits are free
+    let cached = "
+        movea 2, r0, r1         ; 0xfffff000
+        ldsr r1, chcw           ; 0xfffff004
+        mov 2, r1               ; 0xfffff006
+    loop:
+        add -1, r1              ; 0xfffff008, the start of a subblock
+        bnz loop                ; 0xfffff00a";
+    assert_eq!(cycles_in_rom(cached)[2..], [1 + 2 * 2, 1 + 2 * 2, 3, 1, 1]);
+}
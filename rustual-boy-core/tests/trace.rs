@@ -0,0 +1,77 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::emulation_error::{AccessKind, AccessWidth};
+use rustual_boy_core::instruction::SystemRegister;
+use rustual_boy_core::trace::*;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+const PROGRAM: &'static str = "
+        movhi 0x0500, r0, r1    ; 0xfffff000
+        movea 0x1234, r0, r2    ; 0xfffff004
+        st.w r2, 8[r1]          ; 0xfffff008
+        ld.b 9[r1], r3          ; 0xfffff00c
+        ldsr r0, psw            ; 0xfffff010";
+
+// Runs from reset until the program halts, collecting a record for each instruction
+fn trace(virtual_boy: &mut VirtualBoy) -> Vec<Option<TraceRecord>> {
+    let mut records = Vec::new();
+    run_until_halt_with(virtual_boy, |virtual_boy, _| records.push(virtual_boy.cpu.trace_record().cloned()));
+    records
+}
+
+#[test]
+fn records() {
+    let (_, mut virtual_boy) = boot(PROGRAM);
+    virtual_boy.cpu.set_reg_gpr(3, 0);
+    virtual_boy.set_tracing(true);
+
+    let records = trace(&mut virtual_boy).into_iter().map(Option::unwrap).collect::<Vec<_>>();
+    assert_eq!(records.iter().map(|record| record.pc).collect::<Vec<_>>(),
+        vec![0xfffffff0, 0xfffff000, 0xfffff004, 0xfffff008, 0xfffff00c, 0xfffff010, 0xfffff012]);
+
+    let movhi = &records[1];
+    assert_eq!(movhi.bytes()[2..], [0x00, 0x05]);
+    assert_eq!(movhi.register_changes[0].register, TraceRegister::Gpr(1));
+    assert_eq!(movhi.register_changes[0].new_value, 0x05000000);
+    assert!(movhi.memory_accesses.is_empty());
+
+    // Words go over the bus as two halfwords
+    let store = &records[3];
+    assert!(store.register_changes.is_empty());
+    assert_eq!(store.memory_accesses, vec![
+        MemoryAccess { addr: 0x05000008, kind: AccessKind::Write, width: AccessWidth::Halfword, value: 0x1234 },
+        MemoryAccess { addr: 0x0500000a, kind: AccessKind::Write, width: AccessWidth::Halfword, value: 0x0000 },
+    ]);
+
+    let load = &records[4];
+    assert_eq!(load.register_changes, vec![
+        RegisterChange { register: TraceRegister::Gpr(3), old_value: 0, new_value: 0x12 },
+    ]);
+    assert_eq!(load.memory_accesses, vec![
+        MemoryAccess { addr: 0x05000009, kind: AccessKind::Read, width: AccessWidth::Byte, value: 0x12 },
+    ]);
+
+    let ldsr = &records[5];
+    assert_eq!(ldsr.register_changes.len(), 1);
+    assert_eq!(ldsr.register_changes[0].register, TraceRegister::System(SystemRegister::Psw));
+    assert_eq!(ldsr.register_changes[0].new_value, 0);
+}
+
+#[test]
+fn disabled() {
+    let (_, mut virtual_boy) = boot(PROGRAM);
+    assert!(trace(&mut virtual_boy).iter().all(Option::is_none));
+    assert!(virtual_boy.interconnect.take_memory_accesses().is_empty());
+}
+
+#[test]
+fn peeks_are_not_traced() {
+    let (_, mut virtual_boy) = boot(PROGRAM);
+    virtual_boy.set_tracing(true);
+    virtual_boy.interconnect.peek_halfword(0x05000000);
+    assert!(virtual_boy.interconnect.take_memory_accesses().is_empty());
+}
@@ -0,0 +1,116 @@
+extern crate rustual_boy_core;
+
+mod common;
+
+use common::*;
+
+use rustual_boy_core::emulation_error::{AccessKind, AccessWidth};
+use rustual_boy_core::watchpoint::*;
+
+// Runs `program` from reset until it halts, collecting every watchpoint hit on the way
+fn run(program: &str, watchpoints: &[Watchpoint]) -> Vec<WatchpointHit> {
+    let (_, mut virtual_boy) = boot(program);
+    virtual_boy.cpu.watchpoints.extend_from_slice(watchpoints);
+
+    let mut hits = Vec::new();
+    run_until_halt_with(&mut virtual_boy, |virtual_boy, trigger_watchpoint| {
+        assert_eq!(trigger_watchpoint, virtual_boy.cpu.watchpoint_hit().is_some());
+        hits.extend(virtual_boy.cpu.watchpoint_hit());
+    });
+    hits
+}
+
+const LOADS_AND_STORES: &'static str = "
+        movhi 0x0500, r0, r1    ; 0xfffff000
+        movea 0x1234, r0, r2    ; 0xfffff004
+        st.w r2, 8[r1]          ; 0xfffff008
+        st.b r0, 9[r1]          ; 0xfffff00c
+        ld.h 8[r1], r3          ; 0xfffff010
+        in.b 0x10[r1], r4       ; 0xfffff014
+        out.h r2, 0x20[r1]      ; 0xfffff018";
+
+#[test]
+fn kinds() {
+    let writes = run(LOADS_AND_STORES, &[Watchpoint::range(0x05000000, 0x050000ff, WatchpointKind::Write)]);
+    assert_eq!(writes.iter().map(|hit| hit.pc).collect::<Vec<_>>(), vec![0xfffff008, 0xfffff00c, 0xfffff018]);
+
+    let reads = run(LOADS_AND_STORES, &[Watchpoint::range(0x05000000, 0x050000ff, WatchpointKind::Read)]);
+    assert_eq!(reads.iter().map(|hit| hit.pc).collect::<Vec<_>>(), vec![0xfffff010, 0xfffff014]);
+
+    let accesses = run(LOADS_AND_STORES, &[Watchpoint::range(0x05000000, 0x050000ff, WatchpointKind::Access)]);
+    assert_eq!(accesses.len(), 5);
+}
+
+#[test]
+fn access_reports() {
+    let hits = run(LOADS_AND_STORES, &[Watchpoint::new(0x05000009, WatchpointKind::Access)]);
+
+    // Wider accesses that overlap the watched byte count too
+    assert_eq!(hits, vec![
+        WatchpointHit {
+            watchpoint: Watchpoint::new(0x05000009, WatchpointKind::Access),
+            pc: 0xfffff008,
+            addr: 0x05000008,
+            kind: AccessKind::Write,
+            width: AccessWidth::Word,
+            old_value: 0xffffffff,
+            new_value: 0x00001234,
+        },
+        WatchpointHit {
+            watchpoint: Watchpoint::new(0x05000009, WatchpointKind::Access),
+            pc: 0xfffff00c,
+            addr: 0x05000009,
+            kind: AccessKind::Write,
+            width: AccessWidth::Byte,
+            old_value: 0x12,
+            new_value: 0x00,
+        },
+        WatchpointHit {
+            watchpoint: Watchpoint::new(0x05000009, WatchpointKind::Access),
+            pc: 0xfffff010,
+            addr: 0x05000008,
+            kind: AccessKind::Read,
+            width: AccessWidth::Halfword,
+            old_value: 0x0034,
+            new_value: 0x0034,
+        },
+    ]);
+
+    assert_eq!(hits[1].to_string(),
+               "Watchpoint (access 0x05000009) hit: write byte at 0x05000009: 0x12 -> 0x00 (pc: 0xfffff00c)");
+}
+
+#[test]
+fn bit_strings() {
+    let hits = run("
+        movhi 0x0500, r0, r29
+        movea 0x100, r29, r30
+        st.w r0, 0[r29]
+        movea 0xff, r0, r1
+        st.w r1, 0[r30]
+        mov 0, r27
+        mov 4, r26
+        mov 8, r28
+        movbsu                  ; 0xfffff01a", &[Watchpoint::new(0x05000000, WatchpointKind::Write)]);
+
+    // Only the first access of each instruction is reported
+    let hit = hits.last().unwrap();
+    assert_eq!(hit.pc, 0xfffff01a);
+    assert_eq!(hit.width, AccessWidth::Word);
+    assert_eq!((hit.old_value, hit.new_value), (0x00000000, 0x00000010));
+}
+
+#[test]
+fn ranges() {
+    let watchpoint = Watchpoint::range(0x05000010, 0x05000003, WatchpointKind::Access);
+    assert_eq!((watchpoint.start, watchpoint.end), (0x05000003, 0x05000010));
+    assert!(watchpoint.contains(0x05000010));
+    assert!(!watchpoint.contains(0x05000011));
+
+    assert!(watchpoint.matches(0x05000000, AccessKind::Read, AccessWidth::Word));
+    assert!(!watchpoint.matches(0x05000000, AccessKind::Read, AccessWidth::Halfword));
+    assert!(!watchpoint.matches(0x05000012, AccessKind::Write, AccessWidth::Halfword));
+    assert!(Watchpoint::new(0xffffffff, WatchpointKind::Read).matches(0xfffffffc, AccessKind::Read, AccessWidth::Word));
+
+    assert_eq!(watchpoint.to_string(), "access 0x05000003-0x05000010");
+}
@@ -0,0 +1,23 @@
+/// 64-bit FNV-1a, used wherever we need a stable hash that's cheap to compute
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.0 ^= value as u64;
+        self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u8(byte);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
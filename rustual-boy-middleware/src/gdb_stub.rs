@@ -0,0 +1,449 @@
+//! A server for GDB's remote serial protocol, so a debugger like `v810-gdb` can
+//! attach to a running `VirtualBoy` with `target remote localhost:<port>`.
+//!
+//! Registers are numbered the way the V810 GDB port expects: `r0`-`r31` are 0-31,
+//! system register N is 32 + N (so `psw` is 37), and `pc` is 64. All registers are
+//! 32 bits and transferred little endian.
+//!
+//! Breakpoints (`Z0`/`Z1`) are checked against the PC before each instruction, so
+//! memory is never patched for them. Watchpoints (`Z2`-`Z4`) go into
+//! `V810::watchpoints` as write, read and access watchpoints on the requested range,
+//! and the stop reply tells GDB which data address triggered them.
+
+use rustual_boy_core::emulation_error::AccessKind;
+use rustual_boy_core::interconnect::Interconnect;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_core::watchpoint::{Watchpoint, WatchpointHit, WatchpointKind};
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write, Error, ErrorKind};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+const NUM_REGISTERS: usize = 65;
+const REGISTER_SYSTEM_START: usize = 32;
+const REGISTER_PC: usize = 64;
+
+const SIGNAL_INT: u8 = 2;
+const SIGNAL_TRAP: u8 = 5;
+const SIGNAL_BUS: u8 = 10;
+
+// The largest packet (in bytes, excluding framing) we tell GDB we'll send or accept
+const PACKET_SIZE: usize = 0x4000;
+
+/// What the frontend should do with the emulator after `GdbStub::poll`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GdbControl {
+    /// GDB has the target stopped; don't emulate anything
+    Stopped,
+    /// Emulate as normal, calling `GdbStub::instruction_executed` after each step
+    Running,
+    /// GDB detached or killed the session; the stub should be dropped and emulation resumed
+    Detached,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Stopped,
+    Running,
+    Stepping,
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    state: State,
+    last_signal: u8,
+    last_watchpoint_hit: Option<WatchpointHit>,
+
+    breakpoints: HashSet<u32>,
+    watchpoints: Vec<Watchpoint>,
+
+    input: Vec<u8>,
+}
+
+impl GdbStub {
+    /// Waits for GDB to connect to `addr`
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+
+        GdbStub::new(stream)
+    }
+
+    /// Starts a session over an already connected stream. The target starts out stopped,
+    /// as GDB expects when it attaches.
+    pub fn new(stream: TcpStream) -> io::Result<GdbStub> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(GdbStub {
+            stream: stream,
+            state: State::Stopped,
+            last_signal: SIGNAL_TRAP,
+            last_watchpoint_hit: None,
+
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+
+            input: Vec::new(),
+        })
+    }
+
+    /// Handles everything GDB has sent since the last call without blocking, and returns
+    /// whether the emulator should run. While running, this is how an interrupt (Ctrl-C)
+    /// from GDB is noticed, so it should be called regularly, eg. once per host frame.
+    pub fn poll(&mut self, virtual_boy: &mut VirtualBoy) -> io::Result<GdbControl> {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "GDB closed the connection")),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            match packet {
+                None => {
+                    if self.state != State::Stopped {
+                        self.stop(virtual_boy, SIGNAL_INT, None)?;
+                    }
+                }
+                Some(packet) => {
+                    if !self.handle_packet(&packet, virtual_boy)? {
+                        self.detach(virtual_boy);
+                        return Ok(GdbControl::Detached);
+                    }
+                }
+            }
+        }
+
+        Ok(match self.state {
+            State::Stopped => GdbControl::Stopped,
+            State::Running | State::Stepping => GdbControl::Running,
+        })
+    }
+
+    /// Tells the stub an instruction was just executed (or the halted CPU skipped ahead).
+    /// Returns true if GDB should now get control, in which case the frontend should
+    /// stop emulating until `poll` says otherwise.
+    pub fn instruction_executed(&mut self, virtual_boy: &mut VirtualBoy, trigger_watchpoint: bool) -> io::Result<bool> {
+        let is_stopping = match self.state {
+            State::Stopped => return Ok(true),
+            State::Stepping => true,
+            State::Running => trigger_watchpoint || self.breakpoints.contains(&virtual_boy.cpu.reg_pc()),
+        };
+
+        if is_stopping {
+            let watchpoint_hit = if trigger_watchpoint { virtual_boy.cpu.watchpoint_hit() } else { None };
+            self.stop(virtual_boy, SIGNAL_TRAP, watchpoint_hit)?;
+        }
+
+        Ok(is_stopping)
+    }
+
+    /// Hands control to GDB after an emulation error, reporting it as a bus error at
+    /// the faulting instruction
+    pub fn emulation_error(&mut self, virtual_boy: &mut VirtualBoy) -> io::Result<()> {
+        self.stop(virtual_boy, SIGNAL_BUS, None)
+    }
+
+    fn stop(&mut self, virtual_boy: &mut VirtualBoy, signal: u8, watchpoint_hit: Option<WatchpointHit>) -> io::Result<()> {
+        self.state = State::Stopped;
+        self.last_signal = signal;
+        self.last_watchpoint_hit = watchpoint_hit;
+        let reply = self.stop_reply(virtual_boy);
+        self.send(&reply)
+    }
+
+    fn stop_reply(&self, virtual_boy: &VirtualBoy) -> String {
+        // Include the PC so GDB doesn't have to ask for it after every step
+        let mut pc = String::new();
+        push_u32_hex(&mut pc, virtual_boy.cpu.reg_pc());
+        let mut reply = format!("T{:02x}{:02x}:{};", self.last_signal, REGISTER_PC, pc);
+
+        if let Some(hit) = self.last_watchpoint_hit {
+            let name = match (hit.watchpoint.kind, hit.kind) {
+                (WatchpointKind::Access, _) => "awatch",
+                (_, AccessKind::Read) => "rwatch",
+                (_, AccessKind::Write) => "watch",
+            };
+            // GDB looks the address up in its own watchpoint ranges, so it has to be inside one
+            // even when a wider access only overlaps the start of it
+            let addr = hit.addr.max(hit.watchpoint.start);
+            reply.push_str(&format!("{}:{:x};", name, addr));
+        }
+
+        reply
+    }
+
+    /// Removes the stub's watchpoints from `virtual_boy`. This happens automatically
+    /// when GDB detaches, but should be called if the session ends some other way.
+    pub fn detach(&mut self, virtual_boy: &mut VirtualBoy) {
+        for watchpoint in self.watchpoints.drain(..) {
+            remove_watchpoint(virtual_boy, watchpoint);
+        }
+        self.breakpoints.clear();
+        self.state = State::Running;
+    }
+
+    /// Pulls the next complete packet out of the input buffer. `Some(None)` means GDB
+    /// sent an interrupt.
+    fn next_packet(&mut self) -> io::Result<Option<Option<String>>> {
+        loop {
+            let start = match self.input.iter().position(|&x| x == b'$' || x == 0x03) {
+                Some(start) => start,
+                None => {
+                    // Only acks and noise left
+                    self.input.clear();
+                    return Ok(None);
+                }
+            };
+            if self.input[start] == 0x03 {
+                self.input.drain(..start + 1);
+                return Ok(Some(None));
+            }
+
+            let end = match self.input[start..].iter().position(|&x| x == b'#') {
+                Some(end) => start + end,
+                None => return Ok(None),
+            };
+            if self.input.len() < end + 3 {
+                return Ok(None);
+            }
+
+            let packet = self.input[start + 1..end].to_vec();
+            let checksum = String::from_utf8_lossy(&self.input[end + 1..end + 3]).into_owned();
+            self.input.drain(..end + 3);
+
+            let expected_checksum = packet.iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+            if u8::from_str_radix(&checksum, 16).ok() != Some(expected_checksum) {
+                self.write_all(b"-")?;
+                continue;
+            }
+            self.write_all(b"+")?;
+
+            return Ok(Some(Some(String::from_utf8_lossy(&packet).into_owned())));
+        }
+    }
+
+    /// Handles a single packet, returning false if GDB has ended the session
+    fn handle_packet(&mut self, packet: &str, virtual_boy: &mut VirtualBoy) -> io::Result<bool> {
+        let command_len = packet.chars().next().map(|x| x.len_utf8()).unwrap_or(0);
+        let (command, args) = packet.split_at(command_len);
+
+        let reply = match command {
+            "?" => self.stop_reply(virtual_boy),
+            "g" => {
+                let mut reply = String::new();
+                for index in 0..NUM_REGISTERS {
+                    push_u32_hex(&mut reply, read_register(virtual_boy, index));
+                }
+                reply
+            }
+            "G" => {
+                match parse_hex_bytes(args) {
+                    Some(ref bytes) if bytes.len() == NUM_REGISTERS * 4 => {
+                        for (index, value) in bytes.chunks(4).enumerate() {
+                            write_register(virtual_boy, index, u32_from_le_bytes(value));
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "p" => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(index) if index < NUM_REGISTERS => {
+                        let mut reply = String::new();
+                        push_u32_hex(&mut reply, read_register(virtual_boy, index));
+                        reply
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let index = parts.next().and_then(|x| usize::from_str_radix(x, 16).ok());
+                let value = parts.next().and_then(parse_hex_bytes);
+                match (index, value) {
+                    (Some(index), Some(ref value)) if index < NUM_REGISTERS && value.len() == 4 => {
+                        write_register(virtual_boy, index, u32_from_le_bytes(value));
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "m" => {
+                match parse_addr_len(args) {
+                    // Each byte takes two hex digits, so this is the most that fits in a reply
+                    Some((_, len)) if len as usize > PACKET_SIZE / 2 => "E01".into(),
+                    Some((addr, len)) if (0..len).any(|offset| !Interconnect::is_mapped(addr.wrapping_add(offset))) => "E14".into(),
+                    Some((addr, len)) => {
+                        let mut reply = String::new();
+                        for offset in 0..len {
+                            let byte = virtual_boy.interconnect.peek_byte(addr.wrapping_add(offset));
+                            reply.push_str(&format!("{:02x}", byte));
+                        }
+                        reply
+                    }
+                    None => "E01".into(),
+                }
+            }
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let addr_len = parts.next().and_then(parse_addr_len);
+                let data = parts.next().and_then(parse_hex_bytes);
+                match (addr_len, data) {
+                    (Some((addr, len)), Some(ref data)) if data.len() == len as usize => {
+                        for (offset, &byte) in data.iter().enumerate() {
+                            virtual_boy.interconnect.patch_byte(addr.wrapping_add(offset as u32), byte);
+                        }
+                        // Make sure the CPU doesn't keep running the old code out of its cache
+                        virtual_boy.cpu.cache.clear_entries(0, 128);
+                        virtual_boy.interconnect.take_bus_error();
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u32::from_str_radix(args, 16) {
+                        Ok(addr) => virtual_boy.cpu.set_reg_pc(addr),
+                        Err(_) => {
+                            self.send("E01")?;
+                            return Ok(true);
+                        }
+                    }
+                }
+                self.state = if command == "c" { State::Running } else { State::Stepping };
+                // The stop reply is sent once the target stops again
+                return Ok(true);
+            }
+            "Z" | "z" => {
+                let fields = args.split(',').collect::<Vec<_>>();
+                let addr = fields.get(1).and_then(|x| u32::from_str_radix(x, 16).ok());
+                let len = fields.get(2).and_then(|x| u32::from_str_radix(x, 16).ok());
+                match (fields[0], addr, len) {
+                    ("0", Some(addr), _) | ("1", Some(addr), _) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(&addr);
+                        }
+                        "OK".into()
+                    }
+                    ("2", Some(addr), Some(len)) | ("3", Some(addr), Some(len)) | ("4", Some(addr), Some(len)) => {
+                        let kind = match fields[0] {
+                            "2" => WatchpointKind::Write,
+                            "3" => WatchpointKind::Read,
+                            _ => WatchpointKind::Access,
+                        };
+                        let watchpoint = Watchpoint::range(addr, addr.saturating_add(len.max(1) - 1), kind);
+                        if command == "Z" {
+                            virtual_boy.cpu.watchpoints.push(watchpoint);
+                            self.watchpoints.push(watchpoint);
+                        } else if let Some(index) = self.watchpoints.iter().position(|&x| x == watchpoint) {
+                            self.watchpoints.remove(index);
+                            remove_watchpoint(virtual_boy, watchpoint);
+                        }
+                        "OK".into()
+                    }
+                    _ => String::new(),
+                }
+            }
+            "H" => "OK".into(),
+            "q" => {
+                if args.starts_with("Supported") {
+                    format!("PacketSize={:x}", PACKET_SIZE)
+                } else if args == "Attached" {
+                    // Detaching should leave the emulator running rather than quit it
+                    "1".into()
+                } else {
+                    String::new()
+                }
+            }
+            "D" => {
+                self.send("OK")?;
+                return Ok(false);
+            }
+            "k" => return Ok(false),
+            // An empty reply tells GDB we don't support whatever this is
+            _ => String::new(),
+        };
+
+        self.send(&reply)?;
+        Ok(true)
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |acc, x| acc.wrapping_add(x));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.write_all(packet.as_bytes())
+    }
+
+    fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        // The stream is nonblocking, so wait out any backpressure ourselves
+        while !data.is_empty() {
+            match self.stream.write(data) {
+                Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "GDB stopped accepting data")),
+                Ok(n) => data = &data[n..],
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Removes a single copy, in case the frontend added an identical watchpoint of its own
+fn remove_watchpoint(virtual_boy: &mut VirtualBoy, watchpoint: Watchpoint) {
+    if let Some(index) = virtual_boy.cpu.watchpoints.iter().position(|&x| x == watchpoint) {
+        virtual_boy.cpu.watchpoints.remove(index);
+    }
+}
+
+fn read_register(virtual_boy: &VirtualBoy, index: usize) -> u32 {
+    match index {
+        0...31 => virtual_boy.cpu.reg_gpr(index),
+        REGISTER_PC => virtual_boy.cpu.reg_pc(),
+        _ => virtual_boy.cpu.reg_system((index - REGISTER_SYSTEM_START) as u32),
+    }
+}
+
+fn write_register(virtual_boy: &mut VirtualBoy, index: usize, value: u32) {
+    match index {
+        0...31 => virtual_boy.cpu.set_reg_gpr(index, value),
+        REGISTER_PC => virtual_boy.cpu.set_reg_pc(value),
+        _ => virtual_boy.cpu.set_reg_system((index - REGISTER_SYSTEM_START) as u32, value),
+    }
+}
+
+fn push_u32_hex(s: &mut String, value: u32) {
+    for i in 0..4 {
+        s.push_str(&format!("{:02x}", (value >> (i * 8)) as u8));
+    }
+}
+
+fn u32_from_le_bytes(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) | ((bytes[1] as u32) << 8) | ((bytes[2] as u32) << 16) | ((bytes[3] as u32) << 24)
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len() / 2).map(|i| s.get(i * 2..i * 2 + 2).and_then(|x| u8::from_str_radix(x, 16).ok())).collect()
+}
+
+fn parse_addr_len(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, ',');
+    let addr = parts.next().and_then(|x| u32::from_str_radix(x, 16).ok());
+    let len = parts.next().and_then(|x| u32::from_str_radix(x, 16).ok());
+    match (addr, len) {
+        (Some(addr), Some(len)) => Some((addr, len)),
+        _ => None,
+    }
+}
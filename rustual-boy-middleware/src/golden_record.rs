@@ -0,0 +1,208 @@
+use rustual_boy_core::sinks::{AudioFrame, Sink, VideoFrame};
+use rustual_boy_core::virtual_boy::{StopReason, VirtualBoy};
+
+use fnv::Fnv1a;
+use movie::MoviePlayer;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write, Error, ErrorKind};
+use std::path::Path;
+
+const GOLDEN_RECORD_HEADER: &'static str = "rustual-boy golden record v1";
+
+/// Hashes of everything a `VirtualBoy` emitted over a number of frames, used to
+/// check that emulation hasn't changed between builds.
+///
+/// Golden records are stored as text so that changes show up nicely in diffs:
+///
+/// ```text
+/// rustual-boy golden record v1
+/// frame <left display hash> <right display hash>
+/// ...
+/// audio <number of audio frames> <audio stream hash>
+/// ```
+///
+/// All hashes are 64-bit FNV-1a written in hex. There's one `frame` line per
+/// video frame, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenRecord {
+    /// (left, right) display buffer hashes for each frame
+    pub frames: Vec<(u64, u64)>,
+    pub num_audio_frames: u64,
+    pub audio_hash: u64,
+}
+
+/// The first difference found between two golden records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenMismatch {
+    FrameCount { expected: usize, actual: usize },
+    Frame { index: usize, expected: (u64, u64), actual: (u64, u64) },
+    Audio { expected: (u64, u64), actual: (u64, u64) },
+}
+
+impl fmt::Display for GoldenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GoldenMismatch::FrameCount { expected, actual } =>
+                write!(f, "Expected {} frames, got {}", expected, actual),
+            GoldenMismatch::Frame { index, expected, actual } =>
+                write!(f, "Frame {} differs (expected {:016x} {:016x}, got {:016x} {:016x})", index, expected.0, expected.1, actual.0, actual.1),
+            GoldenMismatch::Audio { expected, actual } =>
+                write!(f, "Audio differs (expected {} frames with hash {:016x}, got {} frames with hash {:016x})", expected.0, expected.1, actual.0, actual.1),
+        }
+    }
+}
+
+struct HashingVideoFrameSink {
+    frames: Vec<(u64, u64)>,
+}
+
+impl Sink<VideoFrame> for HashingVideoFrameSink {
+    fn append(&mut self, frame: VideoFrame) {
+        let (left, right) = frame;
+        let mut left_hash = Fnv1a::new();
+        left_hash.write(&left);
+        let mut right_hash = Fnv1a::new();
+        right_hash.write(&right);
+        self.frames.push((left_hash.finish(), right_hash.finish()));
+    }
+}
+
+struct HashingAudioFrameSink {
+    num_frames: u64,
+    hash: Fnv1a,
+}
+
+impl Sink<AudioFrame> for HashingAudioFrameSink {
+    fn append(&mut self, frame: AudioFrame) {
+        let (left, right) = frame;
+        self.num_frames += 1;
+        self.hash.write(&[left as u8, (left >> 8) as u8, right as u8, (right >> 8) as u8]);
+    }
+}
+
+impl GoldenRecord {
+    /// Runs `virtual_boy` headlessly for `num_frames` frames and records what it emitted.
+    /// If a movie is given, its input is applied as each frame is emitted, the same way
+    /// the CLI frontend plays it back.
+    pub fn run<R: Read>(virtual_boy: &mut VirtualBoy, num_frames: usize, mut movie: Option<&mut MoviePlayer<R>>) -> io::Result<GoldenRecord> {
+        let mut video_frame_sink = HashingVideoFrameSink {
+            frames: Vec::new(),
+        };
+        let mut audio_frame_sink = HashingAudioFrameSink {
+            num_frames: 0,
+            hash: Fnv1a::new(),
+        };
+
+        while video_frame_sink.frames.len() < num_frames {
+            let frame_result = virtual_boy.run_frame(&mut video_frame_sink, &mut audio_frame_sink)
+                .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+            if frame_result.stop_reason == StopReason::FrameCompleted {
+                let buttons = match movie {
+                    Some(ref mut movie) => movie.frame()?,
+                    None => None,
+                };
+                if let Some(buttons) = buttons {
+                    virtual_boy.interconnect.game_pad.set_buttons(buttons);
+                }
+            }
+        }
+
+        Ok(GoldenRecord {
+            frames: video_frame_sink.frames,
+            num_audio_frames: audio_frame_sink.num_frames,
+            audio_hash: audio_frame_sink.hash.finish(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(file_name: P) -> io::Result<GoldenRecord> {
+        GoldenRecord::read(&mut BufReader::new(File::open(file_name)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, file_name: P) -> io::Result<()> {
+        self.write(&mut File::create(file_name)?)
+    }
+
+    pub fn read<R: BufRead>(r: &mut R) -> io::Result<GoldenRecord> {
+        let mut lines = r.lines();
+
+        match lines.next() {
+            Some(line) => if line?.trim() != GOLDEN_RECORD_HEADER {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid golden record header"));
+            },
+            None => return Err(Error::new(ErrorKind::UnexpectedEof, "Empty golden record")),
+        }
+
+        let mut frames = Vec::new();
+        let mut audio = None;
+        for line in lines {
+            let line = line?;
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match (fields.get(0).cloned(), audio) {
+                (None, _) => {}
+                (Some("frame"), None) if fields.len() == 3 => {
+                    frames.push((parse_hash(fields[1])?, parse_hash(fields[2])?));
+                }
+                (Some("audio"), None) if fields.len() == 3 => {
+                    let num_audio_frames = fields[1].parse().map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                    audio = Some((num_audio_frames, parse_hash(fields[2])?));
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unexpected line in golden record: {}", line))),
+            }
+        }
+
+        let (num_audio_frames, audio_hash) = match audio {
+            Some(audio) => audio,
+            None => return Err(Error::new(ErrorKind::InvalidData, "Golden record is missing its audio line")),
+        };
+
+        Ok(GoldenRecord {
+            frames: frames,
+            num_audio_frames: num_audio_frames,
+            audio_hash: audio_hash,
+        })
+    }
+
+    pub fn write(&self, w: &mut Write) -> io::Result<()> {
+        writeln!(w, "{}", GOLDEN_RECORD_HEADER)?;
+        for &(left, right) in self.frames.iter() {
+            writeln!(w, "frame {:016x} {:016x}", left, right)?;
+        }
+        writeln!(w, "audio {} {:016x}", self.num_audio_frames, self.audio_hash)
+    }
+
+    /// Checks this record against `expected`, returning the first difference
+    pub fn compare(&self, expected: &GoldenRecord) -> Result<(), GoldenMismatch> {
+        if self.frames.len() != expected.frames.len() {
+            return Err(GoldenMismatch::FrameCount {
+                expected: expected.frames.len(),
+                actual: self.frames.len(),
+            });
+        }
+
+        for (index, (&actual, &expected)) in self.frames.iter().zip(expected.frames.iter()).enumerate() {
+            if actual != expected {
+                return Err(GoldenMismatch::Frame {
+                    index: index,
+                    expected: expected,
+                    actual: actual,
+                });
+            }
+        }
+
+        if self.num_audio_frames != expected.num_audio_frames || self.audio_hash != expected.audio_hash {
+            return Err(GoldenMismatch::Audio {
+                expected: (expected.num_audio_frames, expected.audio_hash),
+                actual: (self.num_audio_frames, self.audio_hash),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_hash(s: &str) -> io::Result<u64> {
+    u64::from_str_radix(s, 16).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
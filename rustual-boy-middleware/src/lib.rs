@@ -3,12 +3,25 @@ extern crate rustual_boy_core;
 mod color;
 mod color_frame;
 mod anaglyphizer;
+mod fnv;
 mod gamma_adjust_sink;
+mod gdb_stub;
+mod golden_record;
 mod most_recent_sink;
+mod movie;
+mod network_link;
+mod rewind_buffer;
+mod trace_logger;
 
 // reexports
 pub use color::Color;
 pub use color_frame::ColorFrame;
 pub use anaglyphizer::Anaglyphizer;
 pub use gamma_adjust_sink::GammaAdjustSink;
+pub use gdb_stub::{GdbControl, GdbStub};
+pub use golden_record::{GoldenMismatch, GoldenRecord};
 pub use most_recent_sink::MostRecentSink;
+pub use movie::{MoviePlayer, MovieRecorder};
+pub use network_link::NetworkLink;
+pub use rewind_buffer::RewindBuffer;
+pub use trace_logger::{TraceFilter, TraceLogger};
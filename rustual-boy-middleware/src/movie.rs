@@ -0,0 +1,232 @@
+//! Input movies record the state of the game pad for every emulated frame, so a
+//! play session can be reproduced exactly by feeding the same input back in from
+//! power-on.
+//!
+//! A movie file is laid out as follows (all integers are little endian):
+//!
+//! | Size | Contents |
+//! |------|----------|
+//! | 4 | Magic: `RBMV` |
+//! | 4 | Format version (currently 1) |
+//! | 4 | Length of the ROM name in bytes |
+//! | n | ROM name (UTF-8) |
+//! | 2 | ROM maker code |
+//! | 2 | ROM game code |
+//! | 1 | ROM game version byte |
+//! | 8 | FNV-1a hash of the entire ROM |
+//! | 2 * frames | Button state for each frame, as returned by `GamePad::buttons` |
+//!
+//! The frame list simply runs until the end of the file. Each entry is applied to
+//! the game pad as soon as the VIP emits the corresponding frame, and holds until
+//! the next one; no buttons are pressed from power-on until the first frame.
+//! Movies don't include SRAM, so playback should start with the same SRAM
+//! contents as the recording did.
+
+use rustual_boy_core::rom::Rom;
+
+use fnv::Fnv1a;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write, Error, ErrorKind};
+use std::path::Path;
+
+const MOVIE_MAGIC: &'static [u8; 4] = b"RBMV";
+const MOVIE_VERSION: u32 = 1;
+
+// The ROM header's 20 Shift JIS bytes decode to at most 60 bytes of UTF-8, so anything
+//  longer than this is a corrupt length that would otherwise be allocated as-is
+const MAX_ROM_NAME_LEN: usize = 256;
+
+/// Identifies the ROM a movie was recorded with
+struct MovieHeader {
+    rom_name: String,
+    maker_code: [u8; 2],
+    game_code: [u8; 2],
+    game_version_byte: u8,
+    rom_hash: u64,
+}
+
+impl MovieHeader {
+    fn from_rom(rom: &Rom) -> MovieHeader {
+        let maker_code = rom.maker_code().unwrap_or_default().into_bytes();
+        let game_code = rom.game_code().unwrap_or_default().into_bytes();
+
+        MovieHeader {
+            rom_name: rom.name().unwrap_or_default(),
+            maker_code: [*maker_code.get(0).unwrap_or(&0), *maker_code.get(1).unwrap_or(&0)],
+            game_code: [*game_code.get(0).unwrap_or(&0), *game_code.get(1).unwrap_or(&0)],
+            game_version_byte: rom.game_version_byte(),
+            rom_hash: rom_hash(rom),
+        }
+    }
+
+    fn write(&self, w: &mut Write) -> io::Result<()> {
+        w.write_all(MOVIE_MAGIC)?;
+        w.write_all(&u32_bytes(MOVIE_VERSION))?;
+
+        w.write_all(&u32_bytes(self.rom_name.len() as u32))?;
+        w.write_all(self.rom_name.as_bytes())?;
+        w.write_all(&self.maker_code)?;
+        w.write_all(&self.game_code)?;
+        w.write_all(&[self.game_version_byte])?;
+        for i in 0..8 {
+            w.write_all(&[(self.rom_hash >> (i * 8)) as u8])?;
+        }
+
+        Ok(())
+    }
+
+    fn read(r: &mut Read) -> io::Result<MovieHeader> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MOVIE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid movie magic"));
+        }
+
+        let version = read_u32(r)?;
+        if version != MOVIE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported movie version: {}", version)));
+        }
+
+        let rom_name_len = read_u32(r)? as usize;
+        if rom_name_len > MAX_ROM_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Invalid movie ROM name length: {}", rom_name_len)));
+        }
+        let mut rom_name = vec![0; rom_name_len];
+        r.read_exact(&mut rom_name)?;
+        let rom_name = String::from_utf8(rom_name).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut maker_code = [0; 2];
+        r.read_exact(&mut maker_code)?;
+        let mut game_code = [0; 2];
+        r.read_exact(&mut game_code)?;
+        let mut game_version_byte = [0; 1];
+        r.read_exact(&mut game_version_byte)?;
+
+        let mut rom_hash_bytes = [0; 8];
+        r.read_exact(&mut rom_hash_bytes)?;
+        let rom_hash = rom_hash_bytes.iter().rev().fold(0, |acc, &x| (acc << 8) | (x as u64));
+
+        Ok(MovieHeader {
+            rom_name: rom_name,
+            maker_code: maker_code,
+            game_code: game_code,
+            game_version_byte: game_version_byte[0],
+            rom_hash: rom_hash,
+        })
+    }
+}
+
+/// Writes the button state of each frame to a movie
+pub struct MovieRecorder<W: Write> {
+    writer: W,
+    num_frames: u64,
+}
+
+impl MovieRecorder<BufWriter<File>> {
+    /// Creates (or truncates) the movie file at `path`
+    pub fn create<P: AsRef<Path>>(path: P, rom: &Rom) -> io::Result<MovieRecorder<BufWriter<File>>> {
+        MovieRecorder::new(BufWriter::new(File::create(path)?), rom)
+    }
+}
+
+impl<W: Write> MovieRecorder<W> {
+    /// Starts a movie for `rom` by writing its header to `writer`
+    pub fn new(mut writer: W, rom: &Rom) -> io::Result<MovieRecorder<W>> {
+        MovieHeader::from_rom(rom).write(&mut writer)?;
+
+        Ok(MovieRecorder {
+            writer: writer,
+            num_frames: 0,
+        })
+    }
+
+    /// Appends the button state (as returned by `GamePad::buttons`) for the next frame
+    pub fn frame(&mut self, buttons: u16) -> io::Result<()> {
+        self.num_frames += 1;
+        self.writer.write_all(&[buttons as u8, (buttons >> 8) as u8])
+    }
+
+    /// Number of frames recorded so far
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the button state of each frame back from a movie
+pub struct MoviePlayer<R: Read> {
+    reader: R,
+    num_frames: u64,
+}
+
+impl MoviePlayer<BufReader<File>> {
+    /// Opens the movie file at `path`, which must have been recorded with `rom`
+    pub fn open<P: AsRef<Path>>(path: P, rom: &Rom) -> io::Result<MoviePlayer<BufReader<File>>> {
+        MoviePlayer::new(BufReader::new(File::open(path)?), rom)
+    }
+}
+
+impl<R: Read> MoviePlayer<R> {
+    /// Reads a movie header from `reader`, failing if the movie wasn't recorded with `rom`
+    pub fn new(mut reader: R, rom: &Rom) -> io::Result<MoviePlayer<R>> {
+        let header = MovieHeader::read(&mut reader)?;
+        if header.rom_hash != rom_hash(rom) {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "Movie was recorded with a different ROM: \"{}\" ({}{} v1.{:#02})",
+                header.rom_name,
+                String::from_utf8_lossy(&header.maker_code),
+                String::from_utf8_lossy(&header.game_code),
+                header.game_version_byte)));
+        }
+
+        Ok(MoviePlayer {
+            reader: reader,
+            num_frames: 0,
+        })
+    }
+
+    /// Returns the button state for the next frame, or `None` once the movie has ended
+    pub fn frame(&mut self) -> io::Result<Option<u16>> {
+        let mut buttons = [0; 2];
+        let mut num_read = 0;
+        while num_read < buttons.len() {
+            match self.reader.read(&mut buttons[num_read..]) {
+                Ok(0) if num_read == 0 => return Ok(None),
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "Movie ends partway through a frame")),
+                Ok(n) => num_read += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.num_frames += 1;
+        Ok(Some((buttons[0] as u16) | ((buttons[1] as u16) << 8)))
+    }
+
+    /// Number of frames played back so far
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+}
+
+fn rom_hash(rom: &Rom) -> u64 {
+    let mut hash = Fnv1a::new();
+    for addr in 0..rom.size() as u32 {
+        hash.write_u8(rom.read_byte(addr));
+    }
+    hash.finish()
+}
+
+fn u32_bytes(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+fn read_u32(r: &mut Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok((bytes[0] as u32) | ((bytes[1] as u32) << 8) | ((bytes[2] as u32) << 16) | ((bytes[3] as u32) << 24))
+}
@@ -0,0 +1,223 @@
+use rustual_boy_core::virtual_boy::VirtualBoy;
+
+use std::collections::VecDeque;
+use std::io::{self, Error, ErrorKind};
+
+/// A ring of save states that can be used to step a `VirtualBoy`
+/// backwards in time.
+///
+/// Only the most recent snapshot is kept in full. Every older snapshot
+/// is stored as the XOR of itself and the snapshot that came after it,
+/// with runs of zero bytes collapsed. Since most of VRAM and WRAM don't
+/// change from frame to frame, these deltas tend to be tiny.
+pub struct RewindBuffer {
+    /// Maximum number of bytes we'll hold on to before dropping the oldest snapshots
+    memory_budget: usize,
+    /// Number of frames between each captured snapshot
+    capture_interval: u32,
+    frame_counter: u32,
+
+    /// Most recently captured snapshot, uncompressed
+    newest: Option<Vec<u8>>,
+    /// Compressed deltas, oldest first. Applying the last delta to `newest`
+    /// yields the snapshot captured before it.
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+}
+
+impl RewindBuffer {
+    /// Create a new RewindBuffer which captures a snapshot every
+    /// `capture_interval` frames and uses at most (roughly) `memory_budget`
+    /// bytes to store them.
+    pub fn new(memory_budget: usize, capture_interval: u32) -> RewindBuffer {
+        RewindBuffer {
+            memory_budget: memory_budget,
+            capture_interval: if capture_interval == 0 { 1 } else { capture_interval },
+            frame_counter: 0,
+
+            newest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+
+    /// Notify the buffer that a frame has been emulated. A snapshot of
+    /// `virtual_boy` is captured every `capture_interval` calls.
+    pub fn frame(&mut self, virtual_boy: &VirtualBoy) {
+        self.frame_counter += 1;
+        if self.frame_counter >= self.capture_interval {
+            self.frame_counter = 0;
+            self.capture(virtual_boy);
+        }
+    }
+
+    /// Unconditionally capture a snapshot of `virtual_boy`.
+    pub fn capture(&mut self, virtual_boy: &VirtualBoy) {
+        let mut snapshot = Vec::new();
+        virtual_boy.save_state(&mut snapshot).unwrap();
+
+        if let Some(previous) = self.newest.take() {
+            let delta = encode_delta(&previous, &snapshot);
+            self.deltas_size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(snapshot);
+
+        while self.memory_usage() > self.memory_budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restore the most recent snapshot in to `virtual_boy` and drop it
+    /// from the buffer, so that the next call goes further back in time.
+    /// Returns false when there's nothing left to rewind to.
+    pub fn rewind(&mut self, virtual_boy: &mut VirtualBoy) -> bool {
+        let snapshot = match self.newest.take() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+
+        virtual_boy.load_state(&mut &snapshot[..]).unwrap();
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.deltas_size -= delta.len();
+            match decode_delta(&snapshot, &delta) {
+                Ok(older) => self.newest = Some(older),
+                // Every older snapshot is built on this one, so none of them can be recovered
+                Err(_) => self.clear(),
+            }
+        }
+        self.frame_counter = 0;
+
+        true
+    }
+
+    /// Drop all captured snapshots
+    pub fn clear(&mut self) {
+        self.frame_counter = 0;
+        self.newest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+
+    /// Number of snapshots currently available to rewind to
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Approximate number of bytes used by the captured snapshots
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, |snapshot| snapshot.len()) + self.deltas_size
+    }
+}
+
+// Delta layout: the older snapshot's length (u32 LE), followed by a sequence of
+//  (zero run length, literal length, literal bytes) chunks, with both lengths encoded
+//  as LEB128 varints. The bytes described are `older ^ newer`, where the shorter of the
+//  two is treated as if it were padded with zeroes.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let len = older.len() as u32;
+    ret.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+
+    let xor_byte = |index: usize| {
+        older.get(index).cloned().unwrap_or(0) ^ newer.get(index).cloned().unwrap_or(0)
+    };
+
+    let total_len = older.len();
+    let mut index = 0;
+    while index < total_len {
+        let zero_run_start = index;
+        while index < total_len && xor_byte(index) == 0 {
+            index += 1;
+        }
+
+        let literal_start = index;
+        while index < total_len && xor_byte(index) != 0 {
+            index += 1;
+        }
+
+        write_varint(&mut ret, literal_start - zero_run_start);
+        write_varint(&mut ret, index - literal_start);
+        for i in literal_start..index {
+            ret.push(xor_byte(i));
+        }
+    }
+
+    ret
+}
+
+fn decode_delta(newer: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    if delta.len() < 4 {
+        return Err(Error::new(ErrorKind::InvalidData, "Delta is missing its length"));
+    }
+    let len = (delta[0] as usize) | ((delta[1] as usize) << 8) | ((delta[2] as usize) << 16) | ((delta[3] as usize) << 24);
+
+    let mut ret = newer.to_vec();
+    ret.resize(len, 0);
+
+    let mut delta_index = 4;
+    let mut index: usize = 0;
+    while delta_index < delta.len() {
+        let zero_run_len = read_varint(delta, &mut delta_index)?;
+        let literal_len = read_varint(delta, &mut delta_index)?;
+        index = index.saturating_add(zero_run_len);
+        if index > len || literal_len > len - index {
+            return Err(Error::new(ErrorKind::InvalidData, "Delta runs past the end of the snapshot"));
+        }
+        if literal_len > delta.len() - delta_index {
+            return Err(Error::new(ErrorKind::InvalidData, "Delta ends partway through a literal"));
+        }
+
+        for (byte, &delta_byte) in ret[index..index + literal_len].iter_mut().zip(&delta[delta_index..delta_index + literal_len]) {
+            *byte ^= delta_byte;
+        }
+        index += literal_len;
+        delta_index += literal_len;
+    }
+
+    Ok(ret)
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.push(byte);
+            break;
+        }
+        buffer.push(byte | 0x80);
+    }
+}
+
+fn read_varint(buffer: &[u8], index: &mut usize) -> io::Result<usize> {
+    let mut ret = 0;
+    let mut shift = 0;
+    loop {
+        let byte = match buffer.get(*index) {
+            Some(&byte) => byte,
+            None => return Err(Error::new(ErrorKind::InvalidData, "Delta ends partway through a length")),
+        };
+        *index += 1;
+        if shift >= usize::max_value().count_ones() {
+            return Err(Error::new(ErrorKind::InvalidData, "Delta has an overlong length"));
+        }
+        ret |= ((byte & 0x7f) as usize) << shift;
+        if (byte & 0x80) == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    Ok(ret)
+}
@@ -0,0 +1,107 @@
+use rustual_boy_core::disassembler::disassemble;
+use rustual_boy_core::trace::TraceRecord;
+
+use std::io::{self, Write};
+
+/// Which instructions a `TraceLogger` writes out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceFilter {
+    /// Only instructions whose PC is in this (inclusive) range
+    pub addr_range: Option<(u32, u32)>,
+    /// Only instructions executed during this (inclusive) range of frames, counted
+    /// from 0 when the logger was created
+    pub frames: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    fn contains_addr(&self, addr: u32) -> bool {
+        match self.addr_range {
+            Some((start, end)) => addr >= start && addr <= end,
+            _ => true,
+        }
+    }
+
+    fn contains_frame(&self, frame: u64) -> bool {
+        match self.frames {
+            Some((first, last)) => frame >= first && frame <= last,
+            _ => true,
+        }
+    }
+}
+
+/// Writes the `TraceRecord`s of a traced `VirtualBoy` as text, one instruction per line:
+///
+/// ```text
+/// 0xfffff00c  61 c0 09 00  ld.b 9[r1], r3            r3: 0x00000000 -> 0x00000012  read byte [0x05000009] -> 0x12
+/// ```
+///
+/// The frontend is expected to pass each step's record to `log` and call `frame`
+/// whenever a video frame is emitted.
+pub struct TraceLogger<W: Write> {
+    writer: W,
+    filter: TraceFilter,
+    frame: u64,
+}
+
+impl<W: Write> TraceLogger<W> {
+    pub fn new(writer: W, filter: TraceFilter) -> TraceLogger<W> {
+        TraceLogger {
+            writer: writer,
+            filter: filter,
+            frame: 0,
+        }
+    }
+
+    pub fn filter(&self) -> TraceFilter {
+        self.filter
+    }
+
+    /// The number of frames emitted since the logger was created
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// True once the logger is past its frame window, so nothing more would be written
+    pub fn is_finished(&self) -> bool {
+        match self.filter.frames {
+            Some((_, last)) => self.frame > last,
+            _ => false,
+        }
+    }
+
+    pub fn log(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if !self.filter.contains_addr(record.pc) || !self.filter.contains_frame(self.frame) {
+            return Ok(());
+        }
+
+        let bytes = record.bytes();
+        // Four bytes always hold a whole instruction
+        let instruction = disassemble(&bytes, record.pc).unwrap();
+        let hex_bytes = bytes[..instruction.size as usize].iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let effects = record.register_changes.iter().map(|register_change| register_change.to_string())
+            .chain(record.memory_accesses.iter().map(|memory_access| memory_access.to_string()))
+            .collect::<Vec<_>>();
+
+        if effects.is_empty() {
+            writeln!(self.writer, "0x{:08x}  {:<11}  {}", record.pc, hex_bytes, instruction)
+        } else {
+            writeln!(self.writer, "0x{:08x}  {:<11}  {:<24}  {}", record.pc, hex_bytes, instruction.to_string(), effects.join("  "))
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -0,0 +1,237 @@
+extern crate rustual_boy_core;
+extern crate rustual_boy_middleware;
+
+#[path = "../../rustual-boy-core/tests/common/mod.rs"]
+mod common;
+
+use common::*;
+
+use rustual_boy_core::emulation_error::{AccessKind, AccessWidth, BusError};
+use rustual_boy_core::interconnect::BusErrorPolicy;
+use rustual_boy_core::trace::MemoryAccess;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_core::watchpoint::{Watchpoint, WatchpointKind};
+use rustual_boy_middleware::{GdbControl, GdbStub};
+
+use std::io::{Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const PROGRAM: &'static str = "
+    start:
+        movea 0x1234, r0, r5    ; 0xfffff000
+        movhi 0x0500, r0, r7    ; 0xfffff004
+    loop:
+        add 1, r6               ; 0xfffff008
+        st.w r6, 0[r7]          ; 0xfffff00a
+        br loop                 ; 0xfffff00e
+";
+
+struct Session {
+    stub: GdbStub,
+    client: TcpStream,
+    virtual_boy: VirtualBoy,
+}
+
+impl Session {
+    fn new() -> Session {
+        let (_, virtual_boy) = boot(PROGRAM);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        Session {
+            stub: GdbStub::new(server).unwrap(),
+            client: client,
+            virtual_boy: virtual_boy,
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |acc, x| acc.wrapping_add(x));
+        write!(self.client, "${}#{:02x}", data, checksum).unwrap();
+    }
+
+    /// Polls the stub (emulating while GDB has it running) until a reply packet arrives
+    fn reply(&mut self) -> String {
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            match self.stub.poll(&mut self.virtual_boy).unwrap() {
+                GdbControl::Running => {
+                    for _ in 0..100 {
+                        let trigger_watchpoint = step(&mut self.virtual_boy);
+                        if self.stub.instruction_executed(&mut self.virtual_boy, trigger_watchpoint).unwrap() {
+                            break;
+                        }
+                    }
+                }
+                GdbControl::Stopped => {}
+                GdbControl::Detached => panic!("Unexpected detach"),
+            }
+
+            let mut buffer = [0; 1024];
+            match self.client.read(&mut buffer) {
+                Ok(n) => received.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => panic!("{}", e),
+            }
+
+            let text = String::from_utf8(received.clone()).unwrap();
+            let text = text.trim_start_matches('+');
+            if let (Some(start), Some(end)) = (text.find('$'), text.find('#')) {
+                if text.len() >= end + 3 {
+                    return text[start + 1..end].into();
+                }
+            }
+        }
+        panic!("No reply from stub");
+    }
+
+    fn transact(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let mut session = Session::new();
+
+    assert_eq!(session.transact("?"), "T0540:f0ffffff;");
+    assert_eq!(session.transact("p40"), "f0ffffff");
+    assert_eq!(session.transact("g").len(), 65 * 8);
+
+    assert_eq!(session.transact("P5=78563412"), "OK");
+    assert_eq!(session.transact("p5"), "78563412");
+    assert_eq!(session.virtual_boy.cpu.reg_gpr(5), 0x12345678);
+
+    // psw is system register 5
+    assert_eq!(session.transact("P25=01000000"), "OK");
+    assert_eq!(session.virtual_boy.cpu.reg_psw() & 0x01, 0x01);
+
+    assert_eq!(session.transact("M5000000,3:abcdef"), "OK");
+    assert_eq!(session.transact("m5000000,3"), "abcdef");
+    assert_eq!(session.transact("mfffff000,4"), "a0a03412");
+
+    // Replies can't be longer than the advertised packet size
+    assert!(session.transact("qSupported").contains("PacketSize=4000"));
+    assert_eq!(session.transact("m5000000,2000").len(), 0x4000);
+    assert_eq!(session.transact("m5000000,2001"), "E01");
+    assert_eq!(session.transact("m5000000,ffffffff"), "E01");
+}
+
+#[test]
+fn reading_memory_leaves_the_cpu_alone() {
+    let mut session = Session::new();
+    session.virtual_boy.set_tracing(true);
+    step(&mut session.virtual_boy);
+    step(&mut session.virtual_boy);
+    let trace_record = session.virtual_boy.cpu.trace_record().cloned();
+    assert!(trace_record.is_some());
+
+    // An unmapped read the CPU hasn't dealt with yet
+    session.virtual_boy.interconnect.take_wait_cycles();
+    session.virtual_boy.interconnect.read_byte(0x03000000);
+
+    assert_eq!(session.transact("m5000000,4"), "ffffffff");
+    assert_eq!(session.transact("m2000010,1"), "02");
+    assert_eq!(session.transact("mfffff000,2"), "a0a0");
+
+    // Unmapped memory, including a read that runs off the end of the VSU into the link port
+    //  registers, is an error however bus errors are handled
+    assert_eq!(session.transact("m3000000,4"), "E14");
+    assert_eq!(session.transact("m1fffffe,4"), "E14");
+    session.virtual_boy.interconnect.set_bus_error_policy(BusErrorPolicy::OpenBus(0xffff));
+    assert_eq!(session.transact("m3000000,4"), "E14");
+    session.virtual_boy.interconnect.set_bus_error_policy(BusErrorPolicy::Stop);
+
+    assert!(session.virtual_boy.cpu.trace_record().cloned() == trace_record);
+    assert_eq!(session.virtual_boy.interconnect.take_memory_accesses(), vec![MemoryAccess {
+        addr: 0x03000000,
+        kind: AccessKind::Read,
+        width: AccessWidth::Byte,
+        value: 0,
+    }]);
+    assert_eq!(session.virtual_boy.interconnect.take_wait_cycles(), 0);
+    assert_eq!(session.virtual_boy.interconnect.take_bus_error(), Some(BusError {
+        addr: 0x03000000,
+        kind: AccessKind::Read,
+        width: AccessWidth::Byte,
+    }));
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut session = Session::new();
+
+    assert_eq!(session.transact("s"), "T0540:00f0ffff;");
+    assert_eq!(session.transact("s"), "T0540:04f0ffff;");
+
+    assert_eq!(session.transact("Z0,fffff00e,2"), "OK");
+    assert_eq!(session.transact("c"), "T0540:0ef0ffff;");
+    assert_eq!(session.virtual_boy.cpu.reg_gpr(5), 0x1234);
+    assert_eq!(session.virtual_boy.cpu.reg_gpr(7), 0x05000000);
+
+    // Each time around the loop stops at the breakpoint again
+    let r6 = session.virtual_boy.cpu.reg_gpr(6);
+    assert_eq!(session.transact("c"), "T0540:0ef0ffff;");
+    assert_eq!(session.virtual_boy.cpu.reg_gpr(6), r6.wrapping_add(1));
+
+    assert_eq!(session.transact("z0,fffff00e,2"), "OK");
+    session.send("c");
+    session.client.write_all(&[0x03]).unwrap();
+    assert!(session.reply().starts_with("T0240:"));
+}
+
+#[test]
+fn watchpoints() {
+    let mut session = Session::new();
+
+    assert_eq!(session.transact("P6=00000000"), "OK");
+    assert_eq!(session.transact("Z2,5000000,4"), "OK");
+    assert_eq!(session.virtual_boy.cpu.watchpoints, vec![Watchpoint::range(0x05000000, 0x05000003, WatchpointKind::Write)]);
+
+    // Stops just after the store
+    assert_eq!(session.transact("c"), "T0540:0ef0ffff;watch:5000000;");
+    assert_eq!(session.transact("m5000000,4"), "01000000");
+    assert_eq!(session.transact("?"), "T0540:0ef0ffff;watch:5000000;");
+
+    assert_eq!(session.transact("z2,5000000,4"), "OK");
+    assert!(session.virtual_boy.cpu.watchpoints.is_empty());
+
+    // Nothing reads memory, so a read watchpoint never stops the loop
+    assert_eq!(session.transact("Z3,5000000,4"), "OK");
+    assert_eq!(session.transact("Z0,fffff00e,2"), "OK");
+    assert_eq!(session.transact("c"), "T0540:0ef0ffff;");
+    assert_eq!(session.transact("z3,5000000,4"), "OK");
+    assert_eq!(session.transact("z0,fffff00e,2"), "OK");
+
+    // The word store only overlaps the start of this range, which is what gets reported
+    assert_eq!(session.transact("Z4,5000002,2"), "OK");
+    assert_eq!(session.transact("c"), "T0540:0ef0ffff;awatch:5000002;");
+}
+
+#[test]
+fn detaching_removes_watchpoints() {
+    let mut session = Session::new();
+    let watchpoint = Watchpoint::new(0x05000100, WatchpointKind::Access);
+    session.virtual_boy.cpu.watchpoints.push(watchpoint);
+
+    assert_eq!(session.transact("Z4,5000000,2"), "OK");
+    session.send("D");
+    let mut control = GdbControl::Stopped;
+    for _ in 0..1000 {
+        control = session.stub.poll(&mut session.virtual_boy).unwrap();
+        if control != GdbControl::Stopped {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(control, GdbControl::Detached);
+
+    // Only the stub's own watchpoints are removed
+    assert_eq!(session.virtual_boy.cpu.watchpoints, vec![watchpoint]);
+}
@@ -0,0 +1,164 @@
+//! Golden-frame regression tests. Every `<name>.vb` ROM in `tests/golden` (or
+//! `<name>.s` source, assembled at the start of Game Pak ROM) is booted headlessly, optionally driven by `<name>.movie`, and checked against the hashes
+//! in `<name>.golden`. Set `RUSTUAL_BOY_BLESS=1` to (re)write the golden records
+//! from the current build instead, running `RUSTUAL_BOY_BLESS_FRAMES` frames
+//! (300 by default) for ROMs that don't have one yet.
+
+extern crate rustual_boy_core;
+extern crate rustual_boy_middleware;
+
+use rustual_boy_core::assembler::assemble;
+use rustual_boy_core::rom::Rom;
+use rustual_boy_core::sram::Sram;
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_middleware::{GoldenRecord, MoviePlayer, MovieRecorder};
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_BLESS_FRAMES: usize = 300;
+
+// A ROM that spins forever at the reset vector
+fn idle_rom() -> Rom {
+    let mut bytes = vec![0; 1024];
+    for i in 0..bytes.len() / 2 {
+        // br 0
+        bytes[i * 2] = 0x00;
+        bytes[i * 2 + 1] = 0x8a;
+    }
+    Rom::from_bytes(&bytes).unwrap()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn load_rom(path: &Path) -> Result<Rom, String> {
+    if path.extension().map(|ext| ext == "s").unwrap_or(false) {
+        let mut source = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut source)).map_err(|e| e.to_string())?;
+        let assembly = assemble(&source, 0x07000000).map_err(|e| e.to_string())?;
+        Rom::from_bytes(&assembly.bytes).map_err(|e| e.to_string())
+    } else {
+        Rom::load(path).map_err(|e| e.to_string())
+    }
+}
+
+#[test]
+fn runs_are_deterministic() {
+    let first = GoldenRecord::run::<File>(&mut VirtualBoy::new(idle_rom(), Sram::new()), 10, None).unwrap();
+    let second = GoldenRecord::run::<File>(&mut VirtualBoy::new(idle_rom(), Sram::new()), 10, None).unwrap();
+
+    assert_eq!(first.frames.len(), 10);
+    assert!(first.num_audio_frames > 0);
+    assert_eq!(first.compare(&second), Ok(()));
+}
+
+#[test]
+fn golden_records_round_trip() {
+    let record = GoldenRecord::run::<File>(&mut VirtualBoy::new(idle_rom(), Sram::new()), 3, None).unwrap();
+
+    let mut text = Vec::new();
+    record.write(&mut text).unwrap();
+    let read_back = GoldenRecord::read(&mut &text[..]).unwrap();
+
+    assert_eq!(read_back, record);
+}
+
+#[test]
+fn movie_input_reaches_the_game_pad() {
+    let rom = idle_rom();
+    let mut movie = Vec::new();
+    {
+        let mut recorder = MovieRecorder::new(&mut movie, &rom).unwrap();
+        recorder.frame(0x0004).unwrap();
+        recorder.frame(0x1000).unwrap();
+    }
+
+    let mut player = MoviePlayer::new(&movie[..], &rom).unwrap();
+    let mut virtual_boy = VirtualBoy::new(rom, Sram::new());
+    GoldenRecord::run(&mut virtual_boy, 2, Some(&mut player)).unwrap();
+
+    assert_eq!(virtual_boy.interconnect.game_pad.buttons(), 0x1000);
+}
+
+#[test]
+fn corrupt_movie_header() {
+    let rom = idle_rom();
+    let mut movie = Vec::new();
+    MovieRecorder::new(&mut movie, &rom).unwrap();
+
+    // The ROM name's length follows the magic and version
+    movie[8..12].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f]);
+    let err = MoviePlayer::new(&movie[..], &rom).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn golden_roms() {
+    let is_blessing = env::var("RUSTUAL_BOY_BLESS").map(|value| value == "1").unwrap_or(false);
+    let bless_frames = env::var("RUSTUAL_BOY_BLESS_FRAMES").ok()
+        .map(|value| value.parse().expect("RUSTUAL_BOY_BLESS_FRAMES must be a number"))
+        .unwrap_or(DEFAULT_BLESS_FRAMES);
+
+    let mut rom_paths = fs::read_dir(golden_dir()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "vb" || ext == "s").unwrap_or(false))
+        .collect::<Vec<_>>();
+    rom_paths.sort();
+    assert!(!rom_paths.is_empty(), "No test ROMs in {}", golden_dir().display());
+
+    let mut failures = Vec::new();
+    for rom_path in rom_paths {
+        let golden_path = rom_path.with_extension("golden");
+        let movie_path = rom_path.with_extension("movie");
+
+        let rom = match load_rom(&rom_path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                failures.push(format!("{}: {}", rom_path.display(), e));
+                continue;
+            }
+        };
+        let mut movie = if movie_path.exists() {
+            Some(MoviePlayer::open(&movie_path, &rom).unwrap())
+        } else {
+            None
+        };
+
+        let golden = if golden_path.exists() {
+            Some(GoldenRecord::load(&golden_path).unwrap())
+        } else {
+            None
+        };
+        let num_frames = match golden {
+            Some(ref golden) => golden.frames.len(),
+            None if is_blessing => bless_frames,
+            None => {
+                failures.push(format!("{}: no golden record (run with RUSTUAL_BOY_BLESS=1 to create one)", rom_path.display()));
+                continue;
+            }
+        };
+
+        let mut virtual_boy = VirtualBoy::new(rom, Sram::new());
+        let record = match GoldenRecord::run::<BufReader<File>>(&mut virtual_boy, num_frames, movie.as_mut()) {
+            Ok(record) => record,
+            Err(e) => {
+                failures.push(format!("{}: {}", rom_path.display(), e));
+                continue;
+            }
+        };
+
+        if is_blessing {
+            record.save(&golden_path).unwrap();
+        } else if let Err(mismatch) = record.compare(golden.as_ref().unwrap()) {
+            failures.push(format!("{}: {}", rom_path.display(), mismatch));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("Golden record mismatches:\n{}", failures.join("\n"));
+    }
+}
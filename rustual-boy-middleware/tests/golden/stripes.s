@@ -0,0 +1,69 @@
+; Scrolls stripes through all four framebuffers with drawing disabled, while the first
+;  sound channel plays a ramp wave. Written for the golden record tests, so it exercises
+;  display and sound output without needing any assets.
+;
+; Like every .s file here, this is assembled at the start of Game Pak ROM and mirrored up
+;  to the reset vector.
+
+start:
+        ; Waveform 0: a ramp
+        movhi 0x0100, r0, r1    ; VSU
+        mov 0, r2
+        movea 32, r0, r3
+wave:
+        st.b r2, 0[r1]
+        add 2, r2
+        add 4, r1
+        add -1, r3
+        bnz wave
+
+        ; Sound 1: full volume on both sides, playing waveform 0
+        movhi 0x0100, r0, r1
+        movea 0xff, r0, r2
+        st.b r2, 0x404[r1]      ; S1LRV
+        mov 0, r2
+        st.b r2, 0x408[r1]      ; S1FQL
+        mov 6, r2
+        st.b r2, 0x40c[r1]      ; S1FQH
+        movea 0xf0, r0, r2
+        st.b r2, 0x410[r1]      ; S1EV0: volume 15, no envelope
+        mov 0, r2
+        st.b r2, 0x414[r1]      ; S1EV1
+        st.b r2, 0x418[r1]      ; S1RAM: waveform 0
+        movea 0x80, r0, r2
+        st.b r2, 0x400[r1]      ; S1INT: enable
+
+        ; Display on, with drawing left off so the framebuffers keep what's written to them
+        movhi 0x0006, r0, r1
+        movea -0x800, r1, r1    ; VIP registers (0x0005f800)
+        movea 32, r0, r2
+        st.h r2, 0x24[r1]       ; BRTA
+        movea 64, r0, r2
+        st.h r2, 0x26[r1]       ; BRTB
+        movea 32, r0, r2
+        st.h r2, 0x28[r1]       ; BRTC
+        movea 0x302, r0, r2
+        st.h r2, 0x22[r1]       ; DPCTRL: DISP, RE, SYNCE
+
+        mov 0, r5               ; pattern
+        mov 0, r6               ; offset into each framebuffer
+        movea 0x6000, r0, r9    ; framebuffer size
+loop:
+        st.w r5, 0[r6]          ; left framebuffer 0
+        movhi 1, r6, r7
+        st.w r5, 0[r7]          ; right framebuffer 0
+        st.w r5, -0x8000[r7]    ; left framebuffer 1
+        movhi 2, r6, r7
+        st.w r5, -0x8000[r7]    ; right framebuffer 1
+        add 4, r6
+        cmp r9, r6
+        bne loop
+
+        mov 0, r6
+        movhi 0x1b1b, r5, r5
+        movea 0x1b1b, r5, r5
+        br loop
+
+        .org 0x07000ff0
+        jr start
+        .fill 12
@@ -0,0 +1,136 @@
+extern crate rustual_boy_core;
+extern crate rustual_boy_middleware;
+
+#[path = "../../rustual-boy-core/tests/common/mod.rs"]
+mod common;
+
+use common::*;
+
+use rustual_boy_middleware::NetworkLink;
+
+use std::io::{self, Cursor, Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const SYNC_PERIOD: u64 = 1000;
+
+// A stream that reads back canned data and collects whatever is written to it
+struct CannedStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for CannedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for CannedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn handshake(magic: &[u8], sync_period: u64) -> Vec<u8> {
+    let mut handshake = magic.to_vec();
+    handshake.push(1);
+    for i in 0..8 {
+        handshake.push((sync_period >> (i * 8)) as u8);
+    }
+    handshake
+}
+
+fn connect(peer_handshake: Vec<u8>) -> io::Result<NetworkLink<CannedStream>> {
+    NetworkLink::new(CannedStream { input: Cursor::new(peer_handshake), output: Vec::new() }, SYNC_PERIOD)
+}
+
+#[test]
+fn handshake_checks_peer() {
+    assert!(connect(handshake(b"RBLK", SYNC_PERIOD)).is_ok());
+    assert_eq!(connect(handshake(b"RBLK", SYNC_PERIOD * 2)).err().unwrap().kind(), ErrorKind::InvalidData);
+    assert_eq!(connect(handshake(b"HTTP", SYNC_PERIOD)).err().unwrap().kind(), ErrorKind::InvalidData);
+    assert_eq!(connect(Vec::new()).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+}
+
+// Runs `link_port_program(setup)` for a fixed number of cycles (so both ends sync the same
+//  number of times), returning the registers its interrupt handler stored
+fn run(stream: TcpStream, setup: &'static str) -> Vec<u32> {
+    let mut link = NetworkLink::new(stream, SYNC_PERIOD).unwrap();
+    let (_, mut virtual_boy) = boot(&link_port_program(setup));
+
+    let mut cycles = 0;
+    while cycles < 100 * SYNC_PERIOD {
+        let (step_cycles, _) = virtual_boy.step(&mut NullSink, &mut NullSink).unwrap();
+        link.cycles(step_cycles, &mut virtual_boy).unwrap();
+        cycles += step_cycles as u64;
+    }
+
+    (5..11).map(|index| virtual_boy.cpu.reg_gpr(index)).collect()
+}
+
+#[test]
+fn byte_exchange() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let slave = thread::spawn(move || run(TcpStream::connect(addr).unwrap(), "
+        movea 0xa5, r0, r2
+        st.b r2, 0x08[r1]       ; CDTR
+        movea 0x14, r0, r2
+        st.b r2, 0x00[r1]       ; CCR: start, external clock, interrupt enabled"));
+    let master = run(listener.accept().unwrap().0, "
+        movea 0x5a, r0, r2
+        st.b r2, 0x08[r1]
+        mov 0x04, r2
+        st.b r2, 0x00[r1]       ; CCR: start, internal clock, interrupt enabled");
+    let slave = slave.join().unwrap();
+
+    // Exception code, CCR, CCSR, CDRR, (unused), done
+    assert_eq!(master[0] & 0xffff, 0xfe30);
+    assert_eq!(master[1], 0x69);
+    assert_eq!(master[3], 0xa5);
+    assert_eq!(master[5], 1);
+    assert_eq!(slave[0] & 0xffff, 0xfe30);
+    assert_eq!(slave[1], 0x79);
+    assert_eq!(slave[3], 0x5a);
+    assert_eq!(slave[5], 1);
+}
+
+#[test]
+fn sync_messages() {
+    // A master's byte goes out with the first sync after it's clocked out, and the reply
+    //  (along with the peer's COMCNT level) is picked up from the peer's messages
+    let mut peer = handshake(b"RBLK", SYNC_PERIOD);
+    for _ in 0..10 {
+        peer.extend_from_slice(&[0x05, 0x00, 0xa5]);
+    }
+    let mut link = connect(peer).unwrap();
+    let (_, mut virtual_boy) = boot(&link_port_program("
+        movea 0x5a, r0, r2
+        st.b r2, 0x08[r1]
+        mov 0x04, r2
+        st.b r2, 0x00[r1]"));
+
+    let mut cycles = 0;
+    while cycles < 10 * SYNC_PERIOD {
+        let (step_cycles, _) = virtual_boy.step(&mut NullSink, &mut NullSink).unwrap();
+        link.cycles(step_cycles, &mut virtual_boy).unwrap();
+        cycles += step_cycles as u64;
+    }
+    assert_eq!(virtual_boy.cpu.reg_gpr(8), 0xa5);
+    assert_eq!(virtual_boy.cpu.reg_gpr(10), 1);
+
+    let output = link.into_inner().output;
+    assert_eq!(output[..13], handshake(b"RBLK", SYNC_PERIOD)[..]);
+    let messages = output[13..].chunks(3).collect::<Vec<_>>();
+    assert_eq!(messages.len(), 10);
+    assert!(messages.iter().all(|message| message[0] & 0x01 != 0));
+    let master_bytes = messages.iter().filter(|message| message[0] & 0x02 != 0).collect::<Vec<_>>();
+    assert_eq!(master_bytes.len(), 1);
+    assert_eq!(master_bytes[0][1], 0x5a);
+}
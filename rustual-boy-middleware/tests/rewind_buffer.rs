@@ -0,0 +1,101 @@
+extern crate rustual_boy_core;
+extern crate rustual_boy_middleware;
+
+#[path = "../../rustual-boy-core/tests/common/mod.rs"]
+mod common;
+
+use common::*;
+
+use rustual_boy_core::virtual_boy::VirtualBoy;
+use rustual_boy_middleware::RewindBuffer;
+
+fn snapshot(virtual_boy: &VirtualBoy) -> Vec<u8> {
+    let mut snapshot = Vec::new();
+    virtual_boy.save_state(&mut snapshot).unwrap();
+    snapshot
+}
+
+// Changes a register and a few scattered bytes of WRAM, so consecutive snapshots
+//  differ in more than one place
+fn change_state(virtual_boy: &mut VirtualBoy, i: u32) {
+    virtual_boy.cpu.set_reg_gpr(1, i);
+    for offset in 0..3 {
+        virtual_boy.interconnect.write_byte(0x05000000 + offset * 0x1000 + i, i as u8 + 1);
+    }
+}
+
+#[test]
+fn rewinds_to_earlier_states() {
+    let (_, mut virtual_boy) = boot("");
+    let mut rewind_buffer = RewindBuffer::new(usize::max_value(), 1);
+
+    let mut snapshots = Vec::new();
+    for i in 0..5 {
+        change_state(&mut virtual_boy, i);
+        snapshots.push(snapshot(&virtual_boy));
+        rewind_buffer.capture(&virtual_boy);
+    }
+    assert_eq!(rewind_buffer.len(), 5);
+
+    // Only the newest snapshot is kept whole; the rest are small deltas
+    assert!(rewind_buffer.memory_usage() < snapshots[0].len() + 4 * 100);
+
+    change_state(&mut virtual_boy, 10);
+    for expected in snapshots.iter().rev() {
+        assert!(rewind_buffer.rewind(&mut virtual_boy));
+        assert_eq!(snapshot(&virtual_boy), *expected);
+    }
+    assert!(rewind_buffer.is_empty());
+    assert!(!rewind_buffer.rewind(&mut virtual_boy));
+    assert_eq!(snapshot(&virtual_boy), snapshots[0]);
+}
+
+#[test]
+fn capture_interval() {
+    let (_, mut virtual_boy) = boot("");
+    let mut rewind_buffer = RewindBuffer::new(usize::max_value(), 3);
+
+    let mut snapshots = Vec::new();
+    for i in 0..7 {
+        change_state(&mut virtual_boy, i);
+        snapshots.push(snapshot(&virtual_boy));
+        rewind_buffer.frame(&virtual_boy);
+    }
+    assert_eq!(rewind_buffer.len(), 2);
+
+    assert!(rewind_buffer.rewind(&mut virtual_boy));
+    assert_eq!(snapshot(&virtual_boy), snapshots[5]);
+    assert!(rewind_buffer.rewind(&mut virtual_boy));
+    assert_eq!(snapshot(&virtual_boy), snapshots[2]);
+}
+
+#[test]
+fn memory_budget() {
+    let (_, mut virtual_boy) = boot("");
+    let snapshot_len = snapshot(&virtual_boy).len();
+    let budget = snapshot_len + 100;
+    let mut rewind_buffer = RewindBuffer::new(budget, 1);
+
+    let mut snapshots = Vec::new();
+    for i in 0..20 {
+        change_state(&mut virtual_boy, i);
+        snapshots.push(snapshot(&virtual_boy));
+        rewind_buffer.capture(&virtual_boy);
+        assert!(rewind_buffer.memory_usage() <= budget);
+    }
+
+    // The oldest snapshots were dropped, and the ones left still rewind correctly
+    let len = rewind_buffer.len();
+    assert!(len > 1 && len < 20, "{} snapshots kept", len);
+    for expected in snapshots.iter().rev().take(len) {
+        assert!(rewind_buffer.rewind(&mut virtual_boy));
+        assert_eq!(snapshot(&virtual_boy), *expected);
+    }
+    assert!(!rewind_buffer.rewind(&mut virtual_boy));
+
+    // The newest snapshot is kept even if it alone is over budget
+    let mut rewind_buffer = RewindBuffer::new(0, 1);
+    rewind_buffer.capture(&virtual_boy);
+    rewind_buffer.capture(&virtual_boy);
+    assert_eq!(rewind_buffer.len(), 1);
+}
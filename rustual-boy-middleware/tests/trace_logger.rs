@@ -0,0 +1,67 @@
+extern crate rustual_boy_core;
+extern crate rustual_boy_middleware;
+
+use rustual_boy_core::emulation_error::{AccessKind, AccessWidth};
+use rustual_boy_core::trace::*;
+use rustual_boy_middleware::{TraceFilter, TraceLogger};
+
+// ld.b 9[r1], r3
+fn load(pc: u32) -> TraceRecord {
+    TraceRecord {
+        pc: pc,
+        first_halfword: 0xc061,
+        second_halfword: 0x0009,
+        register_changes: vec![
+            RegisterChange { register: TraceRegister::Gpr(3), old_value: 0, new_value: 0x12 },
+        ],
+        memory_accesses: vec![
+            MemoryAccess { addr: 0x05000009, kind: AccessKind::Read, width: AccessWidth::Byte, value: 0x12 },
+        ],
+    }
+}
+
+// mov r0, r0
+fn nop(pc: u32) -> TraceRecord {
+    TraceRecord {
+        pc: pc,
+        first_halfword: 0x0000,
+        second_halfword: 0x0000,
+        register_changes: Vec::new(),
+        memory_accesses: Vec::new(),
+    }
+}
+
+fn lines(logger: TraceLogger<Vec<u8>>) -> Vec<String> {
+    String::from_utf8(logger.into_inner()).unwrap().lines().map(String::from).collect()
+}
+
+#[test]
+fn format() {
+    let mut logger = TraceLogger::new(Vec::new(), TraceFilter::default());
+    logger.log(&load(0xfffff00c)).unwrap();
+    logger.log(&nop(0xfffff010)).unwrap();
+
+    assert_eq!(lines(logger), vec![
+        "0xfffff00c  61 c0 09 00  ld.b 9[r1], r3            r3: 0x00000000 -> 0x00000012  read byte [0x05000009] -> 0x12",
+        "0xfffff010  00 00        mov r0, r0",
+    ]);
+}
+
+#[test]
+fn filters() {
+    let mut logger = TraceLogger::new(Vec::new(), TraceFilter {
+        addr_range: Some((0x07000000, 0x070000ff)),
+        frames: Some((1, 2)),
+    });
+
+    for _ in 0..3 {
+        assert!(!logger.is_finished());
+        logger.log(&nop(0x07000000)).unwrap();
+        logger.log(&nop(0x07000100)).unwrap();
+        logger.log(&nop(0x070000fe)).unwrap();
+        logger.frame();
+    }
+    assert!(logger.is_finished());
+
+    assert_eq!(lines(logger).len(), 4);
+}